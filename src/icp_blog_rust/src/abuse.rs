@@ -0,0 +1,235 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::moderation::ReportTarget;
+use crate::{auth, date, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Signals kept; older ones are dropped
+const MAX_SIGNALS: u64 = 50_000;
+// Longest window `get_abuse_signals` summarizes, in days
+const MAX_DAYS: u64 = 90;
+// Entries per top list
+const TOP_LIMIT: usize = 10;
+// How long a flagged principal stays rate limited
+const FLAG_NANOS: u64 = date::NANOS_PER_DAY;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum SignalKind {
+    ReportFiled {
+        reporter: Principal,
+        target: ReportTarget,
+    },
+    RateLimited {
+        principal: Principal,
+    },
+    CommentPosted,
+    // a moderator hid or bulk deleted a comment
+    CommentRemoved,
+    // a decoy method no legitimate client calls
+    HoneypotHit {
+        principal: Principal,
+        method: String,
+    },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Signal {
+    kind: SignalKind,
+    at: u64,
+}
+
+impl Storable for Signal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PrincipalCount {
+    principal: Principal,
+    count: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct TargetCount {
+    target: ReportTarget,
+    count: u64,
+}
+
+// Signal counts of one day, days counted since the Unix epoch. Comments
+// removed over comments posted is the rate at which moderation catches spam.
+#[derive(candid::CandidType, Default, Serialize, Deserialize)]
+pub(crate) struct DailySignals {
+    day: u64,
+    reports: u64,
+    rate_limited: u64,
+    comments_posted: u64,
+    comments_removed: u64,
+    honeypot_hits: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct AbuseSignals {
+    top_reporters: Vec<PrincipalCount>,
+    most_reported: Vec<TargetCount>,
+    most_rate_limited: Vec<PrincipalCount>,
+    honeypot_callers: Vec<PrincipalCount>,
+    // oldest day first; days without signals are left out
+    daily: Vec<DailySignals>,
+}
+
+thread_local! {
+    static SIGNAL_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::SIGNAL_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a signal counter")
+    );
+
+    static SIGNALS: RefCell<StableBTreeMap<u64, Signal, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SIGNALS_MEMORY)));
+
+    // Principals caught by a honeypot, with the time they were caught
+    static FLAGGED: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::FLAGGED_MEMORY)));
+}
+
+// Append a signal to the log
+pub(crate) fn record(kind: SignalKind) {
+    let id = SIGNAL_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the signal counter");
+        id
+    });
+    SIGNALS.with(|signals| {
+        let mut signals = signals.borrow_mut();
+        signals.insert(id, Signal { kind, at: time() });
+        while signals.len() > MAX_SIGNALS {
+            signals.pop_first();
+        }
+    });
+}
+
+// Record a call to a decoy method and flag the caller, who is then rate
+// limited for a day
+pub(crate) fn flag(principal: Principal, method: &str) {
+    record(SignalKind::HoneypotHit {
+        principal,
+        method: method.to_string(),
+    });
+    if principal != Principal::anonymous() {
+        FLAGGED.with(|flagged| {
+            flagged
+                .borrow_mut()
+                .insert(principal_key(&principal), time())
+        });
+    }
+}
+
+pub(crate) fn is_flagged(principal: &Principal) -> bool {
+    FLAGGED
+        .with(|flagged| flagged.borrow().get(&principal_key(principal)))
+        .is_some_and(|at| time() < at.saturating_add(FLAG_NANOS))
+}
+
+fn top<K: Ord>(counts: BTreeMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counts.truncate(TOP_LIMIT);
+    counts
+}
+
+// Query function for moderators to summarize the abuse signals of the last
+// `days` days
+#[ic_cdk::query]
+fn get_abuse_signals(days: u64) -> Result<AbuseSignals, Error> {
+    auth::require_moderator()?;
+    let since = time().saturating_sub(days.clamp(1, MAX_DAYS) * date::NANOS_PER_DAY);
+    let mut reporters = BTreeMap::new();
+    // targets are keyed by kind and ID to stay ordered
+    let mut reported: BTreeMap<(u8, u64), u64> = BTreeMap::new();
+    let mut rate_limited = BTreeMap::new();
+    let mut honeypot_callers = BTreeMap::new();
+    let mut daily: BTreeMap<u64, DailySignals> = BTreeMap::new();
+    SIGNALS.with(|signals| {
+        for (_, signal) in signals.borrow().iter() {
+            if signal.at < since {
+                continue;
+            }
+            let day_number = date::day_number(signal.at);
+            let day = daily.entry(day_number).or_insert_with(|| DailySignals {
+                day: day_number,
+                ..Default::default()
+            });
+            match signal.kind {
+                SignalKind::ReportFiled { reporter, target } => {
+                    day.reports += 1;
+                    *reporters.entry(reporter).or_insert(0) += 1;
+                    let key = match target {
+                        ReportTarget::Post(id) => (0, id),
+                        ReportTarget::Comment(id) => (1, id),
+                    };
+                    *reported.entry(key).or_insert(0) += 1;
+                }
+                SignalKind::RateLimited { principal } => {
+                    day.rate_limited += 1;
+                    *rate_limited.entry(principal).or_insert(0) += 1;
+                }
+                SignalKind::CommentPosted => day.comments_posted += 1,
+                SignalKind::CommentRemoved => day.comments_removed += 1,
+                SignalKind::HoneypotHit { principal, .. } => {
+                    day.honeypot_hits += 1;
+                    *honeypot_callers.entry(principal).or_insert(0) += 1;
+                }
+            }
+        }
+    });
+    let principal_counts = |counts| {
+        top(counts)
+            .into_iter()
+            .map(|(principal, count)| PrincipalCount { principal, count })
+            .collect()
+    };
+    Ok(AbuseSignals {
+        top_reporters: principal_counts(reporters),
+        most_reported: top(reported)
+            .into_iter()
+            .map(|((kind, id), count)| TargetCount {
+                target: if kind == 0 {
+                    ReportTarget::Post(id)
+                } else {
+                    ReportTarget::Comment(id)
+                },
+                count,
+            })
+            .collect(),
+        most_rate_limited: principal_counts(rate_limited),
+        honeypot_callers: principal_counts(honeypot_callers),
+        daily: daily.into_values().collect(),
+    })
+}
+
+// Update function for moderators to lift a honeypot flag, e.g. after a
+// legitimate client called a decoy by mistake
+#[ic_cdk::update]
+fn clear_abuse_flag(principal: Principal) -> Result<(), Error> {
+    auth::require_moderator()?;
+    match FLAGGED.with(|flagged| flagged.borrow_mut().remove(&principal_key(&principal))) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not flagged", principal),
+        }),
+    }
+}
@@ -0,0 +1,159 @@
+use crate::keys::PrincipalIdKey;
+use crate::memory::{self, Memory};
+use crate::{_get_blog_post, comments, likes, privacy, visibility, BlogPost, PostStatus};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Events `get_activity` returns per page
+const ACTIVITY_PAGE_SIZE: usize = 20;
+// Largest page `get_user_activity` returns
+const MAX_ACTIVITY_PAGE: u64 = 50;
+// Events kept per principal; older ones are dropped
+const MAX_EVENTS: usize = 500;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum ActivityKind {
+    Published { post_id: u64 },
+    Commented { post_id: u64, comment_id: u64 },
+    Liked { post_id: u64 },
+}
+
+// Something a principal did in public
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ActivityEvent {
+    kind: ActivityKind,
+    at: u64,
+}
+
+impl Storable for ActivityEvent {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the event log: a principal and an event id, so the events of a
+// principal are one contiguous range in the order they happened
+type EventKey = PrincipalIdKey;
+
+thread_local! {
+    static EVENT_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::EVENT_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create an event counter")
+    );
+
+    static EVENTS: RefCell<StableBTreeMap<EventKey, ActivityEvent, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::EVENTS_MEMORY)));
+}
+
+// Append an event to the log of a principal
+pub(crate) fn log(principal: &Principal, kind: ActivityKind) {
+    if *principal == Principal::anonymous() {
+        return;
+    }
+    let event_id = EVENT_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the event counter");
+        id
+    });
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        events.insert(
+            EventKey::new(principal, event_id),
+            ActivityEvent { kind, at: time() },
+        );
+        let keys: Vec<EventKey> = events
+            .range(EventKey::all_of(principal))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys.iter().take(keys.len().saturating_sub(MAX_EVENTS)) {
+            events.remove(key);
+        }
+    });
+}
+
+// Note that a post went public, either right away or on its schedule
+pub(crate) fn log_published(blog_post: &BlogPost) {
+    if let Ok(author) = Principal::from_text(&blog_post.author) {
+        log(
+            &author,
+            ActivityKind::Published {
+                post_id: blog_post.id,
+            },
+        );
+    }
+}
+
+fn is_published(post_id: u64) -> bool {
+    _get_blog_post(&post_id).is_some_and(|blog_post| {
+        blog_post.status == PostStatus::Published && visibility::is_listed(&blog_post)
+    })
+}
+
+// The log keeps events of content that was since deleted, hidden or unliked;
+// only what is still public is shown
+fn is_public(principal: &Principal, kind: &ActivityKind, likes_public: bool) -> bool {
+    match kind {
+        ActivityKind::Published { post_id } => is_published(*post_id),
+        ActivityKind::Commented {
+            post_id,
+            comment_id,
+        } => {
+            comments::get(*comment_id).is_some_and(|comment| !comment.hidden)
+                && is_published(*post_id)
+        }
+        ActivityKind::Liked { post_id } => {
+            likes_public && likes::has_liked(*post_id, principal) && is_published(*post_id)
+        }
+    }
+}
+
+// Public events of a principal, newest first: the posts they published,
+// their comments and, if they share them, their likes
+fn public_events(principal: &Principal, offset: usize, limit: usize) -> Vec<ActivityEvent> {
+    let likes_public = privacy::can_see(principal, privacy::settings(principal).likes_public);
+    EVENTS.with(|events| {
+        events
+            .borrow()
+            .range(EventKey::all_of(principal))
+            .rev()
+            .map(|(_, event)| event)
+            .filter(|event| is_public(principal, &event.kind, likes_public))
+            .skip(offset)
+            .take(limit)
+            .collect()
+    })
+}
+
+// Query function to page through the public activity of a principal, newest
+// first, in pages of a fixed size
+#[ic_cdk::query]
+fn get_activity(principal: Principal, page: u64) -> Vec<ActivityEvent> {
+    public_events(
+        &principal,
+        (page as usize).saturating_mul(ACTIVITY_PAGE_SIZE),
+        ACTIVITY_PAGE_SIZE,
+    )
+}
+
+// Query function to page through the public activity of a principal, newest
+// first, from `offset`
+#[ic_cdk::query]
+fn get_user_activity(principal: Principal, offset: u64, limit: u64) -> Vec<ActivityEvent> {
+    public_events(
+        &principal,
+        offset as usize,
+        limit.min(MAX_ACTIVITY_PAGE) as usize,
+    )
+}
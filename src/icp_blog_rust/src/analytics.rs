@@ -0,0 +1,266 @@
+use crate::memory::{self, Memory};
+use crate::{_check_if_owner, _get_blog_post, auth, comments, date, Error};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::thread::LocalKey;
+use std::{borrow::Cow, cell::RefCell};
+
+// Days of gateway views kept
+const GATEWAY_DAYS: u64 = 90;
+// Most buckets of expired days dropped per call, so cleanup stays cheap
+const PRUNE_BATCH: usize = 100;
+// Most crawler patterns, and their longest length in bytes
+const MAX_CRAWLER_PATTERNS: usize = 100;
+const MAX_PATTERN_LEN: usize = 100;
+
+// User agents containing one of these, ignoring case, are counted as
+// crawlers until admins set their own list
+const DEFAULT_CRAWLER_PATTERNS: [&str; 10] = [
+    "bot",
+    "crawler",
+    "spider",
+    "slurp",
+    "facebookexternalhit",
+    "headless",
+    "curl",
+    "wget",
+    "python-requests",
+    "go-http-client",
+];
+
+// Views of a post on one UTC day
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DailyViews {
+    // days since the epoch
+    day: u64,
+    views: u64,
+}
+
+// What readers did with a post, as returned by `get_post_analytics`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PostAnalytics {
+    post_id: u64,
+    // counted by `record_view`, at most once per reader per day
+    views: u64,
+    likes: u32,
+    dislikes: u32,
+    comments: u64,
+    // pages of the post served by the HTTP gateway to readers over the last
+    // `GATEWAY_DAYS` days, anonymous and without any detail about the reader.
+    // Only a sample is counted, about one in `http::VIEW_SAMPLE_RATE`.
+    gateway_views: u64,
+    // the same, per day, oldest first; days without any are left out
+    gateway_views_by_day: Vec<DailyViews>,
+    // pages of the post served to crawlers, see `set_crawler_patterns`
+    crawler_views: u64,
+    crawler_views_by_day: Vec<DailyViews>,
+}
+
+// Key of the gateway views map: the day followed by the post id. Days come
+// first so the buckets of expired days can be dropped from the front of the
+// map.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct BucketKey {
+    day: u64,
+    post_id: u64,
+}
+
+impl Storable for BucketKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.day.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        BucketKey {
+            day: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+// User agent patterns admins set
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CrawlerPatterns(Vec<String>);
+
+impl Default for CrawlerPatterns {
+    fn default() -> Self {
+        CrawlerPatterns(
+            DEFAULT_CRAWLER_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+        )
+    }
+}
+
+impl Storable for CrawlerPatterns {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+type Buckets = RefCell<StableBTreeMap<BucketKey, u64, Memory>>;
+
+thread_local! {
+    // (day, post) -> pages of the post the gateway served readers that day
+    static GATEWAY_VIEWS: Buckets =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::GATEWAY_VIEWS_MEMORY)));
+
+    // (day, post) -> pages of the post the gateway served crawlers that day
+    static CRAWLER_VIEWS: Buckets =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CRAWLER_VIEWS_MEMORY)));
+
+    static CRAWLER_PATTERNS: RefCell<Cell<CrawlerPatterns, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::CRAWLER_PATTERNS_MEMORY), CrawlerPatterns::default())
+            .expect("Cannot create the crawler patterns cell")
+    );
+}
+
+fn first_kept_day(today: u64) -> u64 {
+    today.saturating_sub(GATEWAY_DAYS - 1)
+}
+
+fn crawler_patterns() -> Vec<String> {
+    CRAWLER_PATTERNS.with(|cell| cell.borrow().get().0.clone())
+}
+
+// Whether a user agent matches one of the crawler patterns. Requests without
+// one count as readers.
+fn is_crawler(user_agent: Option<&str>) -> bool {
+    let Some(user_agent) = user_agent.map(str::to_lowercase) else {
+        return false;
+    };
+    crawler_patterns()
+        .iter()
+        .any(|pattern| user_agent.contains(pattern.as_str()))
+}
+
+// Count a page of a post served by the HTTP gateway, to a crawler or a reader
+// depending on its user agent
+pub(crate) fn record_gateway_view(post_id: u64, user_agent: Option<&str>) {
+    let buckets = if is_crawler(user_agent) {
+        &CRAWLER_VIEWS
+    } else {
+        &GATEWAY_VIEWS
+    };
+    increment(buckets, post_id, 1);
+}
+
+fn increment(buckets: &'static LocalKey<Buckets>, post_id: u64, added: u64) {
+    let today = date::day_number(time());
+    let key = BucketKey {
+        day: today,
+        post_id,
+    };
+    buckets.with(|views| {
+        let mut views = views.borrow_mut();
+        let expired: Vec<BucketKey> = views
+            .iter()
+            .take_while(|(key, _)| key.day < first_kept_day(today))
+            .take(PRUNE_BATCH)
+            .map(|(key, _)| key)
+            .collect();
+        for key in &expired {
+            views.remove(key);
+        }
+        let count = views.get(&key).unwrap_or(0);
+        views.insert(key, count + added);
+    });
+}
+
+fn views_by_day(buckets: &'static LocalKey<Buckets>, post_id: u64) -> Vec<DailyViews> {
+    let today = date::day_number(time());
+    buckets.with(|views| {
+        let views = views.borrow();
+        (first_kept_day(today)..=today)
+            .filter_map(|day| {
+                views
+                    .get(&BucketKey { day, post_id })
+                    .map(|views| DailyViews { day, views })
+            })
+            .collect()
+    })
+}
+
+// Query function for admins and the post's author to see how a post is read
+#[ic_cdk::query]
+fn get_post_analytics(post_id: u64) -> Result<PostAnalytics, Error> {
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) && !auth::is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Unauthorized to see the analytics of post with id={}.",
+                post_id
+            ),
+        });
+    }
+    let gateway_views_by_day = views_by_day(&GATEWAY_VIEWS, post_id);
+    let crawler_views_by_day = views_by_day(&CRAWLER_VIEWS, post_id);
+    Ok(PostAnalytics {
+        post_id,
+        views: blog_post.views,
+        likes: blog_post.likes,
+        dislikes: blog_post.dislikes,
+        comments: comments::count(post_id) as u64,
+        gateway_views: gateway_views_by_day.iter().map(|day| day.views).sum(),
+        gateway_views_by_day,
+        crawler_views: crawler_views_by_day.iter().map(|day| day.views).sum(),
+        crawler_views_by_day,
+    })
+}
+
+// Query function for admins to list the user agent patterns of crawlers
+#[ic_cdk::query]
+fn get_crawler_patterns() -> Result<Vec<String>, Error> {
+    auth::require_admin()?;
+    Ok(crawler_patterns())
+}
+
+// Update function for admins to replace the user agent patterns of crawlers.
+// A user agent containing any of them, ignoring case, is a crawler's. Views
+// counted before keep the side they were counted on.
+#[ic_cdk::update]
+fn set_crawler_patterns(patterns: Vec<String>) -> Result<Vec<String>, Error> {
+    auth::require_admin()?;
+    let patterns: Vec<String> = patterns
+        .iter()
+        .map(|pattern| pattern.trim().to_lowercase())
+        .collect();
+    if patterns.len() > MAX_CRAWLER_PATTERNS
+        || patterns
+            .iter()
+            .any(|pattern| pattern.is_empty() || pattern.len() > MAX_PATTERN_LEN)
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Up to {} crawler patterns of 1 to {} bytes can be set",
+                MAX_CRAWLER_PATTERNS, MAX_PATTERN_LEN
+            ),
+        });
+    }
+    CRAWLER_PATTERNS.with(|cell| {
+        cell.borrow_mut()
+            .set(CrawlerPatterns(patterns.clone()))
+            .expect("Cannot write the crawler patterns")
+    });
+    Ok(patterns)
+}
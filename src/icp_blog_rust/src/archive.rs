@@ -0,0 +1,211 @@
+use crate::audit::{self, AuditAction};
+use crate::comments::{self, Comment};
+use crate::memory::{self, Memory};
+use crate::{_count_blog_posts, _delete_blog_post, _get_blog_post, _oldest_blog_posts, auth};
+use crate::{BlogPost, Error, PostStatus};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::stable::stable64_size;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+// Most posts moved per call, and the most post text sent in one message, in
+// bytes, so a batch stays below the message size limit
+const MAX_ARCHIVE_BATCH: u64 = 50;
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+
+// Where old posts are moved once the canister grows past either threshold.
+// The archive canister must implement
+// `store_posts : (vec ArchivedPost) -> ()`, replacing posts it already has,
+// and serve them to readers itself.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ArchiveConfig {
+    archive_canister: Principal,
+    max_posts: u64,
+    // in bytes
+    max_stable_memory: u64,
+}
+
+// `None` while archiving is turned off
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredConfig(Option<ArchiveConfig>);
+
+impl Storable for StoredConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A post as sent to the archive canister, along with its comments
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct ArchivedPost {
+    post: BlogPost,
+    comments: Vec<Comment>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<Cell<StoredConfig, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::ARCHIVE_CONFIG_MEMORY), StoredConfig::default())
+            .expect("Cannot create the archive config cell")
+    );
+
+    // Post id -> the archive canister that holds the post
+    static ARCHIVED: RefCell<StableBTreeMap<u64, Blob<29>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::ARCHIVED_POSTS_MEMORY)));
+
+    // Whether an archive call is waiting for the archive canister, so two
+    // calls don't send the same posts
+    static ARCHIVING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+fn config() -> Option<ArchiveConfig> {
+    CONFIG.with(|cell| cell.borrow().get().0.clone())
+}
+
+// The archive canister that holds a post that was moved out of this one
+pub(crate) fn location(id: u64) -> Option<Principal> {
+    ARCHIVED
+        .with(|archived| archived.borrow().get(&id))
+        .map(|canister| Principal::from_slice(canister.as_slice()))
+}
+
+// The error returned for a post that is no longer here, pointing readers to
+// where it went
+pub(crate) fn not_here(id: u64) -> Error {
+    match location(id) {
+        Some(archive_canister) => Error::Archived {
+            msg: format!(
+                "Blog post with ID {} was archived to {}",
+                id, archive_canister
+            ),
+            archive_canister,
+        },
+        None => Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        },
+    }
+}
+
+// Version of a post that was sent, to notice edits made while the archive
+// call was in flight
+fn version(blog_post: &BlogPost) -> (u64, u32, u32, usize) {
+    (
+        blog_post.updated_at.unwrap_or(blog_post.created_at),
+        blog_post.likes,
+        blog_post.dislikes,
+        comments::post_comments(blog_post.id).len(),
+    )
+}
+
+// Oldest published or hidden posts, within the batch limits
+fn next_batch(limit: u64) -> Vec<BlogPost> {
+    let candidates = _oldest_blog_posts(limit.min(MAX_ARCHIVE_BATCH) as usize, |blog_post| {
+        matches!(blog_post.status, PostStatus::Published | PostStatus::Hidden)
+    });
+    let mut bytes = 0;
+    candidates
+        .into_iter()
+        .take_while(|blog_post| {
+            let first = bytes == 0;
+            bytes += blog_post.title.len() + blog_post.content.len();
+            first || bytes <= MAX_BATCH_BYTES
+        })
+        .collect()
+}
+
+async fn archive(config: ArchiveConfig, limit: u64) -> Result<Vec<u64>, Error> {
+    let posts: Vec<ArchivedPost> = next_batch(limit)
+        .into_iter()
+        .map(|post| ArchivedPost {
+            comments: comments::post_comments(post.id),
+            post,
+        })
+        .collect();
+    let sent: Vec<(u64, (u64, u32, u32, usize))> = posts
+        .iter()
+        .map(|archived| (archived.post.id, version(&archived.post)))
+        .collect();
+    let canister = config.archive_canister;
+    let result: Result<(), _> = ic_cdk::call(canister, "store_posts", (posts,)).await;
+    if let Err((code, msg)) = result {
+        return Err(Error::TransferFailed {
+            msg: format!("Cannot reach the archive canister: {:?} {}", code, msg),
+        });
+    }
+    let mut archived = Vec::new();
+    for (id, sent_version) in sent {
+        // posts changed in the meantime stay here and go with a later batch
+        let Some(blog_post) = _get_blog_post(&id) else {
+            continue;
+        };
+        if version(&blog_post) != sent_version {
+            continue;
+        }
+        _delete_blog_post(blog_post);
+        ARCHIVED.with(|map| {
+            map.borrow_mut()
+                .insert(id, Blob::try_from(canister.as_slice()).unwrap())
+        });
+        audit::record(AuditAction::ArchivePost, id);
+        archived.push(id);
+    }
+    Ok(archived)
+}
+
+// Update function for admins to move up to `limit` of the oldest posts to
+// the archive canister, once the post count or stable memory reached the
+// configured threshold. Returns the IDs of the posts moved.
+#[ic_cdk::update]
+async fn archive_old_posts(limit: u64) -> Result<Vec<u64>, Error> {
+    auth::require_admin()?;
+    let Some(config) = config() else {
+        return Err(Error::NotFound {
+            msg: "Archiving is not enabled".to_string(),
+        });
+    };
+    let stable_memory = stable64_size() * WASM_PAGE_SIZE;
+    if _count_blog_posts() < config.max_posts && stable_memory < config.max_stable_memory {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Posts are archived from {} posts or {} bytes of stable memory on",
+                config.max_posts, config.max_stable_memory
+            ),
+        });
+    }
+    if ARCHIVING.with(|archiving| archiving.replace(true)) {
+        return Err(Error::ValidationErrors {
+            errors: "Another archive call is in progress".to_string(),
+        });
+    }
+    let result = archive(config, limit).await;
+    ARCHIVING.with(|archiving| archiving.set(false));
+    result
+}
+
+// Query function for admins to see where old posts are archived to
+#[ic_cdk::query]
+fn get_archive_config() -> Result<Option<ArchiveConfig>, Error> {
+    auth::require_admin()?;
+    Ok(config())
+}
+
+// Update function for admins to set the archive canister and thresholds, or
+// turn archiving off with `null`. Posts already archived keep pointing to
+// the canister they went to.
+#[ic_cdk::update]
+fn set_archive_config(config: Option<ArchiveConfig>) -> Result<(), Error> {
+    auth::require_admin()?;
+    CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredConfig(config))
+            .expect("Cannot write the archive config")
+    });
+    Ok(())
+}
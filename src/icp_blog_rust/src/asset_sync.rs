@@ -0,0 +1,145 @@
+use crate::memory::{self, Memory};
+use crate::{auth, html, visibility, BlogPost, Error};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+// Where rendered pages are mirrored to. The blog canister must have been
+// granted the Commit permission on the asset canister.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AssetSyncConfig {
+    asset_canister: Principal,
+    // origin the pages are served from, used for absolute links
+    base_url: String,
+}
+
+// `None` while syncing is turned off
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredConfig(Option<AssetSyncConfig>);
+
+impl Storable for StoredConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static CONFIG: RefCell<Cell<StoredConfig, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::ASSET_SYNC_MEMORY), StoredConfig::default())
+            .expect("Cannot create the asset sync cell")
+    );
+}
+
+// Argument of the asset canister's `store` method
+#[derive(candid::CandidType)]
+struct StoreArg {
+    key: String,
+    content_type: String,
+    content_encoding: String,
+    content: ByteBuf,
+    sha256: Option<ByteBuf>,
+}
+
+// Argument of the asset canister's `delete_asset` method
+#[derive(candid::CandidType)]
+struct DeleteAssetArg {
+    key: String,
+}
+
+fn config() -> Option<AssetSyncConfig> {
+    CONFIG.with(|cell| cell.borrow().get().0.clone())
+}
+
+// Asset keys are paths; pages are stored as the index of their directory so
+// the asset canister serves them under the same URL as `http_request`
+fn asset_key(path: &str) -> String {
+    format!("{}/index.html", path)
+}
+
+// Push the pages of a post to the asset canister. The calls run after the
+// current message commits; failures are logged and fixed by the next publish.
+// Private posts are taken off instead, as the asset canister serves anyone.
+pub(crate) fn publish(post: &BlogPost) {
+    if visibility::is_private(post) {
+        unpublish(post);
+        return;
+    }
+    let Some(config) = config() else {
+        return;
+    };
+    for (path, page) in html::static_pages(&config.base_url, post) {
+        let content = page.into_bytes();
+        let arg = StoreArg {
+            key: asset_key(&path),
+            content_type: "text/html; charset=utf-8".to_string(),
+            content_encoding: "identity".to_string(),
+            sha256: Some(ByteBuf::from(Sha256::digest(&content).to_vec())),
+            content: ByteBuf::from(content),
+        };
+        let canister = config.asset_canister;
+        ic_cdk::spawn(async move {
+            let key = arg.key.clone();
+            let result: Result<(), _> = ic_cdk::call(canister, "store", (arg,)).await;
+            if let Err((code, msg)) = result {
+                ic_cdk::println!("Cannot store {} in {}: {:?} {}", key, canister, code, msg);
+            }
+        });
+    }
+}
+
+// Remove the pages of a deleted post from the asset canister
+pub(crate) fn unpublish(post: &BlogPost) {
+    let Some(config) = config() else {
+        return;
+    };
+    for (path, _) in html::static_pages(&config.base_url, post) {
+        let arg = DeleteAssetArg {
+            key: asset_key(&path),
+        };
+        let canister = config.asset_canister;
+        ic_cdk::spawn(async move {
+            let key = arg.key.clone();
+            let result: Result<(), _> = ic_cdk::call(canister, "delete_asset", (arg,)).await;
+            if let Err((code, msg)) = result {
+                ic_cdk::println!("Cannot delete {} from {}: {:?} {}", key, canister, code, msg);
+            }
+        });
+    }
+}
+
+// Query function for admins to see where pages are mirrored to
+#[ic_cdk::query]
+fn get_asset_sync_config() -> Result<Option<AssetSyncConfig>, Error> {
+    auth::require_admin()?;
+    Ok(config())
+}
+
+// Update function for admins to point page mirroring at an asset canister,
+// or turn it off with `null`
+#[ic_cdk::update]
+fn set_asset_sync_config(config: Option<AssetSyncConfig>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if let Some(config) = &config {
+        if !config.base_url.starts_with("https://") || config.base_url.ends_with('/') {
+            return Err(Error::ValidationErrors {
+                errors: "base_url must be an https:// origin without a trailing slash"
+                    .to_string(),
+            });
+        }
+    }
+    CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredConfig(config))
+            .expect("Cannot write the asset sync config")
+    });
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use crate::memory::{self, Memory};
+use crate::{auth, config, date, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap};
+
+// Largest page `get_audit_log` returns
+const MAX_AUDIT_PAGE: u64 = 100;
+// Most audit log entries `get_events` reads in one call
+const MAX_EVENTS_PAGE: u64 = 1_000;
+
+// Kinds of changes the audit log records. The target is a post, except for
+// the comment actions and `DismissReport`, whose targets are comments and
+// reports, `LowOnCycles`, whose target is the cycle balance, and
+// `EraseUserData`, whose target is the number of references dropped.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum AuditAction {
+    CreatePost,
+    UpdatePost,
+    DeletePost,
+    LikePost,
+    UnlikePost,
+    DislikePost,
+    UndislikePost,
+    AddComment,
+    DeleteComment,
+    HidePost,
+    HideComment,
+    DismissReport,
+    RecategorizePost,
+    ArchivePost,
+    // writes started being refused for lack of cycles
+    LowOnCycles,
+    // the cleanup after a user deleted their data finished
+    EraseUserData,
+    // a paywalled post was made free for a while, or no longer
+    SetPromoWindow,
+    // a post changed hands with `accept_post_transfer`
+    TransferPost,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEntry {
+    id: u64,
+    caller: Principal,
+    at: u64,
+    action: AuditAction,
+    target_id: u64,
+}
+
+// A change as `get_events` shows it: an audit log entry without its caller
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct LogEvent {
+    index: u64,
+    timestamp: u64,
+    kind: AuditAction,
+    target_id: u64,
+}
+
+// A range of the event log, and how long the log is, so indexers know where
+// to start their next call
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EventPage {
+    log_length: u64,
+    events: Vec<LogEvent>,
+}
+
+impl AuditAction {
+    // Whether `get_events` shows the action; the ones only moderators and
+    // admins act on are left out
+    fn is_public(self) -> bool {
+        !matches!(
+            self,
+            AuditAction::DismissReport | AuditAction::LowOnCycles | AuditAction::EraseUserData
+        )
+    }
+}
+
+// How often the actions monthly reports show were recorded in a month, kept
+// up as they are so reports don't read the log
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct MonthlyActions {
+    pub(crate) likes: u64,
+    pub(crate) hidden_posts: u64,
+    pub(crate) hidden_comments: u64,
+    pub(crate) dismissed_reports: u64,
+}
+
+impl MonthlyActions {
+    // Count an action, unless it is one reports don't show. Returns whether
+    // it was counted.
+    fn add(&mut self, action: AuditAction) -> bool {
+        let count = match action {
+            AuditAction::LikePost => &mut self.likes,
+            AuditAction::HidePost => &mut self.hidden_posts,
+            AuditAction::HideComment => &mut self.hidden_comments,
+            AuditAction::DismissReport => &mut self.dismissed_reports,
+            _ => return false,
+        };
+        *count += 1;
+        true
+    }
+}
+
+impl Storable for MonthlyActions {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Append-only: entries are keyed by their position and never removed
+    static AUDIT_LOG: RefCell<StableBTreeMap<u64, AuditEntry, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUDIT_LOG_MEMORY)));
+
+    // Months since January 1970 -> the actions recorded in that month
+    static MONTHLY_ACTIONS: RefCell<StableBTreeMap<u64, MonthlyActions, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::MONTHLY_ACTIONS_MEMORY)));
+
+    // Principal that started the background job being run, if any
+    static ACTING_FOR: RefCell<Option<Principal>> = const { RefCell::new(None) };
+}
+
+// Run `f` recording its changes as made by `principal`, for background jobs,
+// whose caller is the canister itself
+pub(crate) fn on_behalf_of<R>(principal: Principal, f: impl FnOnce() -> R) -> R {
+    ACTING_FOR.with(|acting_for| *acting_for.borrow_mut() = Some(principal));
+    let result = f();
+    ACTING_FOR.with(|acting_for| *acting_for.borrow_mut() = None);
+    result
+}
+
+// Append a change by the caller to the audit log
+pub(crate) fn record(action: AuditAction, target_id: u64) {
+    let caller = ACTING_FOR
+        .with(|acting_for| *acting_for.borrow())
+        .unwrap_or_else(caller);
+    let at = time();
+    AUDIT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.len();
+        log.insert(
+            id,
+            AuditEntry {
+                id,
+                caller,
+                at,
+                action,
+                target_id,
+            },
+        );
+    });
+    count_action(at, action);
+}
+
+fn month_of(at: u64) -> u64 {
+    let (year, month, _) = date::civil_from_days(date::day_number(at));
+    date::month_number(year, month)
+}
+
+fn count_action(at: u64, action: AuditAction) {
+    MONTHLY_ACTIONS.with(|months| {
+        let mut months = months.borrow_mut();
+        let month = month_of(at);
+        let mut actions = months.get(&month).unwrap_or_default();
+        if actions.add(action) {
+            months.insert(month, actions);
+        }
+    });
+}
+
+// The actions recorded in a month, counted from months since January 1970
+pub(crate) fn monthly_actions(month: u64) -> MonthlyActions {
+    MONTHLY_ACTIONS.with(|months| months.borrow().get(&month).unwrap_or_default())
+}
+
+// Count the actions recorded before they were counted by month
+pub(crate) fn count_existing_actions() {
+    let mut counted: BTreeMap<u64, MonthlyActions> = BTreeMap::new();
+    AUDIT_LOG.with(|log| {
+        for (_, entry) in log.borrow().iter() {
+            counted
+                .entry(month_of(entry.at))
+                .or_default()
+                .add(entry.action);
+        }
+    });
+    MONTHLY_ACTIONS.with(|months| {
+        let mut months = months.borrow_mut();
+        for (month, actions) in counted {
+            months.insert(month, actions);
+        }
+    });
+}
+
+// Query function for indexers to sync the changes to posts and comments
+// incrementally: the audit log entries from `start` on, at most `length` of
+// them, without who made them. Entries keep their index in the log for good,
+// so indexers can resume from the last one they read. Moderation-only
+// entries are left out, leaving gaps in the indexes.
+#[ic_cdk::query]
+fn get_events(start: u64, length: u64) -> Result<EventPage, Error> {
+    config::require_reader()?;
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        Ok(EventPage {
+            log_length: log.len(),
+            events: log
+                .range(start..start.saturating_add(length.min(MAX_EVENTS_PAGE)))
+                .filter(|(_, entry)| entry.action.is_public())
+                .map(|(index, entry)| LogEvent {
+                    index,
+                    timestamp: entry.at,
+                    kind: entry.action,
+                    target_id: entry.target_id,
+                })
+                .collect(),
+        })
+    })
+}
+
+// Query function for admins to page through the audit log, oldest first
+#[ic_cdk::query]
+fn get_audit_log(offset: u64, limit: u64) -> Result<Vec<AuditEntry>, Error> {
+    auth::require_admin()?;
+    Ok(AUDIT_LOG.with(|log| {
+        log.borrow()
+            .range(offset..)
+            .take(limit.min(MAX_AUDIT_PAGE) as usize)
+            .map(|(_, entry)| entry)
+            .collect()
+    }))
+}
@@ -0,0 +1,159 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{cycles, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, is_controller};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{Cell, StableBTreeMap};
+use std::cell::RefCell;
+
+thread_local! {
+    // Principals admins appointed to handle reported content
+    static MODERATORS: RefCell<StableBTreeMap<Blob<29>, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::MODERATORS_MEMORY)));
+
+    // Whether the anonymous principal may like posts; off by default
+    static ALLOW_ANONYMOUS_LIKES: RefCell<Cell<bool, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::ALLOW_ANONYMOUS_LIKES_MEMORY), false)
+            .expect("Cannot create the anonymous likes cell")
+    );
+
+    // Controllers seen installing, upgrading or calling the canister. The
+    // canister can't list its controllers, only tell whether a principal is
+    // one, so this is what `known_admins` filters.
+    static KNOWN_ADMINS: RefCell<StableBTreeMap<Blob<29>, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::KNOWN_ADMINS_MEMORY)));
+}
+
+// Admins are the controllers of the canister
+pub(crate) fn is_admin() -> bool {
+    is_controller(&caller())
+}
+
+// Guard for admin-only endpoints
+pub(crate) fn require_admin() -> Result<(), Error> {
+    if !is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!("Caller {} is not an admin.", caller()),
+        });
+    }
+    remember_admin();
+    Ok(())
+}
+
+// Note the caller down as an admin if they are a controller; in queries the
+// note is discarded with the rest of the changes
+pub(crate) fn remember_admin() {
+    let key = principal_key(&caller());
+    if is_admin() && !KNOWN_ADMINS.with(|admins| admins.borrow().contains_key(&key)) {
+        KNOWN_ADMINS.with(|admins| admins.borrow_mut().insert(key, ()));
+    }
+}
+
+// The admins seen so far that are still controllers
+pub(crate) fn known_admins() -> Vec<Principal> {
+    KNOWN_ADMINS.with(|admins| {
+        admins
+            .borrow()
+            .iter()
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .filter(is_controller)
+            .collect()
+    })
+}
+
+// Moderators are the admins plus the principals they appointed
+pub(crate) fn is_moderator() -> bool {
+    is_admin()
+        || MODERATORS.with(|moderators| moderators.borrow().contains_key(&principal_key(&caller())))
+}
+
+// Guard for moderator-only endpoints
+pub(crate) fn require_moderator() -> Result<(), Error> {
+    if !is_moderator() {
+        return Err(Error::NotAuthorized {
+            msg: format!("Caller {} is not a moderator.", caller()),
+        });
+    }
+    Ok(())
+}
+
+// Guard for every endpoint that changes state. Anything the anonymous
+// principal did would be shared by every anonymous caller, so authorship
+// would mean nothing. Writes are also refused while cycles run low.
+pub(crate) fn require_signed_in() -> Result<(), Error> {
+    if caller() == Principal::anonymous() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "Anonymous callers must sign in first.".to_string(),
+        });
+    }
+    cycles::require_cycles()
+}
+
+pub(crate) fn allows_anonymous_likes() -> bool {
+    ALLOW_ANONYMOUS_LIKES.with(|allow| *allow.borrow().get())
+}
+
+// Guard for liking and unliking, which admins may open to anonymous callers.
+// They then share a single like per post.
+pub(crate) fn require_signed_in_to_like() -> Result<(), Error> {
+    if allows_anonymous_likes() {
+        return cycles::require_cycles();
+    }
+    require_signed_in()
+}
+
+// Update function for admins to appoint a moderator
+#[ic_cdk::update]
+fn add_moderator(principal: Principal) -> Result<(), Error> {
+    require_admin()?;
+    MODERATORS.with(|moderators| {
+        moderators
+            .borrow_mut()
+            .insert(principal_key(&principal), ())
+    });
+    Ok(())
+}
+
+// Update function for admins to dismiss a moderator
+#[ic_cdk::update]
+fn remove_moderator(principal: Principal) -> Result<(), Error> {
+    require_admin()?;
+    match MODERATORS.with(|moderators| moderators.borrow_mut().remove(&principal_key(&principal))) {
+        Some(()) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not a moderator", principal),
+        }),
+    }
+}
+
+// Query function to tell whether anonymous callers may like posts
+#[ic_cdk::query]
+fn get_allow_anonymous_likes() -> bool {
+    ALLOW_ANONYMOUS_LIKES.with(|allow| *allow.borrow().get())
+}
+
+// Update function for admins to let anonymous callers like posts, or stop them
+#[ic_cdk::update]
+fn set_allow_anonymous_likes(allow: bool) -> Result<(), Error> {
+    require_admin()?;
+    ALLOW_ANONYMOUS_LIKES.with(|cell| {
+        cell.borrow_mut()
+            .set(allow)
+            .expect("Cannot write the anonymous likes setting")
+    });
+    Ok(())
+}
+
+// Query function for admins to list the appointed moderators
+#[ic_cdk::query]
+fn list_moderators() -> Result<Vec<Principal>, Error> {
+    require_admin()?;
+    Ok(MODERATORS.with(|moderators| {
+        moderators
+            .borrow()
+            .iter()
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    }))
+}
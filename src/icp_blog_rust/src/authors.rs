@@ -0,0 +1,208 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use candid::Principal;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{borrow::Cow, cell::RefCell};
+
+// Key of the author -> posts index: the author's principal bytes followed by
+// the big-endian post id, so all posts of an author are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct AuthorPost {
+    author: Vec<u8>,
+    post_id: u64,
+}
+
+impl Storable for AuthorPost {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.author.clone();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (author, id) = bytes.split_at(bytes.len() - 8);
+        AuthorPost {
+            author: author.to_vec(),
+            post_id: u64::from_be_bytes(id.try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29 + 8,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // Number of posts of every author
+    static AUTHORS: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUTHORS_MEMORY)));
+
+    static AUTHOR_POSTS: RefCell<StableBTreeMap<AuthorPost, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUTHOR_POSTS_MEMORY)));
+}
+
+// Post authors are stored as principal text
+fn parse_author(author: &str) -> Option<Principal> {
+    Principal::from_text(author).ok()
+}
+
+// Add a post to the index of its author
+pub(crate) fn index_post(post_id: u64, author: &str) {
+    let Some(author) = parse_author(author) else {
+        return;
+    };
+    let key = AuthorPost {
+        author: author.as_slice().to_vec(),
+        post_id,
+    };
+    if AUTHOR_POSTS.with(|index| index.borrow_mut().insert(key, ()).is_none()) {
+        AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            let count = authors.get(&principal_key(&author)).unwrap_or(0);
+            authors.insert(principal_key(&author), count + 1);
+        });
+    }
+}
+
+// Remove a post from the index of its author
+pub(crate) fn unindex_post(post_id: u64, author: &str) {
+    let Some(author) = parse_author(author) else {
+        return;
+    };
+    let key = AuthorPost {
+        author: author.as_slice().to_vec(),
+        post_id,
+    };
+    if AUTHOR_POSTS.with(|index| index.borrow_mut().remove(&key).is_some()) {
+        AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            match authors.get(&principal_key(&author)).unwrap_or(0) {
+                0 | 1 => authors.remove(&principal_key(&author)),
+                count => authors.insert(principal_key(&author), count - 1),
+            };
+        });
+    }
+}
+
+// Whether a post is in the index of its author
+pub(crate) fn is_indexed(post_id: u64, author: &str) -> bool {
+    let Some(author) = parse_author(author) else {
+        return false;
+    };
+    let key = AuthorPost {
+        author: author.as_slice().to_vec(),
+        post_id,
+    };
+    AUTHOR_POSTS.with(|index| index.borrow().contains_key(&key))
+}
+
+// IDs of the `limit` most recent posts of an author, newest first
+pub(crate) fn newest_post_ids(author: &Principal, limit: usize) -> Vec<u64> {
+    post_ids_before(author, u64::MAX, limit)
+}
+
+// IDs of the `limit` most recent posts of an author with an ID below
+// `before`, newest first
+pub(crate) fn post_ids_before(author: &Principal, before: u64, limit: usize) -> Vec<u64> {
+    let start = AuthorPost {
+        author: author.as_slice().to_vec(),
+        post_id: 0,
+    };
+    let end = AuthorPost {
+        author: author.as_slice().to_vec(),
+        post_id: before,
+    };
+    AUTHOR_POSTS.with(|index| {
+        index
+            .borrow()
+            .range(start..end)
+            .rev()
+            .take(limit)
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// Number of authors with at least one post
+pub(crate) fn count() -> u64 {
+    AUTHORS.with(|authors| authors.borrow().len())
+}
+
+// Every author with at least one post, along with their number of posts
+pub(crate) fn list_authors() -> Vec<(Principal, u64)> {
+    AUTHORS.with(|authors| {
+        authors
+            .borrow()
+            .iter()
+            .map(|(author, posts)| (Principal::from_slice(author.as_slice()), posts))
+            .collect()
+    })
+}
+
+// Post IDs of the index entries whose post is gone or has another author.
+// They are removed when `repair` is set; the counts are fixed separately by
+// `count_drift`.
+pub(crate) fn stale_entries(
+    is_current: impl Fn(u64, &Principal) -> bool,
+    repair: bool,
+) -> Vec<u64> {
+    let stale: Vec<AuthorPost> = AUTHOR_POSTS.with(|index| {
+        index
+            .borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !is_current(key.post_id, &Principal::from_slice(&key.author)))
+            .collect()
+    });
+    if repair {
+        AUTHOR_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for key in &stale {
+                index.remove(key);
+            }
+        });
+    }
+    stale.into_iter().map(|key| key.post_id).collect()
+}
+
+// Authors whose number of posts differs from their entries in the index,
+// recounted when `repair` is set
+pub(crate) fn count_drift(repair: bool) -> Vec<Principal> {
+    let mut counted: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+    AUTHOR_POSTS.with(|index| {
+        for (key, _) in index.borrow().iter() {
+            *counted.entry(key.author).or_default() += 1;
+        }
+    });
+    let stored: BTreeMap<Vec<u8>, u64> = AUTHORS.with(|authors| {
+        authors
+            .borrow()
+            .iter()
+            .map(|(author, posts)| (author.as_slice().to_vec(), posts))
+            .collect()
+    });
+    let drifted: BTreeSet<&Vec<u8>> = counted
+        .keys()
+        .chain(stored.keys())
+        .filter(|author| counted.get(*author) != stored.get(*author))
+        .collect();
+    if repair {
+        AUTHORS.with(|authors| {
+            let mut authors = authors.borrow_mut();
+            for author in &drifted {
+                let key = Blob::try_from(author.as_slice()).unwrap();
+                match counted.get(*author) {
+                    Some(posts) => authors.insert(key, *posts),
+                    None => authors.remove(&key),
+                };
+            }
+        });
+    }
+    drifted
+        .into_iter()
+        .map(|author| Principal::from_slice(author))
+        .collect()
+}
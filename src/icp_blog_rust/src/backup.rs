@@ -0,0 +1,102 @@
+use crate::{_newest_blog_posts, auth, Error};
+use ic_cdk::api::stable::{stable64_grow, stable64_read, stable64_size, stable64_write};
+use serde_bytes::ByteBuf;
+use std::cell::Cell;
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+// Size of one backup chunk; 16 pages keeps a reply well below the 2 MiB limit
+const CHUNK_SIZE: u64 = 16 * WASM_PAGE_SIZE;
+
+// One piece of a backup: a slice of the canister's stable memory, which holds
+// every post, comment and index. Clients fetch chunks 0 to `total_chunks - 1`
+// while no writes are going on, so the chunks fit together.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BackupChunk {
+    data: ByteBuf,
+    chunk_index: u64,
+    total_chunks: u64,
+}
+
+thread_local! {
+    // Set once a backup is being imported. The data structures on the heap
+    // still describe the memory that was overwritten, so only an upgrade,
+    // which reloads them, may follow.
+    static RESTORING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub(crate) fn is_restoring() -> bool {
+    RESTORING.with(|restoring| restoring.get())
+}
+
+fn total_chunks(size: u64) -> u64 {
+    size.div_ceil(CHUNK_SIZE)
+}
+
+// Query function for admins to export one chunk of a backup
+#[ic_cdk::query]
+fn export_backup(chunk_index: u64) -> Result<BackupChunk, Error> {
+    auth::require_admin()?;
+    let size = stable64_size() * WASM_PAGE_SIZE;
+    let total_chunks = total_chunks(size);
+    if chunk_index >= total_chunks {
+        return Err(Error::NotFound {
+            msg: format!(
+                "Chunk {} not found; the backup has {} chunks",
+                chunk_index, total_chunks
+            ),
+        });
+    }
+    let offset = chunk_index * CHUNK_SIZE;
+    let mut data = vec![0; CHUNK_SIZE.min(size - offset) as usize];
+    stable64_read(offset, &mut data);
+    Ok(BackupChunk {
+        data: ByteBuf::from(data),
+        chunk_index,
+        total_chunks,
+    })
+}
+
+// Update function for admins to restore a backup into a freshly installed
+// canister. Chunks may arrive over several calls; once all are in, upgrade
+// the canister so it loads the restored data and migrates it if the backup
+// came from an older release.
+#[ic_cdk::update]
+fn import_backup(chunks: Vec<BackupChunk>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if !is_restoring() && !_newest_blog_posts(1, |_| true).is_empty() {
+        return Err(Error::ValidationErrors {
+            errors: "Backups can only be imported into a canister without posts".to_string(),
+        });
+    }
+    for chunk in &chunks {
+        if chunk.chunk_index >= chunk.total_chunks
+            || chunk.data.len() as u64 > CHUNK_SIZE
+            || (chunk.chunk_index + 1 < chunk.total_chunks && chunk.data.len() as u64 != CHUNK_SIZE)
+        {
+            return Err(Error::ValidationErrors {
+                errors: format!("Chunk {} is malformed", chunk.chunk_index),
+            });
+        }
+    }
+    RESTORING.with(|restoring| restoring.set(true));
+    for chunk in chunks {
+        let offset = chunk.chunk_index * CHUNK_SIZE;
+        let end = offset + chunk.data.len() as u64;
+        let pages = stable64_size();
+        if end > pages * WASM_PAGE_SIZE {
+            stable64_grow(end.div_ceil(WASM_PAGE_SIZE) - pages)
+                .expect("Cannot grow stable memory for the backup");
+        }
+        stable64_write(offset, &chunk.data);
+    }
+    Ok(())
+}
+
+// While a backup is being imported, reject every other update call; they
+// would write through data structures that no longer match the memory
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    if !is_restoring() || ic_cdk::api::call::method_name() == "import_backup" {
+        ic_cdk::api::call::accept_message();
+    }
+}
@@ -0,0 +1,310 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::moderation::{self, ReportTarget};
+use crate::{auth, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest blocked term, in characters
+const MAX_TERM_LEN: usize = 50;
+// Most terms the blocklist may have
+const MAX_TERMS: u64 = 1000;
+
+// How offensive a blocked term is. What happens to text using it is up to
+// the action admins set for its tier.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum Tier {
+    Low,
+    Medium,
+    High,
+}
+
+impl Storable for Tier {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum FilterAction {
+    // keep the text and report it to the moderators
+    Warn,
+    // replace the term with asterisks
+    Mask,
+    // refuse the text
+    Reject,
+}
+
+// Action taken for terms of each tier
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct TierActions {
+    low: FilterAction,
+    medium: FilterAction,
+    high: FilterAction,
+}
+
+impl Default for TierActions {
+    fn default() -> Self {
+        TierActions {
+            low: FilterAction::Warn,
+            medium: FilterAction::Mask,
+            high: FilterAction::Reject,
+        }
+    }
+}
+
+impl Storable for TierActions {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl TierActions {
+    fn of(&self, tier: Tier) -> FilterAction {
+        match tier {
+            Tier::Low => self.low,
+            Tier::Medium => self.medium,
+            Tier::High => self.high,
+        }
+    }
+}
+
+// A term as listed by `list_blocked_terms`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BlockedTerm {
+    term: String,
+    tier: Tier,
+}
+
+thread_local! {
+    // Lowercased term -> its tier
+    static TERMS: RefCell<StableBTreeMap<String, Tier, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::BLOCKED_TERMS_MEMORY)));
+
+    static ACTIONS: RefCell<Cell<TierActions, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::TIER_ACTIONS_MEMORY), TierActions::default())
+            .expect("Cannot create the tier actions cell")
+    );
+
+    // Authors whose posts quote blocked terms, such as moderation reports or
+    // research; their text is only ever warned about
+    static EXEMPT: RefCell<StableBTreeMap<Blob<29>, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::FILTER_EXEMPTIONS_MEMORY)));
+}
+
+fn actions() -> TierActions {
+    ACTIONS.with(|cell| cell.borrow().get().clone())
+}
+
+fn exempt_key(principal: &Principal) -> Blob<29> {
+    principal_key(&linking::primary(principal))
+}
+
+fn is_exempt(principal: &Principal) -> bool {
+    EXEMPT.with(|exempt| exempt.borrow().contains_key(&exempt_key(principal)))
+}
+
+// Check text the caller is about to store against the blocklist, word by
+// word and ignoring case. Terms whose tier masks are replaced with asterisks
+// and any rejected term refuses the text. Returns the text to store and the
+// terms to warn the moderators about.
+pub(crate) fn screen(text: String) -> Result<(String, Vec<String>), Error> {
+    let terms: BTreeMap<String, Tier> = TERMS.with(|terms| terms.borrow().iter().collect());
+    if terms.is_empty() {
+        return Ok((text, Vec::new()));
+    }
+    let actions = actions();
+    let exempt = is_exempt(&caller());
+    let mut screened = String::with_capacity(text.len());
+    let mut warned = Vec::new();
+    let mut rejected = Vec::new();
+    let mut rest = text.as_str();
+    while !rest.is_empty() {
+        let start = rest.find(char::is_alphanumeric).unwrap_or(rest.len());
+        screened.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        let lower = word.to_lowercase();
+        let action = match terms.get(&lower) {
+            Some(_) if exempt => Some(FilterAction::Warn),
+            Some(&tier) => Some(actions.of(tier)),
+            None => None,
+        };
+        match action {
+            Some(FilterAction::Mask) => {
+                screened.extend(std::iter::repeat_n('*', word.chars().count()))
+            }
+            Some(FilterAction::Reject) => {
+                rejected.push(lower);
+                screened.push_str(word);
+            }
+            Some(FilterAction::Warn) => {
+                warned.push(lower);
+                screened.push_str(word);
+            }
+            None => screened.push_str(word),
+        }
+        rest = &rest[end..];
+    }
+    if !rejected.is_empty() {
+        rejected.sort();
+        rejected.dedup();
+        return Err(Error::ValidationErrors {
+            errors: format!("Blocked terms are not allowed: {}", rejected.join(", ")),
+        });
+    }
+    warned.sort();
+    warned.dedup();
+    Ok((screened, warned))
+}
+
+// Screen the title and content of a post or translation together. Encrypted
+// content is left alone: it can't be read, and masking would corrupt it.
+pub(crate) fn screen_post(
+    title: String,
+    content: String,
+    encrypted: bool,
+) -> Result<(String, String, Vec<String>), Error> {
+    let (title, mut warned) = screen(title)?;
+    let (content, warned_in_content) = if encrypted {
+        (content, Vec::new())
+    } else {
+        screen(content)?
+    };
+    warned.extend(warned_in_content);
+    warned.sort();
+    warned.dedup();
+    Ok((title, content, warned))
+}
+
+// Report stored content that used terms admins asked to be warned about
+pub(crate) fn warn(target: ReportTarget, warned: &[String]) {
+    if !warned.is_empty() {
+        moderation::file_automatic_report(
+            target,
+            format!("Uses flagged terms: {}", warned.join(", ")),
+        );
+    }
+}
+
+// Update function for admins to put a term on the blocklist with a tier, or
+// take it off with no tier. Only text stored afterwards is screened.
+#[ic_cdk::update]
+fn set_blocked_term(term: String, tier: Option<Tier>) -> Result<(), Error> {
+    auth::require_admin()?;
+    let term = term.to_lowercase();
+    let Some(tier) = tier else {
+        return match TERMS.with(|terms| terms.borrow_mut().remove(&term)) {
+            Some(_) => Ok(()),
+            None => Err(Error::NotFound {
+                msg: format!("'{}' is not blocked", term),
+            }),
+        };
+    };
+    let len = term.chars().count();
+    if len == 0 || len > MAX_TERM_LEN || !term.chars().all(char::is_alphanumeric) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Blocked terms are single words of 1 to {} letters or digits",
+                MAX_TERM_LEN
+            ),
+        });
+    }
+    TERMS.with(|terms| {
+        let mut terms = terms.borrow_mut();
+        if !terms.contains_key(&term) && terms.len() >= MAX_TERMS {
+            return Err(Error::ValidationErrors {
+                errors: format!("The blocklist can have at most {} terms", MAX_TERMS),
+            });
+        }
+        terms.insert(term, tier);
+        Ok(())
+    })
+}
+
+// Query function for admins to list the blocklist, in alphabetical order
+#[ic_cdk::query]
+fn list_blocked_terms() -> Result<Vec<BlockedTerm>, Error> {
+    auth::require_admin()?;
+    Ok(TERMS.with(|terms| {
+        terms
+            .borrow()
+            .iter()
+            .map(|(term, tier)| BlockedTerm { term, tier })
+            .collect()
+    }))
+}
+
+// Query function for admins to read the action taken for each tier
+#[ic_cdk::query]
+fn get_tier_actions() -> Result<TierActions, Error> {
+    auth::require_admin()?;
+    Ok(actions())
+}
+
+// Update function for admins to change the action taken for terms of a tier
+#[ic_cdk::update]
+fn set_tier_action(tier: Tier, action: FilterAction) -> Result<TierActions, Error> {
+    auth::require_admin()?;
+    let mut actions = actions();
+    match tier {
+        Tier::Low => actions.low = action,
+        Tier::Medium => actions.medium = action,
+        Tier::High => actions.high = action,
+    }
+    ACTIONS.with(|cell| {
+        cell.borrow_mut()
+            .set(actions.clone())
+            .expect("Cannot write the tier actions")
+    });
+    Ok(actions)
+}
+
+// Update function for admins to let an author quote blocked terms, which are
+// then kept as written and only reported to the moderators
+#[ic_cdk::update]
+fn set_filter_exemption(author: Principal, exempt: bool) -> Result<(), Error> {
+    auth::require_admin()?;
+    EXEMPT.with(|exemptions| {
+        let mut exemptions = exemptions.borrow_mut();
+        if exempt {
+            exemptions.insert(exempt_key(&author), ());
+        } else {
+            exemptions.remove(&exempt_key(&author));
+        }
+    });
+    Ok(())
+}
+
+// Query function for admins to list the authors exempt from masking and
+// rejection
+#[ic_cdk::query]
+fn list_filter_exemptions() -> Result<Vec<Principal>, Error> {
+    auth::require_admin()?;
+    Ok(EXEMPT.with(|exemptions| {
+        exemptions
+            .borrow()
+            .iter()
+            .map(|(author, _)| Principal::from_slice(author.as_slice()))
+            .collect()
+    }))
+}
@@ -0,0 +1,209 @@
+use crate::keys::PrincipalIdKey;
+use crate::memory::{self, Memory};
+use crate::{_get_visible_blog_post, auth, privacy, BlogPost, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `get_my_bookmarks` returns
+const MAX_BOOKMARKS_PAGE: u64 = 100;
+
+// Key of the bookmarks map: a reader and a post id, so all bookmarks of a
+// reader are one contiguous range
+type BookmarkKey = PrincipalIdKey;
+
+// Key of the post -> bookmarks index: the post id followed by the principal,
+// so all bookmarks of a post are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct BookmarkedBy {
+    post_id: u64,
+    principal: Vec<u8>,
+}
+
+impl Storable for BookmarkedBy {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        BookmarkedBy {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            principal: bytes[8..].to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + 29,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // (principal, post) -> time of the bookmark
+    static BOOKMARKS: RefCell<StableBTreeMap<BookmarkKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::BOOKMARKS_MEMORY)));
+
+    // The bookmarks map turned around, so a deleted post's bookmarks can go
+    static BOOKMARKED_BY: RefCell<StableBTreeMap<BookmarkedBy, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::BOOKMARKED_BY_MEMORY)));
+}
+
+fn add(key: BookmarkKey, bookmarked_at: u64) {
+    BOOKMARKED_BY.with(|index| {
+        index.borrow_mut().insert(
+            BookmarkedBy {
+                post_id: key.id,
+                principal: key.principal.clone(),
+            },
+            (),
+        )
+    });
+    BOOKMARKS.with(|bookmarks| bookmarks.borrow_mut().insert(key, bookmarked_at));
+}
+
+fn remove(key: &BookmarkKey) -> bool {
+    BOOKMARKED_BY.with(|index| {
+        index.borrow_mut().remove(&BookmarkedBy {
+            post_id: key.id,
+            principal: key.principal.clone(),
+        })
+    });
+    BOOKMARKS.with(|bookmarks| bookmarks.borrow_mut().remove(key).is_some())
+}
+
+// Index the bookmarks made before the post -> bookmarks index existed
+pub(crate) fn index_existing_bookmarks() {
+    BOOKMARKS.with(|bookmarks| {
+        BOOKMARKED_BY.with(|index| {
+            let mut index = index.borrow_mut();
+            for (key, _) in bookmarks.borrow().iter() {
+                index.insert(
+                    BookmarkedBy {
+                        post_id: key.id,
+                        principal: key.principal,
+                    },
+                    (),
+                );
+            }
+        })
+    });
+}
+
+// Drop every bookmark of a deleted post
+pub(crate) fn remove_post(post_id: u64) {
+    let start = BookmarkedBy {
+        post_id,
+        principal: Vec::new(),
+    };
+    let keys: Vec<BookmarkKey> = BOOKMARKED_BY.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| BookmarkKey {
+                principal: key.principal,
+                id: post_id,
+            })
+            .collect()
+    });
+    for key in &keys {
+        remove(key);
+    }
+}
+
+// Move up to `limit` bookmarks of `old` to `new`, returning how many were
+// handled
+pub(crate) fn reassign(old: &Principal, new: &Principal, limit: usize) -> usize {
+    let moved: Vec<(BookmarkKey, u64)> = BOOKMARKS.with(|bookmarks| {
+        bookmarks
+            .borrow()
+            .range(BookmarkKey::all_of(old))
+            .take(limit)
+            .collect()
+    });
+    for (key, bookmarked_at) in &moved {
+        remove(key);
+        let key = BookmarkKey::new(new, key.id);
+        if !BOOKMARKS.with(|bookmarks| bookmarks.borrow().contains_key(&key)) {
+            add(key, *bookmarked_at);
+        }
+    }
+    moved.len()
+}
+
+// Update function for the caller to save a post for later
+#[ic_cdk::update]
+fn bookmark_post(post_id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(blog_post) = _get_visible_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot bookmark.", post_id),
+        });
+    };
+    let key = BookmarkKey::new(&caller(), post_id);
+    if BOOKMARKS.with(|bookmarks| bookmarks.borrow().contains_key(&key)) {
+        return Err(Error::AlreadyBookmarked {
+            msg: format!(
+                "Blog post with ID {} is already bookmarked by caller: {}.",
+                post_id,
+                caller()
+            ),
+        });
+    }
+    add(key, time());
+    Ok(blog_post)
+}
+
+// Update function for the caller to drop a saved post
+#[ic_cdk::update]
+fn remove_bookmark(post_id: u64) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = BookmarkKey::new(&caller(), post_id);
+    if !remove(&key) {
+        return Err(Error::NotBookmarked {
+            msg: format!(
+                "Blog post with ID {} isn't bookmarked by caller: {}.",
+                post_id,
+                caller()
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Query function to page through the posts the caller bookmarked, newest
+// post first
+#[ic_cdk::query]
+fn get_my_bookmarks(offset: u64, limit: u64) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    Ok(bookmarked_posts(&caller(), offset, limit))
+}
+
+// Query function to page through the posts a principal bookmarked, if they
+// share their bookmarks
+#[ic_cdk::query]
+fn get_bookmarks(principal: Principal, offset: u64, limit: u64) -> Result<Vec<BlogPost>, Error> {
+    if !privacy::can_see(&principal, privacy::settings(&principal).bookmarks_public) {
+        return Err(Error::NotAuthorized {
+            msg: format!("The bookmarks of {} are private.", principal),
+        });
+    }
+    Ok(bookmarked_posts(&principal, offset, limit))
+}
+
+fn bookmarked_posts(principal: &Principal, offset: u64, limit: u64) -> Vec<BlogPost> {
+    BOOKMARKS.with(|bookmarks| {
+        bookmarks
+            .borrow()
+            .range(BookmarkKey::all_of(principal))
+            .rev()
+            .filter_map(|(key, _)| _get_visible_blog_post(&key.id))
+            .skip(offset as usize)
+            .take(limit.min(MAX_BOOKMARKS_PAGE) as usize)
+            .collect()
+    })
+}
@@ -0,0 +1,113 @@
+use crate::audit::{self, AuditAction};
+use crate::{
+    _delete_blog_post, _get_blog_post, asset_sync, auth, categories, config, do_insert, moderation,
+    BlogPost, Error, PostStatus,
+};
+
+// Most posts a single bulk call may touch
+const MAX_BULK_ITEMS: usize = 100;
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BulkItemReport {
+    id: u64,
+    // why the post can't be changed; None if it can
+    error: Option<Error>,
+}
+
+// Outcome of an admin bulk call. Either every post is changed or, if any of
+// them can't be, none is and the items say which failed.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct BulkReport {
+    applied: bool,
+    items: Vec<BulkItemReport>,
+}
+
+// Check every post with `check`, then apply `apply` to all of them, but only
+// if every check passed
+fn run(
+    ids: Vec<u64>,
+    check: impl Fn(u64) -> Result<BlogPost, Error>,
+    apply: impl Fn(BlogPost),
+) -> Result<BulkReport, Error> {
+    if ids.len() > MAX_BULK_ITEMS {
+        return Err(Error::ValidationErrors {
+            errors: format!("At most {} posts can be changed at once", MAX_BULK_ITEMS),
+        });
+    }
+    let mut ids = ids;
+    ids.sort_unstable();
+    ids.dedup();
+    let checked: Vec<(u64, Result<BlogPost, Error>)> =
+        ids.into_iter().map(|id| (id, check(id))).collect();
+    let applied = checked.iter().all(|(_, result)| result.is_ok());
+    let items = checked
+        .into_iter()
+        .map(|(id, result)| match result {
+            Ok(blog_post) => {
+                if applied {
+                    apply(blog_post);
+                }
+                BulkItemReport { id, error: None }
+            }
+            Err(error) => BulkItemReport {
+                id,
+                error: Some(error),
+            },
+        })
+        .collect();
+    Ok(BulkReport { applied, items })
+}
+
+fn existing(id: u64) -> Result<BlogPost, Error> {
+    _get_blog_post(&id).ok_or(Error::NotFound {
+        msg: format!("Blog post with ID {} not found", id),
+    })
+}
+
+// Update function for admins to delete many posts at once. Posts with likes
+// follow the configured policy, the same as one at a time.
+#[ic_cdk::update]
+fn bulk_delete_posts(ids: Vec<u64>) -> Result<BulkReport, Error> {
+    auth::require_admin()?;
+    run(
+        ids,
+        |id| {
+            let blog_post = existing(id)?;
+            config::check_deletable(&blog_post)?;
+            Ok(blog_post)
+        },
+        |blog_post| {
+            _delete_blog_post(blog_post);
+        },
+    )
+}
+
+// Update function for admins to put many posts into a single category,
+// replacing the ones they had
+#[ic_cdk::update]
+fn bulk_recategorize(ids: Vec<u64>, category: String) -> Result<BulkReport, Error> {
+    auth::require_admin()?;
+    if categories::validate_categories(std::slice::from_ref(&category)).is_err() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Invalid category name: {}", category),
+        });
+    }
+    run(ids, existing, |mut blog_post| {
+        let old = std::mem::replace(&mut blog_post.categories, vec![category.clone()]);
+        categories::reindex_post(blog_post.id, &old, &blog_post.categories);
+        do_insert(&blog_post);
+        audit::record(AuditAction::RecategorizePost, blog_post.id);
+        if blog_post.status == PostStatus::Published {
+            asset_sync::publish(&blog_post);
+        }
+    })
+}
+
+// Update function for admins to hide many posts at once
+#[ic_cdk::update]
+fn bulk_hide(ids: Vec<u64>) -> Result<BulkReport, Error> {
+    auth::require_admin()?;
+    run(ids, existing, |blog_post| {
+        moderation::hide(blog_post);
+    })
+}
@@ -0,0 +1,143 @@
+use crate::embeds::{self, EmbedProvider};
+use crate::migration::CURRENT_SCHEMA_VERSION;
+use crate::{auth, comments, config, humanity, listing, tips};
+use candid::Principal;
+
+// Revisions of the endpoints of each optional subsystem, bumped whenever
+// they change in a way frontends must adapt to
+const COMMENTS_VERSION: u32 = 1;
+const PAYMENTS_VERSION: u32 = 1;
+const SEARCH_VERSION: u32 = 1;
+
+// Whether an optional subsystem is turned on in this deployment, and the
+// revision of its endpoints
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Capability {
+    enabled: bool,
+    version: u32,
+}
+
+// Limits calls are held to. Lengths are in characters.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Limits {
+    max_title_len: u32,
+    max_content_len: u32,
+    max_categories: u32,
+    max_comment_len: u32,
+    max_comment_links: u32,
+    // deepest a reply may be nested; top-level comments are at depth 0
+    max_comment_depth: u32,
+    // largest page `query_blog_posts` returns
+    max_page_size: u64,
+}
+
+// Optional parts of the blog turned on in this deployment
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Features {
+    comments: bool,
+    // uploads with `upload_asset_chunk`, served under `/media`
+    media: bool,
+    // `query_blog_posts`; there is no full-text search
+    search: bool,
+    payments: bool,
+    // standalone pages served at `/{slug}`
+    pages: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CanisterInfo {
+    version: String,
+    schema_version: u32,
+    // in nanoseconds, from `SOURCE_DATE_EPOCH` when the build set it
+    built_at: Option<u64>,
+    features: Features,
+    // controllers seen installing, upgrading or administering the canister;
+    // ones that never did are missing
+    admins: Vec<Principal>,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Capabilities {
+    // of the canister's code and of the layout of its stored data
+    version: String,
+    schema_version: u32,
+    anonymous_reads: bool,
+    anonymous_likes: bool,
+    comments: Capability,
+    // whether comments are rendered from Markdown rather than plain text
+    comment_formatting: bool,
+    // whether likes and comments take `verify_humanity` first
+    humanity_verification: bool,
+    // tips and paywalled posts, paid on the tip ledger
+    payments: Capability,
+    // `query_blog_posts`; there is no full-text search
+    search: Capability,
+    // never enabled: the canister only answers calls and HTTP requests
+    websockets: Capability,
+    embed_providers: Vec<EmbedProvider>,
+    limits: Limits,
+}
+
+// Query function for frontends to find out which optional parts of the blog
+// this deployment offers, and within which limits, so they can adapt their
+// interface to it
+#[ic_cdk::query]
+fn get_capabilities() -> Capabilities {
+    let config = config::get();
+    let comment_limits = config::comment_limits();
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: CURRENT_SCHEMA_VERSION,
+        anonymous_reads: config.allow_anonymous_reads,
+        anonymous_likes: auth::allows_anonymous_likes(),
+        comments: Capability {
+            enabled: true,
+            version: COMMENTS_VERSION,
+        },
+        comment_formatting: comment_limits.formatting,
+        humanity_verification: humanity::is_required(),
+        payments: Capability {
+            enabled: tips::ledger().is_some(),
+            version: PAYMENTS_VERSION,
+        },
+        search: Capability {
+            enabled: true,
+            version: SEARCH_VERSION,
+        },
+        websockets: Capability {
+            enabled: false,
+            version: 0,
+        },
+        embed_providers: embeds::enabled(),
+        limits: Limits {
+            max_title_len: config.max_title_len,
+            max_content_len: config.max_content_len,
+            max_categories: config.max_categories,
+            max_comment_len: comment_limits.max_len,
+            max_comment_links: comment_limits.max_links,
+            max_comment_depth: comments::MAX_DEPTH,
+            max_page_size: listing::MAX_QUERY_PAGE,
+        },
+    }
+}
+
+// Query function for clients and indexers to find out which release they
+// talk to, which parts of the blog it has and who runs it
+#[ic_cdk::query]
+fn get_canister_info() -> CanisterInfo {
+    CanisterInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema_version: CURRENT_SCHEMA_VERSION,
+        built_at: option_env!("SOURCE_DATE_EPOCH")
+            .and_then(|seconds| seconds.parse::<u64>().ok())
+            .map(|seconds| seconds * 1_000_000_000),
+        features: Features {
+            comments: true,
+            media: true,
+            search: true,
+            payments: tips::ledger().is_some(),
+            pages: true,
+        },
+        admins: auth::known_admins(),
+    }
+}
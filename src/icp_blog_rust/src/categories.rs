@@ -0,0 +1,619 @@
+use crate::audit::{self, AuditAction};
+use crate::listing::PostFilter;
+use crate::memory::{self, Memory};
+use crate::moderation::BulkOutcome;
+use crate::{_get_blog_post, asset_sync, auth, do_insert, jobs, BlogPost, Error, PostStatus};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+use validator::ValidationError;
+
+// Longest category name accepted, in bytes
+pub(crate) const MAX_CATEGORY_LEN: usize = 64;
+// Most categories `retag_posts` may add and remove in one call
+const MAX_RETAG_CATEGORIES: usize = 20;
+// Most categories the category report compares with each other when looking
+// for duplicates; every pair is compared, so this keeps the report within
+// the instruction limit of a query
+const MAX_COMPARED_CATEGORIES: usize = 300;
+
+// Categories to add to and remove from every post a filter matches, as
+// queued by `retag_posts`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Retag {
+    filter: PostFilter,
+    add: Vec<String>,
+    remove: Vec<String>,
+}
+
+impl Retag {
+    // Categories of a post once `remove` are taken out and `add` put in after
+    // the ones it keeps
+    fn apply(&self, categories: &[String]) -> Vec<String> {
+        let mut retagged: Vec<String> = categories
+            .iter()
+            .filter(|category| !self.remove.contains(category))
+            .cloned()
+            .collect();
+        for category in &self.add {
+            if !retagged.contains(category) {
+                retagged.push(category.clone());
+            }
+        }
+        retagged
+    }
+
+    // Up to `limit` posts the filter matches whose categories would change,
+    // newest first. Retagged posts no longer need it, so the job runner can
+    // call this again to resume.
+    fn posts(&self, limit: usize) -> Vec<BlogPost> {
+        self.filter
+            .candidates()
+            .into_iter()
+            .filter_map(|id| _get_blog_post(&id))
+            .filter(|blog_post| self.filter.matches(blog_post))
+            .filter(|blog_post| self.apply(&blog_post.categories) != blog_post.categories)
+            .take(limit)
+            .collect()
+    }
+}
+
+// Key of the category registry
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CategoryName(String);
+
+impl Storable for CategoryName {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        CategoryName(String::from_utf8(bytes.into_owned()).unwrap())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_CATEGORY_LEN as u32,
+        is_fixed_size: false,
+    };
+}
+
+// Key of the category -> posts index. Encoded as the name, a NUL separator and
+// the big-endian post id so all posts of a category are one contiguous range.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CategoryPost {
+    category: String,
+    post_id: u64,
+}
+
+impl Storable for CategoryPost {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(self.category.len() + 9);
+        bytes.extend_from_slice(self.category.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (category, id) = bytes.split_at(bytes.len() - 9);
+        CategoryPost {
+            category: String::from_utf8(category.to_vec()).unwrap(),
+            post_id: u64::from_be_bytes(id[1..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_CATEGORY_LEN as u32 + 9,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // Every category ever used with its current number of posts. Entries are
+    // kept at zero when their last post goes away so they can be reported and
+    // cleaned up explicitly.
+    static CATEGORIES: RefCell<StableBTreeMap<CategoryName, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CATEGORIES_MEMORY)));
+
+    static CATEGORY_POSTS: RefCell<StableBTreeMap<CategoryPost, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CATEGORY_POSTS_MEMORY)));
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CategoryStats {
+    name: String,
+    posts: u64,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct MergeSuggestion {
+    from: String,
+    into: String,
+    from_posts: u64,
+    into_posts: u64,
+    reason: String,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CategoryReport {
+    orphaned: Vec<CategoryStats>,
+    sparse: Vec<CategoryStats>,
+    merge_suggestions: Vec<MergeSuggestion>,
+}
+
+fn is_valid(category: &str) -> bool {
+    !category.is_empty() && category.len() <= MAX_CATEGORY_LEN && !category.contains('\0')
+}
+
+// Validator for the categories of a payload
+pub(crate) fn validate_categories(categories: &[String]) -> Result<(), ValidationError> {
+    if categories.iter().all(|category| is_valid(category)) {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_category"))
+    }
+}
+
+// Add a post to the index of each of its categories
+pub(crate) fn index_post(post_id: u64, categories: &[String]) {
+    for category in categories {
+        add_to_category(category, post_id);
+    }
+}
+
+// Remove a post from the index of each of its categories
+pub(crate) fn unindex_post(post_id: u64, categories: &[String]) {
+    for category in categories {
+        remove_from_category(category, post_id);
+    }
+}
+
+// Move a post between category indexes after its categories changed
+pub(crate) fn reindex_post(post_id: u64, old: &[String], new: &[String]) {
+    for category in old.iter().filter(|c| !new.contains(c)) {
+        remove_from_category(category, post_id);
+    }
+    for category in new.iter().filter(|c| !old.contains(c)) {
+        add_to_category(category, post_id);
+    }
+}
+
+// Categories stored before they were validated may not fit an index key, so
+// invalid ones are left out of the indexes
+fn add_to_category(category: &str, post_id: u64) {
+    if !is_valid(category) {
+        return;
+    }
+    let key = CategoryPost {
+        category: category.to_string(),
+        post_id,
+    };
+    let inserted = CATEGORY_POSTS.with(|index| index.borrow_mut().insert(key, ()).is_none());
+    if inserted {
+        adjust_count(category, |count| count + 1);
+    }
+}
+
+fn remove_from_category(category: &str, post_id: u64) {
+    if !is_valid(category) {
+        return;
+    }
+    let key = CategoryPost {
+        category: category.to_string(),
+        post_id,
+    };
+    let removed = CATEGORY_POSTS.with(|index| index.borrow_mut().remove(&key).is_some());
+    if removed {
+        adjust_count(category, |count| count.saturating_sub(1));
+    }
+}
+
+fn adjust_count(category: &str, f: impl FnOnce(u64) -> u64) {
+    CATEGORIES.with(|categories| {
+        let mut categories = categories.borrow_mut();
+        let key = CategoryName(category.to_string());
+        let count = categories.get(&key).unwrap_or(0);
+        categories.insert(key, f(count));
+    });
+}
+
+pub(crate) fn post_count(category: &str) -> Option<u64> {
+    CATEGORIES.with(|categories| categories.borrow().get(&CategoryName(category.to_string())))
+}
+
+// Every category that currently has posts, along with its number of posts
+pub(crate) fn list_categories() -> Vec<(String, u64)> {
+    CATEGORIES.with(|categories| {
+        categories
+            .borrow()
+            .iter()
+            .filter(|(_, posts)| *posts > 0)
+            .map(|(name, posts)| (name.0, posts))
+            .collect()
+    })
+}
+
+// Whether a post is in the index of a category
+pub(crate) fn is_indexed(post_id: u64, category: &str) -> bool {
+    let key = CategoryPost {
+        category: category.to_string(),
+        post_id,
+    };
+    CATEGORY_POSTS.with(|index| index.borrow().contains_key(&key))
+}
+
+// Post IDs of the index entries whose post is gone or no longer has the
+// category. They are removed when `repair` is set; the counts are fixed
+// separately by `count_drift`.
+pub(crate) fn stale_entries(is_current: impl Fn(u64, &str) -> bool, repair: bool) -> Vec<u64> {
+    let stale: Vec<CategoryPost> = CATEGORY_POSTS.with(|index| {
+        index
+            .borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !is_current(key.post_id, &key.category))
+            .collect()
+    });
+    if repair {
+        CATEGORY_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for key in &stale {
+                index.remove(key);
+            }
+        });
+    }
+    stale.into_iter().map(|key| key.post_id).collect()
+}
+
+// Categories whose number of posts differs from their entries in the index,
+// recounted when `repair` is set. Categories left without posts stay at zero.
+pub(crate) fn count_drift(repair: bool) -> Vec<String> {
+    let mut counted: BTreeMap<String, u64> = BTreeMap::new();
+    CATEGORY_POSTS.with(|index| {
+        for (key, _) in index.borrow().iter() {
+            *counted.entry(key.category).or_default() += 1;
+        }
+    });
+    let drifted: Vec<(String, u64)> = CATEGORIES.with(|categories| {
+        let categories = categories.borrow();
+        let mut drifted: Vec<(String, u64)> = categories
+            .iter()
+            .filter(|(name, posts)| counted.get(&name.0).copied().unwrap_or(0) != *posts)
+            .map(|(name, _)| {
+                let posts = counted.get(&name.0).copied().unwrap_or(0);
+                (name.0, posts)
+            })
+            .collect();
+        drifted.extend(
+            counted
+                .iter()
+                .filter(|(name, _)| !categories.contains_key(&CategoryName(name.to_string())))
+                .map(|(name, posts)| (name.clone(), *posts)),
+        );
+        drifted
+    });
+    if repair {
+        CATEGORIES.with(|categories| {
+            let mut categories = categories.borrow_mut();
+            for (name, posts) in &drifted {
+                categories.insert(CategoryName(name.clone()), *posts);
+            }
+        });
+    }
+    drifted.into_iter().map(|(name, _)| name).collect()
+}
+
+// IDs of up to `limit` posts of a category, in ascending order
+pub(crate) fn posts_in_category(category: &str, limit: usize) -> Vec<u64> {
+    let start = CategoryPost {
+        category: category.to_string(),
+        post_id: 0,
+    };
+    let end = CategoryPost {
+        category: category.to_string(),
+        post_id: u64::MAX,
+    };
+    CATEGORY_POSTS.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .take(limit)
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// IDs of the `limit` most recent posts of a category, newest first
+pub(crate) fn newest_post_ids(category: &str, limit: usize) -> Vec<u64> {
+    let start = CategoryPost {
+        category: category.to_string(),
+        post_id: 0,
+    };
+    let end = CategoryPost {
+        category: category.to_string(),
+        post_id: u64::MAX,
+    };
+    CATEGORY_POSTS.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .rev()
+            .take(limit)
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// Move up to `limit` posts from one category to another, keeping the place
+// of the category among the post's categories. Called by the job runner until
+// it reports that no post is left in `from`, which is then dropped.
+pub(crate) fn merge_batch(from: &str, into: &str, limit: usize) -> Result<(u64, bool), String> {
+    let ids = posts_in_category(from, limit);
+    for id in &ids {
+        let Some(mut blog_post) = _get_blog_post(id) else {
+            // the post is gone, only the stale index entry is left
+            remove_from_category(from, *id);
+            continue;
+        };
+        let old = blog_post.categories.clone();
+        let has_into = old.iter().any(|c| c == into);
+        blog_post.categories = old
+            .iter()
+            .filter(|c| !(has_into && *c == from))
+            .map(|c| {
+                if c == from {
+                    into.to_string()
+                } else {
+                    c.clone()
+                }
+            })
+            .collect();
+        reindex_post(*id, &old, &blog_post.categories);
+        do_insert(&blog_post);
+        audit::record(AuditAction::RecategorizePost, *id);
+        if blog_post.status == PostStatus::Published {
+            asset_sync::publish(&blog_post);
+        }
+    }
+    let done = ids.len() < limit;
+    if done && post_count(from) == Some(0) {
+        CATEGORIES.with(|categories| {
+            categories
+                .borrow_mut()
+                .remove(&CategoryName(from.to_string()))
+        });
+    }
+    Ok((ids.len() as u64, done))
+}
+
+// Add and remove categories of up to `limit` posts the filter of a retag
+// matches. Called by the job runner until it reports that no post is left to
+// change.
+pub(crate) fn retag_batch(retag: &Retag, limit: usize) -> Result<(u64, bool), String> {
+    let posts = retag.posts(limit);
+    for mut blog_post in posts.iter().cloned() {
+        let old = std::mem::take(&mut blog_post.categories);
+        blog_post.categories = retag.apply(&old);
+        reindex_post(blog_post.id, &old, &blog_post.categories);
+        do_insert(&blog_post);
+        audit::record(AuditAction::RecategorizePost, blog_post.id);
+        if blog_post.status == PostStatus::Published {
+            asset_sync::publish(&blog_post);
+        }
+    }
+    Ok((posts.len() as u64, posts.len() < limit))
+}
+
+// Normalized form used to detect near-duplicate categories
+fn normalize(category: &str) -> String {
+    let normalized: String = category
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect();
+    match normalized.strip_suffix('s') {
+        Some(singular) if singular.len() > 2 => singular.to_string(),
+        _ => normalized,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Why two categories look like the same topic, if they do
+fn similarity_reason(a: &str, b: &str) -> Option<String> {
+    let (na, nb) = (normalize(a), normalize(b));
+    if na.is_empty() || nb.is_empty() {
+        return None;
+    }
+    if na == nb {
+        return Some("same name after ignoring case, punctuation and plurals".to_string());
+    }
+    let allowed = match na.chars().count().min(nb.chars().count()) {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    let distance = edit_distance(&na, &nb);
+    if distance <= allowed {
+        return Some(format!("names differ by {} character(s)", distance));
+    }
+    None
+}
+
+// Query function for admins listing unused and rarely used categories, along
+// with categories that look like duplicates of each other. Only the
+// `MAX_COMPARED_CATEGORIES` categories with the most posts are checked for
+// duplicates.
+#[ic_cdk::query]
+fn get_category_report(max_posts: u64) -> Result<CategoryReport, Error> {
+    auth::require_admin()?;
+    let stats: Vec<CategoryStats> = CATEGORIES.with(|categories| {
+        categories
+            .borrow()
+            .iter()
+            .map(|(name, posts)| CategoryStats {
+                name: name.0,
+                posts,
+            })
+            .collect()
+    });
+
+    let mut compared: Vec<&CategoryStats> = stats.iter().collect();
+    compared.sort_by_key(|stats| std::cmp::Reverse(stats.posts));
+    compared.truncate(MAX_COMPARED_CATEGORIES);
+    let mut merge_suggestions = Vec::new();
+    for (i, a) in compared.iter().enumerate() {
+        for b in &compared[i + 1..] {
+            if let Some(reason) = similarity_reason(&a.name, &b.name) {
+                // suggest folding the smaller category into the larger one
+                let (from, into) = if a.posts <= b.posts { (a, b) } else { (b, a) };
+                merge_suggestions.push(MergeSuggestion {
+                    from: from.name.clone(),
+                    into: into.name.clone(),
+                    from_posts: from.posts,
+                    into_posts: into.posts,
+                    reason,
+                });
+            }
+        }
+    }
+
+    Ok(CategoryReport {
+        orphaned: stats.iter().filter(|s| s.posts == 0).cloned().collect(),
+        sparse: stats
+            .iter()
+            .filter(|s| s.posts > 0 && s.posts <= max_posts)
+            .cloned()
+            .collect(),
+        merge_suggestions,
+    })
+}
+
+// Update function for admins to drop categories that no longer have any posts
+#[ic_cdk::update]
+fn cleanup_orphaned_categories() -> Result<Vec<String>, Error> {
+    auth::require_admin()?;
+    CATEGORIES.with(|categories| {
+        let mut categories = categories.borrow_mut();
+        let orphaned: Vec<CategoryName> = categories
+            .iter()
+            .filter(|(_, posts)| *posts == 0)
+            .map(|(name, _)| name)
+            .collect();
+        for name in &orphaned {
+            categories.remove(name);
+        }
+        Ok(orphaned.into_iter().map(|name| name.0).collect())
+    })
+}
+
+// Update function for moderators to merge one category into another, e.g.
+// a duplicate into the one to keep. Posts and indexes are rewritten in the
+// background; the returned job reports the progress.
+#[ic_cdk::update]
+fn merge_categories(from: String, into: String) -> Result<jobs::Job, Error> {
+    auth::require_moderator()?;
+    check_merge(&from, &into)?;
+    Ok(jobs::enqueue_merge_categories(from, into))
+}
+
+// Update function for moderators to rename a category, e.g. to fix a typo.
+// Renaming into a category that has posts is a merge and is refused.
+#[ic_cdk::update]
+fn rename_category(old: String, new: String) -> Result<jobs::Job, Error> {
+    auth::require_moderator()?;
+    check_merge(&old, &new)?;
+    if post_count(&new).is_some_and(|posts| posts > 0) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Category {} already has posts; merge into it instead", new),
+        });
+    }
+    Ok(jobs::enqueue_merge_categories(old, new))
+}
+
+// Update function for moderators to add and remove categories of every post
+// matching a filter, e.g. to clean up the taxonomy. Posts are retagged in the
+// background by a job that picks up where it left off. With `dry_run` it only
+// returns the IDs of the posts that would change.
+#[ic_cdk::update]
+fn retag_posts(
+    filter: PostFilter,
+    add: Vec<String>,
+    remove: Vec<String>,
+    dry_run: bool,
+) -> Result<BulkOutcome, Error> {
+    auth::require_moderator()?;
+    if add.is_empty() && remove.is_empty() {
+        return Err(Error::ValidationErrors {
+            errors: "Nothing to add or remove".to_string(),
+        });
+    }
+    if add.len() + remove.len() > MAX_RETAG_CATEGORIES {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "At most {} categories can be added and removed at once",
+                MAX_RETAG_CATEGORIES
+            ),
+        });
+    }
+    if validate_categories(&add).is_err() {
+        return Err(Error::ValidationErrors {
+            errors: "Invalid category name to add".to_string(),
+        });
+    }
+    if let Some(category) = add.iter().find(|category| remove.contains(category)) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Category {} is both added and removed", category),
+        });
+    }
+    let retag = Retag {
+        filter,
+        add,
+        remove,
+    };
+    if dry_run {
+        return Ok(BulkOutcome::DryRun {
+            affected: retag
+                .posts(usize::MAX)
+                .iter()
+                .map(|blog_post| blog_post.id)
+                .collect(),
+        });
+    }
+    Ok(BulkOutcome::Queued {
+        job: jobs::enqueue_retag_posts(retag),
+    })
+}
+
+// Check that the posts of `from` can be moved into `to`
+pub(crate) fn check_merge(from: &str, to: &str) -> Result<(), Error> {
+    if from == to {
+        return Err(Error::ValidationErrors {
+            errors: "Cannot merge a category into itself".to_string(),
+        });
+    }
+    if validate_categories(&[to.to_string()]).is_err() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Invalid category name: {}", to),
+        });
+    }
+    if post_count(from).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Category {} not found", from),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,306 @@
+use crate::memory::{self, Memory};
+use crate::{
+    _check_if_owner, _get_blog_post, auth, blocklist, config, credits, linking, patch_blog_post,
+    BlogPost, Error, PatchPayload, PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most changes waiting for review on one post
+const MAX_PENDING_PER_POST: usize = 20;
+// Longest summary of a change, and longest rejection comment, in characters
+const MAX_SUMMARY_LEN: usize = 500;
+const MAX_COMMENT_LEN: usize = 2_000;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) enum ChangeStatus {
+    Pending,
+    Accepted,
+    Rejected { comment: String },
+}
+
+// Edit a co-author proposed to a published post, applied only once the
+// author accepts it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ChangeRequest {
+    id: u64,
+    post_id: u64,
+    proposer: Principal,
+    // fields left out keep their current value
+    title: Option<String>,
+    content: Option<String>,
+    summary: String,
+    // `updated_at` of the post the change was made against, or its
+    // `created_at` if it was never updated
+    based_on: u64,
+    created_at: u64,
+    status: ChangeStatus,
+    decided_at: Option<u64>,
+}
+
+impl Storable for ChangeRequest {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Payload of `propose_change`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ChangePayload {
+    title: Option<String>,
+    content: Option<String>,
+    summary: String,
+}
+
+// Key of the change requests map: the post id followed by the request id, so
+// the requests on a post are one contiguous range in the order they were made
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ChangeKey {
+    post_id: u64,
+    request_id: u64,
+}
+
+impl Storable for ChangeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.request_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ChangeKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            request_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    static CHANGE_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::CHANGE_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a change request counter")
+    );
+
+    static CHANGES: RefCell<StableBTreeMap<ChangeKey, ChangeRequest, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CHANGE_REQUESTS_MEMORY)));
+}
+
+fn next_id() -> u64 {
+    CHANGE_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the change request counter");
+        id
+    })
+}
+
+fn changes_on(post_id: u64) -> Vec<ChangeRequest> {
+    let start = ChangeKey {
+        post_id,
+        request_id: 0,
+    };
+    let end = ChangeKey {
+        post_id,
+        request_id: u64::MAX,
+    };
+    CHANGES.with(|changes| {
+        changes
+            .borrow()
+            .range(start..=end)
+            .map(|(_, change)| change)
+            .collect()
+    })
+}
+
+fn save(change: &ChangeRequest) {
+    CHANGES.with(|changes| {
+        changes.borrow_mut().insert(
+            ChangeKey {
+                post_id: change.post_id,
+                request_id: change.id,
+            },
+            change.clone(),
+        )
+    });
+}
+
+// Drop the change requests on a deleted post
+pub(crate) fn remove_post_changes(post_id: u64) {
+    let start = ChangeKey {
+        post_id,
+        request_id: 0,
+    };
+    let end = ChangeKey {
+        post_id,
+        request_id: u64::MAX,
+    };
+    CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let keys: Vec<ChangeKey> = changes.range(start..=end).map(|(key, _)| key).collect();
+        for key in keys {
+            changes.remove(&key);
+        }
+    });
+}
+
+fn not_found(post_id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Blog post with ID {} not found", post_id),
+    }
+}
+
+// Load a post whose author is the caller, along with a change waiting for
+// their review
+fn pending_change(post_id: u64, request_id: u64) -> Result<(BlogPost, ChangeRequest), Error> {
+    auth::require_signed_in()?;
+    let blog_post = _get_blog_post(&post_id).ok_or_else(|| not_found(post_id))?;
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Unauthorized to review changes to post with id={}.",
+                post_id
+            ),
+        });
+    }
+    let key = ChangeKey {
+        post_id,
+        request_id,
+    };
+    match CHANGES.with(|changes| changes.borrow().get(&key)) {
+        Some(change) if change.status == ChangeStatus::Pending => Ok((blog_post, change)),
+        _ => Err(Error::NotFound {
+            msg: format!("No pending change {} on post {}", request_id, post_id),
+        }),
+    }
+}
+
+// Update function for principals credited on a published post to propose a
+// new title or content for it. The author reviews it with `accept_change` or
+// `reject_change`.
+#[ic_cdk::update]
+fn propose_change(post_id: u64, payload: ChangePayload) -> Result<ChangeRequest, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let blog_post = _get_blog_post(&post_id).ok_or_else(|| not_found(post_id))?;
+    if blog_post.status != PostStatus::Published || !credits::is_credited(&blog_post, &caller()) {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Only co-authors can propose changes to post with id={}.",
+                post_id
+            ),
+        });
+    }
+    if payload.title.is_none() && payload.content.is_none() {
+        return Err(Error::ValidationErrors {
+            errors: "Changes must propose a title, content or both".to_string(),
+        });
+    }
+    let summary_len = payload.summary.trim().chars().count();
+    if summary_len == 0 || summary_len > MAX_SUMMARY_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!("Change summaries are 1 to {} characters", MAX_SUMMARY_LEN),
+        });
+    }
+    config::check_post(payload.title.as_deref(), payload.content.as_deref(), None)?;
+    // terms the author would be refused are refused here too; masking and
+    // warnings happen when the change is applied
+    blocklist::screen_post(
+        payload.title.clone().unwrap_or_default(),
+        payload.content.clone().unwrap_or_default(),
+        blog_post.encrypted,
+    )?;
+    let pending = changes_on(post_id)
+        .into_iter()
+        .filter(|change| change.status == ChangeStatus::Pending)
+        .count();
+    if pending >= MAX_PENDING_PER_POST {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Posts can have at most {} changes waiting for review",
+                MAX_PENDING_PER_POST
+            ),
+        });
+    }
+    let change = ChangeRequest {
+        id: next_id(),
+        post_id,
+        proposer: linking::primary(&caller()),
+        title: payload.title,
+        content: payload.content,
+        summary: payload.summary,
+        based_on: blog_post.updated_at.unwrap_or(blog_post.created_at),
+        created_at: time(),
+        status: ChangeStatus::Pending,
+        decided_at: None,
+    };
+    save(&change);
+    Ok(change)
+}
+
+// Query function for the author and co-authors of a post to list the changes
+// proposed to it, oldest first
+#[ic_cdk::query]
+fn get_change_requests(post_id: u64) -> Result<Vec<ChangeRequest>, Error> {
+    auth::require_signed_in()?;
+    match _get_blog_post(&post_id) {
+        Some(blog_post)
+            if _check_if_owner(&blog_post) || credits::is_credited(&blog_post, &caller()) =>
+        {
+            Ok(changes_on(post_id))
+        }
+        _ => Err(not_found(post_id)),
+    }
+}
+
+// Update function for authors to apply a pending change to their post, as a
+// new revision. Changes made against an older copy of the post are refused
+// with `ConflictingEdit`; the co-author can propose them again.
+#[ic_cdk::update]
+fn accept_change(post_id: u64, request_id: u64) -> Result<BlogPost, Error> {
+    let (_, mut change) = pending_change(post_id, request_id)?;
+    let blog_post = patch_blog_post(
+        post_id,
+        PatchPayload {
+            title: change.title.clone(),
+            content: change.content.clone(),
+            ..Default::default()
+        },
+        Some(change.based_on),
+    )?;
+    change.status = ChangeStatus::Accepted;
+    change.decided_at = Some(time());
+    save(&change);
+    Ok(blog_post)
+}
+
+// Update function for authors to turn a pending change down, telling the
+// co-author why
+#[ic_cdk::update]
+fn reject_change(post_id: u64, request_id: u64, comment: String) -> Result<ChangeRequest, Error> {
+    let (_, mut change) = pending_change(post_id, request_id)?;
+    if comment.chars().count() > MAX_COMMENT_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!("Comments are at most {} characters", MAX_COMMENT_LEN),
+        });
+    }
+    change.status = ChangeStatus::Rejected { comment };
+    change.decided_at = Some(time());
+    save(&change);
+    Ok(change)
+}
@@ -0,0 +1,284 @@
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{_check_if_owner, _get_blog_post, auth, linking, BlogPost, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most co-authors a post may have at once
+const MAX_COLLABORATORS: usize = 20;
+
+// What a co-author may do with a post. Only its owner may delete or
+// transfer it, or manage its co-authors.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum CollaboratorRole {
+    // reads the post whatever its status or visibility, and edits it
+    Editor,
+    // reads the post whatever its status or visibility
+    Viewer,
+}
+
+impl CollaboratorRole {
+    fn to_byte(self) -> u8 {
+        match self {
+            CollaboratorRole::Editor => 0,
+            CollaboratorRole::Viewer => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            0 => CollaboratorRole::Editor,
+            _ => CollaboratorRole::Viewer,
+        }
+    }
+}
+
+// A co-author of a post, as listed by `get_collaborators`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Collaborator {
+    principal: Principal,
+    role: CollaboratorRole,
+    added_at: u64,
+}
+
+// Key of the collaborators map: the post id followed by the co-author, so
+// all co-authors of a post are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CollaboratorKey {
+    post_id: u64,
+    principal: Vec<u8>,
+}
+
+impl Storable for CollaboratorKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        CollaboratorKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            principal: bytes[8..].to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + 29,
+        is_fixed_size: false,
+    };
+}
+
+impl CollaboratorKey {
+    fn new(post_id: u64, principal: &Principal) -> Self {
+        CollaboratorKey {
+            post_id,
+            principal: principal.as_slice().to_vec(),
+        }
+    }
+}
+
+// Role and time it was granted, packed as the role byte followed by the
+// big-endian time
+#[derive(Clone, Copy)]
+struct Grant {
+    role: CollaboratorRole,
+    added_at: u64,
+}
+
+impl Storable for Grant {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = vec![self.role.to_byte()];
+        bytes.extend_from_slice(&self.added_at.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Grant {
+            role: CollaboratorRole::from_byte(bytes[0]),
+            added_at: u64::from_be_bytes(bytes[1..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1 + 8,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    static COLLABORATORS: RefCell<StableBTreeMap<CollaboratorKey, Grant, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::COLLABORATORS_MEMORY)));
+}
+
+fn post_collaborators(post_id: u64) -> Vec<(Principal, Grant)> {
+    let start = CollaboratorKey {
+        post_id,
+        principal: Vec::new(),
+    };
+    COLLABORATORS.with(|collaborators| {
+        collaborators
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, grant)| (Principal::from_slice(&key.principal), grant))
+            .collect()
+    })
+}
+
+// Role of the caller on a post, counting the principals linked to theirs
+fn caller_role(post_id: u64) -> Option<CollaboratorRole> {
+    post_collaborators(post_id)
+        .into_iter()
+        .find(|(principal, _)| linking::same_person(principal, &caller()))
+        .map(|(_, grant)| grant.role)
+}
+
+// Whether the caller may edit a post as its co-author
+pub(crate) fn can_edit(blog_post: &BlogPost) -> bool {
+    caller_role(blog_post.id) == Some(CollaboratorRole::Editor)
+}
+
+// Whether the caller may read a post as its co-author, whatever its status
+// or visibility
+pub(crate) fn can_view(blog_post: &BlogPost) -> bool {
+    caller_role(blog_post.id).is_some()
+}
+
+// Drop the co-authors of a post `keep` says no to
+fn remove_where(post_id: u64, keep: impl Fn(&Principal) -> bool) {
+    for (principal, _) in post_collaborators(post_id) {
+        if !keep(&principal) {
+            COLLABORATORS.with(|collaborators| {
+                collaborators
+                    .borrow_mut()
+                    .remove(&CollaboratorKey::new(post_id, &principal))
+            });
+        }
+    }
+}
+
+// Drop a co-author, counting the principals linked to theirs, e.g. one who
+// just became the owner of the post
+pub(crate) fn remove(post_id: u64, principal: &Principal) {
+    remove_where(post_id, |other| !linking::same_person(other, principal));
+}
+
+// Drop the co-authors of a deleted post
+pub(crate) fn remove_post_collaborators(post_id: u64) {
+    remove_where(post_id, |_| false);
+}
+
+// Drop up to `limit` co-authorships of `principal`, returning how many were
+// dropped
+pub(crate) fn remove_all(principal: &Principal, limit: usize) -> usize {
+    let keys: Vec<CollaboratorKey> = COLLABORATORS.with(|collaborators| {
+        collaborators
+            .borrow()
+            .iter()
+            .filter(|(key, _)| key.principal == principal.as_slice())
+            .map(|(key, _)| key)
+            .take(limit)
+            .collect()
+    });
+    COLLABORATORS.with(|collaborators| {
+        let mut collaborators = collaborators.borrow_mut();
+        for key in &keys {
+            collaborators.remove(key);
+        }
+    });
+    keys.len()
+}
+
+fn owned_post(post_id: u64) -> Result<BlogPost, Error> {
+    let blog_post = _get_blog_post(&post_id).ok_or_else(|| Error::NotFound {
+        msg: format!("Blog post with ID {} not found", post_id),
+    })?;
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Only the owner of post with id={} can manage its co-authors",
+                post_id
+            ),
+        });
+    }
+    Ok(blog_post)
+}
+
+// Update function for the owner of a post to add a co-author, or change the
+// role of one
+#[ic_cdk::update]
+fn add_collaborator(
+    post_id: u64,
+    principal: Principal,
+    role: CollaboratorRole,
+) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let blog_post = owned_post(post_id)?;
+    let owner = Principal::from_text(&blog_post.author).ok();
+    if principal == Principal::anonymous()
+        || owner.is_some_and(|owner| linking::same_person(&owner, &principal))
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!("{} cannot be a co-author of this post", principal),
+        });
+    }
+    let key = CollaboratorKey::new(post_id, &principal);
+    let existing = COLLABORATORS.with(|collaborators| collaborators.borrow().get(&key));
+    if existing.is_none() && post_collaborators(post_id).len() >= MAX_COLLABORATORS {
+        return Err(Error::ValidationErrors {
+            errors: format!("Posts can have at most {} co-authors", MAX_COLLABORATORS),
+        });
+    }
+    let grant = Grant {
+        role,
+        added_at: existing.map_or_else(time, |existing| existing.added_at),
+    };
+    COLLABORATORS.with(|collaborators| collaborators.borrow_mut().insert(key, grant));
+    if existing.is_none() {
+        notifications::notify(
+            &principal,
+            NotificationKind::AddedAsCollaborator { post_id },
+        );
+    }
+    Ok(())
+}
+
+// Update function for the owner of a post to drop a co-author, or for a
+// co-author to leave
+#[ic_cdk::update]
+fn remove_collaborator(post_id: u64, principal: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    if !linking::same_person(&principal, &caller()) {
+        owned_post(post_id)?;
+    }
+    let key = CollaboratorKey::new(post_id, &principal);
+    match COLLABORATORS.with(|collaborators| collaborators.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not a co-author of post {}", principal, post_id),
+        }),
+    }
+}
+
+// Query function for the owner and co-authors of a post to list its
+// co-authors
+#[ic_cdk::query]
+fn get_collaborators(post_id: u64) -> Result<Vec<Collaborator>, Error> {
+    auth::require_signed_in()?;
+    let blog_post = owned_post(post_id).or_else(|error| match _get_blog_post(&post_id) {
+        Some(blog_post) if can_view(&blog_post) => Ok(blog_post),
+        _ => Err(error),
+    })?;
+    Ok(post_collaborators(blog_post.id)
+        .into_iter()
+        .map(|(principal, grant)| Collaborator {
+            principal,
+            role: grant.role,
+            added_at: grant.added_at,
+        })
+        .collect())
+}
@@ -0,0 +1,648 @@
+use crate::abuse::{self, SignalKind};
+use crate::activity::{self, ActivityKind};
+use crate::audit::{self, AuditAction};
+use crate::blocklist;
+use crate::emoji;
+use crate::keys::PrincipalIdKey;
+use crate::listing::Pagination;
+use crate::memory::{self, Memory};
+use crate::mentions;
+use crate::moderation::ReportTarget;
+use crate::notifications::{self, NotificationKind};
+use crate::render::{self, escape};
+use crate::sessions::{self, WriteAction};
+use crate::{
+    _check_if_owner, _get_blog_post, _get_visible_blog_post, auth, config, date, http, humanity,
+    linking, profiles, rate_limits, Error,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::collections::{BTreeMap, BTreeSet};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest accepted comment, in bytes, whatever the comment limits admins set
+const MAX_COMMENT_LEN: usize = 5000;
+// Largest page `get_comments` and `get_my_comments` return
+const MAX_COMMENTS_PAGE: u64 = 100;
+// Deepest a reply may be nested; top-level comments are at depth 0
+pub(crate) const MAX_DEPTH: u32 = 5;
+// Most comments `get_comment_thread` returns
+const MAX_THREAD_LEN: usize = 500;
+// Comments per chunk of a comment export; at the longest comments allow, a
+// chunk stays well below the reply limit
+const COMMENTS_PER_CHUNK: usize = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Comment {
+    pub(crate) id: u64,
+    pub(crate) post_id: u64,
+    pub(crate) author: Principal,
+    pub(crate) content: String,
+    pub(crate) created_at: u64,
+    // hidden by a moderator; left out of listings
+    pub(crate) hidden: bool,
+    // display name from the author's profile; filled in when the comment is
+    // read and never stored
+    pub(crate) author_name: Option<String>,
+    // the comment this one replies to, if any
+    pub(crate) parent_comment_id: Option<u64>,
+    // the peer blog canister the comment was submitted through, see
+    // `federation`; none for comments made here
+    pub(crate) origin: Option<Principal>,
+}
+
+// A comment in a thread along with how deeply it is nested
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ThreadEntry {
+    comment: Comment,
+    depth: u32,
+}
+
+// A comment along with the path of its anchor on the post page, such as
+// `/post/my-post#comment-7`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CommentPermalink {
+    comment: Comment,
+    post_slug: String,
+    path: String,
+}
+
+// One piece of a comment export. Clients fetch chunks 0 to
+// `total_chunks - 1`; each is a JSON array of whole comments.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct CommentChunk {
+    data: String,
+    chunk_index: u64,
+    total_chunks: u64,
+}
+
+// A comment as written to an export, readable without the canister
+#[derive(Serialize)]
+struct ArchivedComment {
+    id: u64,
+    parent_comment_id: Option<u64>,
+    author: String,
+    author_name: Option<String>,
+    content: String,
+    created_at: u64,
+    hidden: bool,
+}
+
+impl Storable for Comment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the post -> comments index: the post id followed by the comment id,
+// so the comments of a post are one contiguous range in posting order
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PostComment {
+    post_id: u64,
+    comment_id: u64,
+}
+
+impl Storable for PostComment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.comment_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        PostComment {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            comment_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+// Key of the author -> comments index: an author and a comment id, so the
+// comments of an author are one contiguous range in posting order
+type AuthorComment = PrincipalIdKey;
+
+thread_local! {
+    static COMMENT_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::COMMENT_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a comment counter")
+    );
+
+    static COMMENTS: RefCell<StableBTreeMap<u64, Comment, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::COMMENTS_MEMORY)));
+
+    static POST_COMMENTS: RefCell<StableBTreeMap<PostComment, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POST_COMMENTS_MEMORY)));
+
+    static AUTHOR_COMMENTS: RefCell<StableBTreeMap<AuthorComment, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUTHOR_COMMENTS_MEMORY)));
+}
+
+pub(crate) fn get(comment_id: u64) -> Option<Comment> {
+    COMMENTS
+        .with(|comments| comments.borrow().get(&comment_id))
+        .map(|comment| Comment {
+            author_name: profiles::display_name(&comment.author),
+            ..comment
+        })
+}
+
+pub(crate) fn save(comment: &Comment) {
+    let comment = Comment {
+        author_name: None,
+        ..comment.clone()
+    };
+    COMMENTS.with(|comments| comments.borrow_mut().insert(comment.id, comment));
+}
+
+pub(crate) fn remove(comment: &Comment) {
+    COMMENTS.with(|comments| comments.borrow_mut().remove(&comment.id));
+    POST_COMMENTS.with(|index| {
+        index.borrow_mut().remove(&PostComment {
+            post_id: comment.post_id,
+            comment_id: comment.id,
+        })
+    });
+    AUTHOR_COMMENTS.with(|index| {
+        index
+            .borrow_mut()
+            .remove(&AuthorComment::new(&comment.author, comment.id))
+    });
+}
+
+// Index the comments written before the author -> comments index existed
+pub(crate) fn index_existing_comments() {
+    COMMENTS.with(|comments| {
+        AUTHOR_COMMENTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for (id, comment) in comments.borrow().iter() {
+                index.insert(AuthorComment::new(&comment.author, id), ());
+            }
+        })
+    });
+}
+
+fn comment_ids(post_id: u64) -> Vec<u64> {
+    let start = PostComment {
+        post_id,
+        comment_id: 0,
+    };
+    POST_COMMENTS.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| key.comment_id)
+            .collect()
+    })
+}
+
+// IDs of up to `limit` comments containing `pattern`, ignoring case, oldest
+// first
+pub(crate) fn matching_ids(pattern: &str, limit: usize) -> Vec<u64> {
+    let pattern = pattern.to_lowercase();
+    COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .filter(|(_, comment)| comment.content.to_lowercase().contains(&pattern))
+            .take(limit)
+            .map(|(id, _)| id)
+            .collect()
+    })
+}
+
+// Give up to `limit` comments of `old` to `new`, returning how many moved
+pub(crate) fn reassign(old: &Principal, new: &Principal, limit: usize) -> usize {
+    let ids: Vec<u64> = COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .filter(|(_, comment)| comment.author == *old)
+            .take(limit)
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for mut comment in ids.iter().copied().filter_map(get) {
+        AUTHOR_COMMENTS.with(|index| {
+            let mut index = index.borrow_mut();
+            index.remove(&AuthorComment::new(&comment.author, comment.id));
+            index.insert(AuthorComment::new(new, comment.id), ());
+        });
+        comment.author = *new;
+        save(&comment);
+    }
+    ids.len()
+}
+
+// Every comment of a post in posting order, hidden ones included
+pub(crate) fn post_comments(post_id: u64) -> Vec<Comment> {
+    comment_ids(post_id).into_iter().filter_map(get).collect()
+}
+
+// Post IDs of the comments posted from `since` to before `until` that are
+// not hidden, newest first, one per comment. Comment IDs grow with time, so
+// only the comments from `since` on are read.
+pub(crate) fn post_ids_between(since: u64, until: u64) -> Vec<u64> {
+    COMMENTS.with(|comments| {
+        comments
+            .borrow()
+            .iter()
+            .rev()
+            .map(|(_, comment)| comment)
+            .skip_while(|comment| comment.created_at >= until)
+            .take_while(|comment| comment.created_at >= since)
+            .filter(|comment| !comment.hidden)
+            .map(|comment| comment.post_id)
+            .collect()
+    })
+}
+
+// Number of comments of every post, hidden ones included
+pub(crate) fn total() -> u64 {
+    COMMENTS.with(|comments| comments.borrow().len())
+}
+
+// Number of comments of a post that are not hidden
+pub(crate) fn count(post_id: u64) -> usize {
+    comment_ids(post_id)
+        .into_iter()
+        .filter_map(get)
+        .filter(|comment| !comment.hidden)
+        .count()
+}
+
+// Whether anyone commented on a post, hidden comments included
+pub(crate) fn has_comments(post_id: u64) -> bool {
+    !comment_ids(post_id).is_empty()
+}
+
+// Drop every comment of a post that is being deleted
+pub(crate) fn remove_post_comments(post_id: u64) {
+    for id in comment_ids(post_id) {
+        if let Some(comment) = get(id) {
+            remove(&comment);
+        }
+    }
+}
+
+// Depth of a comment in its thread
+fn depth(comment: &Comment) -> u32 {
+    let mut depth = 0;
+    let mut parent = comment.parent_comment_id;
+    while let Some(parent_comment) = parent.and_then(get) {
+        depth += 1;
+        parent = parent_comment.parent_comment_id;
+    }
+    depth
+}
+
+// Update function to comment on a post, or reply to one of its comments
+#[ic_cdk::update]
+fn add_comment(
+    post_id: u64,
+    content: String,
+    parent_comment_id: Option<u64>,
+) -> Result<Comment, Error> {
+    auth::require_signed_in()?;
+    humanity::require_human()?;
+    insert(
+        post_id,
+        content,
+        parent_comment_id,
+        linking::primary(&caller()),
+        None,
+    )
+}
+
+// Add a comment by `author`, made here or, with `origin`, on the peer blog
+// canister that submitted it
+pub(crate) fn insert(
+    post_id: u64,
+    content: String,
+    parent_comment_id: Option<u64>,
+    author: Principal,
+    origin: Option<Principal>,
+) -> Result<Comment, Error> {
+    let Some(blog_post) = _get_visible_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot comment.", post_id),
+        });
+    };
+    if !blog_post.comments_enabled {
+        return Err(Error::NotAuthorized {
+            msg: format!("Comments are turned off on post with id={}.", post_id),
+        });
+    }
+    if content.trim().is_empty() || content.len() > MAX_COMMENT_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Comments must be between 1 and {} bytes long",
+                MAX_COMMENT_LEN
+            ),
+        });
+    }
+    if let Some(parent_id) = parent_comment_id {
+        match get(parent_id) {
+            Some(parent) if parent.post_id == post_id && !parent.hidden => {
+                if depth(&parent) + 1 > MAX_DEPTH {
+                    return Err(Error::ValidationErrors {
+                        errors: format!("Replies can be nested at most {} levels deep", MAX_DEPTH),
+                    });
+                }
+            }
+            _ => {
+                return Err(Error::NotFound {
+                    msg: format!("Comment with ID {} not found. Cannot reply.", parent_id),
+                })
+            }
+        }
+    }
+    config::check_comment(&content)?;
+    let (content, warned) = blocklist::screen(content)?;
+    rate_limits::check(rate_limits::Action::Comment)?;
+
+    let id = COMMENT_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the comment counter");
+        id
+    });
+    let comment = Comment {
+        id,
+        post_id,
+        author,
+        content,
+        created_at: time(),
+        hidden: false,
+        author_name: profiles::display_name(&author),
+        parent_comment_id,
+        origin,
+    };
+    save(&comment);
+    POST_COMMENTS.with(|index| {
+        index.borrow_mut().insert(
+            PostComment {
+                post_id,
+                comment_id: id,
+            },
+            (),
+        )
+    });
+    AUTHOR_COMMENTS.with(|index| {
+        index
+            .borrow_mut()
+            .insert(AuthorComment::new(&comment.author, id), ())
+    });
+    sessions::record(WriteAction::AddComment, id);
+    audit::record(AuditAction::AddComment, id);
+    abuse::record(SignalKind::CommentPosted);
+    activity::log(
+        &comment.author,
+        ActivityKind::Commented {
+            post_id,
+            comment_id: id,
+        },
+    );
+    notifications::notify_author(
+        &blog_post.author,
+        NotificationKind::Commented {
+            post_id,
+            comment_id: id,
+        },
+    );
+    mentions::notify_comment(&blog_post, &comment);
+    blocklist::warn(ReportTarget::Comment(id), &warned);
+    Ok(comment)
+}
+
+// Query function to page through the comments of a post, oldest first
+#[ic_cdk::query]
+fn get_comments(post_id: u64, offset: u64, limit: u64) -> Result<Vec<Comment>, Error> {
+    config::require_reader()?;
+    if _get_visible_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    Ok(comment_ids(post_id)
+        .into_iter()
+        .filter_map(get)
+        .filter(|comment| !comment.hidden)
+        .skip(offset as usize)
+        .take(limit.min(MAX_COMMENTS_PAGE) as usize)
+        .collect())
+}
+
+// The shown comments of a post as threads: each comment is followed by its
+// replies, oldest first, with its depth. Replies to comments that were
+// deleted or hidden move up to the top level.
+pub(crate) fn thread(post_id: u64) -> Vec<ThreadEntry> {
+    let comments: Vec<Comment> = post_comments(post_id)
+        .into_iter()
+        .filter(|comment| !comment.hidden)
+        .collect();
+    let shown: BTreeSet<u64> = comments.iter().map(|comment| comment.id).collect();
+    let mut roots = Vec::new();
+    let mut replies: BTreeMap<u64, Vec<Comment>> = BTreeMap::new();
+    for comment in comments {
+        match comment
+            .parent_comment_id
+            .filter(|parent| shown.contains(parent))
+        {
+            Some(parent) => replies.entry(parent).or_default().push(comment),
+            None => roots.push(comment),
+        }
+    }
+    // walk the threads depth first; the stack holds comments in reverse so
+    // the oldest comes out first
+    let mut thread = Vec::new();
+    let mut stack: Vec<(Comment, u32)> = roots.into_iter().rev().map(|root| (root, 0)).collect();
+    while let Some((comment, depth)) = stack.pop() {
+        if thread.len() == MAX_THREAD_LEN {
+            break;
+        }
+        if let Some(children) = replies.remove(&comment.id) {
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        }
+        thread.push(ThreadEntry { comment, depth });
+    }
+    thread
+}
+
+// Fragment identifying a comment on its post page
+pub(crate) fn anchor(comment_id: u64) -> String {
+    format!("comment-{}", comment_id)
+}
+
+// The comments of a post as an HTML section, each with its anchor, empty if
+// it has none
+pub(crate) fn to_html(post_id: u64) -> String {
+    let thread = thread(post_id);
+    if thread.is_empty() {
+        return String::new();
+    }
+    let formatting = config::comment_limits().formatting;
+    let mut html = String::from(r#"<section class="comments"><h2>Comments</h2>"#);
+    for ThreadEntry { comment, depth } in thread {
+        let anchor = anchor(comment.id);
+        let author = comment
+            .author_name
+            .unwrap_or_else(|| comment.author.to_string());
+        html.push_str(&format!(
+            r##"<div class="comment depth-{depth}" id="{anchor}"><p>{author} on <a href="#{anchor}"><time datetime="{date}">{date}</time></a></p>{content}</div>"##,
+            author = escape(&author),
+            date = date::iso_date(comment.created_at),
+            content = if formatting {
+                render::comment_to_html(&comment.content)
+            } else {
+                format!("<p>{}</p>", emoji::expand(&escape(&comment.content)))
+            },
+        ));
+    }
+    html.push_str("</section>");
+    html
+}
+
+// Query function to get the comments of a post as threads: each comment is
+// followed by its replies, oldest first, with its depth. Replies to comments
+// that were deleted or hidden move up to the top level.
+#[ic_cdk::query]
+fn get_comment_thread(post_id: u64) -> Result<Vec<ThreadEntry>, Error> {
+    config::require_reader()?;
+    if _get_visible_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    Ok(thread(post_id))
+}
+
+// Query function to get a single comment along with where it is shown, so
+// other sites can link to it. Comments are stored by ID along with the ID of
+// their post, so the post is found without scanning.
+#[ic_cdk::query]
+fn get_comment(comment_id: u64) -> Result<CommentPermalink, Error> {
+    config::require_reader()?;
+    let not_found = || Error::NotFound {
+        msg: format!("Comment with ID {} not found", comment_id),
+    };
+    let comment = get(comment_id)
+        .filter(|comment| !comment.hidden)
+        .ok_or_else(not_found)?;
+    let blog_post = _get_visible_blog_post(&comment.post_id).ok_or_else(not_found)?;
+    let path = format!(
+        "/post/{}#{}",
+        http::encode_segment(&blog_post.slug),
+        anchor(comment_id)
+    );
+    Ok(CommentPermalink {
+        comment,
+        post_slug: blog_post.slug,
+        path,
+    })
+}
+
+// Query function for the post's author and admins to export every comment of
+// a post, hidden ones included, oldest first and one chunk at a time, for
+// example to archive the discussion before deleting the post
+#[ic_cdk::query]
+fn export_comments(post_id: u64, chunk_index: u64) -> Result<CommentChunk, Error> {
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) && !auth::is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Unauthorized to export comments of post with id={}.",
+                post_id
+            ),
+        });
+    }
+    let ids = comment_ids(post_id);
+    let total_chunks = ids.len().div_ceil(COMMENTS_PER_CHUNK).max(1) as u64;
+    if chunk_index >= total_chunks {
+        return Err(Error::NotFound {
+            msg: format!(
+                "Chunk {} not found; the export has {} chunks",
+                chunk_index, total_chunks
+            ),
+        });
+    }
+    let chunk: Vec<ArchivedComment> = ids
+        .chunks(COMMENTS_PER_CHUNK)
+        .nth(chunk_index as usize)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|id| get(*id))
+        .map(|comment| ArchivedComment {
+            id: comment.id,
+            parent_comment_id: comment.parent_comment_id,
+            author: comment.author.to_text(),
+            author_name: comment.author_name,
+            content: comment.content,
+            created_at: comment.created_at,
+            hidden: comment.hidden,
+        })
+        .collect();
+    Ok(CommentChunk {
+        data: serde_json::to_string(&chunk).expect("Cannot encode comments"),
+        chunk_index,
+        total_chunks,
+    })
+}
+
+// Update function to delete a comment, by its author or a moderator
+#[ic_cdk::update]
+fn delete_comment(id: u64) -> Result<Comment, Error> {
+    let Some(comment) = get(id) else {
+        return Err(Error::NotFound {
+            msg: format!("Comment with ID {} not found", id),
+        });
+    };
+    if !linking::same_person(&comment.author, &caller()) && !auth::is_moderator() {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to delete comment with id={}.", id),
+        });
+    }
+    sessions::require_trusted_origin()?;
+    remove(&comment);
+    sessions::record(WriteAction::DeleteComment, id);
+    audit::record(AuditAction::DeleteComment, id);
+    Ok(comment)
+}
+
+// Query function for users to revisit their comments, newest first. Comments
+// hidden by a moderator are listed too, marked as hidden.
+#[ic_cdk::query]
+fn get_my_comments(page: Pagination) -> Result<Vec<Comment>, Error> {
+    auth::require_signed_in()?;
+    let author = linking::primary(&caller());
+    let ids: Vec<u64> = AUTHOR_COMMENTS.with(|index| {
+        index
+            .borrow()
+            .range(AuthorComment::all_of(&author))
+            .rev()
+            .skip(page.offset as usize)
+            .take(page.limit.min(MAX_COMMENTS_PAGE) as usize)
+            .map(|(key, _)| key.id)
+            .collect()
+    });
+    Ok(ids.into_iter().filter_map(get).collect())
+}
@@ -0,0 +1,513 @@
+use crate::home_feed::FeedAlgorithm;
+use crate::memory::{self, Memory};
+use crate::rate_limits::{self, RateLimits};
+use crate::{auth, linking, maintenance, render, scheduling, BlogPost, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Bounds on the limits admins may set; messages and stored posts can't grow
+// past these
+const MAX_TITLE_LEN: u32 = 1_000;
+const MAX_CONTENT_LEN: u32 = 1_000_000;
+const MAX_CATEGORIES: u32 = 100;
+// Comments are also held to `comments::MAX_COMMENT_LEN` bytes
+const MAX_COMMENT_LEN: u32 = 5_000;
+const MAX_COMMENT_LINKS: u32 = 50;
+// Most principals that may keep publishing during a freeze
+const MAX_FREEZE_ALLOWLIST: usize = 100;
+// Longest time between maintenance runs, in hours
+const MAX_MAINTENANCE_INTERVAL_HOURS: u32 = 7 * 24;
+// Bounds on the inactivity policy
+const MAX_INACTIVE_MONTHS: u32 = 10 * 12;
+const MAX_GRACE_DAYS: u32 = 90;
+
+// Whether posts that have likes may be deleted, by their author or in bulk
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum LikedPostDeletion {
+    Refuse,
+    Allow,
+}
+
+// A period during which posts can't be published or edited, e.g. around an
+// election or announcement. Times are in nanoseconds since the epoch.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct FreezeWindow {
+    start: u64,
+    end: u64,
+    // principals that may still publish and edit, e.g. the announcers
+    allowlist: Vec<Principal>,
+}
+
+// Which IDs posts are shown with. Sequential IDs give away how many posts
+// there are and in which order they were written; opaque ones don't.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum IdExposure {
+    Sequential,
+    Opaque,
+}
+
+// Limits on comments, which are short and read under someone else's post, so
+// they are held to much less than posts
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct CommentLimits {
+    // in characters
+    pub(crate) max_len: u32,
+    // Markdown links, autolinks and URLs written out in the text
+    pub(crate) max_links: u32,
+    // whether comments are rendered with the Markdown subset comments may
+    // use, see `render::comment_to_html`; they are plain text otherwise
+    pub(crate) formatting: bool,
+}
+
+impl Default for CommentLimits {
+    fn default() -> Self {
+        CommentLimits {
+            max_len: MAX_COMMENT_LEN,
+            max_links: 5,
+            formatting: true,
+        }
+    }
+}
+
+// When the background cleanup runs and what it removes
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MaintenanceSchedule {
+    pub(crate) interval_hours: u32,
+    // drafts not saved for this many days are deleted
+    pub(crate) stale_draft_days: u32,
+    // writers are reminded once of drafts not saved for this many days,
+    // unless they opted out; none sends no reminders
+    pub(crate) draft_reminder_days: Option<u32>,
+}
+
+// When published posts nobody viewed or liked are archived, see `inactivity`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct InactivityPolicy {
+    // posts created this many months (of 30 days) ago qualify
+    pub(crate) months: u32,
+    // days between the author's notification and the archival
+    pub(crate) grace_days: u32,
+}
+
+// Policies admins can change without an upgrade. Lengths are in characters.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Config {
+    pub(crate) max_title_len: u32,
+    pub(crate) max_content_len: u32,
+    pub(crate) max_categories: u32,
+    // when off, reading posts and comments takes a signed-in caller, and the
+    // HTTP pages and feeds are turned off
+    pub(crate) allow_anonymous_reads: bool,
+    pub(crate) rate_limits: RateLimits,
+    liked_post_deletion: LikedPostDeletion,
+    // set with `set_freeze_window`
+    freeze_window: Option<FreezeWindow>,
+    // set with `set_maintenance`; nothing is cleaned up while unset
+    pub(crate) maintenance: Option<MaintenanceSchedule>,
+    // cycle balance below which writes are refused, see `cycles`; none when
+    // unset
+    pub(crate) min_cycles: Option<u128>,
+    // none is `Sequential`, which configs saved before the policy existed use
+    pub(crate) id_exposure: Option<IdExposure>,
+    // set with `set_comment_limits`; none is the default limits
+    pub(crate) comment_limits: Option<CommentLimits>,
+    // whether posts are rendered to HTML when saved, see `rendered`; none is
+    // off
+    pub(crate) store_rendered_html: Option<bool>,
+    // ranking of `get_feed` when callers don't pick one; none is
+    // `Chronological`
+    pub(crate) default_feed: Option<FeedAlgorithm>,
+    // set with `set_inactivity_policy`; nothing is archived for inactivity
+    // while unset
+    pub(crate) inactivity: Option<InactivityPolicy>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_title_len: 300,
+            max_content_len: 100_000,
+            max_categories: 10,
+            allow_anonymous_reads: true,
+            // keep the limits admins set before they moved here
+            rate_limits: rate_limits::legacy_limits(),
+            liked_post_deletion: LikedPostDeletion::Refuse,
+            freeze_window: None,
+            maintenance: None,
+            min_cycles: None,
+            id_exposure: None,
+            comment_limits: None,
+            store_rendered_html: None,
+            default_feed: None,
+            inactivity: None,
+        }
+    }
+}
+
+impl Storable for Config {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Payload of `update_config`; fields left out keep their current value
+#[derive(candid::CandidType, Serialize, Deserialize, Default)]
+pub(crate) struct ConfigPayload {
+    max_title_len: Option<u32>,
+    max_content_len: Option<u32>,
+    max_categories: Option<u32>,
+    allow_anonymous_reads: Option<bool>,
+    rate_limits: Option<RateLimits>,
+    liked_post_deletion: Option<LikedPostDeletion>,
+    // 0 turns the threshold off
+    min_cycles: Option<u128>,
+    id_exposure: Option<IdExposure>,
+    store_rendered_html: Option<bool>,
+    default_feed: Option<FeedAlgorithm>,
+}
+
+thread_local! {
+    static CONFIG: RefCell<Cell<Config, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::CONFIG_MEMORY), Config::default())
+            .expect("Cannot create the config cell")
+    );
+}
+
+pub(crate) fn get() -> Config {
+    CONFIG.with(|cell| cell.borrow().get().clone())
+}
+
+pub(crate) fn save(config: Config) {
+    CONFIG.with(|cell| {
+        cell.borrow_mut()
+            .set(config)
+            .expect("Cannot write the config")
+    });
+}
+
+// Refuse a title, content or categories longer than the configured limits,
+// or content that renders to too much HTML; leave out the parts that don't
+// change
+pub(crate) fn check_post(
+    title: Option<&str>,
+    content: Option<&str>,
+    categories: Option<&[String]>,
+) -> Result<(), Error> {
+    let config = get();
+    let too_long =
+        |text: Option<&str>, max: u32| text.is_some_and(|text| text.chars().count() > max as usize);
+    if too_long(title, config.max_title_len) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Titles can be at most {} characters", config.max_title_len),
+        });
+    }
+    if too_long(content, config.max_content_len) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Content can be at most {} characters",
+                config.max_content_len
+            ),
+        });
+    }
+    if categories.is_some_and(|categories| categories.len() > config.max_categories as usize) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Posts can have at most {} categories",
+                config.max_categories
+            ),
+        });
+    }
+    if let Some(content) = content {
+        render::check_rendered_size(content)?;
+    }
+    Ok(())
+}
+
+// Why a stored post is past the bounds no configured limit may exceed, if it
+// is
+pub(crate) fn out_of_bounds(blog_post: &BlogPost) -> Option<String> {
+    let title_len = blog_post.title.chars().count();
+    let content_len = blog_post.content.chars().count();
+    if title_len > MAX_TITLE_LEN as usize {
+        Some(format!(
+            "Title of {} characters, at most {} allowed",
+            title_len, MAX_TITLE_LEN
+        ))
+    } else if content_len > MAX_CONTENT_LEN as usize {
+        Some(format!(
+            "Content of {} characters, at most {} allowed",
+            content_len, MAX_CONTENT_LEN
+        ))
+    } else if blog_post.categories.len() > MAX_CATEGORIES as usize {
+        Some(format!(
+            "{} categories, at most {} allowed",
+            blog_post.categories.len(),
+            MAX_CATEGORIES
+        ))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn comment_limits() -> CommentLimits {
+    get().comment_limits.unwrap_or_default()
+}
+
+// Refuse a comment longer than the comment limits allow, with more links than
+// they allow, or, when comments are formatted, with Markdown outside the
+// subset comments may use
+pub(crate) fn check_comment(content: &str) -> Result<(), Error> {
+    let limits = comment_limits();
+    if content.chars().count() > limits.max_len as usize {
+        return Err(Error::CommentTooLong {
+            msg: format!("Comments can be at most {} characters", limits.max_len),
+            max_len: limits.max_len,
+        });
+    }
+    if render::comment_links(content) > limits.max_links as usize {
+        return Err(Error::TooManyLinks {
+            msg: format!("Comments can have at most {} links", limits.max_links),
+            max_links: limits.max_links,
+        });
+    }
+    if limits.formatting {
+        if let Some(construct) = render::unsupported_comment_formatting(content) {
+            return Err(Error::UnsupportedFormatting {
+                msg: format!(
+                    "Comments can't use {}; they may use emphasis, code, links, quotes and lists",
+                    construct
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Whether the caller may read content under the configured policy
+pub(crate) fn can_read() -> bool {
+    get().allow_anonymous_reads || caller() != Principal::anonymous()
+}
+
+// Guard for query endpoints that return posts or comments
+pub(crate) fn require_reader() -> Result<(), Error> {
+    if !can_read() {
+        return Err(Error::AnonymousNotAllowed {
+            msg: "Sign in to read this blog.".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// End of the freeze window `principal` is held by at `now`, if any. The
+// canister itself, publishing scheduled posts, is never allowlisted.
+pub(crate) fn frozen_until(principal: &Principal, now: u64) -> Option<u64> {
+    let window = get().freeze_window?;
+    if now < window.start || now >= window.end {
+        return None;
+    }
+    let allowed = window
+        .allowlist
+        .iter()
+        .any(|allowed| linking::same_person(allowed, principal));
+    (!allowed).then_some(window.end)
+}
+
+// Guard for endpoints that publish or edit posts
+pub(crate) fn require_not_frozen() -> Result<(), Error> {
+    match frozen_until(&caller(), time()) {
+        Some(until) => Err(Error::ContentFrozen {
+            msg: "Publishing and editing are paused for now.".to_string(),
+            until,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Refuse to delete a post that has likes unless the policy allows it
+pub(crate) fn check_deletable(blog_post: &BlogPost) -> Result<(), Error> {
+    if blog_post.likes > 0 && get().liked_post_deletion == LikedPostDeletion::Refuse {
+        return Err(Error::HasLikes {
+            msg: format!(
+                "Blog post with ID {} has likes. Cannot delete.",
+                blog_post.id
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<(), Error> {
+    let in_range = |value: u32, min: u32, max: u32| (min..=max).contains(&value);
+    if !in_range(config.max_title_len, 1, MAX_TITLE_LEN)
+        || !in_range(config.max_content_len, 5, MAX_CONTENT_LEN)
+        || !in_range(config.max_categories, 0, MAX_CATEGORIES)
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Titles can be limited to 1 to {} characters, content to 5 to {} and categories to at most {}",
+                MAX_TITLE_LEN, MAX_CONTENT_LEN, MAX_CATEGORIES
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Query function to get the canister configuration
+#[ic_cdk::query]
+fn get_config() -> Config {
+    get()
+}
+
+// Update function for admins to change some of the configuration. Posts that
+// break new limits are kept as they are until they are next edited.
+#[ic_cdk::update]
+fn update_config(payload: ConfigPayload) -> Result<Config, Error> {
+    auth::require_admin()?;
+    let mut config = get();
+    if let Some(max_title_len) = payload.max_title_len {
+        config.max_title_len = max_title_len;
+    }
+    if let Some(max_content_len) = payload.max_content_len {
+        config.max_content_len = max_content_len;
+    }
+    if let Some(max_categories) = payload.max_categories {
+        config.max_categories = max_categories;
+    }
+    if let Some(allow_anonymous_reads) = payload.allow_anonymous_reads {
+        config.allow_anonymous_reads = allow_anonymous_reads;
+    }
+    if let Some(rate_limits) = payload.rate_limits {
+        config.rate_limits = rate_limits;
+    }
+    if let Some(liked_post_deletion) = payload.liked_post_deletion {
+        config.liked_post_deletion = liked_post_deletion;
+    }
+    if let Some(min_cycles) = payload.min_cycles {
+        config.min_cycles = (min_cycles > 0).then_some(min_cycles);
+    }
+    if let Some(id_exposure) = payload.id_exposure {
+        config.id_exposure = Some(id_exposure);
+    }
+    if let Some(store_rendered_html) = payload.store_rendered_html {
+        config.store_rendered_html = Some(store_rendered_html);
+    }
+    if let Some(default_feed) = payload.default_feed {
+        config.default_feed = Some(default_feed);
+    }
+    validate(&config)?;
+    save(config.clone());
+    Ok(config)
+}
+
+// Update function for admins to change the limits new comments are held to.
+// Existing comments are kept as they are, and are rendered with the new
+// `formatting`.
+#[ic_cdk::update]
+fn set_comment_limits(limits: CommentLimits) -> Result<Config, Error> {
+    auth::require_admin()?;
+    if !(1..=MAX_COMMENT_LEN).contains(&limits.max_len) || limits.max_links > MAX_COMMENT_LINKS {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Comments can be limited to 1 to {} characters and at most {} links",
+                MAX_COMMENT_LEN, MAX_COMMENT_LINKS
+            ),
+        });
+    }
+    let mut config = get();
+    config.comment_limits = Some(limits);
+    save(config.clone());
+    Ok(config)
+}
+
+// Update function for admins to pause publishing and editing between two
+// times, or lift the freeze with `null`. Scheduled posts due during the
+// freeze are published when it ends.
+#[ic_cdk::update]
+fn set_freeze_window(freeze_window: Option<FreezeWindow>) -> Result<Config, Error> {
+    auth::require_admin()?;
+    if let Some(window) = &freeze_window {
+        if window.start >= window.end || window.allowlist.len() > MAX_FREEZE_ALLOWLIST {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Freeze windows must end after they start and allow at most {} principals",
+                    MAX_FREEZE_ALLOWLIST
+                ),
+            });
+        }
+    }
+    let mut config = get();
+    config.freeze_window = freeze_window;
+    save(config.clone());
+    scheduling::arm();
+    Ok(config)
+}
+
+// Update function for admins to run the background cleanup every
+// `interval_hours`, or stop it with `null`. Each run deletes the drafts that
+// weren't saved for `stale_draft_days`, and uploads that were never
+// finalized, sends the draft reminders that are due and applies the
+// inactivity policy.
+#[ic_cdk::update]
+fn set_maintenance(schedule: Option<MaintenanceSchedule>) -> Result<Config, Error> {
+    auth::require_admin()?;
+    if let Some(schedule) = &schedule {
+        if !(1..=MAX_MAINTENANCE_INTERVAL_HOURS).contains(&schedule.interval_hours)
+            || schedule.stale_draft_days == 0
+        {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Maintenance runs every 1 to {} hours and keeps drafts for at least a day",
+                    MAX_MAINTENANCE_INTERVAL_HOURS
+                ),
+            });
+        }
+        if schedule
+            .draft_reminder_days
+            .is_some_and(|days| days == 0 || days >= schedule.stale_draft_days)
+        {
+            return Err(Error::ValidationErrors {
+                errors: "Draft reminders are sent after at least a day, before drafts are deleted"
+                    .to_string(),
+            });
+        }
+    }
+    let mut config = get();
+    config.maintenance = schedule;
+    save(config.clone());
+    maintenance::start();
+    Ok(config)
+}
+
+// Update function for admins to have published posts that were never viewed
+// or liked archived once they are `months` old, `grace_days` after their
+// author is told, or to stop with `null`. Applied by the background cleanup,
+// so it needs `set_maintenance` too.
+#[ic_cdk::update]
+fn set_inactivity_policy(policy: Option<InactivityPolicy>) -> Result<Config, Error> {
+    auth::require_admin()?;
+    if let Some(policy) = &policy {
+        if !(1..=MAX_INACTIVE_MONTHS).contains(&policy.months)
+            || !(1..=MAX_GRACE_DAYS).contains(&policy.grace_days)
+        {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Posts can be archived after 1 to {} months of inactivity, with a grace period of 1 to {} days",
+                    MAX_INACTIVE_MONTHS, MAX_GRACE_DAYS
+                ),
+            });
+        }
+    }
+    let mut config = get();
+    config.inactivity = policy;
+    save(config.clone());
+    Ok(config)
+}
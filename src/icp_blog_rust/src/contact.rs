@@ -0,0 +1,280 @@
+use crate::memory::{self, Memory};
+use crate::{auth, blocklist, humanity, linking, rate_limits, webhooks, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest subject, message and reply address, in bytes
+const MAX_SUBJECT_LEN: usize = 200;
+const MAX_MESSAGE_LEN: usize = 5000;
+const MAX_REPLY_TO_LEN: usize = 200;
+// Most links a message may have; more is taken for spam
+const MAX_MESSAGE_LINKS: usize = 3;
+// Most messages the inbox holds; new ones are refused until admins delete
+// some
+const MAX_INBOX: u64 = 10_000;
+// Largest page `list_messages` returns
+const MAX_MESSAGES_PAGE: u64 = 100;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContactMessage {
+    id: u64,
+    sender: Principal,
+    subject: String,
+    message: String,
+    // how the sender wants to be answered, such as an email address
+    reply_to: Option<String>,
+    received_at: u64,
+    // set by `mark_handled`
+    handled_at: Option<u64>,
+}
+
+impl Storable for ContactMessage {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ContactPayload {
+    subject: String,
+    message: String,
+    reply_to: Option<String>,
+}
+
+// Canister method every new message is passed on to, as its only argument
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ContactForwarding {
+    canister: Principal,
+    method: String,
+}
+
+#[derive(candid::CandidType, Default, Serialize, Deserialize)]
+struct Forwarding(Option<ContactForwarding>);
+
+impl Storable for Forwarding {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static CONTACT_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::CONTACT_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a contact message counter")
+    );
+
+    // id -> message, oldest first
+    static MESSAGES: RefCell<StableBTreeMap<u64, ContactMessage, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CONTACT_MESSAGES_MEMORY)));
+
+    // set with `set_contact_forwarding`; messages stay in the inbox either way
+    static FORWARDING: RefCell<Cell<Forwarding, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::CONTACT_FORWARDING_MEMORY), Forwarding::default())
+            .expect("Cannot create the contact forwarding cell")
+    );
+}
+
+fn not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Contact message with ID {} not found", id),
+    }
+}
+
+fn validate(payload: &ContactPayload) -> Result<(), Error> {
+    let too_long = |text: &str, max: usize| text.trim().is_empty() || text.len() > max;
+    if too_long(&payload.subject, MAX_SUBJECT_LEN) || too_long(&payload.message, MAX_MESSAGE_LEN) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Subjects must be between 1 and {} bytes long and messages between 1 and {}",
+                MAX_SUBJECT_LEN, MAX_MESSAGE_LEN
+            ),
+        });
+    }
+    if payload
+        .reply_to
+        .as_ref()
+        .is_some_and(|reply_to| too_long(reply_to, MAX_REPLY_TO_LEN))
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Reply addresses must be between 1 and {} bytes long",
+                MAX_REPLY_TO_LEN
+            ),
+        });
+    }
+    let links =
+        payload.message.matches("http://").count() + payload.message.matches("https://").count();
+    if links > MAX_MESSAGE_LINKS {
+        return Err(Error::ValidationErrors {
+            errors: format!("Messages can have at most {} links", MAX_MESSAGE_LINKS),
+        });
+    }
+    Ok(())
+}
+
+// Pass a new message on to the forwarding canister with a one-way call sent
+// after the current message commits. A canister that can't be reached misses
+// it; the message stays in the inbox.
+fn forward(message: &ContactMessage) {
+    let Some(forwarding) = FORWARDING.with(|cell| cell.borrow().get().0.clone()) else {
+        return;
+    };
+    if let Err(code) = ic_cdk::notify(forwarding.canister, &forwarding.method, (message.clone(),)) {
+        ic_cdk::println!(
+            "Cannot forward contact message {} to {}: {:?}",
+            message.id,
+            forwarding.canister,
+            code
+        );
+    }
+}
+
+// Drop up to `limit` messages `principal` sent, for `erasure`
+pub(crate) fn remove_by_sender(principal: &Principal, limit: usize) -> usize {
+    let ids: Vec<u64> = MESSAGES.with(|messages| {
+        messages
+            .borrow()
+            .iter()
+            .filter(|(_, message)| message.sender == *principal)
+            .map(|(id, _)| id)
+            .take(limit)
+            .collect()
+    });
+    MESSAGES.with(|messages| {
+        let mut messages = messages.borrow_mut();
+        for id in &ids {
+            messages.remove(id);
+        }
+    });
+    ids.len()
+}
+
+// Update function for readers to write to the blog's owners. Messages are
+// screened like comments: blocked terms are refused or masked and a message
+// with many links is taken for spam.
+#[ic_cdk::update]
+fn submit_contact_message(payload: ContactPayload) -> Result<u64, Error> {
+    auth::require_signed_in()?;
+    humanity::require_human()?;
+    validate(&payload)?;
+    let (subject, _) = blocklist::screen(payload.subject)?;
+    let (message, _) = blocklist::screen(payload.message)?;
+    if MESSAGES.with(|messages| messages.borrow().len()) >= MAX_INBOX {
+        return Err(Error::ValidationErrors {
+            errors: "The inbox is full; try again later".to_string(),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Contact)?;
+
+    let id = CONTACT_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the contact message counter");
+        id
+    });
+    let message = ContactMessage {
+        id,
+        sender: linking::primary(&caller()),
+        subject,
+        message,
+        reply_to: payload.reply_to,
+        received_at: time(),
+        handled_at: None,
+    };
+    MESSAGES.with(|messages| messages.borrow_mut().insert(id, message.clone()));
+    forward(&message);
+    Ok(id)
+}
+
+// Query function for admins to page through the inbox, newest first,
+// optionally only the messages not handled yet
+#[ic_cdk::query]
+fn list_messages(
+    offset: u64,
+    limit: u64,
+    unhandled_only: bool,
+) -> Result<Vec<ContactMessage>, Error> {
+    auth::require_admin()?;
+    let limit = limit.min(MAX_MESSAGES_PAGE) as usize;
+    Ok(MESSAGES.with(|messages| {
+        messages
+            .borrow()
+            .iter()
+            .rev()
+            .map(|(_, message)| message)
+            .filter(|message| !unhandled_only || message.handled_at.is_none())
+            .skip(offset as usize)
+            .take(limit)
+            .collect()
+    }))
+}
+
+// Update function for admins to note that a message was dealt with
+#[ic_cdk::update]
+fn mark_handled(id: u64) -> Result<ContactMessage, Error> {
+    auth::require_admin()?;
+    let mut message = MESSAGES
+        .with(|messages| messages.borrow().get(&id))
+        .ok_or_else(|| not_found(id))?;
+    if message.handled_at.is_none() {
+        message.handled_at = Some(time());
+        MESSAGES.with(|messages| messages.borrow_mut().insert(id, message.clone()));
+    }
+    Ok(message)
+}
+
+// Update function for admins to remove a message from the inbox
+#[ic_cdk::update]
+fn delete_message(id: u64) -> Result<(), Error> {
+    auth::require_admin()?;
+    match MESSAGES.with(|messages| messages.borrow_mut().remove(&id)) {
+        Some(_) => Ok(()),
+        None => Err(not_found(id)),
+    }
+}
+
+// Query function for admins to see where new messages are forwarded
+#[ic_cdk::query]
+fn get_contact_forwarding() -> Result<Option<ContactForwarding>, Error> {
+    auth::require_admin()?;
+    Ok(FORWARDING.with(|cell| cell.borrow().get().0.clone()))
+}
+
+// Update function for admins to have every new message passed on to a
+// canister method, or to stop it
+#[ic_cdk::update]
+fn set_contact_forwarding(forwarding: Option<ContactForwarding>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if let Some(forwarding) = &forwarding {
+        if !webhooks::is_canister(&forwarding.canister)
+            || !webhooks::is_method_name(&forwarding.method)
+        {
+            return Err(Error::ValidationErrors {
+                errors: "Messages can only be forwarded to a canister method".to_string(),
+            });
+        }
+    }
+    FORWARDING.with(|cell| {
+        cell.borrow_mut()
+            .set(Forwarding(forwarding))
+            .expect("Cannot write the contact forwarding")
+    });
+    Ok(())
+}
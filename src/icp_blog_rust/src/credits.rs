@@ -0,0 +1,109 @@
+use crate::audit::{self, AuditAction};
+use crate::render::escape;
+use crate::{
+    _check_if_owner, _get_blog_post, asset_sync, auth, do_insert, linking, profiles, BlogPost,
+    Error, PostStatus,
+};
+use candid::Principal;
+
+// Most credits a post may list
+const MAX_CREDITS: usize = 20;
+// Longest accepted role and name, in bytes
+const MAX_ROLE_LEN: usize = 50;
+const MAX_NAME_LEN: usize = 100;
+
+// Someone who contributed to a post, such as its editor, illustrator or
+// translator. Credits don't let anyone change the post, though credited
+// principals may propose changes for the author to review.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Credit {
+    role: String,
+    // the contributor's principal, their name, or both
+    principal: Option<Principal>,
+    name: Option<String>,
+}
+
+impl Credit {
+    // Name shown for the contributor: the given one, else the display name
+    // of their profile, else their principal
+    fn display_name(&self) -> String {
+        self.name
+            .clone()
+            .or_else(|| self.principal.as_ref().and_then(profiles::display_name))
+            .or_else(|| self.principal.map(|principal| principal.to_string()))
+            .unwrap_or_default()
+    }
+}
+
+// Whether a principal, or one linked to it, is credited on a post
+pub(crate) fn is_credited(blog_post: &BlogPost, principal: &Principal) -> bool {
+    blog_post.credits.iter().any(|credit| {
+        credit
+            .principal
+            .is_some_and(|credited| linking::same_person(&credited, principal))
+    })
+}
+
+fn validate(credits: &[Credit]) -> Result<(), Error> {
+    if credits.len() > MAX_CREDITS {
+        return Err(Error::ValidationErrors {
+            errors: format!("Posts can list at most {} credits", MAX_CREDITS),
+        });
+    }
+    for credit in credits {
+        let name_ok = match &credit.name {
+            Some(name) => !name.trim().is_empty() && name.len() <= MAX_NAME_LEN,
+            None => credit.principal.is_some(),
+        };
+        if credit.role.trim().is_empty() || credit.role.len() > MAX_ROLE_LEN || !name_ok {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Credits need a role of up to {} bytes and a principal or a name of up to {} bytes",
+                    MAX_ROLE_LEN, MAX_NAME_LEN
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// The credits of a post as HTML, empty if it has none
+pub(crate) fn to_html(credits: &[Credit]) -> String {
+    if credits.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from(r#"<ul class="credits">"#);
+    for credit in credits {
+        html.push_str(&format!(
+            "<li>{}: {}</li>",
+            escape(&credit.role),
+            escape(&credit.display_name())
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+// Update function for the author to replace the credits of a post
+#[ic_cdk::update]
+fn set_post_credits(id: u64, credits: Vec<Credit>) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to change credits of post with id={}.", id),
+        });
+    }
+    validate(&credits)?;
+    blog_post.credits = credits;
+    do_insert(&blog_post);
+    audit::record(AuditAction::UpdatePost, id);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    Ok(blog_post)
+}
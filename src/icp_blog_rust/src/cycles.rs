@@ -0,0 +1,40 @@
+use crate::audit::{self, AuditAction};
+use crate::{config, Error};
+use ic_cdk::api::{canister_balance128, data_certificate};
+use std::cell::Cell;
+
+thread_local! {
+    // Whether the balance was below the threshold at the last write, so the
+    // audit log gets one entry each time it drops there
+    static LOW: Cell<bool> = const { Cell::new(false) };
+}
+
+// Guard for writes: refuse them while the cycle balance is below the
+// threshold admins set, so the canister turns read-only before it runs out
+// and freezes in the middle of a write. Admin endpoints stay open so the
+// threshold can be changed. Queries don't write and are always answered.
+pub(crate) fn require_cycles() -> Result<(), Error> {
+    // only non-replicated queries can see a data certificate
+    if data_certificate().is_some() {
+        return Ok(());
+    }
+    let Some(min_cycles) = config::get().min_cycles else {
+        return Ok(());
+    };
+    let balance = canister_balance128();
+    if balance >= min_cycles {
+        LOW.with(|low| low.set(false));
+        return Ok(());
+    }
+    if !LOW.with(|low| low.replace(true)) {
+        audit::on_behalf_of(ic_cdk::id(), || {
+            audit::record(
+                AuditAction::LowOnCycles,
+                u64::try_from(balance).unwrap_or(u64::MAX),
+            )
+        });
+    }
+    Err(Error::CanisterLowOnCycles {
+        msg: "The canister is low on cycles and read-only until it is topped up.".to_string(),
+    })
+}
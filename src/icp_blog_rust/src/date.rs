@@ -0,0 +1,129 @@
+// Calendar helpers for IC timestamps (nanoseconds since the Unix epoch, UTC)
+
+pub(crate) const NANOS_PER_SECOND: u64 = 1_000_000_000;
+pub(crate) const NANOS_PER_DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Days since the epoch of a timestamp
+pub(crate) fn day_number(nanos: u64) -> u64 {
+    nanos / NANOS_PER_DAY
+}
+
+// Months since January 1970 of a (year, month 1-12)
+pub(crate) fn month_number(year: u64, month: u32) -> u64 {
+    (year - 1970) * 12 + u64::from(month - 1)
+}
+
+// (year, month 1-12, day 1-31) of a day number, using Howard Hinnant's
+// civil_from_days algorithm
+pub(crate) fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// Day number of a (year, month 1-12, day 1-31) date on or after the epoch,
+// using Howard Hinnant's days_from_civil algorithm. Days past the end of the
+// month roll over into the next.
+pub(crate) fn days_from_civil(year: u64, month: u32, day: u32) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = u64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5 + u64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// RFC 822 date as used by RSS, e.g. "Sun, 05 Nov 2023 14:03:00 GMT"
+pub(crate) fn rfc822(nanos: u64) -> String {
+    let days = day_number(nanos);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = (nanos % NANOS_PER_DAY) / NANOS_PER_SECOND;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// ISO 8601 calendar date, e.g. "2023-11-05"
+pub(crate) fn iso_date(nanos: u64) -> String {
+    let (year, month, day) = civil_from_days(day_number(nanos));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// ISO 8601 UTC timestamp to the second, e.g. "2023-11-05T14:03:00Z"
+pub(crate) fn iso_datetime(nanos: u64) -> String {
+    let seconds = (nanos % NANOS_PER_DAY) / NANOS_PER_SECOND;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        iso_date(nanos),
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_from_days_starts_at_the_epoch() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(31), (1970, 2, 1));
+    }
+
+    #[test]
+    fn civil_from_days_handles_leap_years() {
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        // 2100 is not a leap year
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn month_number_counts_months_since_the_epoch() {
+        assert_eq!(month_number(1970, 1), 0);
+        assert_eq!(month_number(1971, 12), 23);
+    }
+
+    #[test]
+    fn days_from_civil_inverts_civil_from_days() {
+        for days in (0..200_000).step_by(7) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn days_from_civil_rolls_over_past_the_end_of_the_month() {
+        assert_eq!(days_from_civil(2023, 1, 32), days_from_civil(2023, 2, 1));
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        let nanos = (days_from_civil(2023, 11, 5) * 86_400 + 14 * 3600 + 3 * 60) * NANOS_PER_SECOND;
+        assert_eq!(rfc822(nanos), "Sun, 05 Nov 2023 14:03:00 GMT");
+        assert_eq!(iso_date(nanos), "2023-11-05");
+        assert_eq!(iso_datetime(nanos), "2023-11-05T14:03:00Z");
+    }
+}
@@ -0,0 +1,92 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, categories, config, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::Validate;
+
+// Settings an author's new posts start with, unless the payload of
+// `create_blog_post` says otherwise
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Validate)]
+pub(crate) struct AuthorDefaults {
+    // used when the payload has no categories
+    #[validate(custom = "categories::validate_categories")]
+    pub(crate) categories: Vec<String>,
+    // create posts as drafts rather than publishing them
+    pub(crate) draft: bool,
+    // license posts are shared under, such as "CC BY 4.0"
+    #[validate(length(min = 1, max = 100))]
+    pub(crate) license: Option<String>,
+    pub(crate) comments_enabled: bool,
+}
+
+// What authors who never set defaults get, which is how posts were created
+// before defaults existed
+impl Default for AuthorDefaults {
+    fn default() -> Self {
+        AuthorDefaults {
+            categories: Vec::new(),
+            draft: false,
+            license: None,
+            comments_enabled: true,
+        }
+    }
+}
+
+impl Storable for AuthorDefaults {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Author -> their defaults
+    static DEFAULTS: RefCell<StableBTreeMap<Blob<29>, AuthorDefaults, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUTHOR_DEFAULTS_MEMORY)));
+}
+
+fn key(principal: &Principal) -> Blob<29> {
+    principal_key(&linking::primary(principal))
+}
+
+// Defaults of the author behind a principal; linked principals share them
+pub(crate) fn of(principal: &Principal) -> AuthorDefaults {
+    DEFAULTS
+        .with(|defaults| defaults.borrow().get(&key(principal)))
+        .unwrap_or_default()
+}
+
+// Query function for authors to read the defaults their new posts start with
+#[ic_cdk::query]
+fn get_my_defaults() -> Result<AuthorDefaults, Error> {
+    auth::require_signed_in()?;
+    Ok(of(&caller()))
+}
+
+// Update function for authors to set the defaults their new posts start
+// with. Existing posts keep their settings.
+#[ic_cdk::update]
+fn update_my_defaults(payload: AuthorDefaults) -> Result<AuthorDefaults, Error> {
+    auth::require_signed_in()?;
+    if let Err(errors) = payload.validate() {
+        return Err(Error::ValidationErrors {
+            errors: errors.to_string(),
+        });
+    }
+    config::check_post(None, None, Some(&payload.categories))?;
+    DEFAULTS.with(|defaults| {
+        defaults
+            .borrow_mut()
+            .insert(key(&caller()), payload.clone())
+    });
+    Ok(payload)
+}
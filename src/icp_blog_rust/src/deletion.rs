@@ -0,0 +1,84 @@
+use crate::{_check_deletable, _delete_blog_post, comments, BlogPost, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+// How long a deletion token stays valid, in nanoseconds
+const TOKEN_TTL: u64 = 5 * 60 * 1_000_000_000;
+
+// A token handed out by `request_delete`
+struct PendingDeletion {
+    token: String,
+    expires_at: u64,
+}
+
+thread_local! {
+    // Tokens are short-lived, so they are kept on the heap; an upgrade simply
+    // means asking for a new one
+    static PENDING: RefCell<BTreeMap<(Principal, u64), PendingDeletion>> =
+        const { RefCell::new(BTreeMap::new()) };
+
+    static NONCE: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// Posts that were edited or commented on take two calls to delete
+pub(crate) fn needs_confirmation(blog_post: &BlogPost) -> bool {
+    blog_post.revision > 1 || comments::has_comments(blog_post.id)
+}
+
+// The token only guards against accidental or scripted single-call deletes;
+// it is bound to the caller, so it doesn't need to be unguessable
+fn new_token(principal: &Principal, post_id: u64, now: u64) -> String {
+    let nonce = NONCE.with(|nonce| {
+        let mut nonce = nonce.borrow_mut();
+        *nonce += 1;
+        *nonce
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(principal.as_slice());
+    hasher.update(post_id.to_be_bytes());
+    hasher.update(now.to_be_bytes());
+    hasher.update(nonce.to_be_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// Update function to start deleting a blog post. Returns a token to pass to
+// `confirm_delete` within five minutes.
+#[ic_cdk::update]
+fn request_delete(id: u64) -> Result<String, Error> {
+    _check_deletable(id)?;
+    let now = time();
+    let principal = caller();
+    let token = new_token(&principal, id, now);
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.retain(|_, deletion| deletion.expires_at > now);
+        pending.insert(
+            (principal, id),
+            PendingDeletion {
+                token: token.clone(),
+                expires_at: now + TOKEN_TTL,
+            },
+        );
+    });
+    Ok(token)
+}
+
+// Update function to delete a blog post with a token from `request_delete`
+#[ic_cdk::update]
+fn confirm_delete(id: u64, token: String) -> Result<BlogPost, Error> {
+    let blog_post = _check_deletable(id)?;
+    let pending = PENDING.with(|pending| pending.borrow_mut().remove(&(caller(), id)));
+    match pending {
+        Some(deletion) if deletion.token == token && deletion.expires_at > time() => {
+            Ok(_delete_blog_post(blog_post))
+        }
+        _ => Err(Error::ConfirmationRequired {
+            msg: format!(
+                "The deletion token for blog post with ID {} is invalid or expired. Call request_delete again.",
+                id
+            ),
+        }),
+    }
+}
@@ -0,0 +1,179 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{
+    _get_blog_post, auth, comments, config, date, profiles, timeline, visibility, BlogPost, Error,
+    PostStatus,
+};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::{cell::RefCell, collections::BTreeMap};
+
+// Longest period a digest covers; earlier starts are moved up to it
+const MAX_DIGEST_DAYS: u64 = 31;
+// Most new posts a digest lists
+const MAX_NEW_POSTS: usize = 50;
+// Posts a digest lists as its top posts
+const TOP_POSTS: usize = 5;
+// Largest page `list_blog_subscribers` returns
+const MAX_SUBSCRIBERS_PAGE: u64 = 100;
+
+// A post as summarized in a digest
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DigestPost {
+    id: u64,
+    title: String,
+    slug: String,
+    author: String,
+    author_name: Option<String>,
+    created_at: u64,
+    likes: u32,
+    // comments posted during the digest's period
+    new_comments: u64,
+}
+
+// What happened on the blog over a period, for newsletters
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Digest {
+    // start of the period, later than asked when that was over
+    // `MAX_DIGEST_DAYS` ago
+    since: u64,
+    until: u64,
+    // public posts created during the period, newest first
+    new_posts: Vec<DigestPost>,
+    // public posts that got the most comments during the period, the most
+    // liked first among equals
+    pub(crate) top_posts: Vec<DigestPost>,
+    // comments posted during the period on public posts, hidden ones left
+    // out
+    new_comments: u64,
+}
+
+thread_local! {
+    // Readers who want the digest -> when they subscribed
+    static SUBSCRIBERS: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::BLOG_SUBSCRIBERS_MEMORY)));
+}
+
+fn is_public(blog_post: &BlogPost) -> bool {
+    blog_post.status == PostStatus::Published && visibility::is_listed(blog_post)
+}
+
+fn summarize(blog_post: BlogPost, new_comments: u64) -> DigestPost {
+    let author_name = Principal::from_text(&blog_post.author)
+        .ok()
+        .and_then(|author| profiles::display_name(&author));
+    DigestPost {
+        id: blog_post.id,
+        title: blog_post.title,
+        slug: blog_post.slug,
+        author: blog_post.author,
+        author_name,
+        created_at: blog_post.created_at,
+        likes: blog_post.likes,
+        new_comments,
+    }
+}
+
+// Update function for readers to subscribe to the blog's digest
+#[ic_cdk::update]
+fn subscribe_to_blog() -> Result<(), Error> {
+    auth::require_signed_in()?;
+    SUBSCRIBERS.with(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        let key = principal_key(&caller());
+        if !subscribers.contains_key(&key) {
+            subscribers.insert(key, time());
+        }
+    });
+    Ok(())
+}
+
+// Update function for readers to stop getting the blog's digest
+#[ic_cdk::update]
+fn unsubscribe_from_blog() -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = principal_key(&caller());
+    match SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not subscribed to the blog", caller()),
+        }),
+    }
+}
+
+// Query function for admins to page through the readers subscribed to the
+// digest, for the app sending it
+#[ic_cdk::query]
+fn list_blog_subscribers(offset: u64, limit: u64) -> Result<Vec<Principal>, Error> {
+    auth::require_admin()?;
+    Ok(SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_SUBSCRIBERS_PAGE) as usize)
+            .map(|(key, _)| Principal::from_slice(key.as_slice()))
+            .collect()
+    }))
+}
+
+// Summary of the period from `since` to before `until`, for `get_digest`
+// and the monthly reports
+pub(crate) fn summarize_period(since: u64, until: u64) -> Digest {
+    // post id -> comments posted on it during the period
+    let mut commented: BTreeMap<u64, u64> = BTreeMap::new();
+    for post_id in comments::post_ids_between(since, until) {
+        *commented.entry(post_id).or_insert(0) += 1;
+    }
+    let new_comments_on = |post_id: u64| commented.get(&post_id).copied().unwrap_or(0);
+    let new_posts = timeline::newest_created_between(since, until.saturating_sub(1))
+        .into_iter()
+        .filter_map(|id| _get_blog_post(&id))
+        .filter(is_public)
+        .take(MAX_NEW_POSTS)
+        .map(|blog_post| {
+            let new_comments = new_comments_on(blog_post.id);
+            summarize(blog_post, new_comments)
+        })
+        .collect();
+    let mut discussed: Vec<BlogPost> = commented
+        .keys()
+        .filter_map(_get_blog_post)
+        .filter(is_public)
+        .collect();
+    let new_comments = discussed
+        .iter()
+        .map(|blog_post| new_comments_on(blog_post.id))
+        .sum();
+    discussed.sort_by_key(|blog_post| {
+        std::cmp::Reverse((new_comments_on(blog_post.id), blog_post.likes))
+    });
+    let top_posts = discussed
+        .into_iter()
+        .take(TOP_POSTS)
+        .map(|blog_post| {
+            let new_comments = new_comments_on(blog_post.id);
+            summarize(blog_post, new_comments)
+        })
+        .collect();
+    Digest {
+        since,
+        until,
+        new_posts,
+        top_posts,
+        new_comments,
+    }
+}
+
+// Query function to summarize what happened on the blog since a time, in
+// nanoseconds since the epoch: the new posts, the most discussed posts and
+// how many comments were posted
+#[ic_cdk::query]
+fn get_digest(since_timestamp: u64) -> Result<Digest, Error> {
+    config::require_reader()?;
+    let until = time();
+    let since = since_timestamp.max(until.saturating_sub(MAX_DIGEST_DAYS * date::NANOS_PER_DAY));
+    Ok(summarize_period(since, until))
+}
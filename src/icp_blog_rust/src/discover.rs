@@ -0,0 +1,87 @@
+use crate::{
+    _get_visible_blog_post, config, sponsorship, visibility, BlogPost, Error, PostStatus,
+    BLOG_POSTS,
+};
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, time::Duration};
+
+// How often a new random post is drawn
+const DRAW_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// Random IDs tried before settling for the next post after the last one
+const MAX_ATTEMPTS: u64 = 20;
+// Posts looked at after the last ID tried before giving up
+const MAX_SCANNED: usize = 200;
+
+thread_local! {
+    // Randomness drawn for the current interval. Nothing depends on it
+    // staying the same, so it lives on the heap and is drawn again after an
+    // upgrade.
+    static SEED: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+// Draw new randomness now and then every `DRAW_INTERVAL`. Timers don't
+// survive upgrades, so this also runs from `post_upgrade`.
+pub(crate) fn start() {
+    ic_cdk_timers::set_timer(Duration::ZERO, draw);
+    ic_cdk_timers::set_timer_interval(DRAW_INTERVAL, draw);
+}
+
+fn draw() {
+    ic_cdk::spawn(async {
+        match ic_cdk::api::management_canister::main::raw_rand().await {
+            Ok((bytes,)) => SEED.with(|seed| *seed.borrow_mut() = Some(bytes)),
+            Err((code, msg)) => ic_cdk::println!("Cannot draw randomness: {:?} {}", code, msg),
+        }
+    })
+}
+
+// Whether a post may be picked: published, listed and not hidden from the
+// caller
+fn is_candidate(blog_post: &BlogPost) -> bool {
+    blog_post.status == PostStatus::Published
+        && visibility::is_listed(blog_post)
+        && sponsorship::listed_for_caller(blog_post)
+}
+
+// Query function to get a random published post, for widgets that help
+// readers discover something. The same post is picked for everyone until the
+// randomness is drawn again, every hour: random IDs are tried until one is a
+// post that may be shown, and failing that the first such post after the last
+// ID tried is picked. That favours posts that follow gaps in the IDs, so the
+// pick is only close to even while most IDs are posts that may be shown.
+#[ic_cdk::query]
+fn get_random_post() -> Result<BlogPost, Error> {
+    config::require_reader()?;
+    let not_found = || Error::NotFound {
+        msg: "No post to pick from yet".to_string(),
+    };
+    let seed = SEED
+        .with(|seed| seed.borrow().clone())
+        .ok_or_else(not_found)?;
+    let max_id = BLOG_POSTS
+        .with(|posts| posts.borrow().last_key_value().map(|(id, _)| id))
+        .ok_or_else(not_found)?;
+    let mut candidate = 0;
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut hasher = Sha256::new();
+        hasher.update(&seed);
+        hasher.update(attempt.to_be_bytes());
+        let hash = hasher.finalize();
+        candidate = u64::from_be_bytes(hash[..8].try_into().unwrap()) % (max_id + 1);
+        if let Some(blog_post) = _get_visible_blog_post(&candidate).filter(is_candidate) {
+            return Ok(blog_post);
+        }
+    }
+    // few IDs are posts that may be shown: take the first one after the last
+    // ID tried
+    BLOG_POSTS
+        .with(|posts| {
+            let posts = posts.borrow();
+            posts
+                .range(candidate..)
+                .chain(posts.range(..candidate))
+                .take(MAX_SCANNED)
+                .find_map(|(id, _)| _get_visible_blog_post(&id).filter(is_candidate))
+        })
+        .ok_or_else(not_found)
+}
@@ -0,0 +1,265 @@
+use crate::keys::{principal_key, PrincipalIdKey};
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{auth, config, create_blog_post, linking, BlogPost, BlogPostPayload, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most drafts a writer may keep at once
+const MAX_DRAFTS_PER_USER: usize = 50;
+
+// An unfinished post, private to its writer. Drafts have their own IDs and
+// only take a post ID once published.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Draft {
+    id: u64,
+    title: String,
+    content: String,
+    categories: Vec<String>,
+    language: Option<String>,
+    created_at: u64,
+    saved_at: u64,
+    // when the writer was reminded the draft is going stale; saving it again
+    // clears this
+    reminded_at: Option<u64>,
+}
+
+impl Storable for Draft {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Payload of `save_draft`. Without `draft_id` a new draft is started. Drafts
+// may be incomplete; they are validated like any post when published.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DraftPayload {
+    draft_id: Option<u64>,
+    title: String,
+    content: String,
+    categories: Vec<String>,
+    language: Option<String>,
+}
+
+// Key of the drafts map: a writer and a draft id, so every writer's drafts
+// are one contiguous range
+type DraftKey = PrincipalIdKey;
+
+thread_local! {
+    static DRAFT_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::DRAFT_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a draft counter")
+    );
+
+    static DRAFTS: RefCell<StableBTreeMap<DraftKey, Draft, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::DRAFTS_MEMORY)));
+
+    // Writers who don't want to be reminded of stale drafts. Only writers who
+    // opted out have an entry.
+    static REMINDERS_OPTED_OUT: RefCell<StableBTreeMap<Blob<29>, bool, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::DRAFT_REMINDER_OPT_OUT_MEMORY)));
+}
+
+// Linked principals share their drafts, as they share their posts
+fn writer() -> Principal {
+    linking::primary(&caller())
+}
+
+fn my_drafts(writer: &Principal) -> Vec<Draft> {
+    DRAFTS.with(|drafts| {
+        drafts
+            .borrow()
+            .range(DraftKey::all_of(writer))
+            .map(|(_, draft)| draft)
+            .collect()
+    })
+}
+
+fn not_found(draft_id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Draft with ID {} not found", draft_id),
+    }
+}
+
+fn next_id() -> u64 {
+    DRAFT_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the draft counter");
+        id
+    })
+}
+
+// Delete up to `limit` drafts of any writer last saved before `saved_before`.
+// Returns how many were deleted.
+pub(crate) fn purge_stale(saved_before: u64, limit: usize) -> usize {
+    DRAFTS.with(|drafts| {
+        let mut drafts = drafts.borrow_mut();
+        let stale: Vec<DraftKey> = drafts
+            .iter()
+            .filter(|(_, draft)| draft.saved_at < saved_before)
+            .map(|(key, _)| key)
+            .take(limit)
+            .collect();
+        for key in &stale {
+            drafts.remove(key);
+        }
+        stale.len()
+    })
+}
+
+fn reminders_opted_out(writer: &Principal) -> bool {
+    REMINDERS_OPTED_OUT
+        .with(|opted_out| opted_out.borrow().get(&principal_key(writer)))
+        .unwrap_or(false)
+}
+
+// Notify the writers of up to `limit` drafts last saved before
+// `saved_before` that they weren't reminded of yet. Drafts of writers who
+// opted out are marked as reminded without a notification. Returns how many
+// drafts were handled.
+pub(crate) fn remind_stale(saved_before: u64, limit: usize) -> usize {
+    let stale: Vec<(DraftKey, Draft)> = DRAFTS.with(|drafts| {
+        drafts
+            .borrow()
+            .iter()
+            .filter(|(_, draft)| draft.saved_at < saved_before && draft.reminded_at.is_none())
+            .take(limit)
+            .collect()
+    });
+    let now = time();
+    for (key, mut draft) in stale.iter().cloned() {
+        let writer = key.principal();
+        if !reminders_opted_out(&writer) {
+            notifications::notify(&writer, NotificationKind::DraftStale { draft_id: draft.id });
+        }
+        draft.reminded_at = Some(now);
+        DRAFTS.with(|drafts| drafts.borrow_mut().insert(key, draft));
+    }
+    stale.len()
+}
+
+// Update function for writers to autosave a draft, starting a new one when
+// `draft_id` is left out
+#[ic_cdk::update]
+fn save_draft(payload: DraftPayload) -> Result<Draft, Error> {
+    auth::require_signed_in()?;
+    config::check_post(
+        Some(&payload.title),
+        Some(&payload.content),
+        Some(&payload.categories),
+    )?;
+    let writer = writer();
+    let now = time();
+    let (id, created_at) = match payload.draft_id {
+        Some(id) => {
+            let draft = DRAFTS
+                .with(|drafts| drafts.borrow().get(&DraftKey::new(&writer, id)))
+                .ok_or_else(|| not_found(id))?;
+            (id, draft.created_at)
+        }
+        None => {
+            if my_drafts(&writer).len() >= MAX_DRAFTS_PER_USER {
+                return Err(Error::ValidationErrors {
+                    errors: format!("Writers can keep at most {} drafts", MAX_DRAFTS_PER_USER),
+                });
+            }
+            (next_id(), now)
+        }
+    };
+    let draft = Draft {
+        id,
+        title: payload.title,
+        content: payload.content,
+        categories: payload.categories,
+        language: payload.language,
+        created_at,
+        saved_at: now,
+        reminded_at: None,
+    };
+    DRAFTS.with(|drafts| {
+        drafts
+            .borrow_mut()
+            .insert(DraftKey::new(&writer, id), draft.clone())
+    });
+    Ok(draft)
+}
+
+// Query function for writers to list their drafts, oldest first
+#[ic_cdk::query]
+fn get_my_drafts() -> Result<Vec<Draft>, Error> {
+    auth::require_signed_in()?;
+    Ok(my_drafts(&writer()))
+}
+
+// Update function for writers to publish a draft as a new post. The draft is
+// removed once the post is created.
+#[ic_cdk::update]
+fn publish_draft(draft_id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let key = DraftKey::new(&writer(), draft_id);
+    let draft = DRAFTS
+        .with(|drafts| drafts.borrow().get(&key))
+        .ok_or_else(|| not_found(draft_id))?;
+    let blog_post = create_blog_post(BlogPostPayload {
+        title: draft.title,
+        content: draft.content,
+        categories: draft.categories,
+        language: draft.language,
+        draft: None,
+        idempotency_key: None,
+        license: None,
+        comments_enabled: None,
+        visibility: None,
+        encrypted: None,
+    })?;
+    DRAFTS.with(|drafts| drafts.borrow_mut().remove(&key));
+    Ok(blog_post)
+}
+
+// Query function for writers to check whether they are reminded of stale
+// drafts
+#[ic_cdk::query]
+fn get_draft_reminders() -> bool {
+    !reminders_opted_out(&writer())
+}
+
+// Update function for writers to stop or resume reminders of drafts they
+// haven't saved in a while. Admins set after how long with
+// `set_maintenance`.
+#[ic_cdk::update]
+fn set_draft_reminders(enabled: bool) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = principal_key(&writer());
+    REMINDERS_OPTED_OUT.with(|opted_out| {
+        let mut opted_out = opted_out.borrow_mut();
+        if enabled {
+            opted_out.remove(&key);
+        } else {
+            opted_out.insert(key, true);
+        }
+    });
+    Ok(())
+}
+
+// Update function for writers to throw a draft away
+#[ic_cdk::update]
+fn delete_draft(draft_id: u64) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = DraftKey::new(&writer(), draft_id);
+    match DRAFTS.with(|drafts| drafts.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(not_found(draft_id)),
+    }
+}
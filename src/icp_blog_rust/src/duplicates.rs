@@ -0,0 +1,101 @@
+use crate::memory::{self, Memory};
+use crate::{_get_blog_post, auth, authors, Error};
+use candid::{Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most recent posts a guard may be set to compare against
+const MAX_RECENT_POSTS: u32 = 100;
+
+// How new posts are checked against their author's recent posts. A post whose
+// content matches one of them, ignoring surrounding whitespace, is rejected.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct DuplicateGuard {
+    enabled: bool,
+    recent_posts: u32,
+}
+
+impl Default for DuplicateGuard {
+    fn default() -> Self {
+        DuplicateGuard {
+            enabled: true,
+            recent_posts: 20,
+        }
+    }
+}
+
+impl Storable for DuplicateGuard {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static GUARD: RefCell<Cell<DuplicateGuard, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::DUPLICATE_GUARD_MEMORY), DuplicateGuard::default())
+            .expect("Cannot create the duplicate guard cell")
+    );
+}
+
+fn fingerprint(content: &str) -> [u8; 32] {
+    Sha256::digest(content.trim().as_bytes()).into()
+}
+
+// Refuse content the author already posted recently, pointing to the post
+// that has it
+pub(crate) fn check(author: &Principal, content: &str) -> Result<(), Error> {
+    let guard = GUARD.with(|cell| cell.borrow().get().clone());
+    if !guard.enabled {
+        return Ok(());
+    }
+    let new = fingerprint(content);
+    let existing = authors::newest_post_ids(author, guard.recent_posts as usize)
+        .into_iter()
+        .find(|id| _get_blog_post(id).is_some_and(|post| fingerprint(&post.content) == new));
+    match existing {
+        Some(existing_id) => Err(Error::DuplicatePost {
+            msg: format!(
+                "Caller already posted this content as blog post with ID {}",
+                existing_id
+            ),
+            existing_id,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Query function for admins to see how duplicate posts are detected
+#[ic_cdk::query]
+fn get_duplicate_guard() -> Result<DuplicateGuard, Error> {
+    auth::require_admin()?;
+    Ok(GUARD.with(|cell| cell.borrow().get().clone()))
+}
+
+// Update function for admins to turn the duplicate guard on or off, or change
+// how many recent posts it compares against
+#[ic_cdk::update]
+fn set_duplicate_guard(guard: DuplicateGuard) -> Result<(), Error> {
+    auth::require_admin()?;
+    if guard.recent_posts > MAX_RECENT_POSTS {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "The guard can compare against at most {} recent posts",
+                MAX_RECENT_POSTS
+            ),
+        });
+    }
+    GUARD.with(|cell| {
+        cell.borrow_mut()
+            .set(guard)
+            .expect("Cannot write the duplicate guard")
+    });
+    Ok(())
+}
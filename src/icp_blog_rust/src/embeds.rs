@@ -0,0 +1,280 @@
+use crate::memory::{self, Memory};
+use crate::render::escape;
+use crate::{auth, rendered, Error};
+use candid::{Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Sites whose players may be embedded in posts with an `<iframe>`
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum EmbedProvider {
+    YouTube,
+    Twitter,
+    CodePen,
+    IcDashboard,
+}
+
+impl EmbedProvider {
+    // Addresses the provider serves embeds from. An embed's `src` must start
+    // with one of them.
+    fn origins(self) -> &'static [&'static str] {
+        match self {
+            EmbedProvider::YouTube => &[
+                "https://www.youtube.com/embed/",
+                "https://www.youtube-nocookie.com/embed/",
+            ],
+            EmbedProvider::Twitter => &["https://platform.twitter.com/embed/"],
+            EmbedProvider::CodePen => &["https://codepen.io/"],
+            EmbedProvider::IcDashboard => &["https://dashboard.internetcomputer.org/"],
+        }
+    }
+
+    fn class(self) -> &'static str {
+        match self {
+            EmbedProvider::YouTube => "youtube",
+            EmbedProvider::Twitter => "twitter",
+            EmbedProvider::CodePen => "codepen",
+            EmbedProvider::IcDashboard => "ic-dashboard",
+        }
+    }
+}
+
+// Providers admins turned on; none are by default
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct EnabledProviders(Vec<EmbedProvider>);
+
+impl Storable for EnabledProviders {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static ENABLED: RefCell<Cell<EnabledProviders, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::EMBED_PROVIDERS_MEMORY), EnabledProviders::default())
+            .expect("Cannot create the embed providers cell")
+    );
+}
+
+pub(crate) fn enabled() -> Vec<EmbedProvider> {
+    ENABLED.with(|cell| cell.borrow().get().0.clone())
+}
+
+// Value of an attribute of a start tag, quoted with either kind of quote
+pub(crate) fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find(name) {
+        let start = from + found;
+        from = start + name.len();
+        // the name must be a whole attribute name
+        if !lower[..start].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let rest = lower[from..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let offset = tag.len() - value.len() + 1;
+        let end = tag[offset..].find(quote)?;
+        return Some(&tag[offset..offset + end]);
+    }
+    None
+}
+
+// A width or height, kept only when it is a plain number of pixels
+fn dimension(tag: &str, name: &str) -> Option<u32> {
+    attribute(tag, name)
+        .and_then(|value| value.parse().ok())
+        .filter(|&pixels| (1..=2000).contains(&pixels))
+}
+
+// The opening tag of raw embed HTML, without a `</iframe>` closing it on the
+// same line. `None` for a lone closing `</iframe>`.
+fn opening_tag(raw: &str) -> Option<&str> {
+    let tag = raw.trim();
+    if tag.eq_ignore_ascii_case("</iframe>") {
+        return None;
+    }
+    let lower = tag.to_ascii_lowercase();
+    Some(match lower.strip_suffix("</iframe>") {
+        Some(open) => &tag[..open.len()],
+        None => tag,
+    })
+}
+
+// The provider and address of the player an `<iframe>` tag embeds, if it is
+// one from an enabled provider
+fn player(tag: &str) -> Option<(EmbedProvider, String)> {
+    let lower = tag.to_ascii_lowercase();
+    let is_iframe = lower.starts_with("<iframe")
+        && lower[7..].starts_with(|c: char| c.is_whitespace() || c == '>')
+        && tag.ends_with('>')
+        && !tag[1..tag.len() - 1].contains(['<', '>']);
+    if !is_iframe {
+        return None;
+    }
+    let src = attribute(tag, "src")?.replace("&amp;", "&");
+    if src.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return None;
+    }
+    let provider = enabled().into_iter().find(|provider| {
+        provider
+            .origins()
+            .iter()
+            .any(|origin| src.starts_with(origin))
+    })?;
+    Some((provider, src))
+}
+
+// Rebuild raw HTML from a post that embeds a player from an enabled provider
+// as a known-safe `<iframe>`: only its address and size are kept, and the
+// frame is sandboxed. A closing `</iframe>` gives an empty string, since the
+// opening tag was already closed. Returns `None` for any other HTML, which is
+// then shown as text.
+pub(crate) fn to_html(raw: &str) -> Option<String> {
+    let Some(tag) = opening_tag(raw) else {
+        return Some(String::new());
+    };
+    let (provider, src) = player(tag)?;
+    let mut html = format!(
+        r#"<iframe class="embed embed-{}" src="{}""#,
+        provider.class(),
+        escape(&src)
+    );
+    if let Some(width) = dimension(tag, "width") {
+        html.push_str(&format!(r#" width="{}""#, width));
+    }
+    if let Some(height) = dimension(tag, "height") {
+        html.push_str(&format!(r#" height="{}""#, height));
+    }
+    html.push_str(concat!(
+        r#" sandbox="allow-scripts allow-same-origin allow-popups allow-presentation""#,
+        r#" referrerpolicy="strict-origin-when-cross-origin" loading="lazy""#,
+        r#" allowfullscreen="allowfullscreen"></iframe>"#
+    ));
+    Some(html)
+}
+
+// Like `to_html`, but the player becomes a plain link to it, for pages that
+// load nothing from other sites
+pub(crate) fn to_link(raw: &str) -> Option<String> {
+    let Some(tag) = opening_tag(raw) else {
+        return Some(String::new());
+    };
+    let (provider, src) = player(tag)?;
+    Some(format!(
+        r#"<a class="embed embed-{}" href="{}">{}</a>"#,
+        provider.class(),
+        escape(&src),
+        escape(&src)
+    ))
+}
+
+// Query function to list the providers posts may embed players from
+#[ic_cdk::query]
+fn get_embed_providers() -> Vec<EmbedProvider> {
+    enabled()
+}
+
+// Update function for admins to allow or refuse embeds from a provider. Posts
+// are rendered when they are served, so the change applies to existing posts
+// too.
+#[ic_cdk::update]
+fn set_embed_provider(provider: EmbedProvider, enabled: bool) -> Result<Vec<EmbedProvider>, Error> {
+    auth::require_admin()?;
+    let mut providers = self::enabled();
+    providers.retain(|&other| other != provider);
+    if enabled {
+        providers.push(provider);
+    }
+    ENABLED.with(|cell| {
+        cell.borrow_mut()
+            .set(EnabledProviders(providers.clone()))
+            .expect("Cannot write the embed providers")
+    });
+    rendered::invalidate();
+    Ok(providers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enable(providers: Vec<EmbedProvider>) {
+        ENABLED.with(|cell| {
+            cell.borrow_mut()
+                .set(EnabledProviders(providers))
+                .expect("Cannot write the embed providers")
+        });
+    }
+
+    const PLAYER: &str = r#"<iframe src="https://www.youtube.com/embed/abc?t=1&amp;x=2" width="560" height="99999" onload="x()"></iframe>"#;
+
+    #[test]
+    fn to_html_rebuilds_embeds_of_enabled_providers() {
+        enable(vec![EmbedProvider::YouTube]);
+        let html = to_html(PLAYER).unwrap();
+        assert!(html.starts_with(
+            r#"<iframe class="embed embed-youtube" src="https://www.youtube.com/embed/abc?t=1&amp;x=2" width="560" sandbox="#
+        ));
+        assert!(!html.contains("onload"));
+        assert!(!html.contains("99999"));
+    }
+
+    #[test]
+    fn to_html_refuses_providers_that_are_not_enabled() {
+        enable(Vec::new());
+        assert_eq!(to_html(PLAYER), None);
+        enable(vec![EmbedProvider::CodePen]);
+        assert_eq!(to_html(PLAYER), None);
+    }
+
+    #[test]
+    fn to_html_refuses_anything_but_a_lone_iframe() {
+        enable(vec![EmbedProvider::YouTube]);
+        assert_eq!(to_html("<b>bold</b>"), None);
+        assert_eq!(
+            to_html("<iframes src=\"https://www.youtube.com/embed/a\">"),
+            None
+        );
+        assert_eq!(
+            to_html(r#"<iframe src="https://www.youtube.com/embed/a"><script>"#),
+            None
+        );
+        assert_eq!(
+            to_html(r#"<iframe src="https://www.youtube.com/embed/a b">"#),
+            None
+        );
+        assert_eq!(to_html("</iframe>"), Some(String::new()));
+    }
+
+    #[test]
+    fn to_link_links_to_the_player_instead() {
+        enable(vec![EmbedProvider::YouTube]);
+        assert_eq!(
+            to_link(PLAYER).unwrap(),
+            concat!(
+                r#"<a class="embed embed-youtube" href="https://www.youtube.com/embed/abc?t=1&amp;x=2">"#,
+                r#"https://www.youtube.com/embed/abc?t=1&amp;x=2</a>"#
+            )
+        );
+        assert_eq!(to_link("<b>bold</b>"), None);
+    }
+
+    #[test]
+    fn attribute_matches_whole_names_only() {
+        let tag = r#"<iframe data-src="x" src='y'>"#;
+        assert_eq!(attribute(tag, "src"), Some("y"));
+        assert_eq!(attribute(tag, "width"), None);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::http::{self, HttpResponse};
+use crate::memory::{self, Memory};
+use crate::{auth, rendered, Error};
+use candid::{Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde_bytes::ByteBuf;
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest shortcode, without the colons
+const MAX_SHORTCODE_LEN: usize = 32;
+// Largest custom emoji image, in bytes
+const MAX_EMOJI_SIZE: usize = 64 * 1024;
+// Most custom emoji the blog may have
+const MAX_CUSTOM_EMOJI: u64 = 500;
+// Image types custom emoji may have. SVG is left out as it can carry scripts.
+const EMOJI_TYPES: [&str; 3] = ["image/png", "image/gif", "image/webp"];
+
+// Shortcodes every post and comment can use, expanded to Unicode emoji
+const BUILT_IN: [(&str, &str); 16] = [
+    ("+1", "\u{1F44D}"),
+    ("-1", "\u{1F44E}"),
+    ("clap", "\u{1F44F}"),
+    ("eyes", "\u{1F440}"),
+    ("fire", "\u{1F525}"),
+    ("heart", "\u{2764}\u{FE0F}"),
+    ("joy", "\u{1F602}"),
+    ("laughing", "\u{1F606}"),
+    ("rocket", "\u{1F680}"),
+    ("smile", "\u{1F604}"),
+    ("sob", "\u{1F62D}"),
+    ("tada", "\u{1F389}"),
+    ("thinking", "\u{1F914}"),
+    ("thumbsup", "\u{1F44D}"),
+    ("wave", "\u{1F44B}"),
+    ("wink", "\u{1F609}"),
+];
+
+// A small image admins added to be used as `:shortcode:`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct CustomEmoji {
+    content_type: String,
+    data: ByteBuf,
+}
+
+impl Storable for CustomEmoji {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A custom emoji as listed by `list_custom_emoji`, without its image
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EmojiInfo {
+    shortcode: String,
+    content_type: String,
+    // where the gateway serves the image, relative to the blog
+    path: String,
+}
+
+thread_local! {
+    // Shortcode -> image
+    static CUSTOM_EMOJI: RefCell<StableBTreeMap<String, CustomEmoji, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::CUSTOM_EMOJI_MEMORY)));
+}
+
+fn is_shortcode(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_SHORTCODE_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'))
+}
+
+fn path(shortcode: &str) -> String {
+    format!("/emoji/{}", http::encode_segment(shortcode))
+}
+
+// HTML for a shortcode, if it names an emoji
+fn emoji_html(shortcode: &str) -> Option<String> {
+    if CUSTOM_EMOJI.with(|emoji| emoji.borrow().contains_key(&shortcode.to_string())) {
+        return Some(format!(
+            r#"<img class="emoji" src="{}" alt=":{}:" width="20" height="20">"#,
+            path(shortcode),
+            shortcode
+        ));
+    }
+    built_in(shortcode)
+}
+
+fn built_in(shortcode: &str) -> Option<String> {
+    BUILT_IN
+        .iter()
+        .find(|(name, _)| *name == shortcode)
+        .map(|(_, emoji)| emoji.to_string())
+}
+
+// Expand the `:shortcode:` emoji of text that is already escaped for HTML.
+// Shortcodes that name no emoji are left as they are.
+pub(crate) fn expand(escaped: &str) -> String {
+    expand_with(escaped, emoji_html)
+}
+
+// Like `expand`, but custom emoji, which are images, are left as shortcodes
+pub(crate) fn expand_built_in(escaped: &str) -> String {
+    expand_with(escaped, built_in)
+}
+
+fn expand_with(escaped: &str, emoji_html: fn(&str) -> Option<String>) -> String {
+    let mut html = String::with_capacity(escaped.len());
+    let mut rest = escaped;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        let expanded = after.find(':').and_then(|end| {
+            let name = &after[..end];
+            is_shortcode(name)
+                .then(|| emoji_html(name))
+                .flatten()
+                .map(|emoji| (emoji, end))
+        });
+        match expanded {
+            Some((emoji, end)) => {
+                html.push_str(&rest[..start]);
+                html.push_str(&emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                // the closing colon may open the next shortcode
+                html.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    html.push_str(rest);
+    html
+}
+
+// Image of a custom emoji, served over the HTTP gateway
+pub(crate) fn image(file: &str) -> HttpResponse {
+    match CUSTOM_EMOJI.with(|emoji| emoji.borrow().get(&file.to_string())) {
+        Some(emoji) => HttpResponse::ok_bytes(&emoji.content_type, emoji.data.into_vec()),
+        None => HttpResponse::error(404, "Emoji not found"),
+    }
+}
+
+// Update function for admins to add a custom emoji, or replace the image of
+// one
+#[ic_cdk::update]
+fn add_custom_emoji(shortcode: String, content_type: String, data: ByteBuf) -> Result<(), Error> {
+    auth::require_admin()?;
+    if !is_shortcode(&shortcode) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Shortcodes are 1 to {} letters, digits, '_', '+' or '-'",
+                MAX_SHORTCODE_LEN
+            ),
+        });
+    }
+    if !EMOJI_TYPES.contains(&content_type.as_str()) || data.len() > MAX_EMOJI_SIZE {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Custom emoji must be PNG, GIF or WebP images of at most {} bytes",
+                MAX_EMOJI_SIZE
+            ),
+        });
+    }
+    CUSTOM_EMOJI.with(|emoji| {
+        let mut emoji = emoji.borrow_mut();
+        if !emoji.contains_key(&shortcode) && emoji.len() >= MAX_CUSTOM_EMOJI {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "The blog can have at most {} custom emoji",
+                    MAX_CUSTOM_EMOJI
+                ),
+            });
+        }
+        emoji.insert(shortcode, CustomEmoji { content_type, data });
+        rendered::invalidate();
+        Ok(())
+    })
+}
+
+// Update function for admins to remove a custom emoji. Content using it
+// shows the shortcode again.
+#[ic_cdk::update]
+fn remove_custom_emoji(shortcode: String) -> Result<(), Error> {
+    auth::require_admin()?;
+    match CUSTOM_EMOJI.with(|emoji| emoji.borrow_mut().remove(&shortcode)) {
+        Some(_) => {
+            rendered::invalidate();
+            Ok(())
+        }
+        None => Err(Error::NotFound {
+            msg: format!("Custom emoji :{}: not found", shortcode),
+        }),
+    }
+}
+
+// Query function to list the custom emoji, in shortcode order
+#[ic_cdk::query]
+fn list_custom_emoji() -> Vec<EmojiInfo> {
+    CUSTOM_EMOJI.with(|emoji| {
+        emoji
+            .borrow()
+            .iter()
+            .map(|(shortcode, emoji)| EmojiInfo {
+                path: path(&shortcode),
+                shortcode,
+                content_type: emoji.content_type,
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_replaces_built_in_shortcodes() {
+        assert_eq!(expand("Ship it :rocket:!"), "Ship it \u{1F680}!");
+        assert_eq!(expand(":+1::tada:"), "\u{1F44D}\u{1F389}");
+    }
+
+    #[test]
+    fn expand_leaves_unknown_shortcodes_and_stray_colons() {
+        assert_eq!(expand("at 10:30 :nope:"), "at 10:30 :nope:");
+        assert_eq!(expand("a: :smile:"), "a: \u{1F604}");
+        assert_eq!(expand("::"), "::");
+    }
+
+    #[test]
+    fn expand_ignores_names_that_are_not_shortcodes() {
+        assert_eq!(expand(":two words:"), ":two words:");
+        assert_eq!(expand(r#":a"b:"#), r#":a"b:"#);
+    }
+
+    #[test]
+    fn expand_built_in_leaves_custom_emoji_as_shortcodes() {
+        CUSTOM_EMOJI.with(|emoji| {
+            emoji.borrow_mut().insert(
+                "party".to_string(),
+                CustomEmoji {
+                    content_type: "image/png".to_string(),
+                    data: ByteBuf::from(vec![0]),
+                },
+            )
+        });
+        assert!(expand(":party:").starts_with(r#"<img class="emoji" src="/emoji/party""#));
+        assert_eq!(expand_built_in(":party: :tada:"), ":party: \u{1F389}");
+    }
+}
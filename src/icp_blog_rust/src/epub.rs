@@ -0,0 +1,217 @@
+use crate::render::{escape, markdown_to_html};
+use crate::series::{self, Series};
+use crate::{
+    _author_label, _get_visible_blog_post, config, date, profiles, usernames, BlogPost, Error,
+};
+use serde_bytes::ByteBuf;
+
+// Size of the pieces an EPUB is downloaded in, well below the reply limit
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+// One piece of an exported EPUB. Clients fetch chunks 0 to `total_chunks - 1`
+// and concatenate them; `modified` changes whenever the series does, so a
+// mismatch between chunks means the download has to restart.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EpubChunk {
+    data: ByteBuf,
+    chunk_index: u64,
+    total_chunks: u64,
+    total_size: u64,
+    modified: u64,
+}
+
+// Minimal writer for ZIP archives with stored (uncompressed) entries, which is
+// all the EPUB container format requires
+struct ZipWriter {
+    bytes: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+    // modification time and date of every entry, in MS-DOS format
+    dos_time: u16,
+    dos_date: u16,
+}
+
+impl ZipWriter {
+    fn new(modified: u64) -> Self {
+        let (year, month, day) = date::civil_from_days(date::day_number(modified));
+        let seconds = (modified % date::NANOS_PER_DAY) / date::NANOS_PER_SECOND;
+        ZipWriter {
+            bytes: Vec::new(),
+            central_directory: Vec::new(),
+            entries: 0,
+            dos_time: (((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | (seconds % 60 / 2))
+                as u16,
+            dos_date: (((year.saturating_sub(1980) as u32) << 9) | (month << 5) | day) as u16,
+        }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) {
+        let crc = crc32fast::hash(data);
+        let offset = self.bytes.len() as u32;
+        let common = |out: &mut Vec<u8>, zip: &ZipWriter| {
+            out.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            out.extend_from_slice(&0x0800u16.to_le_bytes()); // UTF-8 names
+            out.extend_from_slice(&0u16.to_le_bytes()); // stored
+            out.extend_from_slice(&zip.dos_time.to_le_bytes());
+            out.extend_from_slice(&zip.dos_date.to_le_bytes());
+            out.extend_from_slice(&crc.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        };
+
+        let mut local = 0x04034b50u32.to_le_bytes().to_vec();
+        common(&mut local, self);
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(data);
+
+        let mut central = 0x02014b50u32.to_le_bytes().to_vec();
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        common(&mut central, self);
+        central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        self.bytes.extend_from_slice(&local);
+        self.central_directory.extend_from_slice(&central);
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.bytes.len() as u32;
+        let size = self.central_directory.len() as u32;
+        self.bytes.append(&mut self.central_directory);
+        self.bytes.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.bytes.extend_from_slice(&[0; 4]); // disk numbers
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes.extend_from_slice(&size.to_le_bytes());
+        self.bytes.extend_from_slice(&offset.to_le_bytes());
+        self.bytes.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.bytes
+    }
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+
+fn xhtml(title: &str, language: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE html><html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{0}" lang="{0}"><head><meta charset="utf-8"/><title>{1}</title></head><body>{2}</body></html>"#,
+        escape(language),
+        escape(title),
+        body
+    )
+}
+
+fn chapter(post: &BlogPost, language: &str) -> String {
+    let body = format!(
+        r#"<section epub:type="chapter"><h1>{}</h1><p>{} · {}</p>{}</section>"#,
+        escape(&post.title),
+        escape(_author_label(post)),
+        date::iso_date(post.created_at),
+        markdown_to_html(&post.content)
+    );
+    xhtml(&post.title, post.language.as_deref().unwrap_or(language), &body)
+}
+
+// The whole book: one chapter per post, in series order. Images stay links
+// to where the post embeds them from.
+fn build(series: &Series, posts: &[BlogPost], modified: u64) -> Vec<u8> {
+    // "und" is the BCP 47 tag for an undetermined language
+    let language = posts
+        .iter()
+        .find_map(|post| post.language.as_deref())
+        .unwrap_or("und");
+
+    let mut zip = ZipWriter::new(modified);
+    // the mimetype entry must come first, uncompressed
+    zip.add("mimetype", b"application/epub+zip");
+    zip.add("META-INF/container.xml", CONTAINER_XML.as_bytes());
+
+    let mut manifest = String::from(
+        r#"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#,
+    );
+    let mut spine = String::new();
+    let mut toc = String::new();
+    for (index, post) in posts.iter().enumerate() {
+        let file = format!("chapter-{}.xhtml", index + 1);
+        zip.add(&format!("OEBPS/{}", file), chapter(post, language).as_bytes());
+        manifest.push_str(&format!(
+            r#"<item id="chapter-{0}" href="{1}" media-type="application/xhtml+xml"/>"#,
+            index + 1,
+            file
+        ));
+        spine.push_str(&format!(r#"<itemref idref="chapter-{}"/>"#, index + 1));
+        toc.push_str(&format!(
+            r#"<li><a href="{}">{}</a></li>"#,
+            file,
+            escape(&post.title)
+        ));
+    }
+
+    let nav = format!(
+        r#"<nav epub:type="toc" id="toc"><h1>{}</h1><ol>{}</ol></nav>"#,
+        escape(&series.title),
+        toc
+    );
+    zip.add("OEBPS/nav.xhtml", xhtml(&series.title, language, &nav).as_bytes());
+
+    let creator = profiles::display_name(&series.author)
+        .or_else(|| usernames::username(&series.author))
+        .unwrap_or_else(|| series.author.to_text());
+    let package = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id"><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:identifier id="book-id">urn:icp-blog:series:{}</dc:identifier><dc:title>{}</dc:title><dc:creator>{}</dc:creator><dc:language>{}</dc:language><meta property="dcterms:modified">{}</meta></metadata><manifest>{}</manifest><spine>{}</spine></package>"#,
+        series.id,
+        escape(&series.title),
+        escape(&creator),
+        escape(language),
+        date::iso_datetime(modified),
+        manifest,
+        spine
+    );
+    zip.add("OEBPS/content.opf", package.as_bytes());
+    zip.finish()
+}
+
+// Query function to download a series as an EPUB ebook, one chunk at a time
+#[ic_cdk::query]
+fn export_series_epub(series_id: u64, chunk_index: u64) -> Result<EpubChunk, Error> {
+    config::require_reader()?;
+    let Some(series) = series::get(series_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Series with ID {} not found", series_id),
+        });
+    };
+    let posts: Vec<BlogPost> = series
+        .post_ids
+        .iter()
+        .filter_map(_get_visible_blog_post)
+        .collect();
+    // the book changes whenever the series or any of its posts does
+    let modified = posts
+        .iter()
+        .map(|post| post.updated_at.unwrap_or(post.created_at))
+        .chain([series.updated_at.unwrap_or(series.created_at)])
+        .max()
+        .unwrap_or_default();
+
+    let book = build(&series, &posts, modified);
+    let total_chunks = book.len().div_ceil(CHUNK_SIZE) as u64;
+    let Some(data) = book.chunks(CHUNK_SIZE).nth(chunk_index as usize) else {
+        return Err(Error::NotFound {
+            msg: format!(
+                "Chunk {} not found, the EPUB has {} chunks",
+                chunk_index, total_chunks
+            ),
+        });
+    };
+    Ok(EpubChunk {
+        data: ByteBuf::from(data.to_vec()),
+        chunk_index,
+        total_chunks,
+        total_size: book.len() as u64,
+        modified,
+    })
+}
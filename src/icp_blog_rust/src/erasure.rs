@@ -0,0 +1,48 @@
+use crate::{
+    auth, collaborators, contact, follows, jobs, mentions, notifications, privacy, profiles,
+    rate_limits, sessions, usernames, Error,
+};
+use candid::Principal;
+use ic_cdk::api::caller;
+
+// Drop up to `limit` references to `principal`: its follows either way, its
+// co-authorships, its inbox and mentions, the notifications it caused, the
+// contact messages it sent, and the name index entries offering it
+// for @-mentions. Called by the job runner until it reports that none is left.
+pub(crate) fn erase_batch(principal: &Principal, limit: usize) -> Result<(u64, bool), String> {
+    let steps: [fn(&Principal, usize) -> usize; 7] = [
+        follows::remove_all,
+        collaborators::remove_all,
+        notifications::remove_inbox,
+        mentions::remove_mentions,
+        notifications::remove_by_actor,
+        contact::remove_by_sender,
+        profiles::unindex_batch,
+    ];
+    let mut processed = 0;
+    for step in steps {
+        if processed == limit {
+            break;
+        }
+        processed += step(principal, limit - processed);
+    }
+    Ok((processed as u64, processed < limit))
+}
+
+// Update function for users to delete their profile, username and privacy
+// settings.
+// What still refers to them is dropped by a background job, which records
+// how much it dropped in the audit log when done. Posts and comments are
+// kept; they are deleted one by one.
+#[ic_cdk::update]
+fn delete_my_data() -> Result<(), Error> {
+    auth::require_signed_in()?;
+    sessions::require_trusted_origin()?;
+    rate_limits::check(rate_limits::Action::Interact)?;
+    let principal = caller();
+    profiles::remove(&principal);
+    usernames::release(&principal);
+    privacy::remove(&principal);
+    jobs::enqueue_erase_user_data(principal);
+    Ok(())
+}
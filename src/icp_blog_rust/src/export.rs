@@ -0,0 +1,81 @@
+use crate::feeds::FEED_TITLE;
+use crate::render::{escape, markdown_to_html};
+use crate::{_author_label, _get_visible_blog_post, config, date, BlogPost, Error};
+
+// Styling of printable exports, inlined so the file stands on its own
+const PRINT_STYLE: &str = "@page{margin:2cm}body{max-width:42em;margin:0 auto;\
+font:11pt/1.5 Georgia,serif;color:#000}h1{font-size:20pt}\
+dl{display:grid;grid-template-columns:max-content auto;gap:0 1em;font-size:9pt}\
+dt{font-weight:bold}dd{margin:0}img{max-width:100%;page-break-inside:avoid}\
+pre{white-space:pre-wrap}a{color:inherit}\
+footer{margin-top:2em;border-top:1px solid #000;font-size:9pt}";
+
+// Copyright notice of a post. Without a license of its own, the author keeps
+// all rights.
+fn license(post: &BlogPost) -> String {
+    let (year, _, _) = date::civil_from_days(date::day_number(post.created_at));
+    let author = _author_label(post);
+    match &post.license {
+        Some(license) => format!("© {} {}. Licensed under {}.", year, author, license),
+        None => format!("© {} {}. All rights reserved.", year, author),
+    }
+}
+
+// A post as a single HTML document meant for printing or saving as PDF, with
+// its metadata and license and no external resources besides its images
+fn printable(post: &BlogPost) -> String {
+    let mut page = String::from("<!DOCTYPE html>");
+    match &post.language {
+        Some(language) => page.push_str(&format!(r#"<html lang="{}">"#, escape(language))),
+        None => page.push_str("<html>"),
+    }
+    page.push_str(r#"<head><meta charset="utf-8">"#);
+    page.push_str(&format!(
+        "<title>{} - {}</title>",
+        escape(&post.title),
+        escape(FEED_TITLE)
+    ));
+    page.push_str(&format!(
+        r#"<meta name="author" content="{}">"#,
+        escape(_author_label(post))
+    ));
+    page.push_str(&format!("<style>{}</style>", PRINT_STYLE));
+    page.push_str("</head><body><article><header>");
+    page.push_str(&format!("<h1>{}</h1><dl>", escape(&post.title)));
+
+    let mut field = |name: &str, value: &str| {
+        page.push_str(&format!("<dt>{}</dt><dd>{}</dd>", name, escape(value)));
+    };
+    field("Author", _author_label(post));
+    field("Published", &date::iso_date(post.created_at));
+    if let Some(updated_at) = post.updated_at {
+        field("Updated", &date::iso_date(updated_at));
+    }
+    if !post.categories.is_empty() {
+        field("Categories", &post.categories.join(", "));
+    }
+    if let Some(language) = &post.language {
+        field("Language", language);
+    }
+
+    page.push_str("</dl></header>");
+    page.push_str(&markdown_to_html(&post.content));
+    page.push_str(&format!(
+        "<footer><p>{}</p></footer>",
+        escape(&license(post))
+    ));
+    page.push_str("</article></body></html>");
+    page
+}
+
+// Query function to export a post as printable HTML for offline distribution
+#[ic_cdk::query]
+fn export_post_printable(id: u64) -> Result<String, Error> {
+    config::require_reader()?;
+    match _get_visible_blog_post(&id) {
+        Some(blog_post) => Ok(printable(&blog_post)),
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot export.", id),
+        }),
+    }
+}
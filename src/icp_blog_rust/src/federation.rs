@@ -0,0 +1,106 @@
+use crate::comments::{self, Comment};
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, webhooks, Error};
+use candid::Principal;
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// Most peer blogs that may be federated with at once
+const MAX_PEERS: u64 = 50;
+
+// Payload of `federated_add_comment`: a comment a user of the peer blog wrote
+// on one of this blog's posts
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct FederatedComment {
+    post_id: u64,
+    // the user on the peer blog, which vouches for them
+    author: Principal,
+    content: String,
+    parent_comment_id: Option<u64>,
+}
+
+thread_local! {
+    // Peer blog canisters allowed to submit comments
+    static PEERS: RefCell<StableBTreeMap<Blob<29>, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::FEDERATION_PEERS_MEMORY)));
+}
+
+fn is_peer(canister: &Principal) -> bool {
+    PEERS.with(|peers| peers.borrow().contains_key(&principal_key(canister)))
+}
+
+// Update function for peer blog canisters to comment on a post on behalf of
+// one of their users. The comment is checked like any other and records the
+// peer it came from.
+#[ic_cdk::update]
+fn federated_add_comment(comment: FederatedComment) -> Result<Comment, Error> {
+    let peer = caller();
+    if !is_peer(&peer) {
+        return Err(Error::NotAuthorized {
+            msg: format!("{} is not a federated peer", peer),
+        });
+    }
+    if comment.author == Principal::anonymous() {
+        return Err(Error::ValidationErrors {
+            errors: "Federated comments need an author".to_string(),
+        });
+    }
+    comments::insert(
+        comment.post_id,
+        comment.content,
+        comment.parent_comment_id,
+        comment.author,
+        Some(peer),
+    )
+}
+
+// Query function for admins to list the peer blogs allowed to submit
+// comments
+#[ic_cdk::query]
+fn list_federation_peers() -> Result<Vec<Principal>, Error> {
+    auth::require_admin()?;
+    Ok(PEERS.with(|peers| {
+        peers
+            .borrow()
+            .iter()
+            .map(|(canister, _)| Principal::from_slice(canister.as_slice()))
+            .collect()
+    }))
+}
+
+// Update function for admins to let a peer blog canister submit comments
+#[ic_cdk::update]
+fn add_federation_peer(canister: Principal) -> Result<(), Error> {
+    auth::require_admin()?;
+    if !webhooks::is_canister(&canister) {
+        return Err(Error::ValidationErrors {
+            errors: format!("{} is not a canister", canister),
+        });
+    }
+    PEERS.with(|peers| {
+        let mut peers = peers.borrow_mut();
+        if !peers.contains_key(&principal_key(&canister)) && peers.len() >= MAX_PEERS {
+            return Err(Error::ValidationErrors {
+                errors: format!("At most {} peers can be federated with", MAX_PEERS),
+            });
+        }
+        peers.insert(principal_key(&canister), ());
+        Ok(())
+    })
+}
+
+// Update function for admins to stop accepting comments from a peer blog.
+// Comments it already submitted stay.
+#[ic_cdk::update]
+fn remove_federation_peer(canister: Principal) -> Result<(), Error> {
+    auth::require_admin()?;
+    match PEERS.with(|peers| peers.borrow_mut().remove(&principal_key(&canister))) {
+        Some(()) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not a federated peer", canister),
+        }),
+    }
+}
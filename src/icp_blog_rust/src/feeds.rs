@@ -0,0 +1,221 @@
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::render::{self, escape};
+use crate::visibility::{self, Reader};
+use crate::{
+    _author_label, _get_visible_blog_post, _newest_blog_posts, authors, categories, date, profiles,
+    public_ids, sponsorship, usernames, BlogPost,
+};
+use candid::Principal;
+
+pub(crate) const FEED_TITLE: &str = "ICP Blog";
+// Number of most recent posts listed in a feed
+const FEED_SIZE: usize = 20;
+// Length of the post excerpt used as item description, in characters
+const EXCERPT_LEN: usize = 300;
+
+// RSS feed of the whole blog. Like every feed, it leaves out paywalled
+// posts, since feed readers can't buy access.
+pub(crate) fn main_feed(req: &HttpRequest) -> HttpResponse {
+    let reader = Reader::current();
+    let posts = _newest_blog_posts(FEED_SIZE, |blog_post| {
+        visibility::is_listed_for(blog_post, &reader)
+    });
+    render(req, FEED_TITLE, "/feed.xml", &posts)
+}
+
+// How an author's feed is addressed: by their username, or by their
+// principal if they registered none
+fn author_handle(author: &Principal) -> String {
+    usernames::username(author).unwrap_or_else(|| author.to_text())
+}
+
+fn author_feed_path(author: &Principal) -> String {
+    format!(
+        "/feed/author/{}.xml",
+        http::encode_segment(&author_handle(author))
+    )
+}
+
+fn author_feed_title(author: &Principal) -> String {
+    let name = profiles::display_name(author).unwrap_or_else(|| author_handle(author));
+    format!("{} - posts by {}", FEED_TITLE, name)
+}
+
+// RSS feed of a single author, addressed by username. Principals still
+// address the feeds of authors without one, and keep working for the others.
+pub(crate) fn author_feed(req: &HttpRequest, author: &str) -> HttpResponse {
+    let Some(principal) = usernames::lookup(author).or_else(|| Principal::from_text(author).ok())
+    else {
+        return HttpResponse::error(404, "Unknown author");
+    };
+    let reader = Reader::current();
+    let posts: Vec<BlogPost> = authors::newest_post_ids(&principal, FEED_SIZE)
+        .iter()
+        .filter_map(_get_visible_blog_post)
+        .filter(|blog_post| visibility::is_listed_for(blog_post, &reader))
+        .collect();
+    if posts.is_empty() {
+        return HttpResponse::error(404, "Unknown author");
+    }
+    render(
+        req,
+        &author_feed_title(&principal),
+        &author_feed_path(&principal),
+        &posts,
+    )
+}
+
+// RSS feed of a single category
+pub(crate) fn category_feed(req: &HttpRequest, category: &str) -> HttpResponse {
+    if categories::post_count(category).is_none() {
+        return HttpResponse::error(404, "Unknown category");
+    }
+    let reader = Reader::current();
+    let posts: Vec<BlogPost> = categories::newest_post_ids(category, FEED_SIZE)
+        .iter()
+        .filter_map(_get_visible_blog_post)
+        .filter(|blog_post| visibility::is_listed_for(blog_post, &reader))
+        .collect();
+    let title = format!("{} - {}", FEED_TITLE, category);
+    render(
+        req,
+        &title,
+        &format!("/feed/category/{}.xml", http::encode_segment(category)),
+        &posts,
+    )
+}
+
+// OPML list of every feed of the blog, for importing all of them into a reader
+pub(crate) fn opml(req: &HttpRequest) -> HttpResponse {
+    let base_url = req.base_url();
+    let outline = |title: &str, path: &str| {
+        format!(
+            r#"<outline type="rss" text="{0}" title="{0}" xmlUrl="{1}{2}" htmlUrl="{1}/"/>"#,
+            escape(title),
+            escape(&base_url),
+            escape(path)
+        )
+    };
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<opml version="2.0"><head>"#);
+    xml.push_str(&format!(
+        "<title>{} feeds</title></head><body>",
+        escape(FEED_TITLE)
+    ));
+    xml.push_str(&outline(FEED_TITLE, "/feed.xml"));
+
+    xml.push_str(r#"<outline text="Authors" title="Authors">"#);
+    for (author, _) in authors::list_authors() {
+        xml.push_str(&outline(
+            &author_feed_title(&author),
+            &author_feed_path(&author),
+        ));
+    }
+    xml.push_str("</outline>");
+
+    xml.push_str(r#"<outline text="Categories" title="Categories">"#);
+    for (category, _) in categories::list_categories() {
+        xml.push_str(&outline(
+            &format!("{} - {}", FEED_TITLE, category),
+            &format!("/feed/category/{}.xml", http::encode_segment(&category)),
+        ));
+    }
+    xml.push_str("</outline>");
+
+    xml.push_str("</body></opml>");
+    HttpResponse::ok("text/x-opml; charset=utf-8", xml)
+}
+
+fn render(req: &HttpRequest, title: &str, path: &str, posts: &[BlogPost]) -> HttpResponse {
+    let base_url = req.base_url();
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    xml.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#);
+    xml.push_str(&format!(
+        r#"<title>{}</title><link>{}/</link><description>{}</description>"#,
+        escape(title),
+        escape(&base_url),
+        escape(title)
+    ));
+    xml.push_str(&format!(
+        r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+        escape(&format!("{}{}", base_url, path))
+    ));
+    if let Some(latest) = posts.first() {
+        let updated = latest.updated_at.unwrap_or(latest.created_at);
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>",
+            date::rfc822(updated)
+        ));
+    }
+    for post in posts {
+        xml.push_str("<item>");
+        let disclosure = sponsorship::to_text(&post.sponsored);
+        let title = match &disclosure {
+            Some(_) => format!("[Sponsored] {}", post.title),
+            None => post.title.clone(),
+        };
+        xml.push_str(&format!("<title>{}</title>", escape(&title)));
+        xml.push_str(&format!(
+            r#"<guid isPermaLink="false">post-{}</guid>"#,
+            public_ids::label(post.id)
+        ));
+        xml.push_str(&format!("<author>{}</author>", escape(_author_label(post))));
+        for category in &post.categories {
+            xml.push_str(&format!("<category>{}</category>", escape(category)));
+        }
+        let mut description = excerpt(&render::plain_text(&post.content), EXCERPT_LEN);
+        if let Some(disclosure) = disclosure {
+            description = format!("{}. {}", disclosure, description);
+        }
+        xml.push_str(&format!(
+            "<description>{}</description>",
+            escape(&description)
+        ));
+        xml.push_str(&format!(
+            "<pubDate>{}</pubDate>",
+            date::rfc822(post.created_at)
+        ));
+        xml.push_str("</item>");
+    }
+    xml.push_str("</channel></rss>");
+    HttpResponse::ok("application/rss+xml; charset=utf-8", xml)
+}
+
+// Start of a text cut at a word boundary
+pub(crate) fn excerpt(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(end) if end > 0 => &cut[..end],
+        _ => cut.as_str(),
+    };
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpt_keeps_short_text() {
+        assert_eq!(excerpt("short text", 20), "short text");
+    }
+
+    #[test]
+    fn excerpt_cuts_at_a_word_boundary() {
+        assert_eq!(excerpt("the quick brown fox", 12), "the quick…");
+    }
+
+    #[test]
+    fn excerpt_cuts_long_words() {
+        assert_eq!(excerpt("abcdefghij", 4), "abcd…");
+    }
+
+    #[test]
+    fn excerpt_counts_characters_not_bytes() {
+        assert_eq!(excerpt("ééé ééé", 5), "ééé…");
+    }
+}
@@ -0,0 +1,222 @@
+use crate::keys;
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{
+    _get_visible_blog_post, auth, authors, privacy, rate_limits, sponsorship, visibility, BlogPost,
+    Error,
+};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `get_following_feed` returns
+const MAX_FEED_PAGE: u64 = 50;
+// Largest page `get_following` returns
+const MAX_FOLLOWING_PAGE: u64 = 100;
+
+// Key of the follows map: the follower, prefixed with its length, followed by
+// the author, so everyone a reader follows is one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct FollowKey {
+    follower: Vec<u8>,
+    author: Vec<u8>,
+}
+
+impl Storable for FollowKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        keys::put_principal(&mut bytes, &self.follower);
+        bytes.extend_from_slice(&self.author);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (follower, author) = keys::take_principal(&bytes);
+        FollowKey {
+            follower,
+            author: author.to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: keys::PREFIXED_PRINCIPAL_SIZE + 29,
+        is_fixed_size: false,
+    };
+}
+
+impl FollowKey {
+    fn new(follower: &Principal, author: &Principal) -> Self {
+        FollowKey {
+            follower: follower.as_slice().to_vec(),
+            author: author.as_slice().to_vec(),
+        }
+    }
+}
+
+thread_local! {
+    // (follower, author) -> time of the follow
+    static FOLLOWS: RefCell<StableBTreeMap<FollowKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::FOLLOWS_MEMORY)));
+}
+
+// Authors a reader follows
+pub(crate) fn following(follower: &Principal) -> Vec<Principal> {
+    let start = FollowKey {
+        follower: follower.as_slice().to_vec(),
+        author: Vec::new(),
+    };
+    FOLLOWS.with(|follows| {
+        follows
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.follower == follower.as_slice())
+            .map(|(key, _)| Principal::from_slice(&key.author))
+            .collect()
+    })
+}
+
+pub(crate) fn is_following(follower: &Principal, author: &Principal) -> bool {
+    FOLLOWS.with(|follows| {
+        follows
+            .borrow()
+            .contains_key(&FollowKey::new(follower, author))
+    })
+}
+
+// Move up to `limit` follows of `old`, both as follower and as author, to
+// `new`, returning how many were handled. Follows `new` already has, and
+// follows of `new` by itself, are dropped.
+pub(crate) fn reassign(old: &Principal, new: &Principal, limit: usize) -> usize {
+    let moved: Vec<(FollowKey, u64)> = FOLLOWS.with(|follows| {
+        follows
+            .borrow()
+            .iter()
+            .filter(|(key, _)| key.follower == old.as_slice() || key.author == old.as_slice())
+            .take(limit)
+            .collect()
+    });
+    FOLLOWS.with(|follows| {
+        let mut follows = follows.borrow_mut();
+        for (key, followed_at) in &moved {
+            follows.remove(key);
+            let swap = |principal: &Vec<u8>| {
+                if principal == old.as_slice() {
+                    new.as_slice().to_vec()
+                } else {
+                    principal.clone()
+                }
+            };
+            let key = FollowKey {
+                follower: swap(&key.follower),
+                author: swap(&key.author),
+            };
+            if key.follower != key.author && !follows.contains_key(&key) {
+                follows.insert(key, *followed_at);
+            }
+        }
+    });
+    moved.len()
+}
+
+// Drop up to `limit` follows of `principal`, both as follower and as
+// author, returning how many were dropped
+pub(crate) fn remove_all(principal: &Principal, limit: usize) -> usize {
+    let keys: Vec<FollowKey> = FOLLOWS.with(|follows| {
+        follows
+            .borrow()
+            .iter()
+            .filter(|(key, _)| {
+                key.follower == principal.as_slice() || key.author == principal.as_slice()
+            })
+            .map(|(key, _)| key)
+            .take(limit)
+            .collect()
+    });
+    FOLLOWS.with(|follows| {
+        let mut follows = follows.borrow_mut();
+        for key in &keys {
+            follows.remove(key);
+        }
+    });
+    keys.len()
+}
+
+// Update function for the caller to follow an author
+#[ic_cdk::update]
+fn follow_author(author: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let follower = caller();
+    if author == follower || author == Principal::anonymous() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Cannot follow {}.", author),
+        });
+    }
+    let key = FollowKey::new(&follower, &author);
+    if FOLLOWS.with(|follows| follows.borrow().contains_key(&key)) {
+        return Err(Error::AlreadyFollowing {
+            msg: format!(
+                "Author {} is already followed by caller: {}.",
+                author, follower
+            ),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+    FOLLOWS.with(|follows| follows.borrow_mut().insert(key, time()));
+    notifications::notify(&author, NotificationKind::Followed);
+    Ok(())
+}
+
+// Update function for the caller to stop following an author
+#[ic_cdk::update]
+fn unfollow_author(author: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let follower = caller();
+    let key = FollowKey::new(&follower, &author);
+    if FOLLOWS.with(|follows| follows.borrow_mut().remove(&key).is_none()) {
+        return Err(Error::NotFollowing {
+            msg: format!("Author {} isn't followed by caller: {}.", author, follower),
+        });
+    }
+    Ok(())
+}
+
+// Query function to page through the newest posts of the authors the caller
+// follows, newest first
+#[ic_cdk::query]
+fn get_following_feed(offset: u64, limit: u64) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    let limit = limit.min(MAX_FEED_PAGE) as usize;
+    let wanted = (offset as usize).saturating_add(limit);
+    // no author contributes more than a whole page counted from the start
+    let mut post_ids: Vec<u64> = following(&caller())
+        .iter()
+        .flat_map(|author| authors::newest_post_ids(author, wanted))
+        .collect();
+    post_ids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(post_ids
+        .into_iter()
+        .filter_map(|id| _get_visible_blog_post(&id))
+        .filter(visibility::is_listed)
+        .filter(sponsorship::listed_for_caller)
+        .skip(offset as usize)
+        .take(limit)
+        .collect())
+}
+
+// Query function to page through the authors a principal follows, unless
+// they keep that private
+#[ic_cdk::query]
+fn get_following(principal: Principal, offset: u64, limit: u64) -> Result<Vec<Principal>, Error> {
+    if !privacy::can_see(&principal, privacy::settings(&principal).follows_public) {
+        return Err(Error::NotAuthorized {
+            msg: format!("The follows of {} are private.", principal),
+        });
+    }
+    Ok(following(&principal)
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_FOLLOWING_PAGE) as usize)
+        .collect())
+}
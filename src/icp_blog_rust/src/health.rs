@@ -0,0 +1,225 @@
+use crate::http::HttpResponse;
+use crate::stats::{self, WASM_PAGE_SIZE};
+use crate::{
+    authors, categories, jobs, scheduling, timeline, trending, BlogPost, BLOG_POSTS, ID_COUNTER,
+};
+use ic_cdk::api::stable::stable64_size;
+use ic_cdk::api::time;
+
+// Newest posts checked against the indexes on every call, so a check reads a
+// bounded part of the maps
+const SAMPLE_SIZE: usize = 50;
+// Most problem post IDs named in a check's details
+const MAX_LISTED: usize = 10;
+// Memory a canister may use: 500 GiB of stable memory and 4 GiB of heap
+const STABLE_MEMORY_LIMIT: u64 = 500 * 1024 * 1024 * 1024;
+const HEAP_LIMIT: u64 = 4 * 1024 * 1024 * 1024;
+// Share of a memory limit in use, in percent, from which checks warn or fail
+const WARN_PERCENT: u64 = 80;
+const FAIL_PERCENT: u64 = 95;
+
+// Outcome of a check, worst last so the report takes the highest
+#[derive(
+    candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub(crate) enum HealthStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HealthCheck {
+    name: String,
+    status: HealthStatus,
+    detail: String,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct HealthReport {
+    // the worst status of the checks
+    status: HealthStatus,
+    checks: Vec<HealthCheck>,
+    checked_at: u64,
+}
+
+fn check(name: &str, status: HealthStatus, detail: String) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        status,
+        detail,
+    }
+}
+
+fn listed(ids: &[u64]) -> String {
+    let shown: Vec<String> = ids.iter().take(MAX_LISTED).map(u64::to_string).collect();
+    let more = ids.len().saturating_sub(MAX_LISTED);
+    if more == 0 {
+        shown.join(", ")
+    } else {
+        format!("{} and {} more", shown.join(", "), more)
+    }
+}
+
+// The next post ID must be above every stored one, or new posts would
+// overwrite old ones
+fn id_counter() -> HealthCheck {
+    let next = ID_COUNTER.with(|counter| *counter.borrow().get());
+    let highest = BLOG_POSTS.with(|posts| posts.borrow().last_key_value().map(|(id, _)| id));
+    match highest {
+        Some(highest) if highest >= next => check(
+            "id_counter",
+            HealthStatus::Fail,
+            format!("Next post ID is {} but post {} exists", next, highest),
+        ),
+        _ => check(
+            "id_counter",
+            HealthStatus::Ok,
+            format!("Next post ID is {}", next),
+        ),
+    }
+}
+
+fn is_indexed(blog_post: &BlogPost) -> bool {
+    authors::is_indexed(blog_post.id, &blog_post.author)
+        && blog_post
+            .categories
+            .iter()
+            .all(|category| categories::is_indexed(blog_post.id, category))
+        && timeline::is_indexed(blog_post)
+}
+
+// The newest posts must be in the author, category and sort indexes, and the
+// sort indexes must hold as many posts as there are
+fn indexes() -> HealthCheck {
+    let (total, sample) = BLOG_POSTS.with(|posts| {
+        let posts = posts.borrow();
+        let sample: Vec<BlogPost> = posts
+            .iter()
+            .rev()
+            .take(SAMPLE_SIZE)
+            .map(|(_, blog_post)| blog_post)
+            .collect();
+        (posts.len(), sample)
+    });
+    let missing: Vec<u64> = sample
+        .iter()
+        .filter(|blog_post| !is_indexed(blog_post))
+        .map(|blog_post| blog_post.id)
+        .collect();
+    let indexed = timeline::count();
+    if !missing.is_empty() {
+        check(
+            "indexes",
+            HealthStatus::Fail,
+            format!("Posts missing from the indexes: {}", listed(&missing)),
+        )
+    } else if indexed != total {
+        check(
+            "indexes",
+            HealthStatus::Warn,
+            format!("{} posts but {} in the sort indexes", total, indexed),
+        )
+    } else {
+        check(
+            "indexes",
+            HealthStatus::Ok,
+            format!("{} newest of {} posts indexed", sample.len(), total),
+        )
+    }
+}
+
+// Scheduled posts and queued jobs need a timer armed, and trending scores
+// must have been recomputed recently
+fn timers() -> HealthCheck {
+    let mut stalled = Vec::new();
+    if scheduling::is_stalled() {
+        stalled.push("scheduled posts");
+    }
+    if jobs::is_stalled() {
+        stalled.push("jobs");
+    }
+    if !stalled.is_empty() {
+        return check(
+            "timers",
+            HealthStatus::Fail,
+            format!("No timer armed for {}", stalled.join(" and ")),
+        );
+    }
+    let interval = trending::RECOMPUTE_INTERVAL.as_nanos() as u64;
+    match trending::last_recomputed() {
+        Some(at) if time().saturating_sub(at) <= 2 * interval => {
+            check("timers", HealthStatus::Ok, "Timers armed".to_string())
+        }
+        Some(at) => check(
+            "timers",
+            HealthStatus::Warn,
+            format!("Trending scores last recomputed at {}", at),
+        ),
+        None => check(
+            "timers",
+            HealthStatus::Warn,
+            "Trending scores not recomputed since the last upgrade".to_string(),
+        ),
+    }
+}
+
+fn usage(name: &str, used: u64, limit: u64) -> HealthCheck {
+    let percent = used.saturating_mul(100) / limit;
+    let status = if percent >= FAIL_PERCENT {
+        HealthStatus::Fail
+    } else if percent >= WARN_PERCENT {
+        HealthStatus::Warn
+    } else {
+        HealthStatus::Ok
+    };
+    check(
+        name,
+        status,
+        format!("{} of {} bytes used ({}%)", used, limit, percent),
+    )
+}
+
+fn report() -> HealthReport {
+    let checks = vec![
+        id_counter(),
+        indexes(),
+        timers(),
+        usage(
+            "stable_memory",
+            stable64_size() * WASM_PAGE_SIZE,
+            STABLE_MEMORY_LIMIT,
+        ),
+        usage("heap_memory", stats::heap_size(), HEAP_LIMIT),
+    ];
+    HealthReport {
+        status: checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok),
+        checks,
+        checked_at: time(),
+    }
+}
+
+// Serve the report as JSON at `/health` for uptime monitors: 200 unless a
+// check failed, 503 then
+pub(crate) fn http_health() -> HttpResponse {
+    let report = report();
+    let status_code = if report.status == HealthStatus::Fail {
+        503
+    } else {
+        200
+    };
+    let body = serde_json::to_string(&report).expect("Cannot encode the health report");
+    HttpResponse::uncached(status_code, "application/json", body)
+}
+
+// Query function for operators and monitors to check the invariants the
+// canister relies on: the post ID counter, the indexes of a sample of posts,
+// the timers and the memory left
+#[ic_cdk::query]
+fn health_check() -> HealthReport {
+    report()
+}
@@ -0,0 +1,156 @@
+use crate::timeline::{self, SortBy};
+use crate::visibility::{self, Reader};
+use crate::{
+    _get_blog_post, auth, authors, categories, config, follows, likes, trending, BlogPost, Error,
+};
+use ic_cdk::api::caller;
+use std::collections::BTreeMap;
+
+// Most posts returned in one page of the feed
+const MAX_HOME_FEED_PAGE: u64 = 50;
+// Newest likes of the caller whose categories set the weights
+const LIKES_CONSIDERED: usize = 100;
+// Categories with the highest weights the weighted feed draws from
+const WEIGHTED_CATEGORIES: usize = 10;
+// Newest posts looked at in each of those categories, so a call reads a
+// bounded part of the indexes
+const CANDIDATES_PER_CATEGORY: usize = 200;
+
+// How the home feed ranks posts
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum FeedAlgorithm {
+    // every published post, newest first
+    Chronological,
+    // the trending ranking, recomputed periodically, see `trending`
+    Trending,
+    // posts of the authors the caller follows, newest first
+    FollowedOnly,
+    // posts of the categories the caller liked most, those sharing the most
+    // weight first. Callers without likes get the biggest categories.
+    CategoryWeighted,
+}
+
+// Posts that may be shown in a feed for `reader`
+fn listed(ids: impl IntoIterator<Item = u64>, limit: usize, reader: &Reader) -> Vec<BlogPost> {
+    ids.into_iter()
+        .filter_map(|id| _get_blog_post(&id))
+        .filter(|blog_post| visibility::is_listed_for(blog_post, reader))
+        .take(limit)
+        .collect()
+}
+
+// The IDs of a ranking that come after the cursor post. A cursor that dropped
+// out of the ranking ends the feed.
+fn after(ranked: Vec<u64>, cursor: Option<u64>) -> Vec<u64> {
+    match cursor {
+        Some(cursor) => ranked
+            .into_iter()
+            .skip_while(|&id| id != cursor)
+            .skip(1)
+            .collect(),
+        None => ranked,
+    }
+}
+
+fn followed_only(
+    cursor: Option<u64>,
+    limit: usize,
+    reader: &Reader,
+) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    let before = cursor.unwrap_or(u64::MAX);
+    // no author contributes more than a whole page
+    let mut post_ids: Vec<u64> = follows::following(&caller())
+        .iter()
+        .flat_map(|author| authors::post_ids_before(author, before, limit))
+        .collect();
+    post_ids.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(listed(post_ids, limit, reader))
+}
+
+// Weight of every category: how many of the caller's newest likes are of
+// posts in it, or, without any, how many posts it has
+fn category_weights() -> Vec<(String, u64)> {
+    let mut weights: BTreeMap<String, u64> = BTreeMap::new();
+    for id in likes::newest_liked_post_ids(&caller(), LIKES_CONSIDERED) {
+        if let Some(blog_post) = _get_blog_post(&id) {
+            for category in blog_post.categories {
+                *weights.entry(category).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut weights: Vec<(String, u64)> = if weights.is_empty() {
+        categories::list_categories()
+    } else {
+        weights.into_iter().collect()
+    };
+    weights.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
+    weights.truncate(WEIGHTED_CATEGORIES);
+    weights
+}
+
+fn category_weighted(cursor: Option<u64>, limit: usize, reader: &Reader) -> Vec<BlogPost> {
+    // candidate id -> weights of its categories
+    let mut scores: BTreeMap<u64, u64> = BTreeMap::new();
+    for (category, weight) in category_weights() {
+        for candidate in categories::newest_post_ids(&category, CANDIDATES_PER_CATEGORY) {
+            *scores.entry(candidate).or_insert(0) += weight;
+        }
+    }
+    let mut ranked: Vec<(u64, u64)> = scores
+        .into_iter()
+        .map(|(candidate, score)| (score, candidate))
+        .collect();
+    ranked.sort_unstable_by(|a, b| b.cmp(a));
+    let ranked = ranked.into_iter().map(|(_, candidate)| candidate).collect();
+    listed(after(ranked, cursor), limit, reader)
+}
+
+pub(crate) fn default_algorithm() -> FeedAlgorithm {
+    config::get()
+        .default_feed
+        .unwrap_or(FeedAlgorithm::Chronological)
+}
+
+// Query function to page through the home feed ranked by `algorithm`, or by
+// the deployment's default when it is left out. `cursor` is the ID of the last
+// post of the previous page; leave it out for the first. The trending and
+// weighted rankings change over time, so their pages are only consistent with
+// the rank the cursor post has now.
+#[ic_cdk::query]
+fn get_feed(
+    algorithm: Option<FeedAlgorithm>,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    let limit = limit.min(MAX_HOME_FEED_PAGE);
+    let reader = Reader::current();
+    match algorithm.unwrap_or_else(default_algorithm) {
+        FeedAlgorithm::Chronological => timeline::list_blog_posts(SortBy::Newest, cursor, limit),
+        FeedAlgorithm::Trending => Ok(listed(
+            after(trending::ranked_post_ids(), cursor),
+            limit as usize,
+            &reader,
+        )),
+        FeedAlgorithm::FollowedOnly => followed_only(cursor, limit as usize, &reader),
+        FeedAlgorithm::CategoryWeighted => Ok(category_weighted(cursor, limit as usize, &reader)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paywall;
+    use crate::testing::{ids, insert_post, principal, reader};
+
+    #[test]
+    fn feeds_leave_out_paywalled_posts() {
+        for id in 1..=3 {
+            insert_post(id, principal(1), id, &["rust"]);
+        }
+        paywall::set_price(2, 100);
+        let posts = listed([3, 2, 1], 10, &reader(principal(2), 0));
+        assert_eq!(ids(&posts), vec![3, 1]);
+    }
+}
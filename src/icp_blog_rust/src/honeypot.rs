@@ -0,0 +1,31 @@
+use crate::abuse;
+use crate::Error;
+use candid::Principal;
+use ic_cdk::api::caller;
+
+// Decoy methods. They look like privileged endpoints but no legitimate client
+// calls them, so whoever does is most likely a bot probing the interface: the
+// caller is flagged in the abuse signals and rate limited for a day. They
+// always fail the way a real privileged endpoint would.
+fn trip(method: &str) -> Result<(), Error> {
+    let principal = caller();
+    abuse::flag(principal, method);
+    Err(Error::NotAuthorized {
+        msg: format!("Caller {} is not an admin.", principal),
+    })
+}
+
+#[ic_cdk::update]
+fn set_owner(_owner: Principal) -> Result<(), Error> {
+    trip("set_owner")
+}
+
+#[ic_cdk::update]
+fn withdraw_tips(_to: Principal, _amount: u64) -> Result<(), Error> {
+    trip("withdraw_tips")
+}
+
+#[ic_cdk::update]
+fn delete_all_posts() -> Result<(), Error> {
+    trip("delete_all_posts")
+}
@@ -0,0 +1,342 @@
+use crate::feeds::{self, FEED_TITLE};
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::render::{self, escape, markdown_to_html};
+use crate::{
+    _author_label, _get_visible_blog_post, comments, credits, date, media, navigation, pages,
+    slugs, sponsorship, translations, BlogPost,
+};
+
+// Post content in the language a page is served in
+struct PageText {
+    language: Option<String>,
+    title: String,
+    content: String,
+}
+
+// Longest description in a page's metadata, in characters; link previews
+// cut it further
+const DESCRIPTION_LEN: usize = 200;
+
+// Longest part of the post content shown on a lite page, in bytes of
+// Markdown source
+const LITE_CONTENT_LEN: usize = 16 * 1024;
+
+// The only styling of a lite page, inlined so it loads in a single request
+const LITE_STYLE: &str = "body{max-width:40em;margin:0 auto;padding:0 1em;\
+font:1em/1.5 serif;color:#222}img{max-width:100%;height:auto}\
+pre{overflow-x:auto}";
+
+// Page of a single post, followed by its comments, each anchored at
+// `#comment-{id}`. `?lang=xx` serves one of its translations; every variant
+// links to the others with hreflang alternates.
+pub(crate) fn post_page(req: &HttpRequest, slug: &str) -> HttpResponse {
+    let Some(post) = slugs::lookup(slug).and_then(|id| _get_visible_blog_post(&id)) else {
+        return HttpResponse::error(404, "Post not found");
+    };
+    match page_text(req, &post) {
+        Ok(text) => HttpResponse::ok(
+            "text/html; charset=utf-8",
+            render(&req.base_url(), &post, &text, true),
+        ),
+        Err(response) => response,
+    }
+}
+
+// Minimal variant of a post page for slow connections and text-mode
+// browsers: no scripts, no external resources and a bounded size
+pub(crate) fn lite_page(req: &HttpRequest, slug: &str) -> HttpResponse {
+    let Some(post) = slugs::lookup(slug).and_then(|id| _get_visible_blog_post(&id)) else {
+        return HttpResponse::error(404, "Post not found");
+    };
+    match page_text(req, &post) {
+        Ok(text) => HttpResponse::ok(
+            "text/html; charset=utf-8",
+            render_lite(&req.base_url(), &post, &text),
+        ),
+        Err(response) => response,
+    }
+}
+
+// Page of a standalone page such as About or FAQ
+pub(crate) fn site_page(req: &HttpRequest, slug: &str) -> HttpResponse {
+    let Some(page) = pages::get(slug) else {
+        return HttpResponse::error(404, "Not found");
+    };
+    let base_url = req.base_url();
+    let text = PageText {
+        language: None,
+        title: page.title,
+        content: page.content,
+    };
+    let mut html = head(&text);
+    html.push_str(&format!(
+        r#"<link rel="canonical" href="{}/{}">"#,
+        escape(&base_url),
+        escape(&http::encode_segment(slug))
+    ));
+    html.push_str(&format!(
+        r#"<link rel="alternate" type="application/rss+xml" title="{}" href="{}/feed.xml">"#,
+        escape(FEED_TITLE),
+        escape(&base_url)
+    ));
+    html.push_str("</head><body>");
+    html.push_str(&navigation::to_html(&base_url));
+    html.push_str("<article>");
+    html.push_str(&format!("<h1>{}</h1>", escape(&text.title)));
+    html.push_str(&markdown_to_html(&text.content));
+    html.push_str("</article></body></html>");
+    HttpResponse::ok("text/html; charset=utf-8", html)
+}
+
+// Pages of a post in its original language as (path, HTML) pairs, for
+// hosting them outside of `http_request`. They are only refreshed when the
+// post changes, so they leave the comments out.
+pub(crate) fn static_pages(base_url: &str, post: &BlogPost) -> Vec<(String, String)> {
+    let text = PageText {
+        language: post.language.clone(),
+        title: post.title.clone(),
+        content: post.content.clone(),
+    };
+    let path = format!("/post/{}", http::encode_segment(&post.slug));
+    vec![
+        (path.clone(), render(base_url, post, &text, false)),
+        (format!("{}/lite", path), render_lite(base_url, post, &text)),
+    ]
+}
+
+// The text of a post in the language asked for with `?lang=xx`, the
+// original when none is given
+fn page_text(req: &HttpRequest, post: &BlogPost) -> Result<PageText, HttpResponse> {
+    let requested = req
+        .query_param("lang")
+        .map(|language| language.to_ascii_lowercase());
+    match requested {
+        Some(language) if Some(&language) != post.language.as_ref() => {
+            match translations::get(post.id, &language) {
+                Some(translation) => Ok(PageText {
+                    title: translation.title().to_string(),
+                    content: translation.content().to_string(),
+                    language: Some(language),
+                }),
+                None => Err(HttpResponse::error(404, "Translation not found")),
+            }
+        }
+        _ => Ok(PageText {
+            language: post.language.clone(),
+            title: post.title.clone(),
+            content: post.content.clone(),
+        }),
+    }
+}
+
+// URL of a post page, in a given translation if any
+fn post_url(base_url: &str, post: &BlogPost, language: Option<&str>) -> String {
+    let url = format!("{}/post/{}", base_url, http::encode_segment(&post.slug));
+    match language {
+        Some(language) => format!("{}?lang={}", url, http::encode_segment(language)),
+        None => url,
+    }
+}
+
+// hreflang alternates of a post: the original, every translation and the
+// original again as the default for other languages
+fn alternates(base_url: &str, post: &BlogPost) -> String {
+    let translated = translations::languages(post.id);
+    if translated.is_empty() {
+        return String::new();
+    }
+    let link = |hreflang: &str, href: String| {
+        format!(
+            r#"<link rel="alternate" hreflang="{}" href="{}">"#,
+            escape(hreflang),
+            escape(&href)
+        )
+    };
+    let mut links = String::new();
+    if let Some(language) = &post.language {
+        links.push_str(&link(language, post_url(base_url, post, None)));
+    }
+    for language in &translated {
+        links.push_str(&link(language, post_url(base_url, post, Some(language))));
+    }
+    links.push_str(&link("x-default", post_url(base_url, post, None)));
+    links
+}
+
+// Full page URL of the variant being served: the original is canonical for
+// itself, each translation for itself
+fn canonical_url(base_url: &str, post: &BlogPost, text: &PageText) -> String {
+    match &text.language {
+        Some(language) if Some(language) != post.language.as_ref() => {
+            post_url(base_url, post, Some(language))
+        }
+        _ => post_url(base_url, post, None),
+    }
+}
+
+// Everything of a page up to and including its title
+fn head(text: &PageText) -> String {
+    let mut page = String::from("<!DOCTYPE html>");
+    match &text.language {
+        Some(language) => page.push_str(&format!(r#"<html lang="{}">"#, escape(language))),
+        None => page.push_str("<html>"),
+    }
+    page.push_str(r#"<head><meta charset="utf-8">"#);
+    page.push_str(r#"<meta name="viewport" content="width=device-width, initial-scale=1">"#);
+    page.push_str(&format!(
+        "<title>{} - {}</title>",
+        escape(&text.title),
+        escape(FEED_TITLE)
+    ));
+    page
+}
+
+// Description and OpenGraph and Twitter card tags, so links to the page
+// unfurl on social platforms, with the cover image when the post has one.
+// Encrypted posts are described by their title only.
+fn social_meta(base_url: &str, post: &BlogPost, text: &PageText, canonical: &str) -> String {
+    let meta = |attribute: &str, name: &str, content: &str| {
+        format!(
+            r#"<meta {}="{}" content="{}">"#,
+            attribute,
+            name,
+            escape(content)
+        )
+    };
+    let description = if post.encrypted {
+        String::new()
+    } else {
+        feeds::excerpt(&render::plain_text(&text.content), DESCRIPTION_LEN)
+    };
+    let image = media::cover_url(post).map(|path| format!("{}{}", base_url, path));
+
+    let mut tags = String::new();
+    if !description.is_empty() {
+        tags.push_str(&meta("name", "description", &description));
+    }
+    tags.push_str(&meta("property", "og:type", "article"));
+    tags.push_str(&meta("property", "og:site_name", FEED_TITLE));
+    tags.push_str(&meta("property", "og:title", &text.title));
+    tags.push_str(&meta("property", "og:url", canonical));
+    if !description.is_empty() {
+        tags.push_str(&meta("property", "og:description", &description));
+    }
+    if let Some(language) = &text.language {
+        tags.push_str(&meta("property", "og:locale", language));
+    }
+    if let Some(image) = &image {
+        tags.push_str(&meta("property", "og:image", image));
+    }
+    tags.push_str(&meta(
+        "property",
+        "article:published_time",
+        &date::iso_datetime(post.created_at),
+    ));
+    if let Some(updated_at) = post.updated_at {
+        tags.push_str(&meta(
+            "property",
+            "article:modified_time",
+            &date::iso_datetime(updated_at),
+        ));
+    }
+    for category in &post.categories {
+        tags.push_str(&meta("property", "article:tag", category));
+    }
+    let card = match image {
+        Some(_) => "summary_large_image",
+        None => "summary",
+    };
+    tags.push_str(&meta("name", "twitter:card", card));
+    tags.push_str(&meta("name", "twitter:title", &text.title));
+    if !description.is_empty() {
+        tags.push_str(&meta("name", "twitter:description", &description));
+    }
+    if let Some(image) = &image {
+        tags.push_str(&meta("name", "twitter:image", image));
+    }
+    tags
+}
+
+fn byline(post: &BlogPost) -> String {
+    format!(
+        r#"<p>By {} on <time datetime="{1}">{1}</time></p>"#,
+        escape(_author_label(post)),
+        date::iso_date(post.created_at)
+    )
+}
+
+fn render(base_url: &str, post: &BlogPost, text: &PageText, with_comments: bool) -> String {
+    let canonical = canonical_url(base_url, post, text);
+
+    let mut page = head(text);
+    page.push_str(&format!(
+        r#"<link rel="canonical" href="{}">"#,
+        escape(&canonical)
+    ));
+    page.push_str(&social_meta(base_url, post, text, &canonical));
+    page.push_str(&alternates(base_url, post));
+    page.push_str(&format!(
+        r#"<link rel="alternate" type="application/rss+xml" title="{}" href="{}/feed.xml">"#,
+        escape(FEED_TITLE),
+        escape(base_url)
+    ));
+    page.push_str("</head><body>");
+    page.push_str(&navigation::to_html(base_url));
+    page.push_str("<article>");
+    page.push_str(&format!("<h1>{}</h1>", escape(&text.title)));
+    page.push_str(&byline(post));
+    page.push_str(&sponsorship::to_html(&post.sponsored));
+    page.push_str(&credits::to_html(&post.credits));
+    page.push_str(&media::cover_html(post));
+    page.push_str(&markdown_to_html(&text.content));
+    page.push_str("</article>");
+    if with_comments {
+        page.push_str(&comments::to_html(post.id));
+    }
+    page.push_str("</body></html>");
+    page
+}
+
+fn render_lite(base_url: &str, post: &BlogPost, text: &PageText) -> String {
+    let canonical = canonical_url(base_url, post, text);
+
+    let mut page = head(text);
+    page.push_str(&format!(
+        r#"<link rel="canonical" href="{}">"#,
+        escape(&canonical)
+    ));
+    page.push_str(&social_meta(base_url, post, text, &canonical));
+    page.push_str(&format!("<style>{}</style>", LITE_STYLE));
+    page.push_str("</head><body><article>");
+    page.push_str(&format!("<h1>{}</h1>", escape(&text.title)));
+    page.push_str(&byline(post));
+    page.push_str(&sponsorship::to_html(&post.sponsored));
+    page.push_str(&credits::to_html(&post.credits));
+    let content = lite_content(&text.content);
+    page.push_str(&render::markdown_to_lite_html(content));
+    if content.len() < text.content.len() {
+        page.push_str(&format!(
+            r#"<p><a href="{}">Read the full post</a></p>"#,
+            escape(&canonical)
+        ));
+    }
+    page.push_str("</article></body></html>");
+    page
+}
+
+// Start of the content that fits a lite page, cut at the last paragraph
+// break, or failing that the last line break, before the limit
+fn lite_content(content: &str) -> &str {
+    if content.len() <= LITE_CONTENT_LEN {
+        return content;
+    }
+    let mut end = LITE_CONTENT_LEN;
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &content[..end];
+    match cut.rfind("\n\n").or_else(|| cut.rfind('\n')) {
+        Some(end) if end > 0 => &cut[..end],
+        _ => cut,
+    }
+}
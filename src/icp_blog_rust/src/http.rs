@@ -0,0 +1,233 @@
+use crate::{_get_visible_blog_post, analytics, config, emoji, feeds, health, html, media, slugs};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+// One in this many requests for a post page is upgraded to an update call
+// and counted, so most page views stay cheap queries
+const VIEW_SAMPLE_RATE: u64 = 10;
+
+// Request and response types of the HTTP gateway interface
+#[derive(candid::CandidType, Deserialize)]
+pub(crate) struct HttpRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: ByteBuf,
+}
+
+#[derive(candid::CandidType, Serialize)]
+pub(crate) struct HttpResponse {
+    status_code: u16,
+    headers: Vec<(String, String)>,
+    body: ByteBuf,
+    // asks the gateway to make the request again as an update call, see
+    // `http_request_update`
+    upgrade: Option<bool>,
+}
+
+impl HttpResponse {
+    pub(crate) fn ok(content_type: &str, body: String) -> Self {
+        Self::ok_bytes(content_type, body.into_bytes())
+    }
+
+    pub(crate) fn ok_bytes(content_type: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                (
+                    "Cache-Control".to_string(),
+                    "public, max-age=300".to_string(),
+                ),
+            ],
+            body: ByteBuf::from(body),
+            upgrade: None,
+        }
+    }
+
+    // A response that never changes, such as an uploaded asset
+    pub(crate) fn immutable(content_type: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                (
+                    "Cache-Control".to_string(),
+                    "public, max-age=31536000, immutable".to_string(),
+                ),
+                ("X-Content-Type-Options".to_string(), "nosniff".to_string()),
+            ],
+            body: ByteBuf::from(body),
+            upgrade: None,
+        }
+    }
+
+    // A response that changes with every call, such as a status report
+    pub(crate) fn uncached(status_code: u16, content_type: &str, body: String) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Cache-Control".to_string(), "no-store".to_string()),
+            ],
+            body: ByteBuf::from(body.into_bytes()),
+            upgrade: None,
+        }
+    }
+
+    // A response telling the gateway to call `http_request_update` instead
+    fn upgrade() -> Self {
+        HttpResponse {
+            status_code: 204,
+            headers: Vec::new(),
+            body: ByteBuf::new(),
+            upgrade: Some(true),
+        }
+    }
+
+    pub(crate) fn error(status_code: u16, msg: &str) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![(
+                "Content-Type".to_string(),
+                "text/plain; charset=utf-8".to_string(),
+            )],
+            body: ByteBuf::from(msg.as_bytes().to_vec()),
+            upgrade: None,
+        }
+    }
+}
+
+impl HttpRequest {
+    // Percent-decoded segments of the request path, without the query string
+    fn path_segments(&self) -> Vec<String> {
+        let path = self.url.split(['?', '#']).next().unwrap_or("/");
+        path.trim_matches('/')
+            .split('/')
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+            .collect()
+    }
+
+    // Percent-decoded value of a query string parameter
+    pub(crate) fn query_param(&self, name: &str) -> Option<String> {
+        let url = self.url.split('#').next().unwrap_or_default();
+        let (_, query) = url.split_once('?')?;
+        query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key == name).then(|| {
+                percent_decode_str(&value.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+        })
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Absolute URL of the site the request was made to, used for links
+    pub(crate) fn base_url(&self) -> String {
+        match self.header("Host") {
+            Some(host) => format!("https://{}", host),
+            None => String::new(),
+        }
+    }
+}
+
+// Escape a value so it can be used as a single path segment
+pub(crate) fn encode_segment(segment: &str) -> String {
+    utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string()
+}
+
+// Slug of the post a request is for, if it is for a post page
+fn post_slug(req: &HttpRequest) -> Option<String> {
+    let segments = req.path_segments();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    match segments.as_slice() {
+        ["post", slug] | ["post", slug, "lite"] => Some(slug.to_string()),
+        _ => None,
+    }
+}
+
+// Whether a request falls in the sample of post page views that is counted.
+// Queries can't draw random numbers, so the request and the time pick it.
+fn sampled(req: &HttpRequest) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(time().to_be_bytes());
+    hasher.update(req.url.as_bytes());
+    hasher.update(req.header("User-Agent").unwrap_or_default().as_bytes());
+    let hash = hasher.finalize();
+    u64::from_be_bytes(hash[..8].try_into().unwrap()) % VIEW_SAMPLE_RATE == 0
+}
+
+// Serve the blog over the HTTP gateway. A sample of the requests for the
+// pages of existing posts is upgraded to update calls, so they can be
+// counted.
+#[ic_cdk::query]
+fn http_request(req: HttpRequest) -> HttpResponse {
+    let is_post = post_slug(&req)
+        .and_then(|slug| slugs::lookup(&slug))
+        .and_then(|id| _get_visible_blog_post(&id))
+        .is_some();
+    if req.method == "GET" && config::can_read() && is_post && sampled(&req) {
+        return HttpResponse::upgrade();
+    }
+    serve(&req)
+}
+
+// Update function the gateway calls for requests `http_request` upgraded:
+// serves the post page and counts one gateway view of the post, as a crawl
+// when the user agent looks like a crawler's. The gateway calls anonymously,
+// so calls by signed in principals aren't counted. Nothing about the reader
+// is kept.
+#[ic_cdk::update]
+fn http_request_update(req: HttpRequest) -> HttpResponse {
+    let response = serve(&req);
+    if response.status_code == 200 && caller() == Principal::anonymous() {
+        if let Some(post_id) = post_slug(&req).and_then(|slug| slugs::lookup(&slug)) {
+            analytics::record_gateway_view(post_id, req.header("User-Agent"));
+        }
+    }
+    response
+}
+
+fn serve(req: &HttpRequest) -> HttpResponse {
+    if req.method != "GET" && req.method != "HEAD" {
+        return HttpResponse::error(405, "Method not allowed");
+    }
+    let segments = req.path_segments();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    // monitors can check the canister even when reads take signing in
+    if segments == ["health"] {
+        return health::http_health();
+    }
+    // gateway requests are anonymous
+    if !config::can_read() {
+        return HttpResponse::error(403, "Sign in to read this blog");
+    }
+    match segments.as_slice() {
+        ["feed.xml"] => feeds::main_feed(req),
+        ["feeds.opml"] => feeds::opml(req),
+        ["post", slug] => html::post_page(req, slug),
+        ["post", slug, "lite"] => html::lite_page(req, slug),
+        ["feed", "author", file] => match file.strip_suffix(".xml") {
+            Some(author) => feeds::author_feed(req, author),
+            None => HttpResponse::error(404, "Not found"),
+        },
+        ["feed", "category", file] => match file.strip_suffix(".xml") {
+            Some(category) => feeds::category_feed(req, category),
+            None => HttpResponse::error(404, "Not found"),
+        },
+        ["emoji", shortcode] => emoji::image(shortcode),
+        ["media", file] => media::serve(file),
+        [slug] => html::site_page(req, slug),
+        _ => HttpResponse::error(404, "Not found"),
+    }
+}
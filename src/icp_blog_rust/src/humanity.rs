@@ -0,0 +1,181 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, webhooks, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// How long a principal stays verified before it must ask again, in
+// nanoseconds
+const VERIFIED_TTL: u64 = 7 * 24 * 3600 * 1_000_000_000;
+// How long a refusal is remembered, so a bot can't keep the verifier busy
+const REFUSED_TTL: u64 = 3600 * 1_000_000_000;
+
+// A canister vouching for principals being people, such as an allowlist
+// registry. Its `method` takes a principal and answers with a `bool`.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct HumanityVerifier {
+    canister: Principal,
+    method: String,
+}
+
+// The verifier admins set; `None` while anyone signed in may like and comment
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredVerifier(Option<HumanityVerifier>);
+
+impl Storable for StoredVerifier {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// The verifier's answer for a principal, kept until `expires_at`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Verification {
+    verifier: Principal,
+    human: bool,
+    checked_at: u64,
+    expires_at: u64,
+}
+
+impl Storable for Verification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static VERIFIER: RefCell<Cell<StoredVerifier, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::HUMANITY_VERIFIER_MEMORY), StoredVerifier::default())
+            .expect("Cannot create the humanity verifier cell")
+    );
+
+    // Principal -> the verifier's last answer
+    static VERIFICATIONS: RefCell<StableBTreeMap<Blob<29>, Verification, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::VERIFICATIONS_MEMORY)));
+}
+
+fn verifier() -> Option<HumanityVerifier> {
+    VERIFIER.with(|cell| cell.borrow().get().0.clone())
+}
+
+// Whether likes and comments take a verification
+pub(crate) fn is_required() -> bool {
+    verifier().is_some()
+}
+
+// The cached answer for a principal, unless it expired or came from a
+// verifier admins have since replaced
+fn cached(principal: &Principal, verifier: &HumanityVerifier) -> Option<Verification> {
+    VERIFICATIONS
+        .with(|verifications| verifications.borrow().get(&principal_key(principal)))
+        .filter(|verification| {
+            verification.verifier == verifier.canister && verification.expires_at > time()
+        })
+}
+
+// Guard for liking, commenting and contact messages. Once admins set a
+// verifier, the caller must have been found human by `verify_humanity`;
+// anonymous callers can't be, so they are refused even where anonymous likes
+// are allowed.
+pub(crate) fn require_human() -> Result<(), Error> {
+    let Some(verifier) = verifier() else {
+        return Ok(());
+    };
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return Err(Error::NotVerifiedHuman {
+            msg: "Sign in and call verify_humanity to like or comment".to_string(),
+        });
+    }
+    match cached(&principal, &verifier) {
+        Some(verification) if verification.human => Ok(()),
+        Some(_) => Err(Error::NotVerifiedHuman {
+            msg: format!("{} was not verified as a person", principal),
+        }),
+        None => Err(Error::NotVerifiedHuman {
+            msg: "Call verify_humanity before liking or commenting".to_string(),
+        }),
+    }
+}
+
+// Update function for users to have the verifier vouch for them before they
+// like or comment. A recent answer is returned without asking again.
+#[ic_cdk::update]
+async fn verify_humanity() -> Result<Verification, Error> {
+    auth::require_signed_in()?;
+    let Some(verifier) = verifier() else {
+        return Err(Error::NotFound {
+            msg: "No humanity verifier is set".to_string(),
+        });
+    };
+    let principal = caller();
+    if let Some(verification) = cached(&principal, &verifier) {
+        return Ok(verification);
+    }
+    let result: Result<(bool,), _> =
+        ic_cdk::call(verifier.canister, &verifier.method, (principal,)).await;
+    let human = match result {
+        Ok((human,)) => human,
+        Err((code, msg)) => {
+            return Err(Error::TransferFailed {
+                msg: format!("Cannot reach the humanity verifier: {:?} {}", code, msg),
+            })
+        }
+    };
+    let now = time();
+    let verification = Verification {
+        verifier: verifier.canister,
+        human,
+        checked_at: now,
+        expires_at: now + if human { VERIFIED_TTL } else { REFUSED_TTL },
+    };
+    VERIFICATIONS.with(|verifications| {
+        verifications
+            .borrow_mut()
+            .insert(principal_key(&principal), verification.clone())
+    });
+    Ok(verification)
+}
+
+// Query function for admins to see which canister vouches for users
+#[ic_cdk::query]
+fn get_humanity_verifier() -> Result<Option<HumanityVerifier>, Error> {
+    auth::require_admin()?;
+    Ok(verifier())
+}
+
+// Update function for admins to require users to be verified by a canister
+// before they like or comment, or to drop the requirement with `null`.
+// Answers from a previous verifier no longer count.
+#[ic_cdk::update]
+fn set_humanity_verifier(verifier: Option<HumanityVerifier>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if let Some(verifier) = &verifier {
+        if !webhooks::is_method_name(&verifier.method) {
+            return Err(Error::ValidationErrors {
+                errors: format!("{} is not a method name", verifier.method),
+            });
+        }
+    }
+    VERIFIER.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredVerifier(verifier))
+            .expect("Cannot write the humanity verifier")
+    });
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use crate::keys;
+use crate::memory::{self, Memory};
+use crate::{_get_blog_post, linking, BlogPost, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// How long a request key is remembered: 24 hours, in nanoseconds
+const KEY_TTL: u64 = 24 * 60 * 60 * 1_000_000_000;
+// Longest request key, in characters
+const MAX_KEY_LEN: usize = 64;
+// Most expired keys forgotten per call, so a call does bounded work
+const PRUNE_BATCH: usize = 100;
+
+// Key of the requests map: the caller, prefixed with its length, followed by
+// the key the client picked
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+    caller: Vec<u8>,
+    key: String,
+}
+
+impl Storable for RequestKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        keys::put_principal(&mut bytes, &self.caller);
+        bytes.extend_from_slice(self.key.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (caller, key) = keys::take_principal(&bytes);
+        RequestKey {
+            caller,
+            key: String::from_utf8(key.to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: keys::PREFIXED_PRINCIPAL_SIZE + 4 * MAX_KEY_LEN as u32,
+        is_fixed_size: false,
+    };
+}
+
+impl RequestKey {
+    fn new(caller: &Principal, key: &str) -> Self {
+        RequestKey {
+            caller: caller.as_slice().to_vec(),
+            key: key.to_string(),
+        }
+    }
+}
+
+// The post a request key created, and when
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Request {
+    post_id: u64,
+    at: u64,
+}
+
+impl Storable for Request {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the expiry index: when the key was used followed by the post it
+// created, so the oldest keys come first
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ExpiryKey {
+    at: u64,
+    post_id: u64,
+}
+
+impl Storable for ExpiryKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.at.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ExpiryKey {
+            at: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    // (caller, request key) -> the post it created
+    static REQUESTS: RefCell<StableBTreeMap<RequestKey, Request, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::IDEMPOTENCY_KEYS_MEMORY)));
+
+    // (used at, post id) -> request key, to forget keys once they expire
+    static EXPIRY: RefCell<StableBTreeMap<ExpiryKey, RequestKey, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::IDEMPOTENCY_EXPIRY_MEMORY)));
+}
+
+pub(crate) fn validate_key(key: &str) -> Result<(), Error> {
+    let len = key.chars().count();
+    if len == 0 || len > MAX_KEY_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!("Request keys are 1 to {} characters", MAX_KEY_LEN),
+        });
+    }
+    Ok(())
+}
+
+// The post the caller already created with `key` in the last 24 hours, if it
+// still exists
+pub(crate) fn existing_post(key: &str) -> Option<BlogPost> {
+    let key = RequestKey::new(&linking::primary(&caller()), key);
+    let request = REQUESTS.with(|requests| requests.borrow().get(&key))?;
+    if request.at + KEY_TTL <= time() {
+        return None;
+    }
+    _get_blog_post(&request.post_id)
+}
+
+// Remember that the caller created `post_id` with `key`, forgetting some of
+// the keys that expired on the way
+pub(crate) fn record(key: &str, post_id: u64) {
+    let now = time();
+    prune(now);
+    let key = RequestKey::new(&linking::primary(&caller()), key);
+    let previous = REQUESTS.with(|requests| {
+        requests
+            .borrow_mut()
+            .insert(key.clone(), Request { post_id, at: now })
+    });
+    EXPIRY.with(|expiry| {
+        let mut expiry = expiry.borrow_mut();
+        // a key reused after its post was deleted moves to its new time
+        if let Some(previous) = previous {
+            expiry.remove(&ExpiryKey {
+                at: previous.at,
+                post_id: previous.post_id,
+            });
+        }
+        expiry.insert(ExpiryKey { at: now, post_id }, key);
+    });
+}
+
+fn prune(now: u64) {
+    let expired: Vec<(ExpiryKey, RequestKey)> = EXPIRY.with(|expiry| {
+        expiry
+            .borrow()
+            .iter()
+            .take_while(|(at, _)| at.at + KEY_TTL <= now)
+            .take(PRUNE_BATCH)
+            .collect()
+    });
+    for (at, key) in expired {
+        EXPIRY.with(|expiry| expiry.borrow_mut().remove(&at));
+        REQUESTS.with(|requests| requests.borrow_mut().remove(&key));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::jobs::{self, Job};
+use crate::{
+    _get_blog_post, _save_votes, asset_sync, auth, authors, bookmarks, comments, do_insert,
+    follows, likes, Error, PostStatus,
+};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+// How long a migration proof stays valid, in nanoseconds
+const PROOF_TTL: u64 = 24 * 3600 * 1_000_000_000;
+
+// A migration the old principal asked for, waiting for the new principal to
+// accept it and then for an admin
+struct PendingMigration {
+    new: Principal,
+    proof: String,
+    expires_at: u64,
+    accepted: bool,
+}
+
+thread_local! {
+    // Kept on the heap like deletion tokens; after an upgrade the user simply
+    // asks again
+    static PENDING: RefCell<BTreeMap<Principal, PendingMigration>> =
+        const { RefCell::new(BTreeMap::new()) };
+
+    static NONCE: RefCell<u64> = const { RefCell::new(0) };
+}
+
+fn new_proof(old: &Principal, new: &Principal, now: u64) -> String {
+    let nonce = NONCE.with(|nonce| {
+        let mut nonce = nonce.borrow_mut();
+        *nonce += 1;
+        *nonce
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(old.as_slice());
+    hasher.update(new.as_slice());
+    hasher.update(now.to_be_bytes());
+    hasher.update(nonce.to_be_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// Update function for a user about to rotate identities to ask for their
+// content to move to `new`. Returns the proof an admin passes to
+// `migrate_identity` within a day, showing the old principal agreed; `new`
+// must agree too, with `accept_identity_migration`.
+#[ic_cdk::update]
+fn request_identity_migration(new: Principal) -> Result<String, Error> {
+    auth::require_signed_in()?;
+    let old = caller();
+    if new == old || new == Principal::anonymous() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Cannot migrate to {}.", new),
+        });
+    }
+    let now = time();
+    let proof = new_proof(&old, &new, now);
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.retain(|_, migration| migration.expires_at > now);
+        pending.insert(
+            old,
+            PendingMigration {
+                new,
+                proof: proof.clone(),
+                expires_at: now + PROOF_TTL,
+                accepted: false,
+            },
+        );
+    });
+    Ok(proof)
+}
+
+// Update function for the new principal to agree to take over the content
+// `old` asked to move to it
+#[ic_cdk::update]
+fn accept_identity_migration(old: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    PENDING.with(|pending| match pending.borrow_mut().get_mut(&old) {
+        Some(migration) if migration.new == caller() && migration.expires_at > time() => {
+            migration.accepted = true;
+            Ok(())
+        }
+        _ => Err(Error::NotFound {
+            msg: format!("{} has not asked to migrate to {}.", old, caller()),
+        }),
+    })
+}
+
+// Update function for admins to move the posts, comments, likes, follows and
+// bookmarks of `old` to `new`, with the proof from
+// `request_identity_migration`, once `new` accepted. The work runs as a
+// background job.
+#[ic_cdk::update]
+fn migrate_identity(old: Principal, new: Principal, proof: String) -> Result<Job, Error> {
+    auth::require_admin()?;
+    let pending = PENDING.with(|pending| pending.borrow_mut().remove(&old));
+    match pending {
+        Some(migration)
+            if migration.new == new && migration.proof == proof && migration.expires_at > time() =>
+        {
+            if !migration.accepted {
+                PENDING.with(|pending| pending.borrow_mut().insert(old, migration));
+                return Err(Error::NotAuthorized {
+                    msg: format!(
+                        "{} has not accepted the migration yet. It must call accept_identity_migration.",
+                        new
+                    ),
+                });
+            }
+            Ok(jobs::enqueue_migrate_identity(old, new))
+        }
+        _ => Err(Error::NotAuthorized {
+            msg: format!(
+                "The migration proof of {} is invalid or expired. It must call request_identity_migration again.",
+                old
+            ),
+        }),
+    }
+}
+
+// Give up to `limit` posts of `old` to `new`, returning how many moved
+fn reassign_posts(old: &Principal, new: &Principal, limit: usize) -> usize {
+    let ids = authors::newest_post_ids(old, limit);
+    for mut blog_post in ids.iter().filter_map(_get_blog_post) {
+        authors::unindex_post(blog_post.id, &blog_post.author);
+        blog_post.author = new.to_string();
+        authors::index_post(blog_post.id, &blog_post.author);
+        do_insert(&blog_post);
+        if blog_post.status == PostStatus::Published {
+            asset_sync::publish(&blog_post);
+        }
+    }
+    ids.len()
+}
+
+// Give up to `limit` likes and dislikes of `old` to `new`. A post both had
+// voted on loses the vote of `old`.
+fn reassign_likes(old: &Principal, new: &Principal, limit: usize) -> usize {
+    let (moved, dropped_likes, dropped_dislikes) = likes::reassign(old, new, limit);
+    for mut blog_post in dropped_likes.iter().filter_map(_get_blog_post) {
+        let old = blog_post.clone();
+        blog_post.likes = blog_post.likes.saturating_sub(1);
+        _save_votes(&old, &mut blog_post);
+    }
+    for mut blog_post in dropped_dislikes.iter().filter_map(_get_blog_post) {
+        let old = blog_post.clone();
+        blog_post.dislikes = blog_post.dislikes.saturating_sub(1);
+        _save_votes(&old, &mut blog_post);
+    }
+    moved
+}
+
+// Move up to `limit` items from `old` to `new`. Called by the job runner
+// until it reports that none is left; every item moves along with all its
+// index entries within one batch.
+pub(crate) fn migrate_batch(
+    old: &Principal,
+    new: &Principal,
+    limit: usize,
+) -> Result<(u64, bool), String> {
+    let steps: [fn(&Principal, &Principal, usize) -> usize; 5] = [
+        reassign_posts,
+        comments::reassign,
+        reassign_likes,
+        follows::reassign,
+        bookmarks::reassign,
+    ];
+    let mut processed = 0;
+    for step in steps {
+        if processed == limit {
+            break;
+        }
+        processed += step(old, new, limit - processed);
+    }
+    Ok((processed as u64, processed < limit))
+}
@@ -0,0 +1,76 @@
+use crate::config::InactivityPolicy;
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{_get_blog_post, asset_sync, date, do_insert, timeline, BlogPost, PostStatus};
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+const NANOS_PER_MONTH: u64 = 30 * date::NANOS_PER_DAY;
+
+thread_local! {
+    // Posts whose author was told they will be archived -> when they will be
+    static PENDING: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::INACTIVE_POSTS_MEMORY)));
+}
+
+fn is_inactive(blog_post: &BlogPost, created_before: u64) -> bool {
+    blog_post.status == PostStatus::Published
+        && blog_post.views == 0
+        && blog_post.likes == 0
+        && blog_post.created_at < created_before
+}
+
+// Archive the posts whose grace period is over and which are still inactive,
+// then tell the authors of up to `limit` more inactive posts when theirs will
+// be. Posts viewed or liked in the meantime are kept. Returns how many posts
+// were archived and how many authors were told.
+pub(crate) fn apply(policy: &InactivityPolicy, limit: usize) -> (usize, usize) {
+    let now = time();
+    let created_before = now.saturating_sub(u64::from(policy.months) * NANOS_PER_MONTH);
+    let due: Vec<u64> = PENDING.with(|pending| {
+        pending
+            .borrow()
+            .iter()
+            .filter(|(_, archive_at)| *archive_at <= now)
+            .map(|(post_id, _)| post_id)
+            .take(limit)
+            .collect()
+    });
+    let mut archived = 0;
+    for post_id in due {
+        PENDING.with(|pending| pending.borrow_mut().remove(&post_id));
+        if let Some(mut blog_post) = _get_blog_post(&post_id) {
+            if is_inactive(&blog_post, created_before) {
+                blog_post.status = PostStatus::Archived;
+                asset_sync::unpublish(&blog_post);
+                do_insert(&blog_post);
+                archived += 1;
+            }
+        }
+    }
+    let archive_at = now + u64::from(policy.grace_days) * date::NANOS_PER_DAY;
+    let warned: Vec<BlogPost> = timeline::never_viewed()
+        .into_iter()
+        .filter(|post_id| !PENDING.with(|pending| pending.borrow().contains_key(post_id)))
+        .filter_map(|post_id| _get_blog_post(&post_id))
+        .filter(|blog_post| is_inactive(blog_post, created_before))
+        .take(limit)
+        .collect();
+    for blog_post in &warned {
+        PENDING.with(|pending| pending.borrow_mut().insert(blog_post.id, archive_at));
+        notifications::notify_author(
+            &blog_post.author,
+            NotificationKind::InactivePost {
+                post_id: blog_post.id,
+                archive_at,
+            },
+        );
+    }
+    (archived, warned.len())
+}
+
+// Forget a deleted post
+pub(crate) fn remove_post(post_id: u64) {
+    PENDING.with(|pending| pending.borrow_mut().remove(&post_id));
+}
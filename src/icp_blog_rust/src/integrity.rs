@@ -0,0 +1,256 @@
+use crate::{
+    _get_blog_post, _save_votes, auth, authors, categories, config, likes, slugs, timeline, Error,
+    BLOG_POSTS,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use std::collections::BTreeMap;
+
+// Most issues a report lists; the rest are only counted
+const MAX_LISTED: usize = 500;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum IssueKind {
+    // an index entry whose post is gone or has changed since
+    OrphanedEntry,
+    // a post missing from an index
+    MissingEntry,
+    // a post past the bounds no configured limit may exceed
+    Oversize,
+    // a stored count that differs from what it counts
+    CounterDrift,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct IntegrityIssue {
+    kind: IssueKind,
+    // the map the issue was found in, e.g. "author_posts"
+    map: String,
+    post_id: Option<u64>,
+    detail: String,
+    repaired: bool,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct IntegrityReport {
+    posts: u64,
+    // issues found and repaired; `issues` lists the first `MAX_LISTED`
+    found: u64,
+    repaired: u64,
+    issues: Vec<IntegrityIssue>,
+    checked_at: u64,
+}
+
+impl IntegrityReport {
+    fn add(
+        &mut self,
+        kind: IssueKind,
+        map: &str,
+        post_id: Option<u64>,
+        detail: String,
+        repaired: bool,
+    ) {
+        self.found += 1;
+        if repaired {
+            self.repaired += 1;
+        }
+        if self.issues.len() < MAX_LISTED {
+            self.issues.push(IntegrityIssue {
+                kind,
+                map: map.to_string(),
+                post_id,
+                detail,
+                repaired,
+            });
+        }
+    }
+
+    fn orphans(&mut self, map: &str, post_ids: Vec<u64>, repaired: bool) {
+        for post_id in post_ids {
+            let detail = format!("Entry for post {} that is gone or has changed", post_id);
+            self.add(
+                IssueKind::OrphanedEntry,
+                map,
+                Some(post_id),
+                detail,
+                repaired,
+            );
+        }
+    }
+}
+
+// What the indexes and counters hold of a post
+struct Indexed {
+    author: String,
+    categories: Vec<String>,
+    slug: String,
+    ranks: [u64; 4],
+    likes: u32,
+    dislikes: u32,
+    score: i64,
+}
+
+fn is_author(posts: &BTreeMap<u64, Indexed>, post_id: u64, author: &Principal) -> bool {
+    posts
+        .get(&post_id)
+        .is_some_and(|post| post.author == author.to_text())
+}
+
+// Walk the posts, the indexes kept beside them and the counters derived from
+// them, listing what disagrees and fixing it when `repair` is set. Oversize
+// posts are only reported, as they can't be fixed without losing content.
+fn verify(repair: bool) -> IntegrityReport {
+    let mut report = IntegrityReport {
+        posts: 0,
+        found: 0,
+        repaired: 0,
+        issues: Vec::new(),
+        checked_at: time(),
+    };
+
+    // what each post should be indexed under, and which posts aren't
+    let mut posts: BTreeMap<u64, Indexed> = BTreeMap::new();
+    let mut unindexed = Vec::new();
+    BLOG_POSTS.with(|blog_posts| {
+        for (id, blog_post) in blog_posts.borrow().iter() {
+            if let Some(detail) = config::out_of_bounds(&blog_post) {
+                report.add(IssueKind::Oversize, "blog_posts", Some(id), detail, false);
+            }
+            let missing: Vec<&str> = [
+                ("author_posts", authors::is_indexed(id, &blog_post.author)),
+                (
+                    "category_posts",
+                    blog_post
+                        .categories
+                        .iter()
+                        .all(|category| categories::is_indexed(id, category)),
+                ),
+                ("sort_indexes", timeline::is_indexed(&blog_post)),
+                (
+                    "slugs",
+                    blog_post.slug.is_empty() || slugs::lookup(&blog_post.slug) == Some(id),
+                ),
+            ]
+            .into_iter()
+            .filter(|(_, indexed)| !indexed)
+            .map(|(map, _)| map)
+            .collect();
+            if !missing.is_empty() {
+                unindexed.push((id, missing));
+            }
+            posts.insert(
+                id,
+                Indexed {
+                    ranks: timeline::ranks(&blog_post),
+                    author: blog_post.author,
+                    categories: blog_post.categories,
+                    slug: blog_post.slug,
+                    likes: blog_post.likes,
+                    dislikes: blog_post.dislikes,
+                    score: blog_post.score,
+                },
+            );
+        }
+    });
+    report.posts = posts.len() as u64;
+
+    // entries left behind by posts that are gone or have changed
+    let stale = authors::stale_entries(|id, author| is_author(&posts, id, author), repair);
+    report.orphans("author_posts", stale, repair);
+    let stale = categories::stale_entries(
+        |id, category| {
+            posts
+                .get(&id)
+                .is_some_and(|post| post.categories.iter().any(|c| c == category))
+        },
+        repair,
+    );
+    report.orphans("category_posts", stale, repair);
+    let stale = timeline::stale_entries(|id| posts.get(&id).map(|post| post.ranks), repair);
+    report.orphans("sort_indexes", stale, repair);
+    for (slug, id) in slugs::stale_entries(
+        |id, slug| posts.get(&id).is_some_and(|post| post.slug == slug),
+        repair,
+    ) {
+        let detail = format!(
+            "Slug {} of post {} that is gone or has another slug",
+            slug, id
+        );
+        report.add(IssueKind::OrphanedEntry, "slugs", Some(id), detail, repair);
+    }
+    let stale = likes::stale_entries(|id| posts.contains_key(&id), repair);
+    report.orphans("likes", stale, repair);
+
+    // posts the indexes lack
+    for (id, missing) in unindexed {
+        let post = &posts[&id];
+        for map in missing {
+            let repaired = repair
+                && match map {
+                    "author_posts" => {
+                        authors::index_post(id, &post.author);
+                        true
+                    }
+                    "category_posts" => {
+                        categories::index_post(id, &post.categories);
+                        true
+                    }
+                    "sort_indexes" => _get_blog_post(&id).is_some_and(|blog_post| {
+                        timeline::index_post(&blog_post);
+                        true
+                    }),
+                    _ => slugs::restore_slug(&post.slug, id),
+                };
+            let detail = format!("Post {} is not indexed", id);
+            report.add(IssueKind::MissingEntry, map, Some(id), detail, repaired);
+        }
+    }
+
+    // counts kept on the posts and beside the indexes
+    let tallies = likes::tallies();
+    for (id, post) in &posts {
+        let (likes, dislikes) = tallies.get(id).copied().unwrap_or((0, 0));
+        if (post.likes, post.dislikes, post.score)
+            == (likes, dislikes, likes as i64 - dislikes as i64)
+        {
+            continue;
+        }
+        let detail = format!(
+            "{} likes, {} dislikes and a score of {} stored, but {} likes and {} dislikes recorded",
+            post.likes, post.dislikes, post.score, likes, dislikes
+        );
+        let repaired = repair
+            && _get_blog_post(id).is_some_and(|old| {
+                let mut blog_post = old.clone();
+                blog_post.likes = likes;
+                blog_post.dislikes = dislikes;
+                _save_votes(&old, &mut blog_post);
+                true
+            });
+        report.add(
+            IssueKind::CounterDrift,
+            "blog_posts",
+            Some(*id),
+            detail,
+            repaired,
+        );
+    }
+    for author in authors::count_drift(repair) {
+        let detail = format!("Number of posts of {} differs from the index", author);
+        report.add(IssueKind::CounterDrift, "authors", None, detail, repair);
+    }
+    for category in categories::count_drift(repair) {
+        let detail = format!("Number of posts in {} differs from the index", category);
+        report.add(IssueKind::CounterDrift, "categories", None, detail, repair);
+    }
+    report
+}
+
+// Update function for admins to check that the indexes and counters agree
+// with the posts, repairing what doesn't when `repair` is set. Walks every
+// entry, so it is meant for occasional use, e.g. after an upgrade.
+#[ic_cdk::update]
+fn verify_integrity(repair: bool) -> Result<IntegrityReport, Error> {
+    auth::require_admin()?;
+    Ok(verify(repair))
+}
@@ -0,0 +1,362 @@
+use crate::audit::{self, AuditAction};
+use crate::categories::{self, Retag};
+use crate::memory::{self, Memory};
+use crate::{auth, backup, erasure, identity, moderation, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+// Maximum number of items a job processes per timer tick, so a single
+// execution stays well within the instruction limit
+const BATCH_SIZE: usize = 50;
+// How long an armed timer may go without its batch finishing before it is
+// taken to be gone, and how often the watchdog looks for a stuck queue
+const STUCK_AFTER: Duration = Duration::from_secs(60);
+
+// Work that is too large for a single message and runs in the background
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum JobKind {
+    MergeCategories { from: String, into: String },
+    HideAuthorPosts { author: Principal },
+    DeleteMatchingComments { pattern: String },
+    MigrateIdentity { old: Principal, new: Principal },
+    EraseUserData { principal: Principal },
+    RetagPosts { retag: Box<Retag> },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed { reason: String },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Job {
+    id: u64,
+    kind: JobKind,
+    status: JobStatus,
+    processed: u64,
+    created_by: Principal,
+    created_at: u64,
+    finished_at: Option<u64>,
+}
+
+impl Storable for Job {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static JOB_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::JOB_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a job counter")
+    );
+
+    static JOBS: RefCell<StableBTreeMap<u64, Job, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::JOBS_MEMORY)));
+
+    // ID of the oldest job that may be unfinished. Jobs run one at a time in
+    // ID order, so every job before it has finished.
+    static FIRST_UNFINISHED: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::FIRST_UNFINISHED_JOB_MEMORY), 0)
+            .expect("Cannot create the first unfinished job cell")
+    );
+
+    // When a timer was armed to process the next batch, cleared once it is
+    // done. A batch that traps rolls the clearing back, so a timer armed more
+    // than `STUCK_AFTER` ago is no longer counted on.
+    static ARMED_AT: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+
+    // The job whose batch was handed to a message of its own. Still set when
+    // the next batch starts, it means that batch trapped.
+    static IN_FLIGHT: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+}
+
+// Queue a job that merges every post of `from` into `into`
+pub(crate) fn enqueue_merge_categories(from: String, into: String) -> Job {
+    enqueue(JobKind::MergeCategories { from, into })
+}
+
+// Queue a job that hides every published post of `author`
+pub(crate) fn enqueue_hide_author_posts(author: Principal) -> Job {
+    enqueue(JobKind::HideAuthorPosts { author })
+}
+
+// Queue a job that deletes every comment containing `pattern`
+pub(crate) fn enqueue_delete_matching_comments(pattern: String) -> Job {
+    enqueue(JobKind::DeleteMatchingComments { pattern })
+}
+
+// Queue a job that moves everything of `old` to `new`
+pub(crate) fn enqueue_migrate_identity(old: Principal, new: Principal) -> Job {
+    enqueue(JobKind::MigrateIdentity { old, new })
+}
+
+// Queue a job that adds and removes categories of every post a retag's
+// filter matches
+pub(crate) fn enqueue_retag_posts(retag: Retag) -> Job {
+    enqueue(JobKind::RetagPosts {
+        retag: Box::new(retag),
+    })
+}
+
+// Queue a job that drops what still refers to `principal` after it deleted
+// its data, unless one is already queued
+pub(crate) fn enqueue_erase_user_data(principal: Principal) {
+    let first = FIRST_UNFINISHED.with(|first| *first.borrow().get());
+    let queued = JOBS.with(|jobs| {
+        jobs.borrow().range(first..).any(|(_, job)| {
+            is_unfinished(&job)
+                && matches!(job.kind, JobKind::EraseUserData { principal: other } if other == principal)
+        })
+    });
+    if !queued {
+        enqueue(JobKind::EraseUserData { principal });
+    }
+}
+
+fn enqueue(kind: JobKind) -> Job {
+    let id = JOB_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the job counter");
+        id
+    });
+    let job = Job {
+        id,
+        kind,
+        status: JobStatus::Pending,
+        processed: 0,
+        created_by: caller(),
+        created_at: time(),
+        finished_at: None,
+    };
+    JOBS.with(|jobs| jobs.borrow_mut().insert(id, job.clone()));
+    schedule();
+    job
+}
+
+fn is_armed() -> bool {
+    ARMED_AT
+        .with(|armed| armed.get())
+        .is_some_and(|armed_at| time().saturating_sub(armed_at) < STUCK_AFTER.as_nanos() as u64)
+}
+
+fn arm(f: impl FnOnce() + 'static) {
+    ARMED_AT.with(|armed| armed.set(Some(time())));
+    ic_cdk_timers::set_timer(Duration::ZERO, f);
+}
+
+// Arm a timer to process the next batch, unless one is already armed
+pub(crate) fn schedule() {
+    if !is_armed() {
+        arm(start_next_batch);
+    }
+}
+
+// Resume queued work and keep watching for a queue left without a timer.
+// Timers don't survive upgrades, so this also runs from `post_upgrade`.
+pub(crate) fn start() {
+    schedule();
+    ic_cdk_timers::set_timer_interval(STUCK_AFTER, || {
+        if is_stalled() {
+            schedule();
+        }
+    });
+}
+
+// Whether jobs are waiting with no timer armed to run them
+pub(crate) fn is_stalled() -> bool {
+    !is_armed() && next_job().is_some()
+}
+
+fn is_unfinished(job: &Job) -> bool {
+    matches!(job.status, JobStatus::Pending | JobStatus::Running)
+}
+
+// The oldest unfinished job, moving `FIRST_UNFINISHED` past the finished
+// jobs before it
+fn next_job() -> Option<Job> {
+    let first = FIRST_UNFINISHED.with(|first| *first.borrow().get());
+    let job = JOBS.with(|jobs| {
+        jobs.borrow()
+            .range(first..)
+            .map(|(_, job)| job)
+            .find(is_unfinished)
+    });
+    let next = match &job {
+        Some(job) => job.id,
+        None => JOB_ID_COUNTER.with(|counter| *counter.borrow().get()),
+    };
+    if next != first {
+        FIRST_UNFINISHED.with(|first| {
+            first
+                .borrow_mut()
+                .set(next)
+                .expect("Cannot move the first unfinished job")
+        });
+    }
+    job
+}
+
+// Pick the job to work on and hand its next batch to a message of its own,
+// so that a batch that traps is noticed here and fails its job instead of
+// blocking the queue
+fn start_next_batch() {
+    ARMED_AT.with(|armed| armed.set(None));
+    if backup::is_restoring() {
+        return;
+    }
+    if let Some(id) = IN_FLIGHT.with(|in_flight| in_flight.take()) {
+        if let Some(mut job) = JOBS.with(|jobs| jobs.borrow().get(&id)) {
+            job.status = JobStatus::Failed {
+                reason: "A batch trapped".to_string(),
+            };
+            job.finished_at = Some(time());
+            JOBS.with(|jobs| jobs.borrow_mut().insert(id, job));
+        }
+    }
+    let Some(job) = next_job() else {
+        return;
+    };
+    IN_FLIGHT.with(|in_flight| in_flight.set(Some(job.id)));
+    arm(move || run_next_batch(job));
+}
+
+fn run_next_batch(mut job: Job) {
+    job.status = JobStatus::Running;
+    match audit::on_behalf_of(job.created_by, || run_batch(&job.kind)) {
+        Ok((processed, done)) => {
+            job.processed += processed;
+            if done {
+                job.status = JobStatus::Completed;
+                job.finished_at = Some(time());
+                // the report of an erasure is how many references it dropped
+                if let JobKind::EraseUserData { .. } = job.kind {
+                    audit::on_behalf_of(job.created_by, || {
+                        audit::record(AuditAction::EraseUserData, job.processed)
+                    });
+                }
+            }
+        }
+        Err(reason) => {
+            job.status = JobStatus::Failed { reason };
+            job.finished_at = Some(time());
+        }
+    }
+    JOBS.with(|jobs| jobs.borrow_mut().insert(job.id, job));
+    IN_FLIGHT.with(|in_flight| in_flight.set(None));
+    ARMED_AT.with(|armed| armed.set(None));
+    // keep going while there is queued work
+    schedule();
+}
+
+// Process one batch of a job, returning the number of items handled and
+// whether the job is finished
+fn run_batch(kind: &JobKind) -> Result<(u64, bool), String> {
+    match kind {
+        JobKind::MergeCategories { from, into } => categories::merge_batch(from, into, BATCH_SIZE),
+        JobKind::HideAuthorPosts { author } => {
+            moderation::hide_author_posts_batch(author, BATCH_SIZE)
+        }
+        JobKind::DeleteMatchingComments { pattern } => {
+            moderation::delete_matching_comments_batch(pattern, BATCH_SIZE)
+        }
+        JobKind::MigrateIdentity { old, new } => identity::migrate_batch(old, new, BATCH_SIZE),
+        JobKind::EraseUserData { principal } => erasure::erase_batch(principal, BATCH_SIZE),
+        JobKind::RetagPosts { retag } => categories::retag_batch(retag, BATCH_SIZE),
+    }
+}
+
+// Query function to get a background job by ID
+#[ic_cdk::query]
+fn get_job(id: u64) -> Result<Job, Error> {
+    auth::require_moderator()?;
+    match JOBS.with(|jobs| jobs.borrow().get(&id)) {
+        Some(job) => Ok(job),
+        None => Err(Error::NotFound {
+            msg: format!("Job with ID {} not found", id),
+        }),
+    }
+}
+
+// Query function to list background jobs, oldest first
+#[ic_cdk::query]
+fn list_jobs(offset: u64, limit: u64) -> Result<Vec<Job>, Error> {
+    auth::require_moderator()?;
+    Ok(JOBS.with(|jobs| {
+        jobs.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(_, job)| job)
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::principal;
+
+    fn insert_job(status: JobStatus) {
+        let id = JOB_ID_COUNTER.with(|counter| {
+            let id = *counter.borrow().get();
+            counter.borrow_mut().set(id + 1).unwrap();
+            id
+        });
+        let job = Job {
+            id,
+            kind: JobKind::EraseUserData {
+                principal: principal(1),
+            },
+            status,
+            processed: 0,
+            created_by: principal(2),
+            created_at: 0,
+            finished_at: None,
+        };
+        JOBS.with(|jobs| jobs.borrow_mut().insert(id, job));
+    }
+
+    fn first_unfinished() -> u64 {
+        FIRST_UNFINISHED.with(|first| *first.borrow().get())
+    }
+
+    #[test]
+    fn the_next_job_is_looked_up_from_the_first_unfinished_one() {
+        insert_job(JobStatus::Completed);
+        insert_job(JobStatus::Failed {
+            reason: "A batch trapped".to_string(),
+        });
+        insert_job(JobStatus::Pending);
+        insert_job(JobStatus::Pending);
+
+        assert_eq!(next_job().map(|job| job.id), Some(2));
+        assert_eq!(first_unfinished(), 2);
+
+        for id in 2..4 {
+            let mut job = JOBS.with(|jobs| jobs.borrow().get(&id)).unwrap();
+            job.status = JobStatus::Completed;
+            JOBS.with(|jobs| jobs.borrow_mut().insert(id, job));
+        }
+        assert!(next_job().is_none());
+        assert_eq!(first_unfinished(), 4);
+
+        insert_job(JobStatus::Pending);
+        assert_eq!(next_job().map(|job| job.id), Some(4));
+    }
+}
@@ -0,0 +1,97 @@
+// Keys of stable maps indexed by principal, shared by the modules keeping them
+use candid::Principal;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::Storable;
+use std::{borrow::Cow, ops::RangeInclusive};
+
+// Most bytes a principal takes in a key, prefixed with its length
+pub(crate) const PREFIXED_PRINCIPAL_SIZE: u32 = 1 + 29;
+
+// Key of a map with at most one entry per principal
+pub(crate) fn principal_key(principal: &Principal) -> Blob<29> {
+    Blob::try_from(principal.as_slice()).unwrap()
+}
+
+// Append a principal to a key, prefixed with its length so the parts after
+// it can be told apart
+pub(crate) fn put_principal(bytes: &mut Vec<u8>, principal: &[u8]) {
+    bytes.push(principal.len() as u8);
+    bytes.extend_from_slice(principal);
+}
+
+// Split the principal `put_principal` put at the start of a key from the
+// rest of the key
+pub(crate) fn take_principal(bytes: &[u8]) -> (Vec<u8>, &[u8]) {
+    let len = bytes[0] as usize;
+    (bytes[1..1 + len].to_vec(), &bytes[1 + len..])
+}
+
+// Key of a map with many entries per principal: the principal, prefixed with
+// its length, followed by a number, so the entries of a principal are one
+// contiguous range in the order of their numbers
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PrincipalIdKey {
+    pub(crate) principal: Vec<u8>,
+    pub(crate) id: u64,
+}
+
+impl Storable for PrincipalIdKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        put_principal(&mut bytes, &self.principal);
+        bytes.extend_from_slice(&self.id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (principal, rest) = take_principal(&bytes);
+        PrincipalIdKey {
+            principal,
+            id: u64::from_be_bytes(rest.try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: PREFIXED_PRINCIPAL_SIZE + 8,
+        is_fixed_size: false,
+    };
+}
+
+impl PrincipalIdKey {
+    pub(crate) fn new(principal: &Principal, id: u64) -> Self {
+        PrincipalIdKey {
+            principal: principal.as_slice().to_vec(),
+            id,
+        }
+    }
+
+    // Every key of a principal
+    pub(crate) fn all_of(principal: &Principal) -> RangeInclusive<Self> {
+        Self::new(principal, 0)..=Self::new(principal, u64::MAX)
+    }
+
+    pub(crate) fn principal(&self) -> Principal {
+        Principal::from_slice(&self.principal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn principal_id_keys_keep_their_layout_and_order() {
+        let principal = Principal::from_slice(&[7, 8]);
+        let key = PrincipalIdKey::new(&principal, 258);
+        let bytes = key.to_bytes().into_owned();
+        assert_eq!(bytes, vec![2, 7, 8, 0, 0, 0, 0, 0, 0, 1, 2]);
+
+        let decoded = PrincipalIdKey::from_bytes(Cow::Owned(bytes));
+        assert_eq!(decoded.principal(), principal);
+        assert_eq!(decoded.id, 258);
+
+        let range = PrincipalIdKey::all_of(&principal);
+        assert!(range.contains(&key));
+        assert!(!range.contains(&PrincipalIdKey::new(&Principal::from_slice(&[7]), 258)));
+    }
+}
@@ -1,16 +1,181 @@
 #[macro_use]
 extern crate serde;
 
+mod abuse;
+mod activity;
+mod analytics;
+mod archive;
+mod asset_sync;
+mod audit;
+mod auth;
+mod backup;
+mod blocklist;
+mod authors;
+mod bookmarks;
+mod bulk;
+mod capabilities;
+mod categories;
+mod changes;
+mod collaborators;
+mod comments;
+mod contact;
+mod config;
+mod credits;
+mod cycles;
+mod date;
+mod defaults;
+mod deletion;
+mod digest;
+mod discover;
+mod drafts;
+mod duplicates;
+mod embeds;
+mod erasure;
+mod emoji;
+mod epub;
+mod export;
+mod federation;
+mod feeds;
+mod follows;
+mod health;
+mod home_feed;
+mod honeypot;
+mod html;
+mod humanity;
+mod idempotency;
+mod inactivity;
+mod integrity;
+mod keys;
+mod identity;
+mod http;
+mod jobs;
+mod likes;
+mod linking;
+mod listing;
+mod maintenance;
+mod manifest;
+mod media;
+mod memory;
+mod mentions;
+mod migration;
+mod moderation;
+mod navigation;
+mod notifications;
+mod ownership;
+mod pages;
+mod paywall;
+mod previews;
+mod privacy;
+mod profiles;
+mod public_ids;
+mod quotas;
+mod rate_limits;
+mod reactions;
+mod referrals;
+mod related;
+mod render;
+mod rendered;
+mod reports;
+mod research;
+mod review;
+mod scheduling;
+mod series;
+mod sessions;
+mod slugs;
+mod sponsorship;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod timeline;
+mod tips;
+mod translations;
+mod trending;
+mod usernames;
+mod vetkd;
+mod views;
+mod visibility;
+mod webhooks;
+
 use validator::Validate;
 use candid::{Decode, Encode, Principal}; // Dependencies for serialization/deserialization
 use ic_cdk::api::{time, caller}; // Time-related functions from the IC SDK
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory}; // Custom memory management structures
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, StableBTreeMap, Storable}; // Custom data structures
+// outcall types, named apart from the responses served over HTTP
+use ic_cdk::api::management_canister::http_request::{HttpResponse as OutcallResponse, TransformArgs};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable}; // Custom data structures
+use memory::Memory;
+use serde_bytes::ByteBuf;
+// Types used in the exported Candid interface of other modules
+use abuse::AbuseSignals;
+use activity::ActivityEvent;
+use analytics::PostAnalytics;
+use archive::ArchiveConfig;
+use asset_sync::AssetSyncConfig;
+use audit::{AuditAction, AuditEntry, EventPage};
+use backup::BackupChunk;
+use blocklist::{BlockedTerm, FilterAction, Tier, TierActions};
+use bulk::BulkReport;
+use categories::CategoryReport;
+use changes::{ChangePayload, ChangeRequest};
+use collaborators::{Collaborator, CollaboratorRole};
+use comments::{Comment, CommentChunk, CommentPermalink, ThreadEntry};
+use contact::{ContactForwarding, ContactMessage, ContactPayload};
+use config::{CommentLimits, Config, ConfigPayload, FreezeWindow, InactivityPolicy, MaintenanceSchedule};
+use credits::Credit;
+use defaults::AuthorDefaults;
+use digest::Digest;
+use drafts::{Draft, DraftPayload};
+use duplicates::DuplicateGuard;
+use embeds::EmbedProvider;
+use emoji::EmojiInfo;
+use epub::EpubChunk;
+use health::HealthReport;
+use http::{HttpRequest, HttpResponse};
+use integrity::IntegrityReport;
+use jobs::Job;
+use likes::Liker;
+use listing::{Pagination, PostFilter};
+use manifest::ContentManifest;
+use media::MediaAsset;
+use moderation::{BulkOutcome, Report, ReportTarget};
+use navigation::NavLink;
+use notifications::{Notification, NotificationKind};
+use pages::{Page, PagePayload, PageSummary};
+use paywall::{Entitlement, PromoWindow};
+use referrals::ReferralStats;
+use federation::FederatedComment;
+use ownership::PostTransfer;
+use capabilities::{CanisterInfo, Capabilities};
+use mentions::Mention;
+use home_feed::FeedAlgorithm;
+use humanity::{HumanityVerifier, Verification};
+use previews::LinkPreview;
+use privacy::PrivacySettings;
+use profiles::{UserProfile, UserProfilePayload, UserSuggestion};
+use quotas::QueryQuota;
+use rendered::RenderedBlogPost;
+use reports::{MonthlyReport, ReportDelivery};
+use rate_limits::RateLimits;
+use reactions::{ReactionCount, ReactionKind};
+use research::{EngagementChunk, ExportFormat};
+use review::{ReviewNote, Reviewer};
+use scheduling::EmbargoAudience;
+use series::Series;
+use sessions::{DestructiveActionGuard, SessionActivity, SessionMetadata, WriteAction};
+use sponsorship::SponsorInfo;
+use stats::{SiteStats, Stats};
+use timeline::SortBy;
+use tips::{Account, PayoutSplit};
+use translations::Translation;
+use visibility::Visibility;
+use webhooks::Subscriber;
 use std::{borrow::Cow, cell::RefCell};
 
-type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdCell = Cell<u64, Memory>;
 
+// Most posts `get_blog_posts` returns in one call
+const MAX_BATCH_IDS: usize = 100;
+
 // Define a struct representing a blog post 
 #[derive(candid::CandidType, Clone, Serialize, Deserialize, Default)]
 struct BlogPost {
@@ -22,40 +187,138 @@ struct BlogPost {
     updated_at: Option<u64>,
     likes: u32,
     categories: Vec<String>,
-    liked: Vec<Principal>
+    reactions: Vec<ReactionCount>,
+    slug: String,
+    language: Option<String>,
+    // starts at 1 and goes up with every edit of the post's content
+    revision: u64,
+    // views counted by `record_view`, at most one per principal per day
+    views: u64,
+    status: PostStatus,
+    // when a draft is scheduled to be published, in nanoseconds
+    publish_at: Option<u64>,
+    // when the post is scheduled to go back to draft, in nanoseconds
+    unpublish_at: Option<u64>,
+    // when the post expires and is archived, in nanoseconds; kept once it is
+    expires_at: Option<u64>,
+    // display name from the author's profile; filled in when the post is
+    // read and never stored
+    author_name: Option<String>,
+    // the author's handle from `usernames`; filled in when the post is read
+    // and never stored
+    author_username: Option<String>,
+    // contributors other than the author, shown with the post
+    credits: Vec<Credit>,
+    // downvotes, at most one per principal and never alongside its like
+    dislikes: u32,
+    // likes minus dislikes, kept in step by `_save_votes`
+    score: i64,
+    // set on posts someone paid for, which then always show a disclosure
+    sponsored: Option<SponsorInfo>,
+    // of the content, kept in step by `_analyze_content` so listings can
+    // show them without the content
+    word_count: u32,
+    reading_minutes: u32,
+    // whether the content has math, which clients render with e.g. KaTeX
+    has_math: bool,
+    // whether the content has Mermaid or Graphviz diagrams for clients to draw
+    has_diagrams: bool,
+    // license the author shares the post under, such as "CC BY 4.0"
+    license: Option<String>,
+    // whether readers may comment on the post
+    comments_enabled: bool,
+    visibility: Visibility,
+    // whether the content is ciphertext that only the author and the
+    // principals a private post is shared with can decrypt, see `vetkd`
+    encrypted: bool,
+    // cards for the pages the content links to, set by `fetch_link_previews`
+    link_previews: Vec<LinkPreview>,
+    // opaque ID from `public_ids`, filled in when the post is read under the
+    // opaque ID policy and never stored
+    public_id: Option<String>,
+    // asset shown above the post, set with `set_cover_image`
+    cover_image: Option<u64>,
+}
+
+// Drafts, posts hidden by a moderator and expired posts are only visible to
+// their author; embargoed posts also to their early-access audience until
+// `publish_at`
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+enum PostStatus {
+    Draft,
+    #[default]
+    Published,
+    Hidden,
+    Embargoed,
+    Archived,
 }
 
 impl Storable for BlogPost {
     // Implement the `Storable` trait for serialization
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
-}
 
-impl BoundedStorable for BlogPost {
-    const MAX_SIZE: u32 = 1024; // Maximum size for the serialized data
-    const IS_FIXED_SIZE: bool = false; // Data size is not fixed
+    // Posts have no size limit; maps created with the former 1 KiB bound are
+    // migrated to the unbounded layout by ic-stable-structures when loaded
+    const BOUND: Bound = Bound::Unbounded;
 }
 
 // Thread-local storage for various components
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
-
     static ID_COUNTER: RefCell<IdCell> = RefCell::new(
-        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0))), 0)
+        IdCell::init(memory::get(memory::ID_COUNTER_MEMORY), 0)
             .expect("Cannot create a counter")
     );
 
     static BLOG_POSTS: RefCell<StableBTreeMap<u64, BlogPost, Memory>> =
-        RefCell::new(StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
-        ));
+        RefCell::new(StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY)));
+}
+
+// Fresh installs start out with the current schema
+#[ic_cdk::init]
+fn init() {
+    migration::stamp(migration::CURRENT_SCHEMA_VERSION);
+    auth::remember_admin();
+    trending::start();
+    discover::start();
+    research::ensure_salt();
+    public_ids::ensure_salt();
+    maintenance::start();
+    reports::start();
+    jobs::start();
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    // a restored backup keeps the version it was written with, so the next
+    // release migrates it
+    if backup::is_restoring() {
+        return;
+    }
+    // every record this release wrote uses the current layout; record that so
+    // the next release knows where to migrate from
+    migration::stamp(migration::CURRENT_SCHEMA_VERSION);
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // migrations must run before anything else reads the stable maps
+    migration::migrate();
+    auth::remember_admin();
+    // timers don't survive an upgrade, resume any queued background work
+    jobs::start();
+    scheduling::arm();
+    trending::start();
+    discover::start();
+    research::ensure_salt();
+    public_ids::ensure_salt();
+    maintenance::start();
+    reports::start();
 }
 
 // Define a struct for payload when creating or updating a blog post
@@ -65,46 +328,175 @@ struct BlogPostPayload {
     title: String,
     #[validate(length(min = 5))]
     content: String,
+    // the author's default categories are used when empty on create
+    #[validate(custom = "categories::validate_categories")]
     categories: Vec<String>,
+    // language the post is written in, as a BCP 47 tag such as "en"; kept
+    // when left out on updates
+    #[validate(custom = "translations::validate_language")]
+    language: Option<String>,
+    // create the post as a draft; the author's default when left out, and
+    // ignored on updates
+    draft: Option<bool>,
+    // the author's default when left out on create; kept when left out on
+    // updates
+    #[validate(length(min = 1, max = 100))]
+    license: Option<String>,
+    comments_enabled: Option<bool>,
+    // public when left out on create; kept when left out on updates
+    #[validate(custom = "visibility::validate")]
+    visibility: Option<Visibility>,
+    // the content is encrypted with the key of `get_post_encryption_key`;
+    // only for private posts. Off when left out on create; kept when left
+    // out on updates
+    encrypted: Option<bool>,
+    // key the client picks for this create, so a retried call returns the
+    // post the first one created instead of a duplicate; ignored on updates
+    idempotency_key: Option<String>,
+}
+
+// Payload of `patch_blog_post`; fields left out keep their current value
+#[derive(candid::CandidType, Serialize, Deserialize, Default, Validate)]
+struct PatchPayload {
+    #[validate(length(min = 1))]
+    title: Option<String>,
+    #[validate(length(min = 5))]
+    content: Option<String>,
+    #[validate(custom = "categories::validate_categories")]
+    categories: Option<Vec<String>>,
+    #[validate(length(min = 1, max = 100))]
+    license: Option<String>,
+    comments_enabled: Option<bool>,
+    #[validate(custom = "visibility::validate")]
+    visibility: Option<Visibility>,
+    encrypted: Option<bool>,
 }
 
 // Query function to get a blog post by ID
 #[ic_cdk::query]
 fn get_blog_post(id: u64) -> Result<BlogPost, Error> {
-    match _get_blog_post(&id) {
+    config::require_reader()?;
+    match _get_visible_blog_post(&id) {
         Some(blog_post) => Ok(blog_post),
-        None => Err(Error::NotFound {
-            msg: format!("Blog post with ID {} not found", id),
+        // published private posts are known to exist, but not their content
+        None if _get_blog_post(&id).is_some_and(|blog_post| {
+            blog_post.status == PostStatus::Published && visibility::is_private(&blog_post)
+        }) => Err(Error::NotAuthorized {
+            msg: format!("Blog post with ID {} is private.", id),
+        }),
+        // as are paywalled ones
+        None if _get_blog_post(&id).is_some_and(|blog_post| {
+            blog_post.status == PostStatus::Published && !paywall::can_read(&blog_post)
+        }) => Err(Error::NotAuthorized {
+            msg: format!("Blog post with ID {} is paywalled. Buy access to read it.", id),
         }),
+        None => Err(archive::not_here(id)),
+    }
+}
+
+// Query function to get many blog posts at once, one result per ID in the
+// order asked
+#[ic_cdk::query]
+fn get_blog_posts(ids: Vec<u64>) -> Result<Vec<Result<BlogPost, Error>>, Error> {
+    config::require_reader()?;
+    if ids.len() > MAX_BATCH_IDS {
+        return Err(Error::ValidationErrors {
+            errors: format!("At most {} posts can be fetched at once", MAX_BATCH_IDS),
+        });
     }
+    Ok(ids.into_iter().map(get_blog_post).collect())
 }
 
 // Update function to create a new blog post
 #[ic_cdk::update]
 fn create_blog_post(payload: BlogPostPayload) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    if let Some(key) = &payload.idempotency_key {
+        idempotency::validate_key(key)?;
+        if let Some(blog_post) = idempotency::existing_post(key) {
+            return Ok(blog_post);
+        }
+    }
     let check_payload = payload.validate();
     if check_payload.is_err(){
         return Err(Error::ValidationErrors { errors:  check_payload.err().unwrap().to_string()})
     }
+    let visibility = payload.visibility.unwrap_or_default();
+    let encrypted = payload.encrypted.unwrap_or(false);
+    vetkd::check(encrypted, &visibility)?;
+    let defaults = defaults::of(&caller());
+    let categories = if payload.categories.is_empty() { defaults.categories } else { payload.categories };
+    config::check_post(Some(&payload.title), Some(&payload.content), Some(&categories))?;
+    let content = if encrypted { payload.content } else { render::sanitize(&payload.content) };
+    let (title, content, warned) = blocklist::screen_post(payload.title, content, encrypted)?;
+    duplicates::check(&caller(), &content)?;
+    rate_limits::check(rate_limits::Action::CreatePost)?;
     let id = generate_unique_id();
-    let liked: Vec<Principal> = Vec::new(); // initializes an empty Vec for the liked field
 
     if id.is_none() {
         return Err(Error::NotFound{msg: "lol".to_string()})
     }
+    let id = id.unwrap();
+    public_ids::assign(id);
+    let slug = slugs::assign_slug(id, &title);
+    let word_count = render::word_count(&content);
+    let has_math = render::has_math(&content);
+    let has_diagrams = render::has_diagrams(&content);
     let blog_post = BlogPost {
-        id: id.unwrap(),
-        title: payload.title,
-        content: payload.content,
-        author: caller().to_string(), // the Principal of the caller is saved as the author of the post
+        id,
+        title,
+        content,
+        author: linking::primary(&caller()).to_string(), // the Principal of the caller is saved as the author of the post
         created_at: time(),
         updated_at: None,
         likes: 0,
-        categories: payload.categories,
-        liked
+        categories,
+        reactions: Vec::new(),
+        slug,
+        language: payload.language.map(|language| language.to_ascii_lowercase()),
+        revision: 1,
+        views: 0,
+        status: if payload.draft.unwrap_or(defaults.draft) { PostStatus::Draft } else { PostStatus::Published },
+        publish_at: None,
+        unpublish_at: None,
+        expires_at: None,
+        author_name: profiles::display_name(&caller()),
+        author_username: usernames::username(&caller()),
+        credits: Vec::new(),
+        sponsored: None,
+        dislikes: 0,
+        score: 0,
+        word_count,
+        reading_minutes: render::reading_minutes(word_count),
+        has_math,
+        has_diagrams,
+        license: payload.license.or(defaults.license),
+        comments_enabled: payload.comments_enabled.unwrap_or(defaults.comments_enabled),
+        visibility,
+        encrypted,
+        link_previews: Vec::new(),
+        public_id: None,
+        cover_image: None,
     };
 
     do_insert(&blog_post);
+    rendered::store(&blog_post);
+    categories::index_post(blog_post.id, &blog_post.categories);
+    authors::index_post(blog_post.id, &blog_post.author);
+    timeline::index_post(&blog_post);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+        activity::log_published(&blog_post);
+        webhooks::notify_published(&blog_post);
+        mentions::notify_post(None, &blog_post);
+    }
+    sessions::record(WriteAction::CreatePost, blog_post.id);
+    audit::record(AuditAction::CreatePost, blog_post.id);
+    blocklist::warn(ReportTarget::Post(blog_post.id), &warned);
+    if let Some(key) = &payload.idempotency_key {
+        idempotency::record(key, blog_post.id);
+    }
     Ok(blog_post)
 }
 
@@ -124,13 +516,16 @@ fn generate_unique_id() -> Option<u64> {
     }
 }
 
-// Update function to update an existing blog post
+// Update function to update an existing blog post. With
+// `expected_updated_at`, see `_check_unchanged`.
 #[ic_cdk::update]
-fn update_blog_post(id: u64, payload: BlogPostPayload) -> Result<BlogPost, Error> {
+fn update_blog_post(id: u64, payload: BlogPostPayload, expected_updated_at: Option<u64>) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
     match _get_blog_post(&id){
         Some(mut blog_post) => {
-            // if caller isn't the author, return an error
-            if !_check_if_owner(&blog_post){
+            // if caller isn't the author or an editor, return an error
+            if !_check_if_owner(&blog_post) && !collaborators::can_edit(&blog_post){
                 return Err(Error::NotAuthorized {
                     msg: format!(
                         "Unauthorized to update post with id={}. post not found",
@@ -138,16 +533,49 @@ fn update_blog_post(id: u64, payload: BlogPostPayload) -> Result<BlogPost, Error
                     ),
                 })
             }
+            _check_unchanged(&blog_post, expected_updated_at)?;
             let check_payload = payload.validate();
             if check_payload.is_err(){
                 return Err(Error::ValidationErrors { errors:  check_payload.err().unwrap().to_string()})
             }
-            blog_post.title = payload.title;
-            blog_post.content = payload.content;
+            config::check_post(Some(&payload.title), Some(&payload.content), Some(&payload.categories))?;
+            let encrypted = payload.encrypted.unwrap_or(blog_post.encrypted);
+            vetkd::check(encrypted, payload.visibility.as_ref().unwrap_or(&blog_post.visibility))?;
+            let content = if encrypted { payload.content } else { render::sanitize(&payload.content) };
+            let (title, content, warned) = blocklist::screen_post(payload.title, content, encrypted)?;
+            let previous = blog_post.content.clone();
+            categories::reindex_post(id, &blog_post.categories, &payload.categories);
+            blog_post.title = title;
+            blog_post.content = content;
+            _analyze_content(&mut blog_post);
             blog_post.categories = payload.categories;
+            if let Some(language) = payload.language {
+                blog_post.language = Some(language.to_ascii_lowercase());
+            }
+            if let Some(license) = payload.license {
+                blog_post.license = Some(license);
+            }
+            if let Some(comments_enabled) = payload.comments_enabled {
+                blog_post.comments_enabled = comments_enabled;
+            }
+            if let Some(visibility) = payload.visibility {
+                blog_post.visibility = visibility;
+            }
+            if let Some(encrypted) = payload.encrypted {
+                blog_post.encrypted = encrypted;
+            }
             blog_post.updated_at = Some(time());
+            blog_post.revision += 1;
             
     do_insert(&blog_post);
+    rendered::store(&blog_post);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    mentions::notify_post(Some(&previous), &blog_post);
+    sessions::record(WriteAction::UpdatePost, id);
+    audit::record(AuditAction::UpdatePost, id);
+    blocklist::warn(ReportTarget::Post(id), &warned);
     Ok(blog_post)
         }
         None => Err(Error::NotFound {
@@ -157,10 +585,92 @@ fn update_blog_post(id: u64, payload: BlogPostPayload) -> Result<BlogPost, Error
 
 }
 
+// Update function to change only some fields of a blog post. Editing the
+// title or content counts as a new revision; categories alone don't. With
+// `expected_updated_at`, see `_check_unchanged`.
+#[ic_cdk::update]
+fn patch_blog_post(id: u64, payload: PatchPayload, expected_updated_at: Option<u64>) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let Some(mut blog_post) = _get_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot update.", id),
+        });
+    };
+    if !_check_if_owner(&blog_post) && !collaborators::can_edit(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to update post with id={}.", id),
+        });
+    }
+    _check_unchanged(&blog_post, expected_updated_at)?;
+    if let Err(errors) = payload.validate() {
+        return Err(Error::ValidationErrors { errors: errors.to_string() });
+    }
+    config::check_post(payload.title.as_deref(), payload.content.as_deref(), payload.categories.as_deref())?;
+    let encrypted = payload.encrypted.unwrap_or(blog_post.encrypted);
+    vetkd::check(encrypted, payload.visibility.as_ref().unwrap_or(&blog_post.visibility))?;
+    let edits_content = payload.title.is_some() || payload.content.is_some();
+    let previous = blog_post.content.clone();
+    let mut warned = Vec::new();
+    if let Some(title) = payload.title {
+        let (title, warned_in_title) = blocklist::screen(title)?;
+        warned.extend(warned_in_title);
+        blog_post.title = title;
+    }
+    if let Some(content) = payload.content {
+        let (content, warned_in_content) = if encrypted { (content, Vec::new()) } else { blocklist::screen(render::sanitize(&content))? };
+        warned.extend(warned_in_content);
+        blog_post.content = content;
+        _analyze_content(&mut blog_post);
+    }
+    if let Some(categories) = payload.categories {
+        categories::reindex_post(id, &blog_post.categories, &categories);
+        blog_post.categories = categories;
+    }
+    if let Some(license) = payload.license {
+        blog_post.license = Some(license);
+    }
+    if let Some(comments_enabled) = payload.comments_enabled {
+        blog_post.comments_enabled = comments_enabled;
+    }
+    if let Some(visibility) = payload.visibility {
+        blog_post.visibility = visibility;
+    }
+    if let Some(encrypted) = payload.encrypted {
+        blog_post.encrypted = encrypted;
+    }
+    blog_post.updated_at = Some(time());
+    if edits_content {
+        blog_post.revision += 1;
+    }
+    do_insert(&blog_post);
+    rendered::store(&blog_post);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    mentions::notify_post(Some(&previous), &blog_post);
+    sessions::record(WriteAction::UpdatePost, id);
+    audit::record(AuditAction::UpdatePost, id);
+    blocklist::warn(ReportTarget::Post(id), &warned);
+    Ok(blog_post)
+}
 
-// Update function to delete a blog post by ID
+// Update function to delete a blog post by ID. Posts that were edited or
+// commented on must go through `request_delete` and `confirm_delete` instead.
 #[ic_cdk::update]
 fn delete_blog_post(id: u64) -> Result<BlogPost, Error> {
+    let blog_post = _check_deletable(id)?;
+    if deletion::needs_confirmation(&blog_post) {
+        return Err(Error::ConfirmationRequired {
+            msg: format!("Blog post with ID {} has revisions or comments. Use request_delete and confirm_delete.", id),
+        });
+    }
+    Ok(_delete_blog_post(blog_post))
+}
+
+// Helper function to check that the caller may delete a blog post
+fn _check_deletable(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
     match _get_blog_post(&id) {
         Some(blog_post) => {
             // if caller isn't the author, return an error
@@ -172,14 +682,8 @@ fn delete_blog_post(id: u64) -> Result<BlogPost, Error> {
                     ),
                 })
             }
-            // posts that currently have likes can't be deleted
-            if blog_post.likes > 0 {
-                return Err(Error::HasLikes {
-                    msg: format!("Blog post with ID {} has likes. Cannot delete.", id),
-                });
-            }
-            // delete post from memory
-            BLOG_POSTS.with(|service| service.borrow_mut().remove(&id));
+            sessions::require_trusted_origin()?;
+            config::check_deletable(&blog_post)?;
             Ok(blog_post)
         }
         None => Err(Error::NotFound {
@@ -188,10 +692,45 @@ fn delete_blog_post(id: u64) -> Result<BlogPost, Error> {
     }
 }
 
-// Update function to increment the "likes" count of a blog post
+// Helper function to delete a blog post along with everything attached to it
+fn _delete_blog_post(blog_post: BlogPost) -> BlogPost {
+    let id = blog_post.id;
+    // delete post from memory
+    BLOG_POSTS.with(|service| service.borrow_mut().remove(&id));
+    categories::unindex_post(id, &blog_post.categories);
+    authors::unindex_post(id, &blog_post.author);
+    timeline::unindex_post(&blog_post);
+    reactions::remove_post_reactions(id);
+    likes::remove_post_votes(id);
+    bookmarks::remove_post(id);
+    translations::remove_post_translations(id);
+    slugs::release_slug(&blog_post.slug);
+    public_ids::release(id);
+    asset_sync::unpublish(&blog_post);
+    scheduling::unschedule(&blog_post);
+    scheduling::unschedule_unpublish(&blog_post);
+    scheduling::unschedule_expiry(&blog_post);
+    comments::remove_post_comments(id);
+    review::remove_post_notes(id);
+    collaborators::remove_post_collaborators(id);
+    inactivity::remove_post(id);
+    tips::remove_post_splits(id);
+    ownership::remove_post_transfer(id);
+    rendered::remove(id);
+    paywall::remove_post_price(id);
+    changes::remove_post_changes(id);
+    sessions::record(WriteAction::DeletePost, id);
+    audit::record(AuditAction::DeletePost, id);
+    blog_post
+}
+
+// Update function to increment the "likes" count of a blog post. A downvote
+// by the caller is taken back.
 #[ic_cdk::update]
 fn like_blog_post(id: u64) -> Result<BlogPost, Error> {
-    match _get_blog_post(&id) {
+    auth::require_signed_in_to_like()?;
+    humanity::require_human()?;
+    match _get_visible_blog_post(&id) {
         Some(mut blog_post) => { 
             if blog_post.likes == u32::MAX {
                 return Err(Error::MaxLikes {
@@ -199,17 +738,23 @@ fn like_blog_post(id: u64) -> Result<BlogPost, Error> {
                 });
             }
             let user_principal = caller();
-            // Search for the index of the caller in the liked array
-            let user_index = blog_post.liked.iter().position(|&user| user.to_string() == user_principal.to_string());
-            // if an index is returned, return an error as users can only like once
-            if user_index.is_some(){
+            // users can only like once
+            if likes::has_liked(id, &user_principal) {
                 return Err(Error::AlreadyLiked {
                     msg: format!("Blog post with ID {} has already been liked by caller: {}.", id, user_principal),
                 });
             }
+            rate_limits::check(rate_limits::Action::Interact)?;
+            let old = blog_post.clone();
+            if likes::remove_dislike(id, &user_principal) {
+                blog_post.dislikes -= 1;
+            }
             blog_post.likes += 1;
-            blog_post.liked.push(user_principal);
-            do_insert(&blog_post);
+            likes::add_like(id, &user_principal, time());
+            _save_votes(&old, &mut blog_post);
+            activity::log(&user_principal, activity::ActivityKind::Liked { post_id: id });
+            notifications::notify_author(&blog_post.author, NotificationKind::Liked { post_id: id });
+            audit::record(AuditAction::LikePost, id);
             Ok(blog_post.clone())
         }
         None => Err(Error::NotFound {
@@ -218,37 +763,91 @@ fn like_blog_post(id: u64) -> Result<BlogPost, Error> {
     }
 }
 
-// Update function to decrement the "likes" count of a blog post
+// Update function to take back the caller's like of a blog post
 #[ic_cdk::update]
-fn dislike_blog_post(id: u64) -> Result<BlogPost, Error> {
-    match _get_blog_post(&id) {
+fn unlike_blog_post(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in_to_like()?;
+    match _get_visible_blog_post(&id) {
         Some(mut blog_post) => {
             if blog_post.likes == 0 {
                 return Err(Error::MinLikes {
                     msg: format!("Blog post with ID {} already at minimum likes.", id),
                 });
             }
-            // Search for the index of the caller in the liked array
-            let user_index = blog_post.liked.iter().position(|&user| user.to_string() == caller().to_string());
-            // if no index was found, return an error as only users that liked the post can dislike
-            if user_index.is_none(){
+            // only users that liked the post can unlike
+            if !likes::remove_like(id, &caller()) {
                 return Err(Error::NotLiked {
                     msg: format!("Blog post with ID {} hasn't yet been liked by caller: {}.", id, caller()),
                 });
             }
 
+            let old = blog_post.clone();
             blog_post.likes -= 1;
-            // delete caller from the liked field
-            blog_post.liked.swap_remove(user_index.unwrap());
-            do_insert(&blog_post);
+            _save_votes(&old, &mut blog_post);
+            audit::record(AuditAction::UnlikePost, id);
             Ok(blog_post.clone())
         }
         None => Err(Error::NotFound {
-            msg: format!("Blog post with ID {} not found. Cannot dislike.", id),
+            msg: format!("Blog post with ID {} not found. Cannot unlike.", id),
         }),
     }
 }
 
+// Update function to downvote a blog post. A like by the caller is taken
+// back, as a principal has one vote per post.
+#[ic_cdk::update]
+fn dislike_blog_post(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    humanity::require_human()?;
+    let Some(mut blog_post) = _get_visible_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot dislike.", id),
+        });
+    };
+    if blog_post.dislikes == u32::MAX {
+        return Err(Error::MaxLikes {
+            msg: format!("Blog post with ID {} already at maximum dislikes.", id),
+        });
+    }
+    let user_principal = caller();
+    if likes::has_disliked(id, &user_principal) {
+        return Err(Error::AlreadyDisliked {
+            msg: format!("Blog post with ID {} has already been disliked by caller: {}.", id, user_principal),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+    let old = blog_post.clone();
+    if likes::remove_like(id, &user_principal) {
+        blog_post.likes -= 1;
+    }
+    blog_post.dislikes += 1;
+    likes::add_dislike(id, &user_principal, time());
+    _save_votes(&old, &mut blog_post);
+    audit::record(AuditAction::DislikePost, id);
+    Ok(blog_post)
+}
+
+// Update function to take back the caller's downvote of a blog post
+#[ic_cdk::update]
+fn undislike_blog_post(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_visible_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot undislike.", id),
+        });
+    };
+    if !likes::remove_dislike(id, &caller()) {
+        return Err(Error::NotDisliked {
+            msg: format!("Blog post with ID {} hasn't yet been disliked by caller: {}.", id, caller()),
+        });
+    }
+    let old = blog_post.clone();
+    blog_post.dislikes = blog_post.dislikes.saturating_sub(1);
+    _save_votes(&old, &mut blog_post);
+    audit::record(AuditAction::UndislikePost, id);
+    Ok(blog_post)
+}
+
 // Define an enum to represent errors
 #[derive(candid::CandidType, Deserialize, Serialize)]
 enum Error {
@@ -260,25 +859,152 @@ enum Error {
     HasLikes { msg: String },
     MaxLikes { msg: String },
     MinLikes { msg: String },
+    AlreadyReacted { msg: String },
+    NotReacted { msg: String },
+    AlreadyBookmarked { msg: String },
+    NotBookmarked { msg: String },
+    AlreadyFollowing { msg: String },
+    NotFollowing { msg: String },
+    RateLimited { msg: String },
+    UntrustedOrigin { msg: String },
+    ConfirmationRequired { msg: String },
+    TransferFailed { msg: String },
+    AnonymousNotAllowed { msg: String },
+    DuplicatePost { msg: String, existing_id: u64 },
+    ConflictingEdit { msg: String },
+    AlreadyDisliked { msg: String },
+    NotDisliked { msg: String },
+    Archived { msg: String, archive_canister: Principal },
+    ContentFrozen { msg: String, until: u64 },
+    CanisterLowOnCycles { msg: String },
+    CommentTooLong { msg: String, max_len: u32 },
+    TooManyLinks { msg: String, max_links: u32 },
+    UnsupportedFormatting { msg: String },
+    NotVerifiedHuman { msg: String },
 }
 
-// Helper function to check whether the caller is the author of the blog post
+// Helper function to check whether the caller is the author of the blog post,
+// calling from any of their linked principals
 fn _check_if_owner(blog_post: &BlogPost) -> bool {
-    if blog_post.author.to_string() != caller().to_string(){
-        false  
-    }else{
-        true
+    Principal::from_text(&blog_post.author).is_ok_and(|author| linking::same_person(&author, &caller()))
+}
+
+// Helper function to refuse an edit based on an outdated copy of a post.
+// Editors pass the `updated_at` of the copy they edited, or its `created_at`
+// if it was never updated; `None` skips the check.
+fn _check_unchanged(blog_post: &BlogPost, expected_updated_at: Option<u64>) -> Result<(), Error> {
+    let Some(expected) = expected_updated_at else {
+        return Ok(());
+    };
+    if blog_post.updated_at.unwrap_or(blog_post.created_at) != expected {
+        return Err(Error::ConflictingEdit {
+            msg: format!("Blog post with ID {} was changed since it was read. Reload it and edit again.", blog_post.id),
+        });
     }
+    Ok(())
+}
+
+// Helper function to store a post whose likes or dislikes changed, updating
+// its score and moving it within the sort indexes that rank by them
+fn _save_votes(old: &BlogPost, blog_post: &mut BlogPost) {
+    blog_post.score = blog_post.likes as i64 - blog_post.dislikes as i64;
+    do_insert(blog_post);
+    timeline::rerank(SortBy::MostLiked, blog_post.id, old.likes as u64, blog_post.likes as u64);
+    timeline::rerank(SortBy::TopScore, blog_post.id, timeline::score_rank(old.score), timeline::score_rank(blog_post.score));
 }
 
 // Helper function to insert a blog post into the data store
 fn do_insert(blog_post: &BlogPost) {
-    BLOG_POSTS.with(|service| service.borrow_mut().insert(blog_post.id, blog_post.clone()));
+    let blog_post = BlogPost { author_name: None, author_username: None, public_id: None, ..blog_post.clone() };
+    BLOG_POSTS.with(|service| service.borrow_mut().insert(blog_post.id, blog_post));
+}
+
+// Helper function to update what is derived from a post's content after it
+// changed
+fn _analyze_content(blog_post: &mut BlogPost) {
+    blog_post.word_count = render::word_count(&blog_post.content);
+    blog_post.reading_minutes = render::reading_minutes(blog_post.word_count);
+    blog_post.has_math = render::has_math(&blog_post.content);
+    blog_post.has_diagrams = render::has_diagrams(&blog_post.content);
+    // previews of links that were edited out go
+    let links = render::links(&blog_post.content);
+    blog_post.link_previews.retain(|preview| links.iter().any(|link| link == preview.url()));
 }
 
 // Helper function to retrieve a blog post by ID
 fn _get_blog_post(id: &u64) -> Option<BlogPost> {
-    BLOG_POSTS.with(|service| service.borrow().get(id))
+    BLOG_POSTS.with(|service| service.borrow().get(id)).map(_with_author_name)
+}
+
+// Helper function to resolve the author of a blog post to their display name
+// and username, and to fill in its public ID
+fn _with_author_name(mut blog_post: BlogPost) -> BlogPost {
+    let author = Principal::from_text(&blog_post.author).ok();
+    blog_post.author_name = author.and_then(|author| profiles::display_name(&author));
+    blog_post.author_username = author.and_then(|author| usernames::username(&author));
+    public_ids::expose(&mut blog_post);
+    blog_post
+}
+
+// Helper function to name the author of a blog post for readers: their
+// display name, else their username, else their principal
+fn _author_label(blog_post: &BlogPost) -> &str {
+    blog_post
+        .author_name
+        .as_deref()
+        .or(blog_post.author_username.as_deref())
+        .unwrap_or(&blog_post.author)
+}
+
+// Helper function to retrieve a blog post the caller may see: published posts
+// for everyone, embargoed posts for their audience, drafts for their author
+// and the reviewers they delegated to, and any post for its co-authors
+fn _get_visible_blog_post(id: &u64) -> Option<BlogPost> {
+    _get_blog_post(id).filter(|blog_post| {
+        _check_if_owner(blog_post)
+            || review::can_review(blog_post)
+            || collaborators::can_view(blog_post)
+            || (visibility::can_see(blog_post)
+                && (blog_post.status == PostStatus::Published
+                    || (blog_post.status == PostStatus::Embargoed && scheduling::in_embargo_audience(blog_post))))
+    })
+}
+
+// Helper function to collect the `limit` newest published blog posts matching
+// a filter, newest first. Post IDs grow with creation time, so this walks the
+// map backwards from the highest ID.
+fn _newest_blog_posts(limit: usize, filter: impl Fn(&BlogPost) -> bool) -> Vec<BlogPost> {
+    BLOG_POSTS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .rev()
+            .map(|(_, blog_post)| blog_post)
+            .filter(|blog_post| blog_post.status == PostStatus::Published && filter(blog_post))
+            .take(limit)
+            .map(_with_author_name)
+            .collect()
+    })
+}
+
+// Helper function to collect the `limit` oldest blog posts of any status
+// matching a filter, oldest first
+fn _oldest_blog_posts(limit: usize, filter: impl Fn(&BlogPost) -> bool) -> Vec<BlogPost> {
+    BLOG_POSTS.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .map(|(_, blog_post)| blog_post)
+            .filter(|blog_post| filter(blog_post))
+            .take(limit)
+            .collect()
+    })
+}
+
+// Helper function to count every stored blog post, drafts and hidden ones
+// included
+fn _count_blog_posts() -> u64 {
+    BLOG_POSTS.with(|service| service.borrow().len())
 }
 
 // Export Candid interface for the Dapp
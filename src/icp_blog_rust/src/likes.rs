@@ -0,0 +1,503 @@
+use crate::keys;
+use crate::listing::Pagination;
+use crate::memory::{self, Memory};
+use crate::{_get_visible_blog_post, auth, privacy, profiles, BlogPost, Error};
+use candid::Principal;
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `get_likers` returns
+const MAX_LIKERS_PAGE: u64 = 100;
+// Largest page `get_my_likes` returns
+const MAX_MY_LIKES_PAGE: u64 = 50;
+
+// A principal that liked a post, as listed by `get_post_likers`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Liker {
+    principal: Principal,
+    // from the liker's profile, if they set one up
+    display_name: Option<String>,
+}
+
+// Key of the likes map: the post id followed by the principal that liked it,
+// so all likes of a post are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct LikeKey {
+    post_id: u64,
+    principal: Vec<u8>,
+}
+
+impl Storable for LikeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        LikeKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            principal: bytes[8..].to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + 29,
+        is_fixed_size: false,
+    };
+}
+
+impl LikeKey {
+    fn new(post_id: u64, principal: &Principal) -> Self {
+        LikeKey {
+            post_id,
+            principal: principal.as_slice().to_vec(),
+        }
+    }
+}
+
+// Key of the principal -> likes index: the principal, prefixed with its
+// length, followed by the time of the like and the post id, so the likes of a
+// principal are one contiguous range in the order they were given
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct LikedPost {
+    principal: Vec<u8>,
+    liked_at: u64,
+    post_id: u64,
+}
+
+impl Storable for LikedPost {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        keys::put_principal(&mut bytes, &self.principal);
+        bytes.extend_from_slice(&self.liked_at.to_be_bytes());
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (principal, rest) = keys::take_principal(&bytes);
+        LikedPost {
+            principal,
+            liked_at: u64::from_be_bytes(rest[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(rest[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: keys::PREFIXED_PRINCIPAL_SIZE + 8 + 8,
+        is_fixed_size: false,
+    };
+}
+
+impl LikedPost {
+    fn new(principal: &Principal, liked_at: u64, post_id: u64) -> Self {
+        LikedPost {
+            principal: principal.as_slice().to_vec(),
+            liked_at,
+            post_id,
+        }
+    }
+}
+
+thread_local! {
+    // (post, principal) -> time of the like. Likes recorded before this map
+    // existed were migrated with a time of 0.
+    static LIKES: RefCell<StableBTreeMap<LikeKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::LIKES_MEMORY)));
+
+    // (post, principal) -> time of the downvote
+    static DISLIKES: RefCell<StableBTreeMap<LikeKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::DISLIKES_MEMORY)));
+
+    // The likes map turned around, so a principal can list what they liked
+    static LIKED_POSTS: RefCell<StableBTreeMap<LikedPost, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::LIKED_POSTS_MEMORY)));
+
+    // The dislikes map turned around in the same way
+    static DISLIKED_POSTS: RefCell<StableBTreeMap<LikedPost, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::DISLIKED_POSTS_MEMORY)));
+}
+
+// Number of likes of every post
+pub(crate) fn count() -> u64 {
+    LIKES.with(|likes| likes.borrow().len())
+}
+
+pub(crate) fn has_liked(post_id: u64, principal: &Principal) -> bool {
+    LIKES.with(|likes| {
+        likes
+            .borrow()
+            .contains_key(&LikeKey::new(post_id, principal))
+    })
+}
+
+// Record a like, returning false if the principal already liked the post
+pub(crate) fn add_like(post_id: u64, principal: &Principal, liked_at: u64) -> bool {
+    let previous = LIKES.with(|likes| {
+        likes
+            .borrow_mut()
+            .insert(LikeKey::new(post_id, principal), liked_at)
+    });
+    LIKED_POSTS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&LikedPost::new(principal, previous, post_id));
+        }
+        index.insert(LikedPost::new(principal, liked_at, post_id), ());
+    });
+    previous.is_none()
+}
+
+// Remove a like, returning false if the principal hadn't liked the post
+pub(crate) fn remove_like(post_id: u64, principal: &Principal) -> bool {
+    let removed = LIKES.with(|likes| likes.borrow_mut().remove(&LikeKey::new(post_id, principal)));
+    if let Some(liked_at) = removed {
+        LIKED_POSTS.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&LikedPost::new(principal, liked_at, post_id))
+        });
+    }
+    removed.is_some()
+}
+
+// Index the likes given before the principal -> likes index existed
+pub(crate) fn index_existing_likes() {
+    LIKES.with(|likes| {
+        LIKED_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for (key, liked_at) in likes.borrow().iter() {
+                index.insert(
+                    LikedPost {
+                        principal: key.principal,
+                        liked_at,
+                        post_id: key.post_id,
+                    },
+                    (),
+                );
+            }
+        })
+    });
+}
+
+pub(crate) fn has_disliked(post_id: u64, principal: &Principal) -> bool {
+    DISLIKES.with(|dislikes| {
+        dislikes
+            .borrow()
+            .contains_key(&LikeKey::new(post_id, principal))
+    })
+}
+
+// Record a downvote, returning false if the principal already downvoted
+pub(crate) fn add_dislike(post_id: u64, principal: &Principal, disliked_at: u64) -> bool {
+    let previous = DISLIKES.with(|dislikes| {
+        dislikes
+            .borrow_mut()
+            .insert(LikeKey::new(post_id, principal), disliked_at)
+    });
+    DISLIKED_POSTS.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous {
+            index.remove(&LikedPost::new(principal, previous, post_id));
+        }
+        index.insert(LikedPost::new(principal, disliked_at, post_id), ());
+    });
+    previous.is_none()
+}
+
+// Remove a downvote, returning false if the principal hadn't downvoted
+pub(crate) fn remove_dislike(post_id: u64, principal: &Principal) -> bool {
+    let removed = DISLIKES.with(|dislikes| {
+        dislikes
+            .borrow_mut()
+            .remove(&LikeKey::new(post_id, principal))
+    });
+    if let Some(disliked_at) = removed {
+        DISLIKED_POSTS.with(|index| {
+            index
+                .borrow_mut()
+                .remove(&LikedPost::new(principal, disliked_at, post_id))
+        });
+    }
+    removed.is_some()
+}
+
+// Index the downvotes given before the principal -> dislikes index existed
+pub(crate) fn index_existing_dislikes() {
+    DISLIKES.with(|dislikes| {
+        DISLIKED_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for (key, disliked_at) in dislikes.borrow().iter() {
+                index.insert(
+                    LikedPost {
+                        principal: key.principal,
+                        liked_at: disliked_at,
+                        post_id: key.post_id,
+                    },
+                    (),
+                );
+            }
+        })
+    });
+}
+
+// IDs of the `limit` posts a principal liked most recently, latest like first
+pub(crate) fn newest_liked_post_ids(principal: &Principal, limit: usize) -> Vec<u64> {
+    let start = LikedPost::new(principal, 0, 0);
+    let end = LikedPost::new(principal, u64::MAX, u64::MAX);
+    LIKED_POSTS.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .rev()
+            .take(limit)
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// Drop the likes and dislikes of a deleted post
+pub(crate) fn remove_post_votes(post_id: u64) {
+    let voters = |votes: &StableBTreeMap<LikeKey, u64, Memory>| -> Vec<Principal> {
+        let start = LikeKey {
+            post_id,
+            principal: Vec::new(),
+        };
+        votes
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| Principal::from_slice(&key.principal))
+            .collect()
+    };
+    for principal in LIKES.with(|likes| voters(&likes.borrow())) {
+        remove_like(post_id, &principal);
+    }
+    for principal in DISLIKES.with(|dislikes| voters(&dislikes.borrow())) {
+        remove_dislike(post_id, &principal);
+    }
+}
+
+// Every like of a post along with its time
+pub(crate) fn post_likes(post_id: u64) -> Vec<(Principal, u64)> {
+    let start = LikeKey {
+        post_id,
+        principal: Vec::new(),
+    };
+    LIKES.with(|likes| {
+        likes
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, liked_at)| (Principal::from_slice(&key.principal), liked_at))
+            .collect()
+    })
+}
+
+// Number of likes and dislikes recorded for every post that has any
+pub(crate) fn tallies() -> BTreeMap<u64, (u32, u32)> {
+    let mut tallies: BTreeMap<u64, (u32, u32)> = BTreeMap::new();
+    LIKES.with(|likes| {
+        for (key, _) in likes.borrow().iter() {
+            tallies.entry(key.post_id).or_default().0 += 1;
+        }
+    });
+    DISLIKES.with(|dislikes| {
+        for (key, _) in dislikes.borrow().iter() {
+            tallies.entry(key.post_id).or_default().1 += 1;
+        }
+    });
+    tallies
+}
+
+// Entries of a principal -> votes index without a vote behind them
+fn stale_index_entries(
+    votes: &StableBTreeMap<LikeKey, u64, Memory>,
+    index: &StableBTreeMap<LikedPost, (), Memory>,
+) -> Vec<LikedPost> {
+    index
+        .iter()
+        .map(|(key, _)| key)
+        .filter(|key| {
+            let vote = LikeKey {
+                post_id: key.post_id,
+                principal: key.principal.clone(),
+            };
+            votes.get(&vote) != Some(key.liked_at)
+        })
+        .collect()
+}
+
+// Post IDs of the likes and dislikes of posts that are gone, and of the
+// entries of the principal -> likes and dislikes indexes without a vote
+// behind them. They are removed when `repair` is set.
+pub(crate) fn stale_entries(exists: impl Fn(u64) -> bool, repair: bool) -> Vec<u64> {
+    let stale_likes: Vec<LikeKey> = LIKES.with(|likes| {
+        likes
+            .borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !exists(key.post_id))
+            .collect()
+    });
+    let stale_dislikes: Vec<LikeKey> = DISLIKES.with(|dislikes| {
+        dislikes
+            .borrow()
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| !exists(key.post_id))
+            .collect()
+    });
+    let stale_index: Vec<LikedPost> = LIKES.with(|likes| {
+        LIKED_POSTS.with(|index| stale_index_entries(&likes.borrow(), &index.borrow()))
+    });
+    let stale_dislike_index: Vec<LikedPost> = DISLIKES.with(|dislikes| {
+        DISLIKED_POSTS.with(|index| stale_index_entries(&dislikes.borrow(), &index.borrow()))
+    });
+    if repair {
+        for key in &stale_likes {
+            remove_like(key.post_id, &Principal::from_slice(&key.principal));
+        }
+        for key in &stale_dislikes {
+            remove_dislike(key.post_id, &Principal::from_slice(&key.principal));
+        }
+        LIKED_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for key in &stale_index {
+                index.remove(key);
+            }
+        });
+        DISLIKED_POSTS.with(|index| {
+            let mut index = index.borrow_mut();
+            for key in &stale_dislike_index {
+                index.remove(key);
+            }
+        });
+    }
+    stale_likes
+        .into_iter()
+        .chain(stale_dislikes)
+        .map(|key| key.post_id)
+        .chain(
+            stale_index
+                .into_iter()
+                .chain(stale_dislike_index)
+                .map(|key| key.post_id),
+        )
+        .collect()
+}
+
+// Up to `limit` entries of a principal -> votes index for `principal`
+fn votes_of(
+    index: &'static std::thread::LocalKey<RefCell<StableBTreeMap<LikedPost, (), Memory>>>,
+    principal: &Principal,
+    limit: usize,
+) -> Vec<LikedPost> {
+    let start = LikedPost::new(principal, 0, 0);
+    let end = LikedPost::new(principal, u64::MAX, u64::MAX);
+    index.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .take(limit)
+            .map(|(key, _)| key)
+            .collect()
+    })
+}
+
+// Move up to `limit` likes and dislikes of `old` to `new`. Where `new`
+// already voted on the post, its vote stands and the one of `old` is
+// dropped, so a principal never both likes and dislikes a post. Returns how
+// many were handled along with the posts that lost a like and those that
+// lost a dislike that way.
+pub(crate) fn reassign(
+    old: &Principal,
+    new: &Principal,
+    limit: usize,
+) -> (usize, Vec<u64>, Vec<u64>) {
+    let liked = votes_of(&LIKED_POSTS, old, limit);
+    let disliked = votes_of(&DISLIKED_POSTS, old, limit - liked.len());
+    let voted = |post_id| has_liked(post_id, new) || has_disliked(post_id, new);
+    let mut dropped_likes = Vec::new();
+    for like in &liked {
+        remove_like(like.post_id, old);
+        if voted(like.post_id) {
+            dropped_likes.push(like.post_id);
+        } else {
+            add_like(like.post_id, new, like.liked_at);
+        }
+    }
+    let mut dropped_dislikes = Vec::new();
+    for dislike in &disliked {
+        remove_dislike(dislike.post_id, old);
+        if voted(dislike.post_id) {
+            dropped_dislikes.push(dislike.post_id);
+        } else {
+            add_dislike(dislike.post_id, new, dislike.liked_at);
+        }
+    }
+    (
+        liked.len() + disliked.len(),
+        dropped_likes,
+        dropped_dislikes,
+    )
+}
+
+// Query function to page through the principals that liked a post. Those
+// who keep their likes private are left out.
+#[ic_cdk::query]
+fn get_likers(post_id: u64, offset: u64, limit: u64) -> Result<Vec<Principal>, Error> {
+    likers(post_id, offset, limit)
+}
+
+// Query function to page through the principals that liked a post along with
+// their display names, as `get_likers` does
+#[ic_cdk::query]
+fn get_post_likers(id: u64, offset: u64, limit: u64) -> Result<Vec<Liker>, Error> {
+    Ok(likers(id, offset, limit)?
+        .into_iter()
+        .map(|principal| Liker {
+            display_name: profiles::display_name(&principal),
+            principal,
+        })
+        .collect())
+}
+
+fn likers(post_id: u64, offset: u64, limit: u64) -> Result<Vec<Principal>, Error> {
+    if _get_visible_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    let start = LikeKey {
+        post_id,
+        principal: Vec::new(),
+    };
+    Ok(LIKES.with(|likes| {
+        likes
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| Principal::from_slice(&key.principal))
+            .filter(|liker| privacy::can_see(liker, privacy::settings(liker).likes_public))
+            .skip(offset as usize)
+            .take(limit.min(MAX_LIKERS_PAGE) as usize)
+            .collect()
+    }))
+}
+
+// Query function for users to revisit the posts they liked, most recently
+// liked first. Posts they can no longer see are left out.
+#[ic_cdk::query]
+fn get_my_likes(page: Pagination) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    Ok(newest_liked_post_ids(&caller(), usize::MAX)
+        .into_iter()
+        .filter_map(|id| _get_visible_blog_post(&id))
+        .skip(page.offset as usize)
+        .take(page.limit.min(MAX_MY_LIKES_PAGE) as usize)
+        .collect())
+}
@@ -0,0 +1,174 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+// How long a link challenge stays valid, in nanoseconds
+const CHALLENGE_TTL: u64 = 10 * 60 * 1_000_000_000;
+// Most principals linked to one primary principal
+const MAX_LINKED: usize = 10;
+
+// A link the primary principal offered, waiting for the other to acknowledge
+struct PendingLink {
+    challenge: String,
+    expires_at: u64,
+}
+
+thread_local! {
+    // Linked principal -> the primary principal of the same person. Primary
+    // principals have no entry, so links never chain.
+    static LINKS: RefCell<StableBTreeMap<Blob<29>, Blob<29>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::LINKS_MEMORY)));
+
+    // Challenges are short-lived, so they are kept on the heap like deletion
+    // tokens
+    static PENDING: RefCell<BTreeMap<(Principal, Principal), PendingLink>> =
+        const { RefCell::new(BTreeMap::new()) };
+
+    static NONCE: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// The principal that stands for the person calling from `principal`: the
+// one it is linked to, or itself
+pub(crate) fn primary(principal: &Principal) -> Principal {
+    LINKS
+        .with(|links| links.borrow().get(&principal_key(principal)))
+        .map_or(*principal, |primary| {
+            Principal::from_slice(primary.as_slice())
+        })
+}
+
+// Whether two principals belong to the same person
+pub(crate) fn same_person(a: &Principal, b: &Principal) -> bool {
+    primary(a) == primary(b)
+}
+
+// Principals linked to a primary principal
+fn linked_to(primary: &Principal) -> Vec<Principal> {
+    LINKS.with(|links| {
+        links
+            .borrow()
+            .iter()
+            .filter(|(_, to)| to.as_slice() == primary.as_slice())
+            .map(|(from, _)| Principal::from_slice(from.as_slice()))
+            .collect()
+    })
+}
+
+fn new_challenge(primary: &Principal, other: &Principal, now: u64) -> String {
+    let nonce = NONCE.with(|nonce| {
+        let mut nonce = nonce.borrow_mut();
+        *nonce += 1;
+        *nonce
+    });
+    let mut hasher = Sha256::new();
+    hasher.update(primary.as_slice());
+    hasher.update(other.as_slice());
+    hasher.update(now.to_be_bytes());
+    hasher.update(nonce.to_be_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// Update function to start linking another principal, such as a second
+// wallet, to the caller's. Returns a challenge the other principal passes to
+// `acknowledge_link` within ten minutes.
+#[ic_cdk::update]
+fn request_link(other: Principal) -> Result<String, Error> {
+    auth::require_signed_in()?;
+    let primary = caller();
+    if other == primary || other == Principal::anonymous() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Cannot link {}.", other),
+        });
+    }
+    if self::primary(&primary) != primary {
+        return Err(Error::ValidationErrors {
+            errors: "Linked principals can't link others; call from the primary one".to_string(),
+        });
+    }
+    if linked_to(&primary).len() >= MAX_LINKED {
+        return Err(Error::ValidationErrors {
+            errors: format!("At most {} principals can be linked", MAX_LINKED),
+        });
+    }
+    let now = time();
+    let challenge = new_challenge(&primary, &other, now);
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.retain(|_, link| link.expires_at > now);
+        pending.insert(
+            (primary, other),
+            PendingLink {
+                challenge: challenge.clone(),
+                expires_at: now + CHALLENGE_TTL,
+            },
+        );
+    });
+    Ok(challenge)
+}
+
+// Update function for the other principal to accept a link offered by
+// `primary`, proving it is held by the same person
+#[ic_cdk::update]
+fn acknowledge_link(primary: Principal, challenge: String) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let other = caller();
+    let pending = PENDING.with(|pending| pending.borrow_mut().remove(&(primary, other)));
+    match pending {
+        Some(link) if link.challenge == challenge && link.expires_at > time() => {}
+        _ => {
+            return Err(Error::NotAuthorized {
+                msg: "The link challenge is invalid or expired. Ask for a new one.".to_string(),
+            })
+        }
+    }
+    if self::primary(&other) != other || !linked_to(&other).is_empty() {
+        return Err(Error::ValidationErrors {
+            errors: format!("{} is already linked to other principals", other),
+        });
+    }
+    if self::primary(&primary) != primary {
+        return Err(Error::ValidationErrors {
+            errors: format!("{} was since linked to another principal", primary),
+        });
+    }
+    LINKS.with(|links| {
+        links
+            .borrow_mut()
+            .insert(principal_key(&other), principal_key(&primary))
+    });
+    Ok(())
+}
+
+// Update function to undo a link, called from either of its principals
+#[ic_cdk::update]
+fn unlink_principal(linked: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let principal = caller();
+    if principal != linked && primary(&linked) != principal {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to unlink {}.", linked),
+        });
+    }
+    match LINKS.with(|links| links.borrow_mut().remove(&principal_key(&linked))) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not linked", linked),
+        }),
+    }
+}
+
+// Query function to get every principal of the person calling from
+// `principal`, the primary one first
+#[ic_cdk::query]
+fn get_linked_principals(principal: Principal) -> Vec<Principal> {
+    let primary = primary(&principal);
+    let mut principals = vec![primary];
+    principals.extend(linked_to(&primary));
+    principals
+}
@@ -0,0 +1,104 @@
+use crate::quotas::{self, Endpoint};
+use crate::{
+    _get_visible_blog_post, authors, categories, config, linking, sponsorship, timeline, usernames,
+    visibility, BlogPost, Error, PostStatus,
+};
+use candid::Principal;
+
+// Largest page `query_blog_posts` returns
+pub(crate) const MAX_QUERY_PAGE: u64 = 50;
+
+// Conditions a post must meet to be listed by `query_blog_posts`. Conditions
+// left out match every post.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostFilter {
+    author: Option<Principal>,
+    category: Option<String>,
+    // creation time range, in nanoseconds since the epoch, both ends included
+    from: Option<u64>,
+    to: Option<u64>,
+    min_likes: Option<u32>,
+    // published posts when left out. Posts of other statuses are only listed
+    // to those who may read them.
+    status: Option<PostStatus>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Pagination {
+    pub(crate) offset: u64,
+    pub(crate) limit: u64,
+}
+
+impl PostFilter {
+    pub(crate) fn matches(&self, blog_post: &BlogPost) -> bool {
+        let created_at = blog_post.created_at;
+        blog_post.status == self.status.unwrap_or(PostStatus::Published)
+            && self.from.is_none_or(|from| created_at >= from)
+            && self.to.is_none_or(|to| created_at <= to)
+            && self.min_likes.is_none_or(|min| blog_post.likes >= min)
+            && self
+                .category
+                .as_ref()
+                .is_none_or(|category| blog_post.categories.contains(category))
+    }
+
+    // IDs of the posts that may match, newest first, from the narrowest index
+    // the filter allows
+    pub(crate) fn candidates(&self) -> Vec<u64> {
+        if let Some(author) = &self.author {
+            authors::newest_post_ids(&linking::primary(author), usize::MAX)
+        } else if let Some(category) = &self.category {
+            categories::newest_post_ids(category, usize::MAX)
+        } else {
+            timeline::newest_created_between(self.from.unwrap_or(0), self.to.unwrap_or(u64::MAX))
+        }
+    }
+}
+
+// Query function to page through the posts matching a filter, newest first,
+// so an archive page can combine conditions in one call. Replicated calls
+// over the query quota get a shorter page, from the newest candidates only.
+#[ic_cdk::query]
+fn query_blog_posts(filter: PostFilter, page: Pagination) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    Ok(matching(&filter, page))
+}
+
+// Query function to page through the published posts of the user who
+// registered a username, newest first, for pages addressed by handle
+#[ic_cdk::query]
+fn get_posts_by_username(name: String, page: Pagination) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    let Some(author) = usernames::lookup(&name) else {
+        return Err(Error::NotFound {
+            msg: format!("No user is named {}", name),
+        });
+    };
+    let filter = PostFilter {
+        author: Some(author),
+        category: None,
+        from: None,
+        to: None,
+        min_likes: None,
+        status: None,
+    };
+    Ok(matching(&filter, page))
+}
+
+fn matching(filter: &PostFilter, page: Pagination) -> Vec<BlogPost> {
+    let (scan, limit) = match quotas::record(Endpoint::QueryBlogPosts) {
+        Some(limited) => (quotas::LIMITED_SCAN, page.limit.min(limited)),
+        None => (usize::MAX, page.limit),
+    };
+    filter
+        .candidates()
+        .into_iter()
+        .take(scan)
+        .filter_map(|id| _get_visible_blog_post(&id))
+        .filter(|blog_post| filter.matches(blog_post))
+        .filter(visibility::is_listed)
+        .filter(sponsorship::listed_for_caller)
+        .skip(page.offset as usize)
+        .take(limit.min(MAX_QUERY_PAGE) as usize)
+        .collect()
+}
@@ -0,0 +1,68 @@
+use crate::{backup, config, date, drafts, inactivity, media};
+use ic_cdk::api::time;
+use ic_cdk_timers::TimerId;
+use std::{cell::RefCell, time::Duration};
+
+// Most drafts, and abandoned uploads, one run deletes, and most drafts it
+// sends reminders of or inactive posts it archives or warns about, so a run
+// stays within the instruction limit; the rest go in the next runs
+const MAX_PURGED_PER_RUN: usize = 1_000;
+
+thread_local! {
+    static TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+}
+
+// Run the cleanup on the configured schedule, replacing the timer of the
+// previous one. Timers don't survive upgrades, so this also runs from
+// `post_upgrade`.
+pub(crate) fn start() {
+    TIMER.with(|timer| {
+        let mut timer = timer.borrow_mut();
+        if let Some(id) = timer.take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(schedule) = config::get().maintenance {
+            let interval = Duration::from_secs(u64::from(schedule.interval_hours) * 3600);
+            *timer = Some(ic_cdk_timers::set_timer_interval(interval, run));
+        }
+    });
+}
+
+fn run() {
+    if backup::is_restoring() {
+        return;
+    }
+    let Some(schedule) = config::get().maintenance else {
+        return;
+    };
+    let saved_before =
+        time().saturating_sub(u64::from(schedule.stale_draft_days) * date::NANOS_PER_DAY);
+    let purged = drafts::purge_stale(saved_before, MAX_PURGED_PER_RUN);
+    if purged > 0 {
+        ic_cdk::println!("Maintenance deleted {} stale drafts", purged);
+    }
+    if let Some(days) = schedule.draft_reminder_days {
+        let saved_before = time().saturating_sub(u64::from(days) * date::NANOS_PER_DAY);
+        let reminded = drafts::remind_stale(saved_before, MAX_PURGED_PER_RUN);
+        if reminded > 0 {
+            ic_cdk::println!("Maintenance reminded writers of {} stale drafts", reminded);
+        }
+    }
+    if let Some(policy) = config::get().inactivity {
+        let (archived, warned) = inactivity::apply(&policy, MAX_PURGED_PER_RUN);
+        if archived > 0 || warned > 0 {
+            ic_cdk::println!(
+                "Maintenance archived {} inactive posts and warned the authors of {}",
+                archived,
+                warned
+            );
+        }
+    }
+    let abandoned = media::purge_abandoned(
+        time().saturating_sub(media::ABANDONED_UPLOAD_AGE),
+        MAX_PURGED_PER_RUN,
+    );
+    if abandoned > 0 {
+        ic_cdk::println!("Maintenance deleted {} abandoned uploads", abandoned);
+    }
+}
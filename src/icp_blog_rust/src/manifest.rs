@@ -0,0 +1,69 @@
+use crate::{visibility, BlogPost, PostStatus, BLOG_POSTS};
+use sha2::{Digest, Sha256};
+
+// Largest page `get_content_manifest` returns
+const MAX_MANIFEST_PAGE: u64 = 1000;
+
+// What a mirror needs to tell whether its copy of a post is current
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    id: u64,
+    slug: String,
+    revision: u64,
+    // hex SHA-256 of everything a rendered page is generated from
+    content_hash: String,
+    updated_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct ContentManifest {
+    entries: Vec<ManifestEntry>,
+    total: u64,
+}
+
+// Each field is length-prefixed so moving text between fields changes the hash
+fn content_hash(post: &BlogPost) -> String {
+    let mut hasher = Sha256::new();
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    };
+    field(post.title.as_bytes());
+    field(post.content.as_bytes());
+    field(post.author.as_bytes());
+    field(post.language.as_deref().unwrap_or_default().as_bytes());
+    for category in &post.categories {
+        field(category.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Query function for mirrors to page through every published post in ID order with a
+// hash of its content, so only changed posts need to be fetched again
+#[ic_cdk::query]
+fn get_content_manifest(offset: u64, limit: u64) -> ContentManifest {
+    BLOG_POSTS.with(|service| {
+        let service = service.borrow();
+        let total = service
+            .iter()
+            .filter(|(_, post)| post.status == PostStatus::Published && visibility::is_listed(post))
+            .count() as u64;
+        let entries = service
+            .iter()
+            .filter(|(_, post)| post.status == PostStatus::Published && visibility::is_listed(post))
+            .skip(offset as usize)
+            .take(limit.min(MAX_MANIFEST_PAGE) as usize)
+            .map(|(_, post)| ManifestEntry {
+                content_hash: content_hash(&post),
+                id: post.id,
+                revision: post.revision,
+                updated_at: post.updated_at.unwrap_or(post.created_at),
+                slug: post.slug,
+            })
+            .collect();
+        ContentManifest {
+            entries,
+            total,
+        }
+    })
+}
@@ -0,0 +1,386 @@
+use crate::http::HttpResponse;
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{
+    _check_if_owner, _get_blog_post, asset_sync, auth, do_insert, linking, BlogPost, Error,
+    PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use serde_bytes::ByteBuf;
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest chunk of an upload; a message can't carry much more than 2 MiB
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+// Largest asset, so it is served in a single HTTP response
+const MAX_ASSET_SIZE: u64 = 2 * 1024 * 1024;
+// Bytes of media, uploaded or being uploaded, an author may keep
+const MEDIA_QUOTA: u64 = 100 * 1024 * 1024;
+// Uploads not finalized within this long are deleted by the maintenance run
+pub(crate) const ABANDONED_UPLOAD_AGE: u64 = 24 * 3600 * 1_000_000_000;
+// Types assets may have. SVG is left out as it can carry scripts.
+const MEDIA_TYPES: [&str; 8] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "video/mp4",
+    "video/webm",
+    "audio/mpeg",
+    "audio/ogg",
+];
+
+// An image or other media file an author uploaded, e.g. for a cover image or
+// to show inline. Once finalized it is served at `/media/<id>` to anyone
+// with the URL, whatever posts it is used in.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MediaAsset {
+    id: u64,
+    owner: Principal,
+    // set by `finalize_asset`; none while chunks are being uploaded
+    content_type: Option<String>,
+    size: u64,
+    chunks: u64,
+    created_at: u64,
+}
+
+impl Storable for MediaAsset {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the chunks map: the asset id followed by the chunk's position, so
+// the chunks of an asset are one contiguous range in upload order
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ChunkKey {
+    asset_id: u64,
+    index: u64,
+}
+
+impl Storable for ChunkKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.asset_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ChunkKey {
+            asset_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            index: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    static ASSET_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::ASSET_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create an asset counter")
+    );
+
+    static ASSETS: RefCell<StableBTreeMap<u64, MediaAsset, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::ASSETS_MEMORY)));
+
+    static CHUNKS: RefCell<StableBTreeMap<ChunkKey, Vec<u8>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::ASSET_CHUNKS_MEMORY)));
+
+    // Bytes of media of every author with any
+    static USAGE: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::MEDIA_USAGE_MEMORY)));
+}
+
+// Linked principals share their media, as they share their posts
+fn owner() -> Principal {
+    linking::primary(&caller())
+}
+
+fn usage(owner: &Principal) -> u64 {
+    USAGE.with(|usage| usage.borrow().get(&principal_key(owner)).unwrap_or(0))
+}
+
+fn set_usage(owner: &Principal, bytes: u64) {
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        if bytes == 0 {
+            usage.remove(&principal_key(owner));
+        } else {
+            usage.insert(principal_key(owner), bytes);
+        }
+    });
+}
+
+fn not_found(asset_id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Asset with ID {} not found", asset_id),
+    }
+}
+
+// An asset of the caller
+fn my_asset(asset_id: u64) -> Result<MediaAsset, Error> {
+    ASSETS
+        .with(|assets| assets.borrow().get(&asset_id))
+        .filter(|asset| asset.owner == owner())
+        .ok_or_else(|| not_found(asset_id))
+}
+
+fn finalized(asset_id: u64) -> Option<MediaAsset> {
+    ASSETS
+        .with(|assets| assets.borrow().get(&asset_id))
+        .filter(|asset| asset.content_type.is_some())
+}
+
+// Whether the start of a file looks like the type it claims, so an asset
+// can't be served as something it isn't
+fn has_signature(content_type: &str, head: &[u8]) -> bool {
+    match content_type {
+        "image/png" => head.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => head.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a"),
+        "image/webp" => head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP"),
+        "video/mp4" => head.get(4..8) == Some(b"ftyp"),
+        "video/webm" => head.starts_with(b"\x1a\x45\xdf\xa3"),
+        "audio/mpeg" => {
+            head.starts_with(b"ID3")
+                || (head.len() >= 2 && head[0] == 0xff && head[1] & 0xe0 == 0xe0)
+        }
+        "audio/ogg" => head.starts_with(b"OggS"),
+        _ => false,
+    }
+}
+
+fn remove(asset: &MediaAsset) {
+    CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        for index in 0..asset.chunks {
+            chunks.remove(&ChunkKey {
+                asset_id: asset.id,
+                index,
+            });
+        }
+    });
+    ASSETS.with(|assets| assets.borrow_mut().remove(&asset.id));
+    set_usage(&asset.owner, usage(&asset.owner).saturating_sub(asset.size));
+}
+
+// Delete up to `limit` uploads started before `started_before` that were
+// never finalized. Returns how many were deleted.
+pub(crate) fn purge_abandoned(started_before: u64, limit: usize) -> usize {
+    let abandoned: Vec<MediaAsset> = ASSETS.with(|assets| {
+        assets
+            .borrow()
+            .iter()
+            .map(|(_, asset)| asset)
+            .filter(|asset| asset.content_type.is_none() && asset.created_at < started_before)
+            .take(limit)
+            .collect()
+    });
+    for asset in &abandoned {
+        remove(asset);
+    }
+    abandoned.len()
+}
+
+// A finalized asset, served over the HTTP gateway. Assets never change, so
+// they may be cached for good.
+pub(crate) fn serve(file: &str) -> HttpResponse {
+    let Some(asset) = file.parse().ok().and_then(finalized) else {
+        return HttpResponse::error(404, "Asset not found");
+    };
+    let mut body = Vec::with_capacity(asset.size as usize);
+    CHUNKS.with(|chunks| {
+        let start = ChunkKey {
+            asset_id: asset.id,
+            index: 0,
+        };
+        for (_, chunk) in chunks.borrow().range(start..).take(asset.chunks as usize) {
+            body.extend_from_slice(&chunk);
+        }
+    });
+    HttpResponse::immutable(&asset.content_type.unwrap_or_default(), body)
+}
+
+// Cover image of a post for its page, if it has one that is still there
+pub(crate) fn cover_html(blog_post: &BlogPost) -> String {
+    match cover_url(blog_post) {
+        Some(path) => format!(r#"<img class="cover" src="{}" alt="">"#, path),
+        None => String::new(),
+    }
+}
+
+// Path the cover image of a post is served at
+pub(crate) fn cover_url(blog_post: &BlogPost) -> Option<String> {
+    blog_post
+        .cover_image
+        .and_then(finalized)
+        .map(|asset| format!("/media/{}", asset.id))
+}
+
+// Update function for authors to upload an asset in chunks of at most 1 MiB:
+// the first call leaves out `asset_id` and gets the ID of the new asset, the
+// next ones pass it to append their chunk. Call `finalize_asset` when done.
+#[ic_cdk::update]
+fn upload_asset_chunk(asset_id: Option<u64>, chunk: ByteBuf) -> Result<u64, Error> {
+    auth::require_signed_in()?;
+    let owner = owner();
+    if chunk.is_empty() || chunk.len() > MAX_CHUNK_SIZE {
+        return Err(Error::ValidationErrors {
+            errors: format!("Chunks are 1 to {} bytes", MAX_CHUNK_SIZE),
+        });
+    }
+    let len = chunk.len() as u64;
+    let mut asset = match asset_id {
+        Some(asset_id) => {
+            let asset = my_asset(asset_id)?;
+            if asset.content_type.is_some() {
+                return Err(Error::ValidationErrors {
+                    errors: format!("Asset with ID {} is already finalized", asset_id),
+                });
+            }
+            asset
+        }
+        None => MediaAsset {
+            id: ASSET_ID_COUNTER.with(|counter| {
+                let id = *counter.borrow().get();
+                counter
+                    .borrow_mut()
+                    .set(id + 1)
+                    .expect("Cannot increment the asset counter");
+                id
+            }),
+            owner,
+            content_type: None,
+            size: 0,
+            chunks: 0,
+            created_at: time(),
+        },
+    };
+    if asset.size + len > MAX_ASSET_SIZE {
+        return Err(Error::ValidationErrors {
+            errors: format!("Assets are at most {} bytes", MAX_ASSET_SIZE),
+        });
+    }
+    if usage(&owner) + len > MEDIA_QUOTA {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Authors can keep at most {} bytes of media; delete some first",
+                MEDIA_QUOTA
+            ),
+        });
+    }
+    CHUNKS.with(|chunks| {
+        chunks.borrow_mut().insert(
+            ChunkKey {
+                asset_id: asset.id,
+                index: asset.chunks,
+            },
+            chunk.into_vec(),
+        )
+    });
+    asset.chunks += 1;
+    asset.size += len;
+    ASSETS.with(|assets| assets.borrow_mut().insert(asset.id, asset.clone()));
+    set_usage(&owner, usage(&owner) + len);
+    Ok(asset.id)
+}
+
+// Update function for authors to finish an upload, after which the asset is
+// served with `content_type` and can no longer change. The content must
+// start like a file of that type.
+#[ic_cdk::update]
+fn finalize_asset(asset_id: u64, content_type: String) -> Result<MediaAsset, Error> {
+    auth::require_signed_in()?;
+    let mut asset = my_asset(asset_id)?;
+    if asset.content_type.is_some() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Asset with ID {} is already finalized", asset_id),
+        });
+    }
+    if !MEDIA_TYPES.contains(&content_type.as_str()) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Assets must be one of {}", MEDIA_TYPES.join(", ")),
+        });
+    }
+    let head = CHUNKS
+        .with(|chunks| chunks.borrow().get(&ChunkKey { asset_id, index: 0 }))
+        .unwrap_or_default();
+    if !has_signature(&content_type, &head) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Asset with ID {} is not {}", asset_id, content_type),
+        });
+    }
+    asset.content_type = Some(content_type);
+    ASSETS.with(|assets| assets.borrow_mut().insert(asset_id, asset.clone()));
+    Ok(asset)
+}
+
+// Update function for authors to delete an asset, finalized or not. Posts
+// using it as their cover image show none.
+#[ic_cdk::update]
+fn delete_asset(asset_id: u64) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    remove(&my_asset(asset_id)?);
+    Ok(())
+}
+
+// Query function for authors to list their assets, oldest first
+#[ic_cdk::query]
+fn get_my_assets() -> Result<Vec<MediaAsset>, Error> {
+    auth::require_signed_in()?;
+    let owner = owner();
+    Ok(ASSETS.with(|assets| {
+        assets
+            .borrow()
+            .iter()
+            .map(|(_, asset)| asset)
+            .filter(|asset| asset.owner == owner)
+            .collect()
+    }))
+}
+
+// Update function for the author of a post to set its cover image to one of
+// their finalized images, or remove it with `null`
+#[ic_cdk::update]
+fn set_cover_image(post_id: u64, asset_id: Option<u64>) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to update post with id={}.", post_id),
+        });
+    }
+    if let Some(asset_id) = asset_id {
+        let asset = my_asset(asset_id)?;
+        if !asset
+            .content_type
+            .is_some_and(|content_type| content_type.starts_with("image/"))
+        {
+            return Err(Error::ValidationErrors {
+                errors: format!("Asset with ID {} is not a finalized image", asset_id),
+            });
+        }
+    }
+    blog_post.cover_image = asset_id;
+    do_insert(&blog_post);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    Ok(blog_post)
+}
@@ -0,0 +1,146 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Every stable structure gets its own virtual memory. The ids are persisted in
+// stable memory, so an id must never be reused or renumbered once deployed.
+pub(crate) const ID_COUNTER_MEMORY: MemoryId = MemoryId::new(0);
+pub(crate) const BLOG_POSTS_MEMORY: MemoryId = MemoryId::new(1);
+pub(crate) const CATEGORIES_MEMORY: MemoryId = MemoryId::new(2);
+pub(crate) const CATEGORY_POSTS_MEMORY: MemoryId = MemoryId::new(3);
+pub(crate) const JOBS_MEMORY: MemoryId = MemoryId::new(4);
+pub(crate) const JOB_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(5);
+pub(crate) const SCHEMA_VERSION_MEMORY: MemoryId = MemoryId::new(6);
+pub(crate) const LIKES_MEMORY: MemoryId = MemoryId::new(7);
+pub(crate) const AUTHORS_MEMORY: MemoryId = MemoryId::new(8);
+pub(crate) const AUTHOR_POSTS_MEMORY: MemoryId = MemoryId::new(9);
+pub(crate) const REACTIONS_MEMORY: MemoryId = MemoryId::new(10);
+pub(crate) const SLUGS_MEMORY: MemoryId = MemoryId::new(11);
+pub(crate) const TRANSLATIONS_MEMORY: MemoryId = MemoryId::new(12);
+pub(crate) const BOOKMARKS_MEMORY: MemoryId = MemoryId::new(13);
+pub(crate) const FOLLOWS_MEMORY: MemoryId = MemoryId::new(14);
+pub(crate) const SERIES_MEMORY: MemoryId = MemoryId::new(15);
+pub(crate) const SERIES_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(16);
+pub(crate) const INBOXES_MEMORY: MemoryId = MemoryId::new(17);
+pub(crate) const NOTIFICATION_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(18);
+pub(crate) const UNREAD_NOTIFICATIONS_MEMORY: MemoryId = MemoryId::new(19);
+pub(crate) const RATE_LIMITS_MEMORY: MemoryId = MemoryId::new(20);
+pub(crate) const RECENT_ACTIONS_MEMORY: MemoryId = MemoryId::new(21);
+pub(crate) const DAILY_VIEWS_MEMORY: MemoryId = MemoryId::new(22);
+pub(crate) const ASSET_SYNC_MEMORY: MemoryId = MemoryId::new(23);
+pub(crate) const SCHEDULE_MEMORY: MemoryId = MemoryId::new(24);
+pub(crate) const COMMENTS_MEMORY: MemoryId = MemoryId::new(25);
+pub(crate) const POST_COMMENTS_MEMORY: MemoryId = MemoryId::new(26);
+pub(crate) const COMMENT_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(27);
+pub(crate) const MODERATORS_MEMORY: MemoryId = MemoryId::new(28);
+pub(crate) const REPORTS_MEMORY: MemoryId = MemoryId::new(29);
+pub(crate) const REPORT_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(30);
+pub(crate) const SESSIONS_MEMORY: MemoryId = MemoryId::new(31);
+pub(crate) const SESSION_ACTIVITY_MEMORY: MemoryId = MemoryId::new(32);
+pub(crate) const DESTRUCTIVE_ACTION_GUARD_MEMORY: MemoryId = MemoryId::new(33);
+pub(crate) const PROFILES_MEMORY: MemoryId = MemoryId::new(34);
+pub(crate) const POSTS_BY_DATE_MEMORY: MemoryId = MemoryId::new(35);
+pub(crate) const POSTS_BY_LIKES_MEMORY: MemoryId = MemoryId::new(36);
+pub(crate) const POSTS_BY_VIEWS_MEMORY: MemoryId = MemoryId::new(37);
+pub(crate) const EVENT_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(38);
+pub(crate) const EVENTS_MEMORY: MemoryId = MemoryId::new(39);
+// superseded by PRIVACY_MEMORY; only read by the schema 12 migration
+pub(crate) const PUBLIC_LIKES_MEMORY: MemoryId = MemoryId::new(40);
+pub(crate) const PRIVACY_MEMORY: MemoryId = MemoryId::new(41);
+pub(crate) const EMBARGOES_MEMORY: MemoryId = MemoryId::new(42);
+pub(crate) const RESEARCH_SALT_MEMORY: MemoryId = MemoryId::new(43);
+pub(crate) const TIP_LEDGER_MEMORY: MemoryId = MemoryId::new(44);
+pub(crate) const POST_TIPS_MEMORY: MemoryId = MemoryId::new(45);
+pub(crate) const AUTHOR_TIPS_MEMORY: MemoryId = MemoryId::new(46);
+pub(crate) const SIGNAL_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(47);
+pub(crate) const SIGNALS_MEMORY: MemoryId = MemoryId::new(48);
+pub(crate) const ALLOW_ANONYMOUS_LIKES_MEMORY: MemoryId = MemoryId::new(49);
+pub(crate) const FLAGGED_MEMORY: MemoryId = MemoryId::new(50);
+pub(crate) const DUPLICATE_GUARD_MEMORY: MemoryId = MemoryId::new(51);
+pub(crate) const LINKS_MEMORY: MemoryId = MemoryId::new(52);
+pub(crate) const DISLIKES_MEMORY: MemoryId = MemoryId::new(53);
+pub(crate) const POSTS_BY_SCORE_MEMORY: MemoryId = MemoryId::new(54);
+pub(crate) const SPONSORED_OPT_OUT_MEMORY: MemoryId = MemoryId::new(55);
+pub(crate) const CONFIG_MEMORY: MemoryId = MemoryId::new(56);
+pub(crate) const AUDIT_LOG_MEMORY: MemoryId = MemoryId::new(57);
+pub(crate) const CUSTOM_EMOJI_MEMORY: MemoryId = MemoryId::new(58);
+pub(crate) const ARCHIVE_CONFIG_MEMORY: MemoryId = MemoryId::new(59);
+pub(crate) const ARCHIVED_POSTS_MEMORY: MemoryId = MemoryId::new(60);
+pub(crate) const PROFILE_NAMES_MEMORY: MemoryId = MemoryId::new(61);
+pub(crate) const UNPUBLISH_SCHEDULE_MEMORY: MemoryId = MemoryId::new(62);
+pub(crate) const SUBSCRIBERS_MEMORY: MemoryId = MemoryId::new(63);
+pub(crate) const EMBED_PROVIDERS_MEMORY: MemoryId = MemoryId::new(64);
+pub(crate) const DRAFT_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(65);
+pub(crate) const DRAFTS_MEMORY: MemoryId = MemoryId::new(66);
+pub(crate) const REVIEW_DELEGATIONS_MEMORY: MemoryId = MemoryId::new(67);
+pub(crate) const REVIEW_NOTE_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(68);
+pub(crate) const REVIEW_NOTES_MEMORY: MemoryId = MemoryId::new(69);
+pub(crate) const IDEMPOTENCY_KEYS_MEMORY: MemoryId = MemoryId::new(70);
+pub(crate) const IDEMPOTENCY_EXPIRY_MEMORY: MemoryId = MemoryId::new(71);
+pub(crate) const AUTHOR_DEFAULTS_MEMORY: MemoryId = MemoryId::new(72);
+pub(crate) const LIKED_POSTS_MEMORY: MemoryId = MemoryId::new(73);
+pub(crate) const AUTHOR_COMMENTS_MEMORY: MemoryId = MemoryId::new(74);
+pub(crate) const TOTAL_VIEWS_MEMORY: MemoryId = MemoryId::new(75);
+pub(crate) const BLOCKED_TERMS_MEMORY: MemoryId = MemoryId::new(76);
+pub(crate) const TIER_ACTIONS_MEMORY: MemoryId = MemoryId::new(77);
+pub(crate) const FILTER_EXEMPTIONS_MEMORY: MemoryId = MemoryId::new(78);
+pub(crate) const CHANGE_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(79);
+pub(crate) const CHANGE_REQUESTS_MEMORY: MemoryId = MemoryId::new(80);
+pub(crate) const VETKD_KEY_NAME_MEMORY: MemoryId = MemoryId::new(81);
+pub(crate) const QUERY_QUOTA_MEMORY: MemoryId = MemoryId::new(82);
+pub(crate) const PUBLIC_IDS_MEMORY: MemoryId = MemoryId::new(83);
+pub(crate) const PUBLIC_ID_POSTS_MEMORY: MemoryId = MemoryId::new(84);
+pub(crate) const PUBLIC_ID_SALT_MEMORY: MemoryId = MemoryId::new(85);
+pub(crate) const ASSET_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(86);
+pub(crate) const ASSETS_MEMORY: MemoryId = MemoryId::new(87);
+pub(crate) const ASSET_CHUNKS_MEMORY: MemoryId = MemoryId::new(88);
+pub(crate) const MEDIA_USAGE_MEMORY: MemoryId = MemoryId::new(89);
+pub(crate) const PAYOUT_SPLITS_MEMORY: MemoryId = MemoryId::new(90);
+pub(crate) const RENDERED_POSTS_MEMORY: MemoryId = MemoryId::new(91);
+pub(crate) const RENDER_GENERATION_MEMORY: MemoryId = MemoryId::new(92);
+pub(crate) const POST_PRICES_MEMORY: MemoryId = MemoryId::new(93);
+pub(crate) const ENTITLEMENTS_MEMORY: MemoryId = MemoryId::new(94);
+pub(crate) const PROMO_WINDOWS_MEMORY: MemoryId = MemoryId::new(95);
+pub(crate) const HUMANITY_VERIFIER_MEMORY: MemoryId = MemoryId::new(96);
+pub(crate) const VERIFICATIONS_MEMORY: MemoryId = MemoryId::new(97);
+pub(crate) const EXPIRY_SCHEDULE_MEMORY: MemoryId = MemoryId::new(98);
+pub(crate) const REFERRAL_CODES_MEMORY: MemoryId = MemoryId::new(99);
+pub(crate) const REFERRAL_STATS_MEMORY: MemoryId = MemoryId::new(100);
+pub(crate) const FEDERATION_PEERS_MEMORY: MemoryId = MemoryId::new(101);
+pub(crate) const POST_TRANSFERS_MEMORY: MemoryId = MemoryId::new(102);
+pub(crate) const USERNAMES_MEMORY: MemoryId = MemoryId::new(103);
+pub(crate) const USERNAME_OF_MEMORY: MemoryId = MemoryId::new(104);
+pub(crate) const MENTION_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(105);
+pub(crate) const MENTIONS_MEMORY: MemoryId = MemoryId::new(106);
+pub(crate) const DRAFT_REMINDER_OPT_OUT_MEMORY: MemoryId = MemoryId::new(107);
+pub(crate) const COLLABORATORS_MEMORY: MemoryId = MemoryId::new(108);
+pub(crate) const INACTIVE_POSTS_MEMORY: MemoryId = MemoryId::new(109);
+pub(crate) const GATEWAY_VIEWS_MEMORY: MemoryId = MemoryId::new(110);
+pub(crate) const CRAWLER_VIEWS_MEMORY: MemoryId = MemoryId::new(111);
+pub(crate) const CRAWLER_PATTERNS_MEMORY: MemoryId = MemoryId::new(112);
+pub(crate) const BLOG_SUBSCRIBERS_MEMORY: MemoryId = MemoryId::new(113);
+pub(crate) const PAGES_MEMORY: MemoryId = MemoryId::new(114);
+pub(crate) const KNOWN_ADMINS_MEMORY: MemoryId = MemoryId::new(115);
+pub(crate) const NAVIGATION_MEMORY: MemoryId = MemoryId::new(116);
+pub(crate) const CONTACT_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(117);
+pub(crate) const CONTACT_MESSAGES_MEMORY: MemoryId = MemoryId::new(118);
+pub(crate) const CONTACT_FORWARDING_MEMORY: MemoryId = MemoryId::new(119);
+pub(crate) const MONTHLY_REPORTS_MEMORY: MemoryId = MemoryId::new(120);
+pub(crate) const REPORT_DELIVERY_MEMORY: MemoryId = MemoryId::new(121);
+pub(crate) const DISLIKED_POSTS_MEMORY: MemoryId = MemoryId::new(122);
+pub(crate) const BOOKMARKED_BY_MEMORY: MemoryId = MemoryId::new(123);
+pub(crate) const MONTHLY_ACTIONS_MEMORY: MemoryId = MemoryId::new(124);
+pub(crate) const FIRST_UNFINISHED_JOB_MEMORY: MemoryId = MemoryId::new(125);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+}
+
+// Helper function to hand out the virtual memory backing a stable structure
+pub(crate) fn get(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
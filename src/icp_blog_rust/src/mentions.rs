@@ -0,0 +1,172 @@
+use crate::comments::Comment;
+use crate::keys::PrincipalIdKey;
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{auth, linking, render, usernames, visibility, BlogPost, Error, PostStatus};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `get_my_mentions` returns
+const MAX_MENTIONS_PAGE: u64 = 100;
+// Most users one post or comment may mention; later mentions are ignored
+const MAX_MENTIONS_PER_TEXT: usize = 20;
+
+// A post or comment that mentioned a user as `@username`
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Mention {
+    id: u64,
+    post_id: u64,
+    // set when the mention is in a comment on the post
+    comment_id: Option<u64>,
+    // the author of the post or comment
+    by: Principal,
+    created_at: u64,
+}
+
+impl Storable for Mention {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the mentions map: the user mentioned and a mention id, so every
+// user's mentions are one contiguous range in the order they were made
+type MentionKey = PrincipalIdKey;
+
+thread_local! {
+    static MENTION_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::MENTION_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a mention counter")
+    );
+
+    static MENTIONS: RefCell<StableBTreeMap<MentionKey, Mention, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::MENTIONS_MEMORY)));
+}
+
+fn next_id() -> u64 {
+    MENTION_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the mention counter");
+        id
+    })
+}
+
+// Mentions are only announced for posts everyone who may be mentioned can
+// read
+fn is_announced(blog_post: &BlogPost) -> bool {
+    blog_post.status == PostStatus::Published
+        && !blog_post.encrypted
+        && !visibility::is_private(blog_post)
+}
+
+// Record and notify the users `content` mentions that `previous`, the text
+// it replaces, didn't
+fn announce(
+    previous: Option<&str>,
+    content: &str,
+    by: Principal,
+    post_id: u64,
+    comment_id: Option<u64>,
+) {
+    let already = previous.map(render::mentions).unwrap_or_default();
+    let mentioned = render::mentions(content)
+        .into_iter()
+        .filter(|name| !already.contains(name))
+        .filter_map(|name| usernames::lookup(&name))
+        .filter(|user| !linking::same_person(user, &by))
+        .take(MAX_MENTIONS_PER_TEXT);
+    for user in mentioned {
+        let id = next_id();
+        let mention = Mention {
+            id,
+            post_id,
+            comment_id,
+            by,
+            created_at: time(),
+        };
+        MENTIONS.with(|mentions| {
+            mentions
+                .borrow_mut()
+                .insert(MentionKey::new(&user, id), mention)
+        });
+        notifications::notify(
+            &user,
+            NotificationKind::Mentioned {
+                post_id,
+                comment_id,
+            },
+        );
+    }
+}
+
+// Announce the users a published post mentions. `previous` is its content
+// before an edit, whose mentions were announced already; `None` when the
+// post was just published.
+pub(crate) fn notify_post(previous: Option<&str>, blog_post: &BlogPost) {
+    let Ok(author) = Principal::from_text(&blog_post.author) else {
+        return;
+    };
+    if is_announced(blog_post) {
+        announce(previous, &blog_post.content, author, blog_post.id, None);
+    }
+}
+
+// Announce the users a new comment on `blog_post` mentions
+pub(crate) fn notify_comment(blog_post: &BlogPost, comment: &Comment) {
+    if is_announced(blog_post) {
+        announce(
+            None,
+            &comment.content,
+            comment.author,
+            comment.post_id,
+            Some(comment.id),
+        );
+    }
+}
+
+// Drop up to `limit` of the mentions of `principal`, returning how many were
+// dropped
+pub(crate) fn remove_mentions(principal: &Principal, limit: usize) -> usize {
+    MENTIONS.with(|mentions| {
+        let mut mentions = mentions.borrow_mut();
+        let keys: Vec<MentionKey> = mentions
+            .range(MentionKey::all_of(principal))
+            .map(|(key, _)| key)
+            .take(limit)
+            .collect();
+        for key in &keys {
+            mentions.remove(key);
+        }
+        keys.len()
+    })
+}
+
+// Query function to page through the posts and comments that mentioned the
+// caller, newest first
+#[ic_cdk::query]
+fn get_my_mentions(offset: u64, limit: u64) -> Result<Vec<Mention>, Error> {
+    auth::require_signed_in()?;
+    let user = linking::primary(&caller());
+    Ok(MENTIONS.with(|mentions| {
+        mentions
+            .borrow()
+            .range(MentionKey::all_of(&user))
+            .rev()
+            .skip(offset as usize)
+            .take(limit.min(MAX_MENTIONS_PAGE) as usize)
+            .map(|(_, mention)| mention)
+            .collect()
+    }))
+}
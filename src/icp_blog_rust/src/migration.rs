@@ -0,0 +1,481 @@
+use crate::memory::{self, Memory};
+use crate::timeline::{self, SortBy};
+use crate::privacy::{self, PrivacySettings};
+use crate::{audit, authors, bookmarks, categories, comments, likes, profiles, render, slugs, views};
+use candid::types::value::{IDLArgs, IDLField, IDLValue, VariantValue};
+use candid::types::Label;
+use candid::{idl_hash, Decode, Principal};
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::{Cell, Memory as _, StableBTreeMap};
+use std::cell::RefCell;
+
+// Layout version of the records written by this release. Whenever a stored
+// type changes shape in a way Candid cannot decode on its own, bump it and
+// append a step to `MIGRATIONS`.
+pub(crate) const CURRENT_SCHEMA_VERSION: u32 = 28;
+
+// Version of canisters deployed before the schema was versioned
+const UNVERSIONED_SCHEMA_VERSION: u32 = 1;
+
+// Migration steps in order: `MIGRATIONS[i]` rewrites every record from
+// version `i + 1` to version `i + 2`. Steps run in `post_upgrade` before any
+// other code touches the stable maps, so they may open a map with raw types.
+const MIGRATIONS: &[fn()] = &[
+    move_likes_out_of_posts,
+    index_existing_posts,
+    add_reaction_counts,
+    assign_slugs,
+    add_revisions,
+    add_view_counts,
+    add_post_status,
+    add_comment_rate_limit,
+    index_posts_by_date,
+    index_posts_by_likes_and_views,
+    move_public_likes_to_privacy,
+    add_credits,
+    add_dislikes,
+    index_profile_names,
+    add_reading_time,
+    add_math_flag,
+    add_diagrams_flag,
+    add_comments_switch,
+    index_engagement_by_principal,
+    count_views,
+    add_visibility,
+    add_encrypted_flag,
+    add_link_previews,
+    trust_principals_not_origins,
+    index_dislikes_by_principal,
+    index_bookmarks_by_post,
+    count_audit_actions,
+];
+
+thread_local! {
+    static SCHEMA_VERSION: RefCell<Cell<u32, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::SCHEMA_VERSION_MEMORY), UNVERSIONED_SCHEMA_VERSION)
+            .expect("Cannot create the schema version cell")
+    );
+}
+
+pub(crate) fn schema_version() -> u32 {
+    SCHEMA_VERSION.with(|version| *version.borrow().get())
+}
+
+// Record the layout version the stored records are in
+pub(crate) fn stamp(version: u32) {
+    SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut()
+            .set(version)
+            .expect("Cannot write the schema version")
+    });
+}
+
+// Bring the stored records from whatever version they were written with up to
+// the current one, one step at a time
+pub(crate) fn migrate() {
+    let mut version = schema_version();
+    if version > CURRENT_SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "Stored schema version {} is newer than this release ({}); refusing to downgrade",
+            version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+    while version < CURRENT_SCHEMA_VERSION {
+        let step = MIGRATIONS[(version - 1) as usize];
+        step();
+        version += 1;
+        stamp(version);
+    }
+}
+
+// Rewrite every stored blog post through `f`, which receives the record as
+// written by the previous schema version and returns the new encoding. The
+// posts map is opened with raw values, so this must only run from `migrate`.
+fn rewrite_blog_posts(mut f: impl FnMut(&[u8]) -> Vec<u8>) {
+    let mut posts: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY));
+    let ids: Vec<u64> = posts.keys().collect();
+    for id in ids {
+        let old = posts.get(&id).expect("Post disappeared during migration");
+        posts.insert(id, f(&old));
+    }
+}
+
+// Edit the fields of a Candid-encoded record without a Rust type for its
+// layout. Migration steps use this so they keep working after the current
+// types move on.
+fn edit_record(bytes: &[u8], f: impl FnOnce(&mut Vec<IDLField>)) -> Vec<u8> {
+    let mut args = IDLArgs::from_bytes(bytes).expect("Cannot decode a stored record");
+    match args.args.first_mut() {
+        Some(IDLValue::Record(fields)) => f(fields),
+        _ => ic_cdk::trap("Stored value is not a record"),
+    }
+    args.to_bytes().expect("Cannot encode a stored record")
+}
+
+// Set a field of a record, adding it if it is missing. Fields that are not
+// `opt` can't be decoded from records written without them, so new fields of
+// stored types are introduced through this.
+fn set_field(fields: &mut Vec<IDLField>, name: &str, value: IDLValue) {
+    fields.retain(|field| field.id.get_id() != idl_hash(name));
+    fields.push(IDLField {
+        id: Label::Named(name.to_string()),
+        val: value,
+    });
+}
+
+fn remove_field(fields: &mut Vec<IDLField>, name: &str) {
+    fields.retain(|field| field.id.get_id() != idl_hash(name));
+}
+
+// The fields of a blog post in schema version 1 needed to move its likes
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostV1Likes {
+    id: u64,
+    liked: Vec<Principal>,
+}
+
+// 1 -> 2: likes move to their own map and posts only keep the count. The
+// time of those likes was never recorded, so they are stored with time 0.
+fn move_likes_out_of_posts() {
+    rewrite_blog_posts(|bytes| {
+        let old = Decode!(bytes, BlogPostV1Likes).expect("Cannot decode a version 1 blog post");
+        for principal in &old.liked {
+            likes::add_like(old.id, principal, 0);
+        }
+        edit_record(bytes, |fields| {
+            remove_field(fields, "liked");
+            // the list is authoritative if the two ever drifted apart
+            set_field(fields, "likes", IDLValue::Nat32(old.liked.len() as u32));
+        })
+    });
+}
+
+// The fields of a blog post since schema version 1 needed to index it
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostIndexFields {
+    id: u64,
+    author: String,
+    categories: Vec<String>,
+}
+
+// 2 -> 3: posts created before the category and author indexes existed are
+// added to them. Indexing is idempotent, so already indexed posts are fine.
+fn index_existing_posts() {
+    let posts: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY));
+    for (_, bytes) in posts.iter() {
+        let post = Decode!(&bytes, BlogPostIndexFields).expect("Cannot decode a stored blog post");
+        categories::index_post(post.id, &post.categories);
+        authors::index_post(post.id, &post.author);
+    }
+}
+
+// 3 -> 4: posts carry their per-kind reaction counts
+fn add_reaction_counts() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| {
+            set_field(fields, "reactions", IDLValue::Vec(Vec::new()))
+        })
+    });
+}
+
+// The fields of a blog post since schema version 1 needed to derive its slug
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostSlugFields {
+    id: u64,
+    title: String,
+}
+
+// 4 -> 5: every post gets a slug derived from its title, in creation order so
+// older posts keep the plain form when titles collide
+fn assign_slugs() {
+    rewrite_blog_posts(|bytes| {
+        let post = Decode!(bytes, BlogPostSlugFields).expect("Cannot decode a stored blog post");
+        let slug = slugs::assign_slug(post.id, &post.title);
+        edit_record(bytes, |fields| {
+            set_field(fields, "slug", IDLValue::Text(slug))
+        })
+    });
+}
+
+// 5 -> 6: posts carry a revision number. Earlier edits weren't counted, so
+// every existing post starts at revision 1.
+fn add_revisions() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "revision", IDLValue::Nat64(1)))
+    });
+}
+
+// 6 -> 7: posts carry a view count, starting from zero
+fn add_view_counts() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "views", IDLValue::Nat64(0)))
+    });
+}
+
+// 7 -> 8: posts have a status; every existing post was public, so published
+fn add_post_status() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| {
+            let published = IDLField {
+                id: Label::Named("Published".to_string()),
+                val: IDLValue::Null,
+            };
+            set_field(fields, "status", IDLValue::Variant(VariantValue(Box::new(published), 0)))
+        })
+    });
+}
+
+// 8 -> 9: the rate limits gain a comment limit, starting at the default
+fn add_comment_rate_limit() {
+    // the cell is created on first use, which may not have happened yet
+    if memory::get(memory::RATE_LIMITS_MEMORY).size() == 0 {
+        return;
+    }
+    let mut cell: Cell<Vec<u8>, Memory> =
+        Cell::init(memory::get(memory::RATE_LIMITS_MEMORY), Vec::new())
+            .expect("Cannot open the rate limits cell");
+    let limits = edit_record(cell.get(), |fields| {
+        set_field(fields, "comments_per_hour", IDLValue::Nat32(30))
+    });
+    cell.set(limits).expect("Cannot write the rate limits cell");
+}
+
+// The fields of a blog post since schema version 1 needed to date it
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostDateFields {
+    id: u64,
+    created_at: u64,
+}
+
+// 9 -> 10: existing posts are added to the creation time index
+fn index_posts_by_date() {
+    let posts: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY));
+    for (_, bytes) in posts.iter() {
+        let post = Decode!(&bytes, BlogPostDateFields).expect("Cannot decode a stored blog post");
+        timeline::insert(SortBy::Newest, post.id, post.created_at);
+    }
+}
+
+// The fields of a blog post since schema version 7 needed to rank it
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostRankFields {
+    id: u64,
+    likes: u32,
+    views: u64,
+}
+
+// 10 -> 11: existing posts are added to the likes and views indexes
+fn index_posts_by_likes_and_views() {
+    let posts: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY));
+    for (_, bytes) in posts.iter() {
+        let post = Decode!(&bytes, BlogPostRankFields).expect("Cannot decode a stored blog post");
+        timeline::insert(SortBy::MostLiked, post.id, post.likes as u64);
+        timeline::insert(SortBy::MostViewed, post.id, post.views);
+    }
+}
+
+// 11 -> 12: principals who made their likes public on their activity keep
+// them public under the privacy settings that replace that opt-in
+fn move_public_likes_to_privacy() {
+    let mut public_likes: StableBTreeMap<Blob<29>, (), Memory> =
+        StableBTreeMap::init(memory::get(memory::PUBLIC_LIKES_MEMORY));
+    let principals: Vec<Blob<29>> = public_likes.keys().collect();
+    for principal in principals {
+        privacy::save(
+            &Principal::from_slice(principal.as_slice()),
+            PrivacySettings {
+                likes_public: true,
+                ..PrivacySettings::default()
+            },
+        );
+        public_likes.remove(&principal);
+    }
+}
+
+// 12 -> 13: posts list contributor credits, starting with none
+fn add_credits() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "credits", IDLValue::Vec(Vec::new())))
+    });
+}
+
+// The fields of a blog post since schema version 2 needed to score it
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostLikeFields {
+    id: u64,
+    likes: u32,
+}
+
+// 13 -> 14: posts carry a dislike count, starting from zero, and a score,
+// which is then their likes; they are added to the score index
+fn add_dislikes() {
+    rewrite_blog_posts(|bytes| {
+        let post = Decode!(bytes, BlogPostLikeFields).expect("Cannot decode a stored blog post");
+        timeline::insert(SortBy::TopScore, post.id, timeline::score_rank(post.likes as i64));
+        edit_record(bytes, |fields| {
+            set_field(fields, "dislikes", IDLValue::Nat32(0));
+            set_field(fields, "score", IDLValue::Int64(post.likes as i64));
+        })
+    });
+}
+
+// 14 -> 15: display names of existing profiles are added to the name index
+fn index_profile_names() {
+    profiles::index_existing_names();
+}
+
+// The fields of a blog post needed to count its words
+#[derive(candid::CandidType, Deserialize)]
+struct BlogPostContentFields {
+    content: String,
+}
+
+// 15 -> 16: posts carry their word count and reading time
+fn add_reading_time() {
+    rewrite_blog_posts(|bytes| {
+        let post = Decode!(bytes, BlogPostContentFields).expect("Cannot decode a stored blog post");
+        let words = render::word_count(&post.content);
+        edit_record(bytes, |fields| {
+            set_field(fields, "word_count", IDLValue::Nat32(words));
+            set_field(fields, "reading_minutes", IDLValue::Nat32(render::reading_minutes(words)));
+        })
+    });
+}
+
+// 16 -> 17: posts carry whether their content has math
+fn add_math_flag() {
+    rewrite_blog_posts(|bytes| {
+        let post = Decode!(bytes, BlogPostContentFields).expect("Cannot decode a stored blog post");
+        edit_record(bytes, |fields| {
+            set_field(fields, "has_math", IDLValue::Bool(render::has_math(&post.content)))
+        })
+    });
+}
+
+// 17 -> 18: posts carry whether their content has diagrams
+fn add_diagrams_flag() {
+    rewrite_blog_posts(|bytes| {
+        let post = Decode!(bytes, BlogPostContentFields).expect("Cannot decode a stored blog post");
+        edit_record(bytes, |fields| {
+            set_field(fields, "has_diagrams", IDLValue::Bool(render::has_diagrams(&post.content)))
+        })
+    });
+}
+
+// 18 -> 19: posts carry whether readers may comment, which they all could
+fn add_comments_switch() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "comments_enabled", IDLValue::Bool(true)))
+    });
+}
+
+// 19 -> 20: likes and comments are indexed by who gave them
+fn index_engagement_by_principal() {
+    likes::index_existing_likes();
+    comments::index_existing_comments();
+}
+
+// 20 -> 21: the views of existing posts start the site-wide views counter
+fn count_views() {
+    let posts: StableBTreeMap<u64, Vec<u8>, Memory> =
+        StableBTreeMap::init(memory::get(memory::BLOG_POSTS_MEMORY));
+    let mut total = 0;
+    for (_, bytes) in posts.iter() {
+        let post = Decode!(&bytes, BlogPostRankFields).expect("Cannot decode a stored blog post");
+        total += post.views;
+    }
+    views::set_total(total);
+}
+
+// 21 -> 22: posts carry who may see them; existing posts stay public
+fn add_visibility() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| {
+            let public = IDLField {
+                id: Label::Named("Public".to_string()),
+                val: IDLValue::Null,
+            };
+            set_field(fields, "visibility", IDLValue::Variant(VariantValue(Box::new(public), 0)))
+        })
+    });
+}
+
+// 22 -> 23: no post was encrypted before private posts could be
+fn add_encrypted_flag() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "encrypted", IDLValue::Bool(false)))
+    });
+}
+
+// 23 -> 24: posts carry previews of the pages they link to, starting with none
+fn add_link_previews() {
+    rewrite_blog_posts(|bytes| {
+        edit_record(bytes, |fields| set_field(fields, "link_previews", IDLValue::Vec(Vec::new())))
+    });
+}
+
+// 24 -> 25: the destructive action guard trusts principals instead of the
+// origins callers declared. The old origins can't be turned into principals,
+// so the list starts empty; an enabled guard then only lets admins through
+// until they list the trusted principals.
+fn trust_principals_not_origins() {
+    // the cell is created on first use, which may not have happened yet
+    if memory::get(memory::DESTRUCTIVE_ACTION_GUARD_MEMORY).size() == 0 {
+        return;
+    }
+    let mut cell: Cell<Vec<u8>, Memory> =
+        Cell::init(memory::get(memory::DESTRUCTIVE_ACTION_GUARD_MEMORY), Vec::new())
+            .expect("Cannot open the destructive action guard cell");
+    let guard = edit_record(cell.get(), |fields| {
+        remove_field(fields, "allowed_origins");
+        set_field(fields, "trusted_principals", IDLValue::Vec(Vec::new()));
+    });
+    cell.set(guard).expect("Cannot write the destructive action guard cell");
+}
+
+// 25 -> 26: dislikes are indexed by who gave them, like likes
+fn index_dislikes_by_principal() {
+    likes::index_existing_dislikes();
+}
+
+// 26 -> 27: bookmarks are indexed by post
+fn index_bookmarks_by_post() {
+    bookmarks::index_existing_bookmarks();
+}
+
+// 27 -> 28: audit log actions are counted by month for the monthly reports
+fn count_audit_actions() {
+    audit::count_existing_actions();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{do_insert, testing, BlogPost};
+
+    #[test]
+    fn legacy_posts_with_invalid_categories_are_indexed_without_them() {
+        let blog_post = BlogPost {
+            id: 1,
+            author: testing::principal(1).to_text(),
+            categories: vec![
+                "rust".to_string(),
+                "x".repeat(categories::MAX_CATEGORY_LEN + 1),
+                "nul\0byte".to_string(),
+                String::new(),
+            ],
+            ..BlogPost::default()
+        };
+        do_insert(&blog_post);
+
+        index_existing_posts();
+
+        assert_eq!(categories::post_count("rust"), Some(1));
+        assert_eq!(categories::list_categories(), vec![("rust".to_string(), 1)]);
+        assert!(authors::is_indexed(blog_post.id, &blog_post.author));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::abuse::{self, SignalKind};
+use crate::audit::{self, AuditAction};
+use crate::jobs::{self, Job};
+use crate::memory::{self, Memory};
+use crate::{
+    _get_blog_post, _get_visible_blog_post, asset_sync, auth, authors, categories, comments,
+    do_insert, rate_limits, BlogPost, Error, PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest accepted report reason, in bytes
+const MAX_REASON_LEN: usize = 1000;
+// Largest page `get_reports` returns
+const MAX_REPORTS_PAGE: u64 = 100;
+// Shortest pattern `bulk_delete_comments` accepts, so a typo can't match
+// nearly every comment
+const MIN_PATTERN_LEN: usize = 3;
+
+#[derive(candid::CandidType, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum ReportTarget {
+    Post(u64),
+    Comment(u64),
+}
+
+// A reader's complaint about a post or comment, open until a moderator acts
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Report {
+    id: u64,
+    target: ReportTarget,
+    reporter: Principal,
+    reason: String,
+    created_at: u64,
+}
+
+impl Storable for Report {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static REPORT_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::REPORT_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a report counter")
+    );
+
+    // Open reports, oldest first
+    static REPORTS: RefCell<StableBTreeMap<u64, Report, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REPORTS_MEMORY)));
+}
+
+fn next_report_id() -> u64 {
+    REPORT_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the report counter");
+        id
+    })
+}
+
+// File a report in the canister's own name, such as for content using terms
+// on the blocklist. A target has at most one open report of this kind.
+pub(crate) fn file_automatic_report(target: ReportTarget, reason: String) {
+    let reporter = ic_cdk::id();
+    let already_reported = REPORTS.with(|reports| {
+        reports
+            .borrow()
+            .iter()
+            .any(|(_, report)| report.target == target && report.reporter == reporter)
+    });
+    if already_reported {
+        return;
+    }
+    let id = next_report_id();
+    let report = Report {
+        id,
+        target,
+        reporter,
+        reason,
+        created_at: time(),
+    };
+    REPORTS.with(|reports| reports.borrow_mut().insert(id, report));
+}
+
+fn file_report(target: ReportTarget, reason: String) -> Result<Report, Error> {
+    auth::require_signed_in()?;
+    if reason.trim().is_empty() || reason.len() > MAX_REASON_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Reasons must be between 1 and {} bytes long",
+                MAX_REASON_LEN
+            ),
+        });
+    }
+    let reporter = caller();
+    let already_reported = REPORTS.with(|reports| {
+        reports
+            .borrow()
+            .iter()
+            .any(|(_, report)| report.target == target && report.reporter == reporter)
+    });
+    if already_reported {
+        return Err(Error::ValidationErrors {
+            errors: "Caller already reported this content".to_string(),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+
+    let id = next_report_id();
+    let report = Report {
+        id,
+        target,
+        reporter,
+        reason,
+        created_at: time(),
+    };
+    REPORTS.with(|reports| reports.borrow_mut().insert(id, report.clone()));
+    abuse::record(SignalKind::ReportFiled {
+        reporter,
+        target: report.target.clone(),
+    });
+    Ok(report)
+}
+
+// Close every open report about a target once a moderator dealt with it
+fn resolve_reports(target: &ReportTarget) {
+    REPORTS.with(|reports| {
+        let mut reports = reports.borrow_mut();
+        let ids: Vec<u64> = reports
+            .iter()
+            .filter(|(_, report)| report.target == *target)
+            .map(|(id, _)| id)
+            .collect();
+        for id in ids {
+            reports.remove(&id);
+        }
+    });
+}
+
+// Update function to report a post to the moderators
+#[ic_cdk::update]
+fn report_post(id: u64, reason: String) -> Result<Report, Error> {
+    if _get_visible_blog_post(&id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    }
+    file_report(ReportTarget::Post(id), reason)
+}
+
+// Update function to report a comment to the moderators
+#[ic_cdk::update]
+fn report_comment(id: u64, reason: String) -> Result<Report, Error> {
+    match comments::get(id) {
+        Some(comment) if !comment.hidden => file_report(ReportTarget::Comment(id), reason),
+        _ => Err(Error::NotFound {
+            msg: format!("Comment with ID {} not found", id),
+        }),
+    }
+}
+
+// Number of reports no moderator acted on yet
+pub(crate) fn open_reports() -> u64 {
+    REPORTS.with(|reports| reports.borrow().len())
+}
+
+// Query function for moderators to page through open reports, oldest first
+#[ic_cdk::query]
+fn get_reports(offset: u64, limit: u64) -> Result<Vec<Report>, Error> {
+    auth::require_moderator()?;
+    Ok(REPORTS.with(|reports| {
+        reports
+            .borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_REPORTS_PAGE) as usize)
+            .map(|(_, report)| report)
+            .collect()
+    }))
+}
+
+// Update function for moderators to take a post out of public view. Its
+// author still sees it; the reports about it are closed.
+#[ic_cdk::update]
+fn hide_post(id: u64) -> Result<BlogPost, Error> {
+    auth::require_moderator()?;
+    let Some(blog_post) = _get_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    };
+    Ok(hide(blog_post))
+}
+
+pub(crate) fn hide(mut blog_post: BlogPost) -> BlogPost {
+    if blog_post.status == PostStatus::Published {
+        asset_sync::unpublish(&blog_post);
+    }
+    blog_post.status = PostStatus::Hidden;
+    do_insert(&blog_post);
+    resolve_reports(&ReportTarget::Post(blog_post.id));
+    audit::record(AuditAction::HidePost, blog_post.id);
+    blog_post
+}
+
+// Update function for moderators to hide a comment from listings; the
+// reports about it are closed
+#[ic_cdk::update]
+fn hide_comment(id: u64) -> Result<(), Error> {
+    auth::require_moderator()?;
+    let Some(mut comment) = comments::get(id) else {
+        return Err(Error::NotFound {
+            msg: format!("Comment with ID {} not found", id),
+        });
+    };
+    comment.hidden = true;
+    comments::save(&comment);
+    abuse::record(SignalKind::CommentRemoved);
+    resolve_reports(&ReportTarget::Comment(id));
+    audit::record(AuditAction::HideComment, id);
+    Ok(())
+}
+
+// Update function for moderators to close a report without acting on it
+#[ic_cdk::update]
+fn dismiss_report(id: u64) -> Result<Report, Error> {
+    auth::require_moderator()?;
+    match REPORTS.with(|reports| reports.borrow_mut().remove(&id)) {
+        Some(report) => {
+            audit::record(AuditAction::DismissReport, id);
+            Ok(report)
+        }
+        None => Err(Error::NotFound {
+            msg: format!("Report with ID {} not found", id),
+        }),
+    }
+}
+
+// Result of a bulk moderation call: either the items a dry run would touch,
+// or the background job doing the work
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) enum BulkOutcome {
+    DryRun { affected: Vec<u64> },
+    Queued { job: Job },
+}
+
+// IDs of up to `limit` published posts of an author, oldest first
+fn published_posts_of(author: &Principal, limit: usize) -> Vec<u64> {
+    let mut ids = authors::newest_post_ids(author, usize::MAX);
+    ids.reverse();
+    ids.into_iter()
+        .filter(|id| {
+            _get_blog_post(id).is_some_and(|blog_post| blog_post.status == PostStatus::Published)
+        })
+        .take(limit)
+        .collect()
+}
+
+// Hide up to `limit` published posts of an author. Called by the job runner
+// until it reports that none is left.
+pub(crate) fn hide_author_posts_batch(
+    author: &Principal,
+    limit: usize,
+) -> Result<(u64, bool), String> {
+    let ids = published_posts_of(author, limit);
+    for blog_post in ids.iter().filter_map(_get_blog_post) {
+        hide(blog_post);
+    }
+    Ok((ids.len() as u64, ids.len() < limit))
+}
+
+// Delete up to `limit` comments containing `pattern`. Called by the job runner
+// until it reports that none is left.
+pub(crate) fn delete_matching_comments_batch(
+    pattern: &str,
+    limit: usize,
+) -> Result<(u64, bool), String> {
+    let ids = comments::matching_ids(pattern, limit);
+    for comment in ids.iter().copied().filter_map(comments::get) {
+        comments::remove(&comment);
+        resolve_reports(&ReportTarget::Comment(comment.id));
+        abuse::record(SignalKind::CommentRemoved);
+        audit::record(AuditAction::DeleteComment, comment.id);
+    }
+    Ok((ids.len() as u64, ids.len() < limit))
+}
+
+// Update function for moderators to hide every published post of an author.
+// With `dry_run` it only returns the IDs of the posts that would be hidden.
+#[ic_cdk::update]
+fn bulk_hide_author_posts(author: Principal, dry_run: bool) -> Result<BulkOutcome, Error> {
+    auth::require_moderator()?;
+    if dry_run {
+        return Ok(BulkOutcome::DryRun {
+            affected: published_posts_of(&author, usize::MAX),
+        });
+    }
+    Ok(BulkOutcome::Queued {
+        job: jobs::enqueue_hide_author_posts(author),
+    })
+}
+
+// Update function for moderators to delete every comment containing a
+// pattern, ignoring case. With `dry_run` it only returns the IDs of the
+// comments that would be deleted.
+#[ic_cdk::update]
+fn bulk_delete_comments(pattern: String, dry_run: bool) -> Result<BulkOutcome, Error> {
+    auth::require_moderator()?;
+    if pattern.trim().chars().count() < MIN_PATTERN_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Patterns must be at least {} characters long",
+                MIN_PATTERN_LEN
+            ),
+        });
+    }
+    if dry_run {
+        return Ok(BulkOutcome::DryRun {
+            affected: comments::matching_ids(&pattern, usize::MAX),
+        });
+    }
+    Ok(BulkOutcome::Queued {
+        job: jobs::enqueue_delete_matching_comments(pattern),
+    })
+}
+
+// Update function for moderators to move every post of a category into
+// another. With `dry_run` it only returns the IDs of the posts that would move.
+#[ic_cdk::update]
+fn bulk_reassign_category(from: String, to: String, dry_run: bool) -> Result<BulkOutcome, Error> {
+    auth::require_moderator()?;
+    categories::check_merge(&from, &to)?;
+    if dry_run {
+        return Ok(BulkOutcome::DryRun {
+            affected: categories::posts_in_category(&from, usize::MAX),
+        });
+    }
+    Ok(BulkOutcome::Queued {
+        job: jobs::enqueue_merge_categories(from, to),
+    })
+}
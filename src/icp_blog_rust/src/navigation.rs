@@ -0,0 +1,163 @@
+use crate::http;
+use crate::memory::{self, Memory};
+use crate::render::escape;
+use crate::{auth, categories, config, pages, Error};
+use candid::{Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most links the navigation can hold
+const MAX_NAV_LINKS: usize = 20;
+// Longest link label, in characters
+const MAX_LABEL_LEN: usize = 50;
+// Longest external URL, in bytes
+const MAX_URL_LEN: usize = 500;
+
+// Where a navigation link leads
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum NavTarget {
+    // a page of `pages`, by slug
+    Page(String),
+    // the feed of a category, as there are no category pages
+    Category(String),
+    External(String),
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct NavLink {
+    label: String,
+    target: NavTarget,
+}
+
+#[derive(candid::CandidType, Default, Serialize, Deserialize)]
+struct Navigation(Vec<NavLink>);
+
+impl Storable for Navigation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Links shown atop every page, in order; none until admins set them
+    static NAVIGATION: RefCell<Cell<Navigation, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::NAVIGATION_MEMORY), Navigation::default())
+            .expect("Cannot create the navigation cell")
+    );
+}
+
+fn links() -> Vec<NavLink> {
+    NAVIGATION.with(|cell| cell.borrow().get().0.clone())
+}
+
+fn validate(link: &NavLink) -> Result<(), Error> {
+    let label_len = link.label.trim().chars().count();
+    if label_len == 0 || label_len > MAX_LABEL_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Link labels must be between 1 and {} characters long",
+                MAX_LABEL_LEN
+            ),
+        });
+    }
+    match &link.target {
+        NavTarget::Page(slug) if pages::get(slug).is_none() => Err(Error::NotFound {
+            msg: format!("Page {} not found", slug),
+        }),
+        NavTarget::Category(category) if categories::post_count(category).is_none() => {
+            Err(Error::NotFound {
+                msg: format!("Category {} not found", category),
+            })
+        }
+        NavTarget::External(url)
+            if !(url.starts_with("https://") || url.starts_with("http://"))
+                || url.len() > MAX_URL_LEN =>
+        {
+            Err(Error::ValidationErrors {
+                errors: format!(
+                    "External links must be http or https URLs of at most {} bytes",
+                    MAX_URL_LEN
+                ),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Where a link leads, relative to the canister's URL unless external; None
+// for pages deleted since the link was set
+fn href(base_url: &str, target: &NavTarget) -> Option<String> {
+    match target {
+        NavTarget::Page(slug) => {
+            pages::get(slug).map(|_| format!("{}/{}", base_url, http::encode_segment(slug)))
+        }
+        NavTarget::Category(category) => Some(format!(
+            "{}/feed/category/{}.xml",
+            base_url,
+            http::encode_segment(category)
+        )),
+        NavTarget::External(url) => Some(url.clone()),
+    }
+}
+
+// The navigation as a `<nav>` bar, empty when no links are set
+pub(crate) fn to_html(base_url: &str) -> String {
+    let items: String = links()
+        .iter()
+        .filter_map(|link| {
+            href(base_url, &link.target).map(|href| {
+                format!(
+                    r#"<li><a href="{}">{}</a></li>"#,
+                    escape(&href),
+                    escape(&link.label)
+                )
+            })
+        })
+        .collect();
+    if items.is_empty() {
+        return String::new();
+    }
+    format!("<nav><ul>{}</ul></nav>", items)
+}
+
+// Query function for frontends to build the site's menu
+#[ic_cdk::query]
+fn get_navigation() -> Result<Vec<NavLink>, Error> {
+    config::require_reader()?;
+    Ok(links())
+}
+
+// Update function for admins to replace the navigation links, shown in the
+// given order. Pages and categories must exist when set.
+#[ic_cdk::update]
+fn set_navigation(links: Vec<NavLink>) -> Result<Vec<NavLink>, Error> {
+    auth::require_admin()?;
+    if links.len() > MAX_NAV_LINKS {
+        return Err(Error::ValidationErrors {
+            errors: format!("The navigation holds at most {} links", MAX_NAV_LINKS),
+        });
+    }
+    let links: Vec<NavLink> = links
+        .into_iter()
+        .map(|link| NavLink {
+            label: link.label.trim().to_string(),
+            target: link.target,
+        })
+        .collect();
+    for link in &links {
+        validate(link)?;
+    }
+    NAVIGATION.with(|cell| {
+        cell.borrow_mut()
+            .set(Navigation(links.clone()))
+            .expect("Cannot write the navigation")
+    });
+    Ok(links)
+}
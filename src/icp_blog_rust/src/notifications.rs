@@ -0,0 +1,208 @@
+use crate::keys::{principal_key, PrincipalIdKey};
+use crate::memory::{self, Memory};
+use crate::reactions::ReactionKind;
+use crate::{auth, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `get_my_notifications` returns
+const MAX_NOTIFICATIONS_PAGE: u64 = 100;
+
+// What happened to a user's content
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum NotificationKind {
+    Liked { post_id: u64 },
+    Reacted { post_id: u64, reaction: ReactionKind },
+    Followed,
+    Commented { post_id: u64, comment_id: u64 },
+    Tipped { post_id: u64, amount: u64 },
+    GiftedAccess { post_id: u64 },
+    TransferOffered { post_id: u64 },
+    Mentioned { post_id: u64, comment_id: Option<u64> },
+    DraftStale { draft_id: u64 },
+    AddedAsCollaborator { post_id: u64 },
+    InactivePost { post_id: u64, archive_at: u64 },
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Notification {
+    id: u64,
+    kind: NotificationKind,
+    // who liked, reacted, followed, commented, tipped, gifted access,
+    // offered a post, mentioned the recipient or made them a co-author; the
+    // canister itself for
+    // reminders
+    actor: Principal,
+    created_at: u64,
+    read: bool,
+}
+
+impl Storable for Notification {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the inboxes map: a recipient and a notification id, so every inbox
+// is one contiguous range in arrival order
+type InboxKey = PrincipalIdKey;
+
+thread_local! {
+    static NOTIFICATION_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::NOTIFICATION_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a notification counter")
+    );
+
+    static INBOXES: RefCell<StableBTreeMap<InboxKey, Notification, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::INBOXES_MEMORY)));
+
+    // Number of unread notifications of every user with any
+    static UNREAD: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::UNREAD_NOTIFICATIONS_MEMORY)));
+}
+
+fn unread_count(principal: &Principal) -> u64 {
+    UNREAD.with(|unread| unread.borrow().get(&principal_key(principal)).unwrap_or(0))
+}
+
+fn set_unread_count(principal: &Principal, count: u64) {
+    UNREAD.with(|unread| {
+        let mut unread = unread.borrow_mut();
+        if count == 0 {
+            unread.remove(&principal_key(principal));
+        } else {
+            unread.insert(principal_key(principal), count);
+        }
+    });
+}
+
+// Append a notification to the inbox of `recipient` about something the
+// caller did. Users aren't notified of their own actions.
+pub(crate) fn notify(recipient: &Principal, kind: NotificationKind) {
+    let actor = caller();
+    if *recipient == actor || *recipient == Principal::anonymous() {
+        return;
+    }
+    let id = NOTIFICATION_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the notification counter");
+        id
+    });
+    let notification = Notification {
+        id,
+        kind,
+        actor,
+        created_at: time(),
+        read: false,
+    };
+    INBOXES.with(|inboxes| {
+        inboxes
+            .borrow_mut()
+            .insert(InboxKey::new(recipient, id), notification)
+    });
+    set_unread_count(recipient, unread_count(recipient) + 1);
+}
+
+// Post authors are stored as principal text
+pub(crate) fn notify_author(author: &str, kind: NotificationKind) {
+    if let Ok(author) = Principal::from_text(author) {
+        notify(&author, kind);
+    }
+}
+
+// Drop up to `limit` notifications of the inbox of `principal`, returning
+// how many were dropped
+pub(crate) fn remove_inbox(principal: &Principal, limit: usize) -> usize {
+    let keys: Vec<InboxKey> = INBOXES.with(|inboxes| {
+        inboxes
+            .borrow()
+            .range(InboxKey::all_of(principal))
+            .map(|(key, _)| key)
+            .take(limit)
+            .collect()
+    });
+    INBOXES.with(|inboxes| {
+        let mut inboxes = inboxes.borrow_mut();
+        for key in &keys {
+            inboxes.remove(key);
+        }
+    });
+    if keys.len() < limit {
+        set_unread_count(principal, 0);
+    }
+    keys.len()
+}
+
+// Drop up to `limit` notifications about something `principal` did from the
+// inboxes of other users, returning how many were dropped
+pub(crate) fn remove_by_actor(principal: &Principal, limit: usize) -> usize {
+    let removed: Vec<(InboxKey, Notification)> = INBOXES.with(|inboxes| {
+        inboxes
+            .borrow()
+            .iter()
+            .filter(|(_, notification)| notification.actor == *principal)
+            .take(limit)
+            .collect()
+    });
+    for (key, notification) in &removed {
+        INBOXES.with(|inboxes| inboxes.borrow_mut().remove(key));
+        if !notification.read {
+            let recipient = key.principal();
+            set_unread_count(&recipient, unread_count(&recipient).saturating_sub(1));
+        }
+    }
+    removed.len()
+}
+
+// Query function to page through the caller's notifications, newest first
+#[ic_cdk::query]
+fn get_my_notifications(offset: u64, limit: u64) -> Result<Vec<Notification>, Error> {
+    auth::require_signed_in()?;
+    Ok(INBOXES.with(|inboxes| {
+        inboxes
+            .borrow()
+            .range(InboxKey::all_of(&caller()))
+            .rev()
+            .skip(offset as usize)
+            .take(limit.min(MAX_NOTIFICATIONS_PAGE) as usize)
+            .map(|(_, notification)| notification)
+            .collect()
+    }))
+}
+
+// Update function for the caller to mark one of their notifications as read
+#[ic_cdk::update]
+fn mark_notification_read(id: u64) -> Result<Notification, Error> {
+    auth::require_signed_in()?;
+    let recipient = caller();
+    let key = InboxKey::new(&recipient, id);
+    let Some(mut notification) = INBOXES.with(|inboxes| inboxes.borrow().get(&key)) else {
+        return Err(Error::NotFound {
+            msg: format!("Notification with ID {} not found", id),
+        });
+    };
+    if !notification.read {
+        notification.read = true;
+        INBOXES.with(|inboxes| inboxes.borrow_mut().insert(key, notification.clone()));
+        set_unread_count(&recipient, unread_count(&recipient).saturating_sub(1));
+    }
+    Ok(notification)
+}
+
+// Query function for the number of the caller's unread notifications
+#[ic_cdk::query]
+fn get_my_unread_count() -> u64 {
+    unread_count(&caller())
+}
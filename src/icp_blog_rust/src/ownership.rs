@@ -0,0 +1,151 @@
+use crate::audit::{self, AuditAction};
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{
+    _check_if_owner, _get_blog_post, asset_sync, auth, authors, collaborators, config, do_insert,
+    linking, sessions, tips, BlogPost, Error, PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// How long the new owner has to accept a transfer, in nanoseconds
+const TRANSFER_TTL: u64 = 7 * 24 * 3600 * 1_000_000_000;
+
+// A post offered to a new owner, waiting for them to accept it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PostTransfer {
+    post_id: u64,
+    from: Principal,
+    to: Principal,
+    offered_at: u64,
+    expires_at: u64,
+}
+
+impl Storable for PostTransfer {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Post -> the transfer offered for it
+    static TRANSFERS: RefCell<StableBTreeMap<u64, PostTransfer, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POST_TRANSFERS_MEMORY)));
+}
+
+// Drop the pending transfer of a deleted post
+pub(crate) fn remove_post_transfer(post_id: u64) {
+    TRANSFERS.with(|transfers| transfers.borrow_mut().remove(&post_id));
+}
+
+fn not_found(id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Blog post with ID {} not found", id),
+    }
+}
+
+// Update function for the author of a post, or an admin, to offer it to a
+// new owner, who takes it over with `accept_post_transfer` within a week.
+// Offering it again replaces the offer.
+#[ic_cdk::update]
+fn transfer_post_ownership(id: u64, new_owner: Principal) -> Result<PostTransfer, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let blog_post = _get_blog_post(&id).ok_or_else(|| not_found(id))?;
+    if !_check_if_owner(&blog_post) && !auth::is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to transfer post with id={}.", id),
+        });
+    }
+    sessions::require_trusted_origin()?;
+    let Ok(from) = Principal::from_text(&blog_post.author) else {
+        return Err(not_found(id));
+    };
+    if new_owner == Principal::anonymous() || linking::same_person(&from, &new_owner) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Cannot transfer post with id={} to {}", id, new_owner),
+        });
+    }
+    let now = time();
+    let transfer = PostTransfer {
+        post_id: id,
+        from,
+        to: new_owner,
+        offered_at: now,
+        expires_at: now + TRANSFER_TTL,
+    };
+    TRANSFERS.with(|transfers| transfers.borrow_mut().insert(id, transfer.clone()));
+    notifications::notify(
+        &new_owner,
+        NotificationKind::TransferOffered { post_id: id },
+    );
+    Ok(transfer)
+}
+
+// Update function for the author of a post, or an admin, to withdraw an
+// offer not yet accepted
+#[ic_cdk::update]
+fn cancel_post_transfer(id: u64) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let blog_post = _get_blog_post(&id).ok_or_else(|| not_found(id))?;
+    if !_check_if_owner(&blog_post) && !auth::is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to transfer post with id={}.", id),
+        });
+    }
+    match TRANSFERS.with(|transfers| transfers.borrow_mut().remove(&id)) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with ID {} is not being transferred", id),
+        }),
+    }
+}
+
+// Update function for the new owner to take over a post offered to them.
+// Its payout splits are dropped, since they name the previous owner's
+// co-authors.
+#[ic_cdk::update]
+fn accept_post_transfer(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    sessions::require_trusted_origin()?;
+    let transfer = TRANSFERS
+        .with(|transfers| transfers.borrow().get(&id))
+        .filter(|transfer| {
+            transfer.expires_at > time() && linking::same_person(&transfer.to, &caller())
+        })
+        .ok_or_else(|| Error::NotFound {
+            msg: format!("Blog post with ID {} was not offered to {}", id, caller()),
+        })?;
+    TRANSFERS.with(|transfers| transfers.borrow_mut().remove(&id));
+    let mut blog_post = _get_blog_post(&id).ok_or_else(|| not_found(id))?;
+    // the post may have changed hands another way since it was offered
+    if blog_post.author != transfer.from.to_string() {
+        return Err(Error::ConflictingEdit {
+            msg: format!(
+                "Blog post with ID {} changed owner since it was offered",
+                id
+            ),
+        });
+    }
+    authors::unindex_post(id, &blog_post.author);
+    blog_post.author = linking::primary(&caller()).to_string();
+    authors::index_post(id, &blog_post.author);
+    tips::remove_post_splits(id);
+    collaborators::remove(id, &caller());
+    do_insert(&blog_post);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    audit::record(AuditAction::TransferPost, id);
+    Ok(blog_post)
+}
@@ -0,0 +1,201 @@
+use crate::memory::{self, Memory};
+use crate::{auth, authors, config, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest page slug, in bytes
+const MAX_PAGE_SLUG_LEN: usize = 80;
+// Most pages a site can have
+const MAX_PAGES: u64 = 100;
+// First path segments of the other HTTP routes, which pages can't take
+const RESERVED_SLUGS: [&str; 5] = ["emoji", "feed", "health", "media", "post"];
+
+// A standalone page such as About, Contact or FAQ, served at `/{slug}`.
+// Pages are kept apart from posts and never show up in feeds or listings.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Page {
+    pub(crate) slug: String,
+    pub(crate) title: String,
+    // Markdown, rendered like post content
+    pub(crate) content: String,
+    pub(crate) author: Principal,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+}
+
+impl Storable for Page {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A page as `list_pages` lists it, without its content
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PageSummary {
+    slug: String,
+    title: String,
+    // the time of the last change, or of the creation if never changed
+    updated_at: u64,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct PagePayload {
+    title: String,
+    content: String,
+}
+
+thread_local! {
+    // slug -> page
+    static PAGES: RefCell<StableBTreeMap<String, Page, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PAGES_MEMORY)));
+}
+
+pub(crate) fn get(slug: &str) -> Option<Page> {
+    PAGES.with(|pages| pages.borrow().get(&slug.to_string()))
+}
+
+fn not_found(slug: &str) -> Error {
+    Error::NotFound {
+        msg: format!("Page {} not found", slug),
+    }
+}
+
+// Slugs are lowercase ASCII letters and digits separated by single dashes,
+// like the ones generated for posts
+fn validate_slug(slug: &str) -> Result<(), Error> {
+    let valid = !slug.is_empty()
+        && slug.len() <= MAX_PAGE_SLUG_LEN
+        && slug.split('-').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        });
+    if !valid {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Page slugs are up to {} lowercase letters and digits separated by single dashes",
+                MAX_PAGE_SLUG_LEN
+            ),
+        });
+    }
+    if RESERVED_SLUGS.contains(&slug) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Slug {} is taken by another route", slug),
+        });
+    }
+    Ok(())
+}
+
+fn validate(payload: &PagePayload) -> Result<(), Error> {
+    if payload.title.is_empty() || payload.content.is_empty() {
+        return Err(Error::ValidationErrors {
+            errors: "Pages need a title and content".to_string(),
+        });
+    }
+    config::check_post(Some(&payload.title), Some(&payload.content), None)
+}
+
+// Load a page the caller may change: admins may change any page, authors the
+// ones they created
+fn owned(slug: &str) -> Result<Page, Error> {
+    auth::require_signed_in()?;
+    let page = get(slug).ok_or_else(|| not_found(slug))?;
+    if !auth::is_admin() && !linking::same_person(&page.author, &caller()) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to change page {}.", slug),
+        });
+    }
+    Ok(page)
+}
+
+fn save(page: &Page) {
+    PAGES.with(|pages| pages.borrow_mut().insert(page.slug.clone(), page.clone()));
+}
+
+// Update function for admins and authors with at least one post to add a
+// page under a slug that is still free
+#[ic_cdk::update]
+fn create_page(slug: String, payload: PagePayload) -> Result<Page, Error> {
+    auth::require_signed_in()?;
+    let author = linking::primary(&caller());
+    if !auth::is_admin() && authors::newest_post_ids(&author, 1).is_empty() {
+        return Err(Error::NotAuthorized {
+            msg: "Only admins and authors can create pages.".to_string(),
+        });
+    }
+    validate_slug(&slug)?;
+    validate(&payload)?;
+    if get(&slug).is_some() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Page {} already exists", slug),
+        });
+    }
+    if PAGES.with(|pages| pages.borrow().len()) >= MAX_PAGES {
+        return Err(Error::ValidationErrors {
+            errors: format!("A site can have at most {} pages", MAX_PAGES),
+        });
+    }
+    let page = Page {
+        slug,
+        title: payload.title,
+        content: payload.content,
+        author,
+        created_at: time(),
+        updated_at: None,
+    };
+    save(&page);
+    Ok(page)
+}
+
+// Update function to change the title and content of a page; its slug stays
+// so links keep working
+#[ic_cdk::update]
+fn update_page(slug: String, payload: PagePayload) -> Result<Page, Error> {
+    let mut page = owned(&slug)?;
+    validate(&payload)?;
+    page.title = payload.title;
+    page.content = payload.content;
+    page.updated_at = Some(time());
+    save(&page);
+    Ok(page)
+}
+
+#[ic_cdk::update]
+fn delete_page(slug: String) -> Result<Page, Error> {
+    let page = owned(&slug)?;
+    PAGES.with(|pages| pages.borrow_mut().remove(&slug));
+    Ok(page)
+}
+
+#[ic_cdk::query]
+fn get_page(slug: String) -> Result<Page, Error> {
+    config::require_reader()?;
+    get(&slug).ok_or_else(|| not_found(&slug))
+}
+
+// Query function to list every page, ordered by slug
+#[ic_cdk::query]
+fn list_pages() -> Result<Vec<PageSummary>, Error> {
+    config::require_reader()?;
+    Ok(PAGES.with(|pages| {
+        pages
+            .borrow()
+            .iter()
+            .map(|(slug, page)| PageSummary {
+                slug,
+                title: page.title,
+                updated_at: page.updated_at.unwrap_or(page.created_at),
+            })
+            .collect()
+    }))
+}
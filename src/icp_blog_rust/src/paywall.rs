@@ -0,0 +1,421 @@
+use crate::audit::{self, AuditAction};
+use crate::keys::PrincipalIdKey;
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::visibility::Reader;
+use crate::{
+    _check_if_owner, _get_blog_post, _get_visible_blog_post, auth, linking, rate_limits, referrals,
+    tips, BlogPost, Error, PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap};
+
+// How long a purchase holds its reservation while the ledger is called, in
+// nanoseconds. A purchase that trapped after the call gives up the
+// reservation once it is this old.
+const RESERVATION_TTL: u64 = 10 * 60 * 1_000_000_000;
+
+// Access to a paywalled post, bought by the reader or gifted to them
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Entitlement {
+    post_id: u64,
+    // what was paid, in the tip ledger's base units
+    price: u64,
+    granted_at: u64,
+    // who paid, when it was a gift
+    gifted_by: Option<Principal>,
+}
+
+impl Storable for Entitlement {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A period during which a paywalled post is free to read. Times are in
+// nanoseconds since the epoch.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PromoWindow {
+    start: u64,
+    end: u64,
+}
+
+impl Storable for PromoWindow {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the entitlements map: a reader and a post id, so every reader's
+// entitlements are one contiguous range
+type EntitlementKey = PrincipalIdKey;
+
+// Entitlements are kept under the primary principal of the reader, so every
+// principal linked to it can read
+fn entitlement_key(reader: &Principal, post_id: u64) -> EntitlementKey {
+    EntitlementKey::new(&linking::primary(reader), post_id)
+}
+
+thread_local! {
+    // Post -> what reading it costs; posts without a price are free
+    static PRICES: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POST_PRICES_MEMORY)));
+
+    static ENTITLEMENTS: RefCell<StableBTreeMap<EntitlementKey, Entitlement, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::ENTITLEMENTS_MEMORY)));
+
+    // Post -> when it is free to read for a while
+    static PROMO_WINDOWS: RefCell<StableBTreeMap<u64, PromoWindow, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PROMO_WINDOWS_MEMORY)));
+
+    // Purchases waiting on the ledger, by reader and post, with when they
+    // started, so the same access isn't paid for twice at once. Only lives
+    // across the calls of one purchase, so it is kept on the heap.
+    static RESERVATIONS: RefCell<BTreeMap<EntitlementKey, u64>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+pub(crate) fn price(post_id: u64) -> Option<u64> {
+    PRICES.with(|prices| prices.borrow().get(&post_id))
+}
+
+fn is_entitled(reader: &Principal, post_id: u64) -> bool {
+    ENTITLEMENTS.with(|entitlements| {
+        entitlements
+            .borrow()
+            .contains_key(&entitlement_key(reader, post_id))
+    })
+}
+
+fn in_promo(post_id: u64, now: u64) -> bool {
+    PROMO_WINDOWS
+        .with(|windows| windows.borrow().get(&post_id))
+        .is_some_and(|window| window.start <= now && now < window.end)
+}
+
+// Whether the caller may read a post as far as the paywall goes: it is free,
+// for good or during a promotion, theirs, or they bought or were gifted
+// access. Moderators read everything.
+pub(crate) fn can_read(blog_post: &BlogPost) -> bool {
+    price(blog_post.id).is_none() || can_read_as(blog_post, &Reader::current())
+}
+
+// `can_read` for a reader other than the caller, or one taken once for a
+// whole listing
+pub(crate) fn can_read_as(blog_post: &BlogPost, reader: &Reader) -> bool {
+    price(blog_post.id).is_none()
+        || in_promo(blog_post.id, reader.now)
+        || Principal::from_text(&blog_post.author)
+            .is_ok_and(|author| linking::same_person(&author, &reader.principal))
+        || is_entitled(&reader.principal, blog_post.id)
+        || reader.moderator
+}
+
+pub(crate) fn set_price(post_id: u64, price: u64) {
+    PRICES.with(|prices| prices.borrow_mut().insert(post_id, price));
+}
+
+// Drop the price of a deleted post, or one made free, and its promotion.
+// Entitlements to it are kept, as a record of what was paid.
+pub(crate) fn remove_post_price(post_id: u64) {
+    PRICES.with(|prices| prices.borrow_mut().remove(&post_id));
+    PROMO_WINDOWS.with(|windows| windows.borrow_mut().remove(&post_id));
+}
+
+// Load a post the caller is the author of
+fn my_post(post_id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    match _get_blog_post(&post_id) {
+        Some(blog_post) if _check_if_owner(&blog_post) => Ok(blog_post),
+        Some(_) => Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to update post with id={}.", post_id),
+        }),
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        }),
+    }
+}
+
+// Hold access to a post for `reader` while it is paid for, failing if
+// another purchase of it is under way
+fn reserve(reader: &Principal, post_id: u64) -> Result<(), Error> {
+    let now = time();
+    RESERVATIONS.with(|reservations| {
+        let mut reservations = reservations.borrow_mut();
+        reservations.retain(|_, started_at| now.saturating_sub(*started_at) < RESERVATION_TTL);
+        match reservations.insert(entitlement_key(reader, post_id), now) {
+            None => Ok(()),
+            Some(started_at) => {
+                reservations.insert(entitlement_key(reader, post_id), started_at);
+                Err(Error::ValidationErrors {
+                    errors: format!(
+                        "Access to post with id={} is already being bought for {}",
+                        post_id, reader
+                    ),
+                })
+            }
+        }
+    })
+}
+
+fn release(reader: &Principal, post_id: u64) {
+    RESERVATIONS.with(|reservations| {
+        reservations
+            .borrow_mut()
+            .remove(&entitlement_key(reader, post_id))
+    });
+}
+
+// Pay the price of a published paywalled post from the caller's tip deposit
+// to its authors, as split for tips, and let `reader` read it. The purchase
+// is credited to the owner of the `referral` code, if any. Nothing is paid
+// unless the deposit covers the price and the fee on every share, and access
+// is only granted once the whole price was paid.
+async fn purchase(post_id: u64, reader: Principal, referral: Option<String>) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let Some(ledger) = tips::ledger() else {
+        return Err(Error::NotFound {
+            msg: "Payments are not enabled".to_string(),
+        });
+    };
+    let blog_post = match _get_visible_blog_post(&post_id) {
+        Some(blog_post) if blog_post.status == PostStatus::Published => blog_post,
+        _ => {
+            return Err(Error::NotFound {
+                msg: format!("Blog post with ID {} not found", post_id),
+            })
+        }
+    };
+    let Some(price) = price(post_id) else {
+        return Err(Error::ValidationErrors {
+            errors: format!("Blog post with ID {} is free to read", post_id),
+        });
+    };
+    let Ok(author) = Principal::from_text(&blog_post.author) else {
+        return Err(Error::NotFound {
+            msg: format!("Author of blog post with ID {} not found", post_id),
+        });
+    };
+    if reader == Principal::anonymous()
+        || linking::same_person(&author, &reader)
+        || is_entitled(&reader, post_id)
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!("{} can already read post with id={}", reader, post_id),
+        });
+    }
+    let payer = caller();
+    let shares = tips::shares(post_id, author, price);
+    if shares.iter().any(|(recipient, _)| *recipient == payer) {
+        return Err(Error::ValidationErrors {
+            errors: "Authors can't pay for their own posts".to_string(),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+    reserve(&reader, post_id)?;
+    if let Err(error) = tips::check_funds(ledger, &payer, &shares).await {
+        release(&reader, post_id);
+        return Err(error);
+    }
+
+    let mut paid = 0;
+    let result = tips::pay(ledger, &payer, post_id, shares, "payment", |_, share| {
+        paid += share
+    })
+    .await;
+    release(&reader, post_id);
+    if paid != price {
+        return result;
+    }
+    let gifted_by = (payer != reader).then_some(payer);
+    ENTITLEMENTS.with(|entitlements| {
+        entitlements.borrow_mut().insert(
+            entitlement_key(&reader, post_id),
+            Entitlement {
+                post_id,
+                price: paid,
+                granted_at: time(),
+                gifted_by,
+            },
+        )
+    });
+    referrals::record_purchase(referral.as_deref(), paid);
+    if gifted_by.is_some() {
+        notifications::notify(&reader, NotificationKind::GiftedAccess { post_id });
+    }
+    Ok(())
+}
+
+// Query function to get what reading a post costs, `null` when it is free
+#[ic_cdk::query]
+fn get_post_price(post_id: u64) -> Result<Option<u64>, Error> {
+    if _get_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    Ok(price(post_id))
+}
+
+// Update function for authors to put a post behind a paywall at `price`, in
+// the tip ledger's base units, or make it free again with `null`. Paywalled
+// posts are only shown to readers that bought or were gifted access.
+#[ic_cdk::update]
+fn set_post_price(post_id: u64, price: Option<u64>) -> Result<(), Error> {
+    my_post(post_id)?;
+    match price {
+        Some(0) => Err(Error::ValidationErrors {
+            errors: "Prices must be positive; use null to make a post free".to_string(),
+        }),
+        Some(price) => {
+            set_price(post_id, price);
+            Ok(())
+        }
+        None => {
+            remove_post_price(post_id);
+            Ok(())
+        }
+    }
+}
+
+// Query function to get when a paywalled post is free to read, if it is or
+// will be
+#[ic_cdk::query]
+fn get_promo_window(post_id: u64) -> Result<Option<PromoWindow>, Error> {
+    if _get_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    Ok(PROMO_WINDOWS.with(|windows| windows.borrow().get(&post_id)))
+}
+
+// Update function for authors to make a paywalled post free to read from
+// `from` until `to`, replacing any promotion set before. The change shows up
+// in the event log, for indexers and frontends to announce.
+#[ic_cdk::update]
+fn set_promo_window(post_id: u64, from: u64, to: u64) -> Result<PromoWindow, Error> {
+    my_post(post_id)?;
+    if price(post_id).is_none() {
+        return Err(Error::ValidationErrors {
+            errors: format!("Blog post with ID {} is free to read", post_id),
+        });
+    }
+    if from >= to || to <= time() {
+        return Err(Error::ValidationErrors {
+            errors: "Promotions must end after they start, in the future".to_string(),
+        });
+    }
+    let window = PromoWindow {
+        start: from,
+        end: to,
+    };
+    PROMO_WINDOWS.with(|windows| windows.borrow_mut().insert(post_id, window.clone()));
+    audit::record(AuditAction::SetPromoWindow, post_id);
+    Ok(window)
+}
+
+// Update function for authors to end the promotion of a paywalled post
+#[ic_cdk::update]
+fn remove_promo_window(post_id: u64) -> Result<(), Error> {
+    my_post(post_id)?;
+    match PROMO_WINDOWS.with(|windows| windows.borrow_mut().remove(&post_id)) {
+        Some(_) => {
+            audit::record(AuditAction::SetPromoWindow, post_id);
+            Ok(())
+        }
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with ID {} has no promotion", post_id),
+        }),
+    }
+}
+
+// Query function for readers to list the posts they bought or were gifted
+// access to, most recent first. Access never expires.
+#[ic_cdk::query]
+fn get_my_entitlements() -> Result<Vec<Entitlement>, Error> {
+    auth::require_signed_in()?;
+    let reader = caller();
+    let mut mine: Vec<Entitlement> = ENTITLEMENTS.with(|entitlements| {
+        entitlements
+            .borrow()
+            .range(EntitlementKey::all_of(&linking::primary(&reader)))
+            .map(|(_, entitlement)| entitlement)
+            .collect()
+    });
+    mine.sort_by_key(|entitlement| std::cmp::Reverse(entitlement.granted_at));
+    Ok(mine)
+}
+
+// Update function for readers to buy access to a paywalled post, paying its
+// price from their tip deposit. `referral` is the `?ref=` code of the link
+// they came by.
+#[ic_cdk::update]
+async fn buy_access(post_id: u64, referral: Option<String>) -> Result<(), Error> {
+    purchase(post_id, caller(), referral).await
+}
+
+// Update function to buy access to a paywalled post for someone else, who is
+// notified of the gift
+#[ic_cdk::update]
+async fn gift_access(
+    post_id: u64,
+    recipient: Principal,
+    referral: Option<String>,
+) -> Result<(), Error> {
+    purchase(post_id, recipient, referral).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{insert_post, principal, reader};
+
+    #[test]
+    fn paywalled_posts_are_read_by_their_author_buyers_and_moderators() {
+        let post = insert_post(1, principal(1), 1, &[]);
+        assert!(can_read_as(&post, &reader(principal(2), 0)));
+        set_price(1, 100);
+        assert!(!can_read_as(&post, &reader(principal(2), 0)));
+        assert!(can_read_as(&post, &reader(principal(1), 0)));
+        let moderator = Reader {
+            moderator: true,
+            ..reader(principal(3), 0)
+        };
+        assert!(can_read_as(&post, &moderator));
+        PROMO_WINDOWS.with(|windows| {
+            windows
+                .borrow_mut()
+                .insert(1, PromoWindow { start: 10, end: 20 })
+        });
+        assert!(can_read_as(&post, &reader(principal(2), 15)));
+        assert!(!can_read_as(&post, &reader(principal(2), 20)));
+        ENTITLEMENTS.with(|entitlements| {
+            entitlements.borrow_mut().insert(
+                entitlement_key(&principal(2), 1),
+                Entitlement {
+                    post_id: 1,
+                    price: 100,
+                    granted_at: 0,
+                    gifted_by: None,
+                },
+            )
+        });
+        assert!(can_read_as(&post, &reader(principal(2), 0)));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{_check_if_owner, _get_blog_post, auth, do_insert, embeds, rate_limits, render, Error};
+// named apart from the responses the canister serves over HTTP
+use ic_cdk::api::management_canister::http_request::HttpResponse as OutcallResponse;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, TransformArgs,
+    TransformContext,
+};
+use ic_cdk::api::time;
+
+// Most links of a post previews are fetched for
+const MAX_PREVIEWS: usize = 5;
+// Bytes of a page read, enough for its `<head>`. Servers that ignore the
+// range and send more make the fetch fail; the link then gets no preview.
+const MAX_PAGE_BYTES: u64 = 512 * 1024;
+// Nodes of the subnet the canister runs on, which outcall fees scale with
+const SUBNET_NODES: u128 = 13;
+// Longest title, description and image URL kept, in characters
+const MAX_TITLE_LEN: usize = 300;
+const MAX_DESCRIPTION_LEN: usize = 1_000;
+const MAX_IMAGE_URL_LEN: usize = 2_000;
+
+// Card for a page a post links to, for front-ends to show the link with
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct LinkPreview {
+    url: String,
+    title: Option<String>,
+    description: Option<String>,
+    // absolute HTTPS URL of the page's preview image
+    image: Option<String>,
+    fetched_at: u64,
+}
+
+impl LinkPreview {
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+// What the transform keeps of a page, so every replica agrees on the
+// response whatever else differs between the copies they fetched
+#[derive(Default, Serialize, Deserialize)]
+struct PageMeta {
+    title: Option<String>,
+    description: Option<String>,
+    image: Option<String>,
+}
+
+// Fee of an outcall, per the formula for HTTPS outcalls
+fn outcall_cycles(request_bytes: u64, max_response_bytes: u64) -> u128 {
+    (3_000_000 + 60_000 * SUBNET_NODES) * SUBNET_NODES
+        + 400 * SUBNET_NODES * request_bytes as u128
+        + 800 * SUBNET_NODES * max_response_bytes as u128
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+fn clean(text: &str, max_len: usize) -> Option<String> {
+    let text = unescape(
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .as_str(),
+    );
+    (!text.is_empty()).then(|| text.chars().take(max_len).collect())
+}
+
+// Read the title, description and image of a page from its Open Graph and
+// description `<meta>` tags, falling back to its `<title>`
+fn page_meta(html: &str) -> PageMeta {
+    let head = match html.to_ascii_lowercase().find("</head") {
+        Some(end) => &html[..end],
+        None => html,
+    };
+    let mut meta = PageMeta::default();
+    let mut fallback = PageMeta::default();
+    let mut rest = head;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..=end];
+        rest = &rest[end + 1..];
+        let lower = tag.to_ascii_lowercase();
+        if lower.starts_with("<title") && fallback.title.is_none() {
+            let text = rest.find('<').map_or(rest, |end| &rest[..end]);
+            fallback.title = clean(text, MAX_TITLE_LEN);
+            continue;
+        }
+        if !lower.starts_with("<meta") {
+            continue;
+        }
+        let Some(content) = embeds::attribute(tag, "content") else {
+            continue;
+        };
+        let key = embeds::attribute(tag, "property")
+            .or_else(|| embeds::attribute(tag, "name"))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let (field, max_len) = match key.as_str() {
+            "og:title" => (&mut meta.title, MAX_TITLE_LEN),
+            "twitter:title" => (&mut fallback.title, MAX_TITLE_LEN),
+            "og:description" => (&mut meta.description, MAX_DESCRIPTION_LEN),
+            "description" | "twitter:description" => {
+                (&mut fallback.description, MAX_DESCRIPTION_LEN)
+            }
+            "og:image" => (&mut meta.image, MAX_IMAGE_URL_LEN),
+            "twitter:image" => (&mut fallback.image, MAX_IMAGE_URL_LEN),
+            _ => continue,
+        };
+        if field.is_none() {
+            *field = clean(content, max_len);
+        }
+    }
+    PageMeta {
+        title: meta.title.or(fallback.title),
+        description: meta.description.or(fallback.description),
+        image: meta
+            .image
+            .or(fallback.image)
+            .filter(|image| image.starts_with("https://")),
+    }
+}
+
+// Transform of the outcalls, run by every replica on the page it fetched:
+// only the metadata is kept, as JSON, and the headers are dropped
+#[ic_cdk::query]
+fn transform_link_preview(args: TransformArgs) -> OutcallResponse {
+    let ok = args.response.status == 200u16 || args.response.status == 206u16;
+    let meta = if ok {
+        page_meta(&String::from_utf8_lossy(&args.response.body))
+    } else {
+        PageMeta::default()
+    };
+    OutcallResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: serde_json::to_vec(&meta).expect("Cannot encode the page metadata"),
+    }
+}
+
+// Fetch the preview of one link; `None` if the page can't be fetched or has
+// no title
+async fn fetch(url: &str) -> Option<LinkPreview> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(MAX_PAGE_BYTES),
+        method: HttpMethod::GET,
+        headers: vec![
+            HttpHeader {
+                name: "Range".to_string(),
+                value: format!("bytes=0-{}", MAX_PAGE_BYTES / 2),
+            },
+            HttpHeader {
+                name: "Accept".to_string(),
+                value: "text/html".to_string(),
+            },
+        ],
+        body: None,
+        transform: Some(TransformContext::from_name(
+            "transform_link_preview".to_string(),
+            Vec::new(),
+        )),
+    };
+    let cycles = outcall_cycles(url.len() as u64 + 100, MAX_PAGE_BYTES);
+    let (response,) = match http_request(request, cycles).await {
+        Ok(response) => response,
+        Err((code, msg)) => {
+            ic_cdk::println!("Cannot fetch a preview of {}: {:?} {}", url, code, msg);
+            return None;
+        }
+    };
+    let meta: PageMeta = serde_json::from_slice(&response.body).ok()?;
+    meta.title.as_ref()?;
+    Some(LinkPreview {
+        url: url.to_string(),
+        title: meta.title,
+        description: meta.description,
+        image: meta.image,
+        fetched_at: time(),
+    })
+}
+
+// Update function for authors to fetch previews of the first pages their post
+// links to over HTTPS, replacing the ones fetched before. Links whose page
+// can't be fetched get no preview. The fetches cost the canister cycles, so
+// they are rate limited like interactions.
+#[ic_cdk::update]
+async fn fetch_link_previews(post_id: u64) -> Result<Vec<LinkPreview>, Error> {
+    auth::require_signed_in()?;
+    let blog_post = match _get_blog_post(&post_id) {
+        Some(blog_post) if _check_if_owner(&blog_post) => blog_post,
+        _ => {
+            return Err(Error::NotAuthorized {
+                msg: format!(
+                    "Unauthorized to fetch previews for post with id={}.",
+                    post_id
+                ),
+            })
+        }
+    };
+    if blog_post.encrypted {
+        return Err(Error::ValidationErrors {
+            errors: "Encrypted posts can't have link previews".to_string(),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+    let mut previews = Vec::new();
+    for url in render::links(&blog_post.content)
+        .into_iter()
+        .take(MAX_PREVIEWS)
+    {
+        previews.extend(fetch(&url).await);
+    }
+    // the post may have changed or gone while the pages were fetched
+    let Some(mut blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    let links = render::links(&blog_post.content);
+    previews.retain(|preview| links.contains(&preview.url));
+    blog_post.link_previews = previews.clone();
+    do_insert(&blog_post);
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_meta_prefers_open_graph() {
+        let meta = page_meta(concat!(
+            "<html><head><title>Page</title>",
+            r#"<meta name="description" content="Plain">"#,
+            r#"<meta property="og:title" content="OG &amp; title">"#,
+            r#"<meta property="og:description" content="  Open   Graph ">"#,
+            r#"<meta property="og:image" content="https://example.com/a.png">"#,
+            "</head><body></body></html>"
+        ));
+        assert_eq!(meta.title.as_deref(), Some("OG & title"));
+        assert_eq!(meta.description.as_deref(), Some("Open Graph"));
+        assert_eq!(meta.image.as_deref(), Some("https://example.com/a.png"));
+    }
+
+    #[test]
+    fn page_meta_falls_back_to_the_title_and_description() {
+        let meta = page_meta(concat!(
+            "<head><title>\n  A page\n</title>",
+            r#"<meta name="description" content="About it"></head>"#
+        ));
+        assert_eq!(meta.title.as_deref(), Some("A page"));
+        assert_eq!(meta.description.as_deref(), Some("About it"));
+        assert_eq!(meta.image, None);
+    }
+
+    #[test]
+    fn page_meta_drops_images_not_served_over_https() {
+        let meta = page_meta(r#"<meta property="og:image" content="http://example.com/a.png">"#);
+        assert_eq!(meta.image, None);
+    }
+
+    #[test]
+    fn page_meta_ignores_the_body() {
+        let meta = page_meta(r#"<head></head><body><meta property="og:title" content="x">"#);
+        assert_eq!(meta.title, None);
+    }
+}
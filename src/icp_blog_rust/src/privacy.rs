@@ -0,0 +1,83 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// What of a principal's activity other users may see. Likes and bookmarks
+// stay private unless shared; who someone follows is public unless hidden.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PrivacySettings {
+    pub(crate) likes_public: bool,
+    pub(crate) bookmarks_public: bool,
+    pub(crate) follows_public: bool,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings {
+            likes_public: false,
+            bookmarks_public: false,
+            follows_public: true,
+        }
+    }
+}
+
+impl Storable for PrivacySettings {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Only principals who changed the defaults have an entry
+    static PRIVACY: RefCell<StableBTreeMap<Blob<29>, PrivacySettings, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PRIVACY_MEMORY)));
+}
+
+pub(crate) fn settings(principal: &Principal) -> PrivacySettings {
+    PRIVACY
+        .with(|privacy| privacy.borrow().get(&principal_key(principal)))
+        .unwrap_or_default()
+}
+
+pub(crate) fn save(principal: &Principal, settings: PrivacySettings) {
+    PRIVACY.with(|privacy| {
+        privacy
+            .borrow_mut()
+            .insert(principal_key(principal), settings)
+    });
+}
+
+// Go back to the default settings
+pub(crate) fn remove(principal: &Principal) {
+    PRIVACY.with(|privacy| privacy.borrow_mut().remove(&principal_key(principal)));
+}
+
+// Whether the caller may see something of `owner` that `public` shares
+pub(crate) fn can_see(owner: &Principal, public: bool) -> bool {
+    public || linking::same_person(owner, &caller())
+}
+
+// Query function to get the caller's privacy settings
+#[ic_cdk::query]
+fn get_my_privacy() -> PrivacySettings {
+    settings(&caller())
+}
+
+// Update function to change the caller's privacy settings
+#[ic_cdk::update]
+fn set_my_privacy(settings: PrivacySettings) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    save(&caller(), settings);
+    Ok(())
+}
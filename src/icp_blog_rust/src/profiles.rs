@@ -0,0 +1,255 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::Validate;
+
+// Most suggestions `suggest_users` returns
+const MAX_SUGGESTIONS: u64 = 20;
+// Longest indexed name, in bytes; lowercasing may lengthen a display name
+const MAX_INDEXED_NAME: usize = 200;
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct UserProfile {
+    display_name: String,
+    bio: String,
+    avatar_url: Option<String>,
+}
+
+impl Storable for UserProfile {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize, Validate)]
+pub(crate) struct UserProfilePayload {
+    #[validate(length(min = 1, max = 50))]
+    display_name: String,
+    #[validate(length(max = 1000))]
+    bio: String,
+    #[validate(url, length(max = 500))]
+    avatar_url: Option<String>,
+}
+
+// A user offered for an @-mention
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct UserSuggestion {
+    principal: Principal,
+    display_name: String,
+}
+
+// Entry of the name index: a lowercased display name, from the start of one
+// of its words, followed by the principal whose profile it is
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct NameKey(Vec<u8>);
+
+impl NameKey {
+    fn new(name: &str, principal: &Blob<29>) -> Self {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.push(0);
+        bytes.extend_from_slice(principal.as_slice());
+        NameKey(bytes)
+    }
+
+    fn name(&self) -> &[u8] {
+        let end = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        &self.0[..end]
+    }
+
+    fn principal(&self) -> Blob<29> {
+        let start = self.name().len() + 1;
+        Blob::try_from(&self.0[start..]).unwrap()
+    }
+}
+
+impl Storable for NameKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        NameKey(bytes.into_owned())
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_INDEXED_NAME as u32 + 1 + 29,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static PROFILES: RefCell<StableBTreeMap<Blob<29>, UserProfile, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PROFILES_MEMORY)));
+
+    // Prefix index of display names, one entry per word of each name
+    static NAME_INDEX: RefCell<StableBTreeMap<NameKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PROFILE_NAMES_MEMORY)));
+}
+
+// Linked principals share the profile of their primary principal
+fn profile_key(principal: &Principal) -> Blob<29> {
+    principal_key(&linking::primary(principal))
+}
+
+// Lowercased form names are matched in, without the NUL byte that ends the
+// name in index keys
+fn normalize(name: &str) -> String {
+    let mut normalized: String = name.to_lowercase().replace('\0', "");
+    let mut end = normalized.len().min(MAX_INDEXED_NAME);
+    while !normalized.is_char_boundary(end) {
+        end -= 1;
+    }
+    normalized.truncate(end);
+    normalized
+}
+
+// Index keys of a display name, so a prefix of any of its words finds it
+fn name_keys(display_name: &str, principal: &Blob<29>) -> Vec<NameKey> {
+    let name = normalize(display_name);
+    name.char_indices()
+        .filter(|&(i, c)| {
+            !c.is_whitespace() && (i == 0 || name[..i].ends_with(char::is_whitespace))
+        })
+        .map(|(i, _)| NameKey::new(&name[i..], principal))
+        .collect()
+}
+
+fn index_name(display_name: &str, principal: &Blob<29>) {
+    NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in name_keys(display_name, principal) {
+            index.insert(key, ());
+        }
+    });
+}
+
+fn unindex_name(display_name: &str, principal: &Blob<29>) {
+    NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in name_keys(display_name, principal) {
+            index.remove(&key);
+        }
+    });
+}
+
+// Index the display names of the profiles set up before names were indexed
+pub(crate) fn index_existing_names() {
+    PROFILES.with(|profiles| {
+        for (principal, profile) in profiles.borrow().iter() {
+            index_name(&profile.display_name, &principal);
+        }
+    });
+}
+
+// Display name of a post or comment author, if they have set up a profile
+pub(crate) fn display_name(author: &Principal) -> Option<String> {
+    PROFILES
+        .with(|profiles| profiles.borrow().get(&profile_key(author)))
+        .map(|profile| profile.display_name)
+}
+
+// Delete the profile of `principal`. Its entries in the name index are left
+// for `unindex_batch`; suggestions skip names without a profile meanwhile.
+pub(crate) fn remove(principal: &Principal) -> bool {
+    PROFILES
+        .with(|profiles| profiles.borrow_mut().remove(&profile_key(principal)))
+        .is_some()
+}
+
+// Drop up to `limit` entries of the name index that point at the profile of
+// `principal`, so it is no longer offered for @-mentions, returning how many
+// were dropped
+pub(crate) fn unindex_batch(principal: &Principal, limit: usize) -> usize {
+    let profile = profile_key(principal);
+    NAME_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let keys: Vec<NameKey> = index
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.principal() == profile)
+            .take(limit)
+            .collect();
+        for key in &keys {
+            index.remove(key);
+        }
+        keys.len()
+    })
+}
+
+// Update function to create or replace the caller's profile
+#[ic_cdk::update]
+fn set_my_profile(payload: UserProfilePayload) -> Result<UserProfile, Error> {
+    auth::require_signed_in()?;
+    if let Err(errors) = payload.validate() {
+        return Err(Error::ValidationErrors {
+            errors: errors.to_string(),
+        });
+    }
+    let profile = UserProfile {
+        display_name: payload.display_name.trim().to_string(),
+        bio: payload.bio,
+        avatar_url: payload.avatar_url,
+    };
+    let key = profile_key(&caller());
+    let previous = PROFILES.with(|profiles| profiles.borrow_mut().insert(key, profile.clone()));
+    if let Some(previous) = previous {
+        unindex_name(&previous.display_name, &key);
+    }
+    index_name(&profile.display_name, &key);
+    Ok(profile)
+}
+
+// Query function to get the profile of a principal
+#[ic_cdk::query]
+fn get_profile(principal: Principal) -> Result<UserProfile, Error> {
+    PROFILES
+        .with(|profiles| profiles.borrow().get(&profile_key(&principal)))
+        .ok_or(Error::NotFound {
+            msg: format!("No profile found for {}", principal),
+        })
+}
+
+// Query function for composers to autocomplete @-mentions: users with a word
+// of their display name starting with `prefix`, ignoring case, in name order
+#[ic_cdk::query]
+fn suggest_users(prefix: String, limit: u64) -> Vec<UserSuggestion> {
+    let prefix = normalize(prefix.trim());
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+    let limit = limit.min(MAX_SUGGESTIONS) as usize;
+    let mut principals: Vec<Blob<29>> = Vec::new();
+    NAME_INDEX.with(|index| {
+        let index = index.borrow();
+        for (key, _) in index.range(NameKey(prefix.as_bytes().to_vec())..) {
+            if principals.len() >= limit || !key.name().starts_with(prefix.as_bytes()) {
+                break;
+            }
+            // a user shows up once even when several words of their name match
+            if !principals.contains(&key.principal()) {
+                principals.push(key.principal());
+            }
+        }
+    });
+    principals
+        .into_iter()
+        .filter_map(|principal| {
+            let profile = PROFILES.with(|profiles| profiles.borrow().get(&principal))?;
+            Some(UserSuggestion {
+                principal: Principal::from_slice(principal.as_slice()),
+                display_name: profile.display_name,
+            })
+        })
+        .collect()
+}
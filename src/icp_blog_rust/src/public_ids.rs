@@ -0,0 +1,140 @@
+use crate::config::{self, IdExposure};
+use crate::memory::{self, Memory};
+use crate::{_get_visible_blog_post, BlogPost, Error, BLOG_POSTS};
+use ic_stable_structures::{Cell, StableBTreeMap};
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, time::Duration};
+
+// Length of a public ID: a u64 in base 62, zero padded
+const PUBLIC_ID_LEN: usize = 11;
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+thread_local! {
+    // post id -> public id
+    static PUBLIC_IDS: RefCell<StableBTreeMap<u64, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PUBLIC_IDS_MEMORY)));
+
+    // public id -> post id
+    static POST_IDS: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PUBLIC_ID_POSTS_MEMORY)));
+
+    // Random salt public IDs are drawn from, so they can't be computed from
+    // post IDs; empty until drawn
+    static SALT: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::PUBLIC_ID_SALT_MEMORY), Vec::new())
+            .expect("Cannot create the public ID salt cell")
+    );
+}
+
+// Draw the salt if there is none yet, then give every post a public ID.
+// Randomness takes an inter-canister call, so this runs from a timer after
+// install and upgrade, like the research salt.
+pub(crate) fn ensure_salt() {
+    if SALT.with(|salt| !salt.borrow().get().is_empty()) {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            match ic_cdk::api::management_canister::main::raw_rand().await {
+                Ok((bytes,)) => {
+                    SALT.with(|salt| {
+                        salt.borrow_mut()
+                            .set(bytes)
+                            .expect("Cannot write the public ID salt");
+                    });
+                    let ids: Vec<u64> = BLOG_POSTS
+                        .with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+                    for id in ids {
+                        assign(id);
+                    }
+                }
+                Err((code, msg)) => {
+                    ic_cdk::println!("Cannot draw the public ID salt: {:?} {}", code, msg)
+                }
+            }
+        })
+    });
+}
+
+fn encode(mut value: u64) -> String {
+    let mut id = vec![ALPHABET[0]; PUBLIC_ID_LEN];
+    for byte in id.iter_mut().rev() {
+        *byte = ALPHABET[(value % 62) as usize];
+        value /= 62;
+    }
+    String::from_utf8(id).unwrap()
+}
+
+// Give a post a public ID, unless it has one. Posts created before the salt
+// is drawn get theirs once it is.
+pub(crate) fn assign(post_id: u64) {
+    let salt = SALT.with(|salt| salt.borrow().get().clone());
+    if salt.is_empty() || public_id(post_id).is_some() {
+        return;
+    }
+    // a collision takes another draw
+    let public_id = (0u32..)
+        .map(|attempt| {
+            let mut hasher = Sha256::new();
+            hasher.update(&salt);
+            hasher.update(post_id.to_be_bytes());
+            hasher.update(attempt.to_be_bytes());
+            let digest = hasher.finalize();
+            encode(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+        })
+        .find(|public_id| lookup(public_id).is_none())
+        .unwrap();
+    PUBLIC_IDS.with(|ids| ids.borrow_mut().insert(post_id, public_id.clone()));
+    POST_IDS.with(|ids| ids.borrow_mut().insert(public_id, post_id));
+}
+
+// Free the public ID of a deleted post
+pub(crate) fn release(post_id: u64) {
+    if let Some(public_id) = PUBLIC_IDS.with(|ids| ids.borrow_mut().remove(&post_id)) {
+        POST_IDS.with(|ids| ids.borrow_mut().remove(&public_id));
+    }
+}
+
+pub(crate) fn public_id(post_id: u64) -> Option<String> {
+    PUBLIC_IDS.with(|ids| ids.borrow().get(&post_id))
+}
+
+pub(crate) fn lookup(public_id: &str) -> Option<u64> {
+    POST_IDS.with(|ids| ids.borrow().get(&public_id.to_string()))
+}
+
+fn is_opaque() -> bool {
+    config::get().id_exposure == Some(IdExposure::Opaque)
+}
+
+// Fill in the public ID of a post being read, when admins chose to expose
+// those
+pub(crate) fn expose(blog_post: &mut BlogPost) {
+    blog_post.public_id = if is_opaque() {
+        public_id(blog_post.id)
+    } else {
+        None
+    };
+}
+
+// How a post is named where its ID shows up outside the candid interface,
+// such as feed GUIDs and the suffix of a taken slug. Feed readers see the
+// GUIDs change when the policy does.
+pub(crate) fn label(post_id: u64) -> String {
+    match public_id(post_id).filter(|_| is_opaque()) {
+        Some(public_id) => public_id,
+        None => post_id.to_string(),
+    }
+}
+
+// Query function to get a blog post by the public ID it was given
+#[ic_cdk::query]
+fn get_blog_post_by_public_id(public_id: String) -> Result<BlogPost, Error> {
+    config::require_reader()?;
+    match lookup(&public_id).and_then(|id| _get_visible_blog_post(&id)) {
+        Some(blog_post) => Ok(blog_post),
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with public ID {} not found", public_id),
+        }),
+    }
+}
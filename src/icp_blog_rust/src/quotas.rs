@@ -0,0 +1,121 @@
+use crate::memory::{self, Memory};
+use crate::{auth, date, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, data_certificate, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use std::collections::BTreeMap;
+use std::{borrow::Cow, cell::RefCell};
+
+// Length of the windows calls are counted in
+const WINDOW_NANOS: u64 = 60 * date::NANOS_PER_SECOND;
+// Most principals tracked at once; windows that ended are dropped beyond it
+const MAX_TRACKED: usize = 10_000;
+// Most candidates an expensive query looks at for a caller over the quota
+pub(crate) const LIMITED_SCAN: usize = 200;
+
+// Expensive queries whose calls are counted, each separately
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Endpoint {
+    QueryBlogPosts,
+    RelatedPosts,
+}
+
+// Calls a principal may make to each expensive query per minute before it
+// gets limited results: at most `limited_results` posts, found among fewer
+// candidates. Only replicated calls, such as a query called as an update,
+// are counted and limited: a plain query call can't keep the count, so a
+// caller can always get full results that way.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct QueryQuota {
+    calls_per_minute: u32,
+    limited_results: u64,
+}
+
+// `None` while quotas are turned off, as they are by default
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredQuota(Option<QueryQuota>);
+
+impl Storable for StoredQuota {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static QUOTA: RefCell<Cell<StoredQuota, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::QUERY_QUOTA_MEMORY), StoredQuota::default())
+            .expect("Cannot create the query quota cell")
+    );
+
+    // (caller, endpoint) -> (start of the current window, calls in it), for
+    // replicated calls only. Kept on the heap, so it starts over after an
+    // upgrade.
+    static CALLS: RefCell<BTreeMap<(Principal, Endpoint), (u64, u32)>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+fn quota() -> Option<QueryQuota> {
+    QUOTA.with(|cell| cell.borrow().get().0.clone())
+}
+
+// Count a replicated call of the caller to an expensive query. Returns the
+// most results the call may return when the caller is over the quota, `None`
+// otherwise and for plain query calls, whose count would be thrown away.
+pub(crate) fn record(endpoint: Endpoint) -> Option<u64> {
+    // only non-replicated queries can see a data certificate
+    if data_certificate().is_some() {
+        return None;
+    }
+    let quota = quota()?;
+    let now = time();
+    CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        if calls.len() >= MAX_TRACKED {
+            calls.retain(|_, (start, _)| now.saturating_sub(*start) < WINDOW_NANOS);
+        }
+        let entry = calls.entry((caller(), endpoint)).or_insert((now, 0));
+        if now.saturating_sub(entry.0) >= WINDOW_NANOS {
+            *entry = (now, 0);
+        }
+        entry.1 = entry.1.saturating_add(1);
+        (entry.1 > quota.calls_per_minute).then_some(quota.limited_results)
+    })
+}
+
+// Query function for admins to see the quota on expensive queries
+#[ic_cdk::query]
+fn get_query_quota() -> Result<Option<QueryQuota>, Error> {
+    auth::require_admin()?;
+    Ok(quota())
+}
+
+// Update function for admins to limit the results of replicated calls to
+// expensive queries, such as `query_blog_posts` and `get_related_posts`, for
+// principals calling them too often, or to turn that off with `null`. Plain
+// query calls are never limited.
+#[ic_cdk::update]
+fn set_query_quota(quota: Option<QueryQuota>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if quota
+        .as_ref()
+        .is_some_and(|quota| quota.calls_per_minute == 0)
+    {
+        return Err(Error::ValidationErrors {
+            errors: "Quotas must allow at least one call per minute".to_string(),
+        });
+    }
+    QUOTA.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredQuota(quota))
+            .expect("Cannot write the query quota")
+    });
+    CALLS.with(|calls| calls.borrow_mut().clear());
+    Ok(())
+}
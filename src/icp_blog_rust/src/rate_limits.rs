@@ -0,0 +1,231 @@
+use crate::abuse::{self, SignalKind};
+use crate::keys;
+use crate::memory::{self, Memory};
+use crate::{auth, config, date, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Length of the sliding window limits apply to
+const WINDOW_NANOS: u64 = 3600 * date::NANOS_PER_SECOND;
+// Contact messages a principal may send per hour until admins set a limit
+const DEFAULT_CONTACT_MESSAGES_PER_HOUR: u32 = 5;
+
+// Kinds of calls that are limited separately
+#[derive(Clone, Copy)]
+pub(crate) enum Action {
+    CreatePost,
+    // likes, reactions, follows and reports
+    Interact,
+    Comment,
+    Contact,
+}
+
+impl Action {
+    // Stable one-byte code used in storage keys; never renumber
+    fn code(self) -> u8 {
+        match self {
+            Action::CreatePost => 0,
+            Action::Interact => 1,
+            Action::Comment => 2,
+            Action::Contact => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::CreatePost => "posts",
+            Action::Interact => "interactions",
+            Action::Comment => "comments",
+            Action::Contact => "contact messages",
+        }
+    }
+}
+
+// Most actions of each kind a principal may take per hour; 0 turns a limit off
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct RateLimits {
+    posts_per_hour: u32,
+    interactions_per_hour: u32,
+    comments_per_hour: u32,
+    // `DEFAULT_CONTACT_MESSAGES_PER_HOUR` when unset
+    contact_messages_per_hour: Option<u32>,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            posts_per_hour: 10,
+            interactions_per_hour: 300,
+            comments_per_hour: 30,
+            contact_messages_per_hour: None,
+        }
+    }
+}
+
+impl Storable for RateLimits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl RateLimits {
+    fn limit(&self, action: Action) -> u32 {
+        match action {
+            Action::CreatePost => self.posts_per_hour,
+            Action::Interact => self.interactions_per_hour,
+            Action::Comment => self.comments_per_hour,
+            Action::Contact => self
+                .contact_messages_per_hour
+                .unwrap_or(DEFAULT_CONTACT_MESSAGES_PER_HOUR),
+        }
+    }
+}
+
+// Key of the recent actions map: the principal, prefixed with its length, the
+// action code and the time of the action, so the recent actions of one kind
+// by one principal are a contiguous range in time order
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ActionKey {
+    principal: Vec<u8>,
+    action: u8,
+    at: u64,
+}
+
+impl Storable for ActionKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        keys::put_principal(&mut bytes, &self.principal);
+        bytes.push(self.action);
+        bytes.extend_from_slice(&self.at.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (principal, rest) = keys::take_principal(&bytes);
+        ActionKey {
+            principal,
+            action: rest[0],
+            at: u64::from_be_bytes(rest[1..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: keys::PREFIXED_PRINCIPAL_SIZE + 1 + 8,
+        is_fixed_size: false,
+    };
+}
+
+impl ActionKey {
+    fn new(principal: &Principal, action: Action, at: u64) -> Self {
+        ActionKey {
+            principal: principal.as_slice().to_vec(),
+            action: action.code(),
+            at,
+        }
+    }
+}
+
+thread_local! {
+    // Limits set before they moved into the canister configuration; only
+    // read to seed it
+    static RATE_LIMITS: RefCell<Cell<RateLimits, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::RATE_LIMITS_MEMORY), RateLimits::default())
+            .expect("Cannot create the rate limits cell")
+    );
+
+    // Actions taken within the last window
+    static RECENT_ACTIONS: RefCell<StableBTreeMap<ActionKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::RECENT_ACTIONS_MEMORY)));
+}
+
+pub(crate) fn legacy_limits() -> RateLimits {
+    RATE_LIMITS.with(|limits| limits.borrow().get().clone())
+}
+
+// Record an action by the caller, or refuse it if the caller already reached
+// the limit for this kind of action within the last hour or was flagged for
+// abuse. Admins are exempt.
+pub(crate) fn check(action: Action) -> Result<(), Error> {
+    if auth::is_admin() {
+        return Ok(());
+    }
+    let principal = caller();
+    if abuse::is_flagged(&principal) {
+        return Err(Error::RateLimited {
+            msg: format!(
+                "Caller {} was flagged for abuse; retry tomorrow.",
+                principal
+            ),
+        });
+    }
+    let limit = config::get().rate_limits.limit(action);
+    if limit == 0 {
+        return Ok(());
+    }
+    let now = time();
+    let first = ActionKey::new(&principal, action, 0);
+    let window_start = ActionKey::new(&principal, action, now.saturating_sub(WINDOW_NANOS));
+    let last = ActionKey::new(&principal, action, u64::MAX);
+    RECENT_ACTIONS.with(|recent| {
+        let mut recent = recent.borrow_mut();
+        // forget actions that left the window
+        let expired: Vec<ActionKey> = recent
+            .range(first..window_start.clone())
+            .map(|(key, _)| key)
+            .collect();
+        for key in expired {
+            recent.remove(&key);
+        }
+        let in_window: Vec<u64> = recent
+            .range(window_start..=last)
+            .rev()
+            .take(limit as usize)
+            .map(|(key, _)| key.at)
+            .collect();
+        if in_window.len() >= limit as usize {
+            // the oldest action counted leaves the window first
+            let oldest = in_window[in_window.len() - 1];
+            let retry_after = (oldest + WINDOW_NANOS)
+                .saturating_sub(now)
+                .div_ceil(date::NANOS_PER_SECOND);
+            abuse::record(SignalKind::RateLimited { principal });
+            return Err(Error::RateLimited {
+                msg: format!(
+                    "Caller {} reached the limit of {} {} per hour; retry in {} seconds.",
+                    principal,
+                    limit,
+                    action.name(),
+                    retry_after
+                ),
+            });
+        }
+        recent.insert(ActionKey::new(&principal, action, now), ());
+        Ok(())
+    })
+}
+
+// Query function to get the current rate limits
+#[ic_cdk::query]
+fn get_rate_limits() -> RateLimits {
+    config::get().rate_limits
+}
+
+// Update function for admins to change only the rate limits of the
+// configuration
+#[ic_cdk::update]
+fn set_rate_limits(limits: RateLimits) -> Result<RateLimits, Error> {
+    auth::require_admin()?;
+    let mut config = config::get();
+    config.rate_limits = limits.clone();
+    config::save(config);
+    Ok(limits)
+}
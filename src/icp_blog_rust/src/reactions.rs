@@ -0,0 +1,208 @@
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{_get_visible_blog_post, auth, do_insert, rate_limits, BlogPost, Error};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// The fixed set of reactions a post can receive
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+pub(crate) enum ReactionKind {
+    Clap,
+    Heart,
+    Fire,
+    Laugh,
+    Insightful,
+}
+
+impl ReactionKind {
+    // Stable one-byte code used in storage keys; never renumber
+    fn code(self) -> u8 {
+        match self {
+            ReactionKind::Clap => 0,
+            ReactionKind::Heart => 1,
+            ReactionKind::Fire => 2,
+            ReactionKind::Laugh => 3,
+            ReactionKind::Insightful => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ReactionKind::Clap),
+            1 => Some(ReactionKind::Heart),
+            2 => Some(ReactionKind::Fire),
+            3 => Some(ReactionKind::Laugh),
+            4 => Some(ReactionKind::Insightful),
+            _ => None,
+        }
+    }
+}
+
+// Number of reactions of one kind on a post
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReactionCount {
+    kind: ReactionKind,
+    count: u64,
+}
+
+// Key of the reactions map: post id, reaction code, then the principal that
+// reacted, so all reactions of a post are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ReactionKey {
+    post_id: u64,
+    kind: u8,
+    principal: Vec<u8>,
+}
+
+impl Storable for ReactionKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.push(self.kind);
+        bytes.extend_from_slice(&self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ReactionKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            kind: bytes[8],
+            principal: bytes[9..].to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + 1 + 29,
+        is_fixed_size: false,
+    };
+}
+
+impl ReactionKey {
+    fn new(post_id: u64, kind: ReactionKind, principal: &Principal) -> Self {
+        ReactionKey {
+            post_id,
+            kind: kind.code(),
+            principal: principal.as_slice().to_vec(),
+        }
+    }
+}
+
+thread_local! {
+    // (post, kind, principal) -> time of the reaction
+    static REACTIONS: RefCell<StableBTreeMap<ReactionKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REACTIONS_MEMORY)));
+}
+
+// Add `delta` to the count of a reaction kind kept on the post
+fn adjust_count(blog_post: &mut BlogPost, kind: ReactionKind, delta: i64) {
+    match blog_post.reactions.iter_mut().find(|r| r.kind == kind) {
+        Some(reaction) => reaction.count = reaction.count.saturating_add_signed(delta),
+        None if delta > 0 => blog_post.reactions.push(ReactionCount {
+            kind,
+            count: delta as u64,
+        }),
+        None => {}
+    }
+    blog_post.reactions.retain(|r| r.count > 0);
+}
+
+// Every reaction of a post: its kind, who reacted and when
+pub(crate) fn post_reactions(post_id: u64) -> Vec<(ReactionKind, Principal, u64)> {
+    let start = ReactionKey {
+        post_id,
+        kind: 0,
+        principal: Vec::new(),
+    };
+    REACTIONS.with(|reactions| {
+        reactions
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .filter_map(|(key, reacted_at)| {
+                let kind = ReactionKind::from_code(key.kind)?;
+                Some((kind, Principal::from_slice(&key.principal), reacted_at))
+            })
+            .collect()
+    })
+}
+
+// Drop every reaction of a post that is being deleted
+pub(crate) fn remove_post_reactions(post_id: u64) {
+    let start = ReactionKey {
+        post_id,
+        kind: 0,
+        principal: Vec::new(),
+    };
+    REACTIONS.with(|reactions| {
+        let mut reactions = reactions.borrow_mut();
+        let keys: Vec<ReactionKey> = reactions
+            .range(start..)
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            reactions.remove(&key);
+        }
+    });
+}
+
+// Update function to react to a blog post, once per kind per caller
+#[ic_cdk::update]
+fn react(post_id: u64, kind: ReactionKind) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_visible_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot react.", post_id),
+        });
+    };
+    let key = ReactionKey::new(post_id, kind, &caller());
+    if REACTIONS.with(|reactions| reactions.borrow().contains_key(&key)) {
+        return Err(Error::AlreadyReacted {
+            msg: format!(
+                "Blog post with ID {} already has a {:?} reaction from caller: {}.",
+                post_id,
+                kind,
+                caller()
+            ),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+    REACTIONS.with(|reactions| reactions.borrow_mut().insert(key, time()));
+    adjust_count(&mut blog_post, kind, 1);
+    do_insert(&blog_post);
+    notifications::notify_author(
+        &blog_post.author,
+        NotificationKind::Reacted {
+            post_id,
+            reaction: kind,
+        },
+    );
+    Ok(blog_post)
+}
+
+// Update function to take back a reaction
+#[ic_cdk::update]
+fn unreact(post_id: u64, kind: ReactionKind) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_visible_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found. Cannot unreact.", post_id),
+        });
+    };
+    let key = ReactionKey::new(post_id, kind, &caller());
+    if REACTIONS.with(|reactions| reactions.borrow_mut().remove(&key).is_none()) {
+        return Err(Error::NotReacted {
+            msg: format!(
+                "Blog post with ID {} has no {:?} reaction from caller: {}.",
+                post_id,
+                kind,
+                caller()
+            ),
+        });
+    }
+    adjust_count(&mut blog_post, kind, -1);
+    do_insert(&blog_post);
+    Ok(blog_post)
+}
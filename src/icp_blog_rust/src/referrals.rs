@@ -0,0 +1,136 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use sha2::{Digest, Sha256};
+use std::{borrow::Cow, cell::RefCell};
+
+// Length of a referral code, in hex digits
+const CODE_LEN: usize = 8;
+
+// What the readers a principal referred with `?ref={code}` went on to do
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReferralStats {
+    code: String,
+    // views counted by `record_view`
+    views: u64,
+    // paywalled posts bought or gifted
+    purchases: u64,
+    // what those purchases paid, in the tip ledger's base units
+    purchase_total: u64,
+}
+
+impl Storable for ReferralStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // code -> the principal it refers for
+    static CODES: RefCell<StableBTreeMap<String, Blob<29>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REFERRAL_CODES_MEMORY)));
+
+    // principal -> its code and what it brought in
+    static STATS: RefCell<StableBTreeMap<Blob<29>, ReferralStats, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REFERRAL_STATS_MEMORY)));
+}
+
+// Who a code refers for, unless the code is unknown or the caller is
+// referring themselves
+fn referrer(code: &str) -> Option<Blob<29>> {
+    let referrer = CODES.with(|codes| codes.borrow().get(&code.to_string()))?;
+    let principal = Principal::from_slice(referrer.as_slice());
+    (!linking::same_person(&principal, &caller())).then_some(referrer)
+}
+
+fn attribute(code: Option<&str>, update: impl FnOnce(&mut ReferralStats)) {
+    let Some(referrer) = code.and_then(referrer) else {
+        return;
+    };
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if let Some(mut referral) = stats.get(&referrer) {
+            update(&mut referral);
+            stats.insert(referrer, referral);
+        }
+    });
+}
+
+// Credit a counted view to the principal whose code the reader came with
+pub(crate) fn record_view(code: Option<&str>) {
+    attribute(code, |stats| stats.views += 1);
+}
+
+// Credit a purchase of access to a post, at `price`, to the principal whose
+// code the buyer came with
+pub(crate) fn record_purchase(code: Option<&str>, price: u64) {
+    attribute(code, |stats| {
+        stats.purchases += 1;
+        stats.purchase_total = stats.purchase_total.saturating_add(price);
+    });
+}
+
+fn new_code(principal: &Principal) -> String {
+    let mut nonce: u64 = 0;
+    loop {
+        let mut hasher = Sha256::new();
+        hasher.update(principal.as_slice());
+        hasher.update(time().to_be_bytes());
+        hasher.update(nonce.to_be_bytes());
+        let code = format!("{:x}", hasher.finalize())[..CODE_LEN].to_string();
+        if !CODES.with(|codes| codes.borrow().contains_key(&code)) {
+            return code;
+        }
+        nonce += 1;
+    }
+}
+
+// Update function for users to get the code they add to links they share as
+// `?ref={code}`. Frontends pass it on to `record_view`, `buy_access` and
+// `gift_access`. The code is made on the first call and kept afterwards;
+// linked principals share one.
+#[ic_cdk::update]
+fn get_my_referral_code() -> Result<String, Error> {
+    auth::require_signed_in()?;
+    let principal = linking::primary(&caller());
+    let key = principal_key(&principal);
+    if let Some(stats) = STATS.with(|stats| stats.borrow().get(&key)) {
+        return Ok(stats.code);
+    }
+    let code = new_code(&principal);
+    CODES.with(|codes| codes.borrow_mut().insert(code.clone(), key));
+    STATS.with(|stats| {
+        stats.borrow_mut().insert(
+            key,
+            ReferralStats {
+                code: code.clone(),
+                views: 0,
+                purchases: 0,
+                purchase_total: 0,
+            },
+        )
+    });
+    Ok(code)
+}
+
+// Query function for users to see what the links they shared brought in
+#[ic_cdk::query]
+fn get_my_referral_stats() -> Result<ReferralStats, Error> {
+    auth::require_signed_in()?;
+    let key = principal_key(&linking::primary(&caller()));
+    STATS
+        .with(|stats| stats.borrow().get(&key))
+        .ok_or_else(|| Error::NotFound {
+            msg: "Call get_my_referral_code to start referring readers".to_string(),
+        })
+}
@@ -0,0 +1,96 @@
+use crate::quotas::{self, Endpoint};
+use crate::visibility::{self, Reader};
+use crate::{
+    _get_blog_post, _get_visible_blog_post, archive, authors, categories, config, BlogPost, Error,
+};
+use candid::Principal;
+use std::collections::BTreeMap;
+
+// Most related posts returned
+const MAX_RELATED: u64 = 20;
+// Newest posts looked at in each category, and of the author, so a call
+// reads a bounded part of the indexes
+const CANDIDATES_PER_INDEX: usize = 200;
+
+// Query function to suggest posts related to a post: those sharing the most
+// categories with it, newest first among equals. With `by_author`, other
+// posts of the same author count as sharing one more category. Replicated
+// calls over the query quota get fewer posts, from fewer candidates.
+#[ic_cdk::query]
+fn get_related_posts(id: u64, limit: u64, by_author: bool) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    let (per_index, limit) = match quotas::record(Endpoint::RelatedPosts) {
+        Some(limited) => (CANDIDATES_PER_INDEX / 10, limit.min(limited)),
+        None => (CANDIDATES_PER_INDEX, limit),
+    };
+    let Some(blog_post) = _get_visible_blog_post(&id) else {
+        return Err(archive::not_here(id));
+    };
+    Ok(related_posts(
+        &blog_post,
+        per_index,
+        limit.min(MAX_RELATED) as usize,
+        by_author,
+        &Reader::current(),
+    ))
+}
+
+// Posts listed for `reader` that share the most categories with a post, see
+// `get_related_posts`
+fn related_posts(
+    blog_post: &BlogPost,
+    per_index: usize,
+    limit: usize,
+    by_author: bool,
+    reader: &Reader,
+) -> Vec<BlogPost> {
+    // candidate id -> categories shared with the post, plus one for the author
+    let mut shared: BTreeMap<u64, u32> = BTreeMap::new();
+    for category in &blog_post.categories {
+        for candidate in categories::newest_post_ids(category, per_index) {
+            *shared.entry(candidate).or_insert(0) += 1;
+        }
+    }
+    if by_author {
+        if let Ok(author) = Principal::from_text(&blog_post.author) {
+            for candidate in authors::newest_post_ids(&author, per_index) {
+                *shared.entry(candidate).or_insert(0) += 1;
+            }
+        }
+    }
+    shared.remove(&blog_post.id);
+    let mut ranked: Vec<(u32, u64)> = shared
+        .into_iter()
+        .map(|(candidate, count)| (count, candidate))
+        .collect();
+    ranked.sort_unstable_by(|a, b| b.cmp(a));
+    ranked
+        .into_iter()
+        .filter_map(|(_, candidate)| _get_blog_post(&candidate))
+        .filter(|related| visibility::is_listed_for(related, reader))
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paywall;
+    use crate::testing::{ids, insert_post, principal, reader};
+
+    #[test]
+    fn related_posts_leave_out_paywalled_posts() {
+        let post = insert_post(1, principal(1), 1, &["rust", "icp"]);
+        insert_post(2, principal(1), 2, &["rust", "icp"]);
+        insert_post(3, principal(1), 3, &["rust"]);
+        paywall::set_price(2, 100);
+        let posts = related_posts(
+            &post,
+            CANDIDATES_PER_INDEX,
+            10,
+            false,
+            &reader(principal(2), 0),
+        );
+        assert_eq!(ids(&posts), vec![3]);
+    }
+}
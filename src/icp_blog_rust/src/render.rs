@@ -0,0 +1,798 @@
+use crate::{embeds, emoji, Error};
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use std::ops::Range;
+
+// URL schemes links and images may use; anything else is neutralized
+const SAFE_SCHEMES: [&str; 3] = ["http:", "https:", "mailto:"];
+// Reading speed reading times are estimated with
+const WORDS_PER_MINUTE: u32 = 200;
+// GitHub-flavored extensions posts may use, and `$...$` and `$$...$$` math
+const EXTENSIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_TASKLISTS)
+    .union(Options::ENABLE_STRIKETHROUGH)
+    .union(Options::ENABLE_MATH);
+// Comments are parsed with strikethrough only: no tables, task lists or math
+const COMMENT_EXTENSIONS: Options = Options::ENABLE_STRIKETHROUGH;
+// Raw HTML elements `sanitize` removes along with everything they hold
+const REMOVED_WITH_CONTENT: [&str; 5] = ["script", "style", "object", "applet", "template"];
+// Raw HTML elements `sanitize` removes the tags of, keeping what they hold
+const REMOVED_TAGS: [&str; 6] = ["base", "meta", "link", "embed", "form", "frame"];
+// Largest HTML post content may render to, in bytes, so pages stay well
+// within a response. Tables can render to far more HTML than their source,
+// as missing cells are filled in.
+const MAX_RENDERED_LEN: usize = 2 * 1024 * 1024;
+
+// A spoiler or content warning block: the lines between one starting with
+// `:::spoiler` or `:::cw`, optionally followed by a summary, and one holding
+// only `:::`. Blocks don't nest; one left open runs to the end of the content.
+struct Spoiler {
+    // the whole block, markers included
+    block: Range<usize>,
+    // the content between the markers
+    content: Range<usize>,
+    summary: String,
+}
+
+// Summary of a line opening a spoiler block, with a default for blocks
+// without one
+fn spoiler_start(line: &str) -> Option<String> {
+    let rest = line.trim().strip_prefix(":::")?;
+    let lower = rest.to_ascii_lowercase();
+    let (default, len) = if lower.starts_with("spoiler") {
+        ("Spoiler", "spoiler".len())
+    } else if lower.starts_with("cw") {
+        ("Content warning", "cw".len())
+    } else {
+        return None;
+    };
+    let summary = &rest[len..];
+    if !summary.is_empty() && !summary.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(match summary.trim() {
+        "" => default.to_string(),
+        summary => summary.to_string(),
+    })
+}
+
+// Fence of a line opening or closing a fenced code block, as its character
+// and length
+fn code_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let fence = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = trimmed.chars().take_while(|&c| c == fence).count();
+    (len >= 3).then_some((fence, len))
+}
+
+// Spoiler blocks of Markdown, in order. Marker lines inside fenced code blocks
+// are code.
+fn spoilers(markdown: &str) -> Vec<Spoiler> {
+    let mut spoilers = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    // summary, start of the block and start of its content
+    let mut open: Option<(String, usize, usize)> = None;
+    let mut start = 0;
+    for line in markdown.split_inclusive('\n') {
+        let end = start + line.len();
+        match (fence, code_fence(line)) {
+            (Some((c, len)), Some((closing, closing_len)))
+                if closing == c && closing_len >= len && line.trim().chars().all(|d| d == c) =>
+            {
+                fence = None
+            }
+            (Some(_), _) => {}
+            (None, Some(opening)) => fence = Some(opening),
+            (None, None) => match &open {
+                Some((summary, block_start, content_start)) if line.trim() == ":::" => {
+                    spoilers.push(Spoiler {
+                        block: *block_start..end,
+                        content: *content_start..start,
+                        summary: summary.clone(),
+                    });
+                    open = None;
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(summary) = spoiler_start(line) {
+                        open = Some((summary, start, end));
+                    }
+                }
+            },
+        }
+        start = end;
+    }
+    if let Some((summary, block_start, content_start)) = open {
+        spoilers.push(Spoiler {
+            block: block_start..markdown.len(),
+            content: content_start..markdown.len(),
+            summary,
+        });
+    }
+    spoilers
+}
+
+// Markdown with its spoiler blocks left out, for excerpts and descriptions
+fn without_spoilers(markdown: &str) -> String {
+    let mut text = markdown.to_string();
+    for spoiler in spoilers(markdown).into_iter().rev() {
+        text.replace_range(spoiler.block, "\n");
+    }
+    text
+}
+
+// Render post content, written in Markdown with tables, task lists,
+// strikethrough and math, to HTML. Math is kept as escaped TeX in
+// `<span class="math math-inline">` and `math-display` elements for clients
+// to typeset. Raw HTML in the source is shown as text instead of being passed
+// through, except for embeds from providers admins allowed. `:shortcode:`
+// emoji are expanded, except in code blocks and image descriptions. Fenced
+// `mermaid` and `graphviz` (or `dot`) blocks are wrapped in a
+// `<div class="diagram" data-diagram="...">` for clients to draw. Spoiler
+// blocks become collapsed `<details class="spoiler">` sections showing their
+// summary.
+pub(crate) fn markdown_to_html(markdown: &str) -> String {
+    render_markdown(markdown, false)
+}
+
+// Render Markdown like `markdown_to_html` for pages that load nothing from
+// other sites and run no scripts: embeds and images become plain links, and
+// custom emoji, being images, stay shortcodes.
+pub(crate) fn markdown_to_lite_html(markdown: &str) -> String {
+    render_markdown(markdown, true)
+}
+
+fn render_markdown(markdown: &str, lite: bool) -> String {
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    let mut rendered = 0;
+    for spoiler in spoilers(markdown) {
+        output.push_str(&blocks_to_html(
+            &markdown[rendered..spoiler.block.start],
+            lite,
+        ));
+        output.push_str(&format!(
+            "<details class=\"spoiler\"><summary>{}</summary>\n{}</details>\n",
+            escape(&spoiler.summary),
+            blocks_to_html(&markdown[spoiler.content], lite)
+        ));
+        rendered = spoiler.block.end;
+    }
+    output.push_str(&blocks_to_html(&markdown[rendered..], lite));
+    output
+}
+
+// Render Markdown without spoiler blocks to HTML, see `markdown_to_html` and
+// `markdown_to_lite_html`
+fn blocks_to_html(markdown: &str, lite: bool) -> String {
+    let embed = if lite {
+        embeds::to_link
+    } else {
+        embeds::to_html
+    };
+    let expand = if lite {
+        emoji::expand_built_in
+    } else {
+        emoji::expand
+    };
+    // code blocks and images the parser is inside of
+    let mut verbatim = 0;
+    let mut in_diagram = false;
+    let parser = Parser::new_ext(markdown, EXTENSIONS).map(move |event| match event {
+        Event::Start(Tag::CodeBlock(kind)) => {
+            verbatim += 1;
+            match diagram_kind(&kind) {
+                Some(diagram) => {
+                    in_diagram = true;
+                    Event::InlineHtml(
+                        format!(
+                            r#"<div class="diagram" data-diagram="{0}"><pre><code class="language-{0}">"#,
+                            diagram
+                        )
+                        .into(),
+                    )
+                }
+                None => Event::Start(Tag::CodeBlock(kind)),
+            }
+        }
+        Event::End(TagEnd::CodeBlock) if in_diagram => {
+            verbatim -= 1;
+            in_diagram = false;
+            Event::InlineHtml("</code></pre></div>\n".into())
+        }
+        Event::End(TagEnd::Image) if lite => {
+            verbatim -= 1;
+            Event::InlineHtml("</a>".into())
+        }
+        Event::End(TagEnd::CodeBlock) | Event::End(TagEnd::Image) => {
+            verbatim -= 1;
+            event
+        }
+        Event::Html(html) | Event::InlineHtml(html) if verbatim == 0 => match embed(&html) {
+            Some(embed) => Event::InlineHtml(embed.into()),
+            None if html.contains(':') => Event::InlineHtml(expand(&escape(&html)).into()),
+            None => Event::Text(html),
+        },
+        Event::Text(text) if verbatim == 0 && text.contains(':') => {
+            Event::InlineHtml(expand(&escape(&text)).into())
+        }
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image { dest_url, .. }) if lite => {
+            // the description, shown as the link text, follows
+            verbatim += 1;
+            Event::InlineHtml(
+                format!(r#"<a class="image" href="{}">Image: "#, escape(&safe_url(dest_url))).into(),
+            )
+        }
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => {
+            verbatim += 1;
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url: safe_url(dest_url),
+                title,
+                id,
+            })
+        }
+        other => other,
+    });
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut output, parser);
+    output
+}
+
+// Take dangerous raw HTML out of post content before it is stored: script,
+// style and similar elements with what they hold, tags that change how a page
+// loads, and event handler attributes and `javascript:` URLs from the tags
+// left. Raw HTML is shown as text anyway, except for embeds, but content is
+// also read by other clients. Code is left as written.
+pub(crate) fn sanitize(markdown: &str) -> String {
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    // element being removed with its content, and where it starts
+    let mut open: Option<(&str, usize)> = None;
+    for (event, range) in Parser::new_ext(markdown, EXTENSIONS).into_offset_iter() {
+        if !matches!(event, Event::Html(_) | Event::InlineHtml(_)) {
+            continue;
+        }
+        // an HTML block is one event per line, which may hold many tags
+        for tag in tags(&markdown[range.clone()]) {
+            let tag = range.start + tag.start..range.start + tag.end;
+            let html = &markdown[tag.clone()];
+            let name = start_tag_name(html).unwrap_or_default();
+            let is_end = html.starts_with("</");
+            if let Some((removed, start)) = open {
+                if is_end && name == removed {
+                    edits.push((start..tag.end, String::new()));
+                    open = None;
+                }
+                continue;
+            }
+            if let Some(&removed) = REMOVED_WITH_CONTENT
+                .iter()
+                .find(|&&removed| removed == name)
+            {
+                if is_end {
+                    edits.push((tag, String::new()));
+                } else {
+                    open = Some((removed, tag.start));
+                }
+            } else if REMOVED_TAGS.contains(&name.as_str()) {
+                edits.push((tag, String::new()));
+            } else {
+                let cleaned = clean_tag(html);
+                if cleaned != html {
+                    edits.push((tag, cleaned));
+                }
+            }
+        }
+    }
+    if let Some((_, start)) = open {
+        edits.push((start..markdown.len(), String::new()));
+    }
+    let mut sanitized = markdown.to_string();
+    for (range, replacement) in edits.into_iter().rev() {
+        sanitized.replace_range(range, &replacement);
+    }
+    sanitized
+}
+
+// Lowercase name of the element raw HTML starts or ends with a tag of
+fn start_tag_name(html: &str) -> Option<String> {
+    let tag = html.trim_start().strip_prefix('<')?;
+    let tag = tag.strip_prefix('/').unwrap_or(tag);
+    let end = tag
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(tag.len());
+    (end > 0).then(|| tag[..end].to_ascii_lowercase())
+}
+
+// Where the tags of raw HTML are: a `<` followed by a letter, `/` or `!` up
+// to the `>` that isn't in a quoted attribute value, or to the end of the
+// HTML if none closes it. A `<` followed by anything else is text.
+fn tags(html: &str) -> Vec<Range<usize>> {
+    let mut tags = Vec::new();
+    let mut from = 0;
+    while let Some(found) = html[from..].find('<') {
+        let start = from + found;
+        let opens_tag = html[start + 1..]
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !opens_tag {
+            from = start + 1;
+            continue;
+        }
+        let end = start + tag_len(&html[start..]);
+        tags.push(start..end);
+        from = end;
+    }
+    tags
+}
+
+// Length of the tag raw HTML starts with, read the way `clean_tag` reads its
+// attributes: a quote right after `=` opens a value that runs to the next
+// such quote
+fn tag_len(html: &str) -> usize {
+    let mut quote = None;
+    let mut after_equals = false;
+    for (i, c) in html.char_indices().skip(1) {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => continue,
+            None if c == '>' => return i + 1,
+            None if after_equals && matches!(c, '"' | '\'') => {
+                quote = Some(c);
+                continue;
+            }
+            None => {}
+        }
+        after_equals = c == '=' || (after_equals && c.is_whitespace());
+    }
+    html.len()
+}
+
+// A tag without its event handler attributes, such as `onclick`, and the
+// attributes holding `javascript:` URLs
+fn clean_tag(tag: &str) -> String {
+    let name_len = tag[1..]
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .map_or(tag.len(), |end| end + 1);
+    let mut cleaned = tag[..name_len].to_string();
+    let mut rest = &tag[name_len..];
+    loop {
+        let attribute = rest.trim_start();
+        let space = &rest[..rest.len() - attribute.len()];
+        if attribute.is_empty() || attribute.starts_with(['>', '/']) {
+            cleaned.push_str(rest);
+            return cleaned;
+        }
+        let name_len = attribute
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(attribute.len());
+        let mut after = &attribute[name_len..];
+        let mut value = "";
+        if let Some(quoted) = after.trim_start().strip_prefix('=') {
+            let quoted = quoted.trim_start();
+            let value_len = match quoted.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    quoted[1..].find(quote).map_or(quoted.len(), |end| end + 2)
+                }
+                _ => quoted
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(quoted.len()),
+            };
+            value = &quoted[..value_len];
+            after = &quoted[value_len..];
+        }
+        if after.len() == attribute.len() {
+            // a stray character that starts no attribute
+            cleaned.push_str(rest);
+            return cleaned;
+        }
+        let url = value
+            .trim_matches(['"', '\''])
+            .trim_start()
+            .to_ascii_lowercase();
+        let dangerous = attribute[..name_len].to_ascii_lowercase().starts_with("on")
+            || url.starts_with("javascript:");
+        if !dangerous {
+            cleaned.push_str(space);
+            cleaned.push_str(&attribute[..attribute.len() - after.len()]);
+        }
+        rest = after;
+    }
+}
+
+// Words of post content as read, leaving out Markdown syntax, link targets
+// and raw HTML
+pub(crate) fn word_count(markdown: &str) -> u32 {
+    Parser::new_ext(markdown, EXTENSIONS)
+        .map(|event| match event {
+            Event::Text(text) | Event::Code(text) => text.split_whitespace().count(),
+            _ => 0,
+        })
+        .sum::<usize>() as u32
+}
+
+// Post content as plain text for page descriptions and feed excerpts, with
+// blocks separated by spaces. Spoiler blocks are left out.
+pub(crate) fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new_ext(&without_spoilers(markdown), EXTENSIONS) {
+        match event {
+            Event::Text(part) | Event::Code(part) => text.push_str(&part),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) if !text.ends_with(' ') => {
+                text.push(' ')
+            }
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+// Usernames mentioned as `@name` in the text of Markdown, outside of code,
+// lowercased and in the order they first appear
+pub(crate) fn mentions(markdown: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    // the parser may split a run of text, e.g. at an underscore
+    let mut text = String::new();
+    for event in Parser::new_ext(markdown, EXTENSIONS) {
+        if let Event::Text(part) = event {
+            text.push_str(&part);
+        } else {
+            add_mentions(&text, &mut names);
+            text.clear();
+        }
+    }
+    add_mentions(&text, &mut names);
+    names
+}
+
+fn add_mentions(text: &str, names: &mut Vec<String>) {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        // an `@` inside a word is part of an email address
+        if c == '@' && !previous.is_some_and(|p: char| p.is_alphanumeric() || p == '_') {
+            let name: String = text[i + 1..]
+                .chars()
+                .take_while(|&c| is_name_char(c))
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if !name.is_empty() && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        previous = Some(c);
+    }
+}
+
+// HTTPS URLs post content links to, either as Markdown links or written out
+// in the text, in the order they first appear
+pub(crate) fn links(markdown: &str) -> Vec<String> {
+    let mut links: Vec<String> = Vec::new();
+    for event in Parser::new_ext(markdown, EXTENSIONS) {
+        let found: Vec<String> = match event {
+            Event::Start(Tag::Link { dest_url, .. }) => vec![dest_url.to_string()],
+            Event::Text(text) => text
+                .split_whitespace()
+                .filter(|word| word.starts_with("https://"))
+                .map(|word| {
+                    word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')'])
+                        .to_string()
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        for link in found {
+            if link.starts_with("https://") && !links.contains(&link) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+// Render a comment to HTML with the Markdown subset comments may use:
+// emphasis, strikethrough, code, links, quotes and lists. Links are marked
+// `rel="nofollow ugc"`. Comments written before the subset was enforced are
+// still shown: headings become paragraphs, images links to the image, and
+// rules are left out. Raw HTML is shown as text and `:shortcode:` emoji are
+// expanded outside code, as in posts.
+pub(crate) fn comment_to_html(markdown: &str) -> String {
+    let mut in_code = false;
+    let parser = Parser::new_ext(markdown, COMMENT_EXTENSIONS).filter_map(move |event| {
+        Some(match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                in_code = true;
+                Event::Start(Tag::CodeBlock(kind))
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code = false;
+                event
+            }
+            Event::Start(Tag::Heading { .. }) => Event::Start(Tag::Paragraph),
+            Event::End(TagEnd::Heading(_)) => Event::End(TagEnd::Paragraph),
+            Event::Start(Tag::Link { dest_url, .. })
+            | Event::Start(Tag::Image { dest_url, .. }) => Event::InlineHtml(
+                format!(
+                    r#"<a href="{}" rel="nofollow ugc">"#,
+                    escape(&safe_url(dest_url))
+                )
+                .into(),
+            ),
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                Event::InlineHtml("</a>".into())
+            }
+            Event::Rule => return None,
+            Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+            Event::Text(text) if !in_code && text.contains(':') => {
+                Event::InlineHtml(emoji::expand(&escape(&text)).into())
+            }
+            other => other,
+        })
+    });
+    let mut output = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut output, parser);
+    output
+}
+
+// First construct of a comment outside the Markdown subset comments may use,
+// named for an error message
+pub(crate) fn unsupported_comment_formatting(markdown: &str) -> Option<&'static str> {
+    Parser::new_ext(markdown, COMMENT_EXTENSIONS).find_map(|event| match event {
+        Event::Start(Tag::Heading { .. }) => Some("headings"),
+        Event::Start(Tag::Image { .. }) => Some("images"),
+        Event::Rule => Some("horizontal rules"),
+        _ => None,
+    })
+}
+
+// Links in a comment: Markdown links and autolinks, and URLs written out in
+// the text. Unlike `links`, any scheme counts.
+pub(crate) fn comment_links(markdown: &str) -> usize {
+    let mut in_link = false;
+    let mut count = 0;
+    for event in Parser::new_ext(markdown, COMMENT_EXTENSIONS) {
+        match event {
+            Event::Start(Tag::Link { .. }) => {
+                in_link = true;
+                count += 1;
+            }
+            Event::End(TagEnd::Link) => in_link = false,
+            Event::Text(text) if !in_link => {
+                count += text
+                    .split_whitespace()
+                    .filter(|word| {
+                        let word = word.to_ascii_lowercase();
+                        word.starts_with("http://")
+                            || word.starts_with("https://")
+                            || word.starts_with("www.")
+                    })
+                    .count();
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+// Diagram language of a fenced code block, if it holds a diagram
+fn diagram_kind(kind: &CodeBlockKind) -> Option<&'static str> {
+    let CodeBlockKind::Fenced(info) = kind else {
+        return None;
+    };
+    match info
+        .split_whitespace()
+        .next()?
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "mermaid" => Some("mermaid"),
+        "graphviz" | "dot" => Some("graphviz"),
+        _ => None,
+    }
+}
+
+// Whether post content has a diagram block
+pub(crate) fn has_diagrams(markdown: &str) -> bool {
+    Parser::new_ext(markdown, EXTENSIONS).any(|event| {
+        matches!(event, Event::Start(Tag::CodeBlock(kind)) if diagram_kind(&kind).is_some())
+    })
+}
+
+// Whether post content has inline or display math
+pub(crate) fn has_math(markdown: &str) -> bool {
+    Parser::new_ext(markdown, EXTENSIONS)
+        .any(|event| matches!(event, Event::InlineMath(_) | Event::DisplayMath(_)))
+}
+
+// Refuse content whose HTML would be larger than pages can carry
+pub(crate) fn check_rendered_size(markdown: &str) -> Result<(), Error> {
+    if markdown_to_html(markdown).len() > MAX_RENDERED_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Content renders to more than {} bytes of HTML",
+                MAX_RENDERED_LEN
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Estimated minutes to read a number of words, at least one for any content
+pub(crate) fn reading_minutes(words: u32) -> u32 {
+    words.div_ceil(WORDS_PER_MINUTE)
+}
+
+// Relative URLs and URLs with a known safe scheme are kept, others (such as
+// `javascript:`) are replaced by an inert anchor
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    let lower = url.trim().to_ascii_lowercase();
+    let scheme_end = lower.find([':', '/', '?', '#']);
+    let has_scheme = matches!(scheme_end, Some(i) if lower.as_bytes()[i] == b':');
+    if !has_scheme || SAFE_SCHEMES.iter().any(|scheme| lower.starts_with(scheme)) {
+        url
+    } else {
+        CowStr::Borrowed("#")
+    }
+}
+
+// Escape text for use in HTML or XML content and attribute values
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_removes_scripts_with_their_content() {
+        let sanitized = sanitize("Hello\n\n<script>alert(1)</script>\n\nworld");
+        assert!(!sanitized.contains("script"));
+        assert!(!sanitized.contains("alert"));
+        assert!(sanitized.contains("Hello") && sanitized.contains("world"));
+    }
+
+    #[test]
+    fn sanitize_removes_an_unclosed_script_to_the_end() {
+        assert_eq!(sanitize("Hello\n\n<script>\nalert(1)"), "Hello\n\n");
+    }
+
+    #[test]
+    fn sanitize_removes_tags_that_change_how_a_page_loads() {
+        assert_eq!(
+            sanitize(r#"<base href="https://evil.example/"> text"#),
+            " text"
+        );
+    }
+
+    #[test]
+    fn sanitize_looks_at_every_tag_of_an_html_block() {
+        assert_eq!(
+            sanitize("<div><p>a</p><style>p{}</style><meta http-equiv=refresh>b</div>"),
+            "<div><p>a</p>b</div>"
+        );
+        assert_eq!(
+            sanitize("<div>\n<script>alert(1)</script>\n</div>\n\nok"),
+            "<div>\n\n</div>\n\nok"
+        );
+    }
+
+    #[test]
+    fn sanitize_reads_quoted_attribute_values_whole() {
+        assert_eq!(
+            sanitize(r#"<div><img alt=">" onerror=alert(1) src=x></div>"#),
+            r#"<div><img alt=">" src=x></div>"#
+        );
+    }
+
+    #[test]
+    fn sanitize_keeps_text_that_only_looks_like_a_tag() {
+        assert_eq!(
+            sanitize("<div>a < b <script>alert(1)</script></div>"),
+            "<div>a < b </div>"
+        );
+    }
+
+    #[test]
+    fn sanitize_strips_event_handlers_and_javascript_urls() {
+        let sanitized = sanitize(r#"<a href="javascript:alert(1)" onclick="x()" title="t">a</a>"#);
+        assert_eq!(sanitized, r#"<a title="t">a</a>"#);
+    }
+
+    #[test]
+    fn sanitize_leaves_code_as_written() {
+        let markdown = "```\n<script>alert(1)</script>\n```\n\nand `<b onclick=x>`";
+        assert_eq!(sanitize(markdown), markdown);
+    }
+
+    #[test]
+    fn clean_tag_keeps_safe_attributes() {
+        assert_eq!(
+            clean_tag(r#"<img src="a.png" alt='x' width=10>"#),
+            r#"<img src="a.png" alt='x' width=10>"#
+        );
+        assert_eq!(clean_tag("<br/>"), "<br/>");
+    }
+
+    #[test]
+    fn clean_tag_drops_handlers_in_any_case_and_quoting() {
+        assert_eq!(
+            clean_tag("<img src=a.png OnError=alert(1)>"),
+            "<img src=a.png>"
+        );
+        assert_eq!(clean_tag(r#"<div onmouseover='x()'>"#), "<div>");
+        assert_eq!(clean_tag(r#"<a href=" JavaScript:alert(1)">"#), "<a>");
+    }
+
+    #[test]
+    fn safe_url_neutralizes_unknown_schemes() {
+        assert_eq!(&*safe_url("javascript:alert(1)".into()), "#");
+        assert_eq!(&*safe_url(" JAVASCRIPT:alert(1)".into()), "#");
+        assert_eq!(&*safe_url("data:text/html,hi".into()), "#");
+    }
+
+    #[test]
+    fn safe_url_keeps_safe_and_relative_urls() {
+        for url in [
+            "https://example.com/a",
+            "http://example.com",
+            "mailto:a@example.com",
+            "/posts/1",
+            "page?q=a:b",
+            "#top",
+        ] {
+            assert_eq!(&*safe_url(url.into()), url);
+        }
+    }
+
+    #[test]
+    fn rendering_shows_raw_html_as_text_and_neutralizes_links() {
+        let html = markdown_to_html("<b>bold</b> [x](javascript:alert(1))");
+        assert!(html.contains("&lt;b&gt;bold&lt;/b&gt;"));
+        assert!(html.contains(r##"<a href="#">x</a>"##));
+    }
+
+    #[test]
+    fn lite_rendering_links_to_images_instead_of_loading_them() {
+        let html = markdown_to_lite_html("![A cat :tada:](https://example.com/cat.png)");
+        assert!(html.contains(
+            r#"<a class="image" href="https://example.com/cat.png">Image: A cat :tada:</a>"#
+        ));
+        assert!(!html.contains("<img"));
+    }
+
+    #[test]
+    fn plain_text_leaves_out_spoilers() {
+        let text = plain_text("Intro\n\n:::spoiler Ending\nThe butler did it\n:::\n\nOutro");
+        assert!(text.contains("Intro") && text.contains("Outro"));
+        assert!(!text.contains("butler"));
+    }
+}
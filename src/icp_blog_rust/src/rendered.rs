@@ -0,0 +1,118 @@
+use crate::memory::{self, Memory};
+use crate::render::markdown_to_html;
+use crate::{config, get_blog_post, BlogPost, Error};
+use candid::{Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// HTML a post's content rendered to when it was saved
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct StoredHtml {
+    html: String,
+    // the post's revision and the render generation it was rendered at
+    revision: u64,
+    generation: u64,
+}
+
+impl Storable for StoredHtml {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A post along with its content as sanitized HTML
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct RenderedBlogPost {
+    blog_post: BlogPost,
+    html: String,
+}
+
+thread_local! {
+    static RENDERED: RefCell<StableBTreeMap<u64, StoredHtml, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::RENDERED_POSTS_MEMORY)));
+
+    // Bumped whenever a setting rendering depends on changes, such as the
+    // embed providers or custom emoji, so HTML rendered before is ignored
+    static GENERATION: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::RENDER_GENERATION_MEMORY), 0)
+            .expect("Cannot create the render generation cell")
+    );
+}
+
+fn generation() -> u64 {
+    GENERATION.with(|cell| *cell.borrow().get())
+}
+
+// Render and keep the content of a post that was created or edited, when
+// admins turned storing rendered HTML on. Encrypted content is never
+// rendered here.
+pub(crate) fn store(blog_post: &BlogPost) {
+    if !config::get().store_rendered_html.unwrap_or(false) || blog_post.encrypted {
+        remove(blog_post.id);
+        return;
+    }
+    let stored = StoredHtml {
+        html: markdown_to_html(&blog_post.content),
+        revision: blog_post.revision,
+        generation: generation(),
+    };
+    RENDERED.with(|rendered| rendered.borrow_mut().insert(blog_post.id, stored));
+}
+
+// Drop the HTML of a deleted post
+pub(crate) fn remove(post_id: u64) {
+    RENDERED.with(|rendered| rendered.borrow_mut().remove(&post_id));
+}
+
+// Stop using HTML rendered so far; posts are rendered when they are read
+// until they are next saved
+pub(crate) fn invalidate() {
+    GENERATION.with(|cell| {
+        let next = *cell.borrow().get() + 1;
+        cell.borrow_mut()
+            .set(next)
+            .expect("Cannot write the render generation")
+    });
+}
+
+// The HTML of a post: the stored one if it is still current, rendered now
+// otherwise
+fn html(blog_post: &BlogPost) -> String {
+    let stored = RENDERED.with(|rendered| rendered.borrow().get(&blog_post.id));
+    match stored {
+        Some(stored)
+            if stored.revision == blog_post.revision && stored.generation == generation() =>
+        {
+            stored.html
+        }
+        _ => markdown_to_html(&blog_post.content),
+    }
+}
+
+// Query function to get a blog post along with its content rendered to
+// sanitized HTML, the way the blog's pages show it, so clients don't have to
+// render Markdown themselves. Encrypted posts are rendered by the clients
+// that decrypt them.
+#[ic_cdk::query]
+fn get_blog_post_rendered(id: u64) -> Result<RenderedBlogPost, Error> {
+    let blog_post = get_blog_post(id)?;
+    if blog_post.encrypted {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Blog post with ID {} is encrypted and can only be rendered once decrypted",
+                id
+            ),
+        });
+    }
+    Ok(RenderedBlogPost {
+        html: html(&blog_post),
+        blog_post,
+    })
+}
@@ -0,0 +1,304 @@
+use crate::audit;
+use crate::digest::{self, DigestPost};
+use crate::memory::{self, Memory};
+use crate::stats::{self, WASM_PAGE_SIZE};
+use crate::{
+    _count_blog_posts, auth, authors, backup, comments, date, likes, moderation, timeline, views,
+    webhooks, Error,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::stable::stable64_size;
+use ic_cdk::api::{canister_balance128, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+// How often the timer looks for a month without a report
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+// Largest page `list_monthly_reports` returns
+const MAX_REPORTS_PAGE: u64 = 24;
+
+// How the site grew over the month, and its size when the report was made.
+// Post counts include drafts and hidden posts.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Growth {
+    new_posts: u64,
+    // not counting comments hidden since
+    new_comments: u64,
+    new_likes: u64,
+    total_posts: u64,
+    total_comments: u64,
+    total_likes: u64,
+    total_authors: u64,
+    total_views: u64,
+}
+
+// Moderator actions over the month, and the reports still open when the
+// report was made
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ModerationVolume {
+    hidden_posts: u64,
+    hidden_comments: u64,
+    dismissed_reports: u64,
+    open_reports: u64,
+}
+
+// Memory and cycles when the report was made. The changes are since the
+// report of the month before, if there is one; cycles topped up in between
+// make the consumption look lower than it was.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ResourceUsage {
+    // in bytes
+    stable_memory_size: u64,
+    heap_size: u64,
+    cycle_balance: u128,
+    stable_memory_growth: Option<u64>,
+    cycles_consumed: Option<u128>,
+}
+
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct MonthlyReport {
+    year: u64,
+    // 1 to 12
+    month: u32,
+    // the month runs from `since` to before `until`
+    since: u64,
+    until: u64,
+    generated_at: u64,
+    growth: Growth,
+    // public posts that got the most comments during the month
+    top_posts: Vec<DigestPost>,
+    moderation: ModerationVolume,
+    resources: ResourceUsage,
+}
+
+impl Storable for MonthlyReport {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Canister method every new report is passed on to, as its only argument
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReportDelivery {
+    canister: Principal,
+    method: String,
+}
+
+#[derive(candid::CandidType, Default, Serialize, Deserialize)]
+struct Delivery(Option<ReportDelivery>);
+
+impl Storable for Delivery {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    // Months since January 1970 -> the report of that month
+    static REPORTS: RefCell<StableBTreeMap<u64, MonthlyReport, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::MONTHLY_REPORTS_MEMORY)));
+
+    // set with `set_report_delivery`; reports are kept either way
+    static DELIVERY: RefCell<Cell<Delivery, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::REPORT_DELIVERY_MEMORY), Delivery::default())
+            .expect("Cannot create the report delivery cell")
+    );
+}
+
+fn month_start(year: u64, month: u32) -> u64 {
+    date::days_from_civil(year, month, 1) * date::NANOS_PER_DAY
+}
+
+fn next_month(year: u64, month: u32) -> (u64, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+// The last month that is over
+fn last_complete_month() -> (u64, u32) {
+    let (year, month, _) = date::civil_from_days(date::day_number(time()));
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
+
+fn get(year: u64, month: u32) -> Option<MonthlyReport> {
+    REPORTS.with(|reports| reports.borrow().get(&date::month_number(year, month)))
+}
+
+fn generate(year: u64, month: u32) -> MonthlyReport {
+    let since = month_start(year, month);
+    let (next_year, next) = next_month(year, month);
+    let until = month_start(next_year, next);
+    let actions = audit::monthly_actions(date::month_number(year, month));
+    let stable_memory_size = stable64_size() * WASM_PAGE_SIZE;
+    let cycle_balance = canister_balance128();
+    let (before_year, before) = if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    };
+    let previous = get(before_year, before);
+    MonthlyReport {
+        year,
+        month,
+        since,
+        until,
+        generated_at: time(),
+        growth: Growth {
+            new_posts: timeline::newest_created_between(since, until - 1).len() as u64,
+            new_comments: comments::post_ids_between(since, until).len() as u64,
+            new_likes: actions.likes,
+            total_posts: _count_blog_posts(),
+            total_comments: comments::total(),
+            total_likes: likes::count(),
+            total_authors: authors::count(),
+            total_views: views::total(),
+        },
+        top_posts: digest::summarize_period(since, until).top_posts,
+        moderation: ModerationVolume {
+            hidden_posts: actions.hidden_posts,
+            hidden_comments: actions.hidden_comments,
+            dismissed_reports: actions.dismissed_reports,
+            open_reports: moderation::open_reports(),
+        },
+        resources: ResourceUsage {
+            stable_memory_size,
+            heap_size: stats::heap_size(),
+            cycle_balance,
+            stable_memory_growth: previous.as_ref().map(|previous| {
+                stable_memory_size.saturating_sub(previous.resources.stable_memory_size)
+            }),
+            cycles_consumed: previous.as_ref().map(|previous| {
+                previous
+                    .resources
+                    .cycle_balance
+                    .saturating_sub(cycle_balance)
+            }),
+        },
+    }
+}
+
+// Store a report and pass it on with a one-way call sent after the current
+// message commits. A canister that can't be reached misses it; the report is
+// kept.
+fn save(report: &MonthlyReport) {
+    REPORTS.with(|reports| {
+        reports.borrow_mut().insert(
+            date::month_number(report.year, report.month),
+            report.clone(),
+        )
+    });
+    let Some(delivery) = DELIVERY.with(|cell| cell.borrow().get().0.clone()) else {
+        return;
+    };
+    if let Err(code) = ic_cdk::notify(delivery.canister, &delivery.method, (report.clone(),)) {
+        ic_cdk::println!(
+            "Cannot deliver the report of {}-{:02} to {}: {:?}",
+            report.year,
+            report.month,
+            delivery.canister,
+            code
+        );
+    }
+}
+
+// Report on the last month once it is over. Timers don't survive upgrades,
+// so this also runs from `post_upgrade`.
+pub(crate) fn start() {
+    ic_cdk_timers::set_timer_interval(CHECK_INTERVAL, || {
+        if backup::is_restoring() {
+            return;
+        }
+        let (year, month) = last_complete_month();
+        if get(year, month).is_none() {
+            save(&generate(year, month));
+        }
+    });
+}
+
+// Query function for admins to read the report of a month
+#[ic_cdk::query]
+fn get_monthly_report(year: u64, month: u32) -> Result<MonthlyReport, Error> {
+    auth::require_admin()?;
+    get(year, month).ok_or_else(|| Error::NotFound {
+        msg: format!("No report for {}-{:02}", year, month),
+    })
+}
+
+// Query function for admins to page through the reports, newest first
+#[ic_cdk::query]
+fn list_monthly_reports(offset: u64, limit: u64) -> Result<Vec<MonthlyReport>, Error> {
+    auth::require_admin()?;
+    Ok(REPORTS.with(|reports| {
+        reports
+            .borrow()
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit.min(MAX_REPORTS_PAGE) as usize)
+            .map(|(_, report)| report)
+            .collect()
+    }))
+}
+
+// Update function for admins to make the report of a month that is over now,
+// replacing the one made before. Totals, memory and cycles are as of now.
+#[ic_cdk::update]
+fn generate_monthly_report(year: u64, month: u32) -> Result<MonthlyReport, Error> {
+    auth::require_admin()?;
+    if !(1..=12).contains(&month) || year < 1970 || (year, month) > last_complete_month() {
+        return Err(Error::ValidationErrors {
+            errors: "Reports can only be made for months that are over".to_string(),
+        });
+    }
+    let report = generate(year, month);
+    save(&report);
+    Ok(report)
+}
+
+// Query function for admins to see where new reports are delivered
+#[ic_cdk::query]
+fn get_report_delivery() -> Result<Option<ReportDelivery>, Error> {
+    auth::require_admin()?;
+    Ok(DELIVERY.with(|cell| cell.borrow().get().0.clone()))
+}
+
+// Update function for admins to have every new report passed on to a
+// canister method, or to stop it
+#[ic_cdk::update]
+fn set_report_delivery(delivery: Option<ReportDelivery>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if let Some(delivery) = &delivery {
+        if !webhooks::is_canister(&delivery.canister) || !webhooks::is_method_name(&delivery.method)
+        {
+            return Err(Error::ValidationErrors {
+                errors: "Reports can only be delivered to a canister method".to_string(),
+            });
+        }
+    }
+    DELIVERY.with(|cell| {
+        cell.borrow_mut()
+            .set(Delivery(delivery))
+            .expect("Cannot write the report delivery")
+    });
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use crate::memory::{self, Memory};
+use crate::reactions::{self, ReactionKind};
+use crate::{_check_if_owner, _get_blog_post, auth, comments, likes, Error};
+use candid::Principal;
+use ic_stable_structures::Cell;
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, time::Duration};
+
+// Events per chunk of an engagement export
+const EVENTS_PER_CHUNK: usize = 1000;
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum ExportFormat {
+    Csv,
+    Json,
+}
+
+// One piece of an engagement export. Clients fetch chunks 0 to
+// `total_chunks - 1`; each holds whole rows, with the CSV header repeated.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct EngagementChunk {
+    data: String,
+    chunk_index: u64,
+    total_chunks: u64,
+}
+
+// An interaction with a post. The participant is a salted hash of the
+// principal: stable within the canister, so a participant's events can be
+// grouped, but not reversible by hashing known principals.
+#[derive(Serialize)]
+struct EngagementEvent {
+    participant: String,
+    timestamp: u64,
+    event: String,
+}
+
+thread_local! {
+    // Random salt for participant hashes, drawn once from the management
+    // canister; empty until then
+    static SALT: RefCell<Cell<Vec<u8>, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::RESEARCH_SALT_MEMORY), Vec::new())
+            .expect("Cannot create the research salt cell")
+    );
+}
+
+// Draw the salt if there is none yet. Randomness takes an inter-canister call,
+// so this runs from a timer after install and upgrade.
+pub(crate) fn ensure_salt() {
+    if SALT.with(|salt| !salt.borrow().get().is_empty()) {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            match ic_cdk::api::management_canister::main::raw_rand().await {
+                Ok((bytes,)) => SALT.with(|salt| {
+                    salt.borrow_mut()
+                        .set(bytes)
+                        .expect("Cannot write the research salt");
+                }),
+                Err((code, msg)) => {
+                    ic_cdk::println!("Cannot draw the research salt: {:?} {}", code, msg)
+                }
+            }
+        })
+    });
+}
+
+fn participant(salt: &[u8], principal: &Principal) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(principal.as_slice());
+    format!("{:x}", hasher.finalize())
+}
+
+fn reaction_name(kind: ReactionKind) -> &'static str {
+    match kind {
+        ReactionKind::Clap => "reaction:clap",
+        ReactionKind::Heart => "reaction:heart",
+        ReactionKind::Fire => "reaction:fire",
+        ReactionKind::Laugh => "reaction:laugh",
+        ReactionKind::Insightful => "reaction:insightful",
+    }
+}
+
+// Every like, reaction and comment of a post, oldest first
+fn engagement(post_id: u64, salt: &[u8]) -> Vec<EngagementEvent> {
+    let mut events: Vec<EngagementEvent> = likes::post_likes(post_id)
+        .into_iter()
+        .map(|(principal, at)| EngagementEvent {
+            participant: participant(salt, &principal),
+            timestamp: at,
+            event: "like".to_string(),
+        })
+        .collect();
+    events.extend(
+        reactions::post_reactions(post_id)
+            .into_iter()
+            .map(|(kind, principal, at)| EngagementEvent {
+                participant: participant(salt, &principal),
+                timestamp: at,
+                event: reaction_name(kind).to_string(),
+            }),
+    );
+    events.extend(
+        comments::post_comments(post_id)
+            .into_iter()
+            .map(|comment| EngagementEvent {
+                participant: participant(salt, &comment.author),
+                timestamp: comment.created_at,
+                event: "comment".to_string(),
+            }),
+    );
+    events.sort_by_key(|event| event.timestamp);
+    events
+}
+
+fn to_csv(events: &[EngagementEvent]) -> String {
+    let mut csv = String::from("participant,timestamp,event\n");
+    for event in events {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            event.participant, event.timestamp, event.event
+        ));
+    }
+    csv
+}
+
+// Query function for admins and the post's author to export anonymized
+// engagement data of a post, one chunk at a time
+#[ic_cdk::query]
+fn export_engagement(
+    post_id: u64,
+    format: ExportFormat,
+    chunk_index: u64,
+) -> Result<EngagementChunk, Error> {
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) && !auth::is_admin() {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Unauthorized to export engagement of post with id={}.",
+                post_id
+            ),
+        });
+    }
+    let salt = SALT.with(|salt| salt.borrow().get().clone());
+    if salt.is_empty() {
+        return Err(Error::NotFound {
+            msg: "Engagement exports are not available yet; try again shortly.".to_string(),
+        });
+    }
+    let events = engagement(post_id, &salt);
+    let total_chunks = events.len().div_ceil(EVENTS_PER_CHUNK).max(1) as u64;
+    if chunk_index >= total_chunks {
+        return Err(Error::NotFound {
+            msg: format!(
+                "Chunk {} not found; the export has {} chunks",
+                chunk_index, total_chunks
+            ),
+        });
+    }
+    let chunk: &[EngagementEvent] = events
+        .chunks(EVENTS_PER_CHUNK)
+        .nth(chunk_index as usize)
+        .unwrap_or_default();
+    let data = match format {
+        ExportFormat::Csv => to_csv(chunk),
+        ExportFormat::Json => serde_json::to_string(chunk).expect("Cannot encode engagement"),
+    };
+    Ok(EngagementChunk {
+        data,
+        chunk_index,
+        total_chunks,
+    })
+}
@@ -0,0 +1,344 @@
+use crate::keys;
+use crate::memory::{self, Memory};
+use crate::{_check_if_owner, _get_blog_post, auth, authors, linking, BlogPost, Error, PostStatus};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most reviewers an author may delegate to at once
+const MAX_REVIEWERS: usize = 20;
+// Longest editorial note, in characters
+const MAX_NOTE_LEN: usize = 5_000;
+// Most drafts `get_drafts_for_review` returns
+const MAX_REVIEW_DRAFTS: usize = 100;
+
+// A reviewer an author delegated to, as listed by `get_my_reviewers`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Reviewer {
+    reviewer: Principal,
+    // in nanoseconds since the epoch
+    until: u64,
+}
+
+// Editorial comment on a draft, seen only by its author and reviewers
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct ReviewNote {
+    id: u64,
+    post_id: u64,
+    reviewer: Principal,
+    content: String,
+    created_at: u64,
+}
+
+impl Storable for ReviewNote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the delegations map: the author, prefixed with its length, followed
+// by the reviewer, so every author's reviewers are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DelegationKey {
+    author: Vec<u8>,
+    reviewer: Vec<u8>,
+}
+
+impl Storable for DelegationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::new();
+        keys::put_principal(&mut bytes, &self.author);
+        bytes.extend_from_slice(&self.reviewer);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (author, reviewer) = keys::take_principal(&bytes);
+        DelegationKey {
+            author,
+            reviewer: reviewer.to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: keys::PREFIXED_PRINCIPAL_SIZE + 29,
+        is_fixed_size: false,
+    };
+}
+
+impl DelegationKey {
+    fn new(author: &Principal, reviewer: &Principal) -> Self {
+        DelegationKey {
+            author: author.as_slice().to_vec(),
+            reviewer: reviewer.as_slice().to_vec(),
+        }
+    }
+}
+
+// Key of the notes map: the post id followed by the note id, so the notes of
+// a draft are one contiguous range in the order they were left
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct NoteKey {
+    post_id: u64,
+    note_id: u64,
+}
+
+impl Storable for NoteKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.note_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        NoteKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            note_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    // (author, reviewer) -> when the review rights end
+    static DELEGATIONS: RefCell<StableBTreeMap<DelegationKey, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REVIEW_DELEGATIONS_MEMORY)));
+
+    static NOTE_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::REVIEW_NOTE_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a review note counter")
+    );
+
+    static NOTES: RefCell<StableBTreeMap<NoteKey, ReviewNote, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::REVIEW_NOTES_MEMORY)));
+}
+
+// Every delegation of an author, including the ones that ran out
+fn delegations_of(author: &Principal) -> Vec<(DelegationKey, u64)> {
+    let start = DelegationKey {
+        author: author.as_slice().to_vec(),
+        reviewer: Vec::new(),
+    };
+    DELEGATIONS.with(|delegations| {
+        delegations
+            .borrow()
+            .range(start..)
+            .take_while(|(key, _)| key.author == author.as_slice())
+            .collect()
+    })
+}
+
+fn reviewers_of(author: &Principal) -> Vec<Reviewer> {
+    let now = time();
+    delegations_of(author)
+        .into_iter()
+        .filter(|(_, until)| *until > now)
+        .map(|(key, until)| Reviewer {
+            reviewer: Principal::from_slice(&key.reviewer),
+            until,
+        })
+        .collect()
+}
+
+fn is_reviewer_of(author: &Principal, reviewer: &Principal) -> bool {
+    let key = DelegationKey::new(author, &linking::primary(reviewer));
+    DELEGATIONS
+        .with(|delegations| delegations.borrow().get(&key))
+        .is_some_and(|until| until > time())
+}
+
+// Whether the caller may review a post: it is a draft and its author
+// delegated review rights to the caller that haven't run out
+pub(crate) fn can_review(blog_post: &BlogPost) -> bool {
+    blog_post.status == PostStatus::Draft
+        && Principal::from_text(&blog_post.author)
+            .is_ok_and(|author| is_reviewer_of(&author, &caller()))
+}
+
+// Drop the editorial notes of a deleted post
+pub(crate) fn remove_post_notes(post_id: u64) {
+    let start = NoteKey {
+        post_id,
+        note_id: 0,
+    };
+    let end = NoteKey {
+        post_id,
+        note_id: u64::MAX,
+    };
+    NOTES.with(|notes| {
+        let mut notes = notes.borrow_mut();
+        let keys: Vec<NoteKey> = notes.range(start..=end).map(|(key, _)| key).collect();
+        for key in keys {
+            notes.remove(&key);
+        }
+    });
+}
+
+// Load a draft the caller may read the notes of, as its author or reviewer
+fn reviewable_draft(post_id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    match _get_blog_post(&post_id) {
+        Some(blog_post) if _check_if_owner(&blog_post) || can_review(&blog_post) => Ok(blog_post),
+        _ => Err(Error::NotFound {
+            msg: format!("Draft with ID {} not found", post_id),
+        }),
+    }
+}
+
+// Update function for authors to let `reviewer` read their drafts and leave
+// editorial notes on them until `until`, in nanoseconds since the epoch.
+// Reviewers can't edit the drafts. Delegating again changes the end.
+#[ic_cdk::update]
+fn delegate_review(reviewer: Principal, until: u64) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let author = linking::primary(&caller());
+    let reviewer = linking::primary(&reviewer);
+    if reviewer == author || reviewer == Principal::anonymous() {
+        return Err(Error::ValidationErrors {
+            errors: "Reviewers must be someone else, signed in".to_string(),
+        });
+    }
+    let now = time();
+    if until <= now {
+        return Err(Error::ValidationErrors {
+            errors: "Review rights must end in the future".to_string(),
+        });
+    }
+    // delegations that ran out are dropped here, the next time the author
+    // delegates
+    for (key, until) in delegations_of(&author) {
+        if until <= now {
+            DELEGATIONS.with(|delegations| delegations.borrow_mut().remove(&key));
+        }
+    }
+    if !is_reviewer_of(&author, &reviewer) && reviewers_of(&author).len() >= MAX_REVIEWERS {
+        return Err(Error::ValidationErrors {
+            errors: format!("Authors can have at most {} reviewers", MAX_REVIEWERS),
+        });
+    }
+    DELEGATIONS.with(|delegations| {
+        delegations
+            .borrow_mut()
+            .insert(DelegationKey::new(&author, &reviewer), until)
+    });
+    Ok(())
+}
+
+// Update function for authors to take review rights back before they end
+#[ic_cdk::update]
+fn revoke_review(reviewer: Principal) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = DelegationKey::new(&linking::primary(&caller()), &linking::primary(&reviewer));
+    match DELEGATIONS.with(|delegations| delegations.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not a reviewer", reviewer),
+        }),
+    }
+}
+
+// Query function for authors to list the reviewers whose rights haven't run
+// out
+#[ic_cdk::query]
+fn get_my_reviewers() -> Result<Vec<Reviewer>, Error> {
+    auth::require_signed_in()?;
+    Ok(reviewers_of(&linking::primary(&caller())))
+}
+
+// Query function for reviewers to list the drafts of an author that
+// delegated review rights to them, newest first
+#[ic_cdk::query]
+fn get_drafts_for_review(author: Principal) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    let author = linking::primary(&author);
+    if !is_reviewer_of(&author, &caller()) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Not a reviewer of {}", author),
+        });
+    }
+    Ok(authors::newest_post_ids(&author, usize::MAX)
+        .into_iter()
+        .filter_map(|id| _get_blog_post(&id))
+        .filter(|blog_post| blog_post.status == PostStatus::Draft)
+        .take(MAX_REVIEW_DRAFTS)
+        .collect())
+}
+
+// Update function for reviewers to leave an editorial note on a draft
+#[ic_cdk::update]
+fn add_review_note(post_id: u64, content: String) -> Result<ReviewNote, Error> {
+    auth::require_signed_in()?;
+    match _get_blog_post(&post_id) {
+        Some(blog_post) if can_review(&blog_post) => {}
+        _ => {
+            return Err(Error::NotAuthorized {
+                msg: format!("Unauthorized to review post with id={}.", post_id),
+            })
+        }
+    }
+    let len = content.trim().chars().count();
+    if len == 0 || len > MAX_NOTE_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!("Notes are 1 to {} characters", MAX_NOTE_LEN),
+        });
+    }
+    let id = NOTE_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the review note counter");
+        id
+    });
+    let note = ReviewNote {
+        id,
+        post_id,
+        reviewer: linking::primary(&caller()),
+        content,
+        created_at: time(),
+    };
+    NOTES.with(|notes| {
+        notes.borrow_mut().insert(
+            NoteKey {
+                post_id,
+                note_id: id,
+            },
+            note.clone(),
+        )
+    });
+    Ok(note)
+}
+
+// Query function for a draft's author and reviewers to read its editorial
+// notes, oldest first
+#[ic_cdk::query]
+fn get_review_notes(post_id: u64) -> Result<Vec<ReviewNote>, Error> {
+    reviewable_draft(post_id)?;
+    let start = NoteKey {
+        post_id,
+        note_id: 0,
+    };
+    let end = NoteKey {
+        post_id,
+        note_id: u64::MAX,
+    };
+    Ok(NOTES.with(|notes| {
+        notes
+            .borrow()
+            .range(start..=end)
+            .map(|(_, note)| note)
+            .collect()
+    }))
+}
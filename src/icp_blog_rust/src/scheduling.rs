@@ -0,0 +1,451 @@
+use crate::listing::Pagination;
+use crate::memory::{self, Memory};
+use crate::{
+    _check_if_owner, _get_blog_post, activity, asset_sync, auth, authors, backup, config,
+    do_insert, follows, linking, mentions, webhooks, BlogPost, Error, PostStatus,
+};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::thread::LocalKey;
+use std::{borrow::Cow, cell::RefCell, time::Duration};
+
+// Longest early-access period, in hours
+const MAX_EMBARGO_HOURS: u64 = 30 * 24;
+// Most principals an early-access list may name
+const MAX_EMBARGO_PRINCIPALS: usize = 500;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Largest page `get_archived_posts` returns
+const MAX_ARCHIVED_PAGE: u64 = 50;
+
+// Who may read an embargoed post before it becomes public
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) enum EmbargoAudience {
+    Principals(Vec<Principal>),
+    Followers,
+}
+
+impl Storable for EmbargoAudience {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of a schedule: the time the post is due followed by its id, so the
+// next post due is the first entry
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduleKey {
+    at: u64,
+    post_id: u64,
+}
+
+impl Storable for ScheduleKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.at.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ScheduleKey {
+            at: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+type Schedule = RefCell<StableBTreeMap<ScheduleKey, (), Memory>>;
+
+thread_local! {
+    static SCHEDULE: Schedule =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SCHEDULE_MEMORY)));
+
+    // Published posts due to go back to draft
+    static UNPUBLISH_SCHEDULE: Schedule =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::UNPUBLISH_SCHEDULE_MEMORY)));
+
+    // Posts due to expire and be archived
+    static EXPIRY_SCHEDULE: Schedule =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::EXPIRY_SCHEDULE_MEMORY)));
+
+    // Early-access audience of every embargoed post
+    static EMBARGOES: RefCell<StableBTreeMap<u64, EmbargoAudience, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::EMBARGOES_MEMORY)));
+
+    // The timer armed for the next post due, and when it fires
+    static TIMER: RefCell<Option<(u64, TimerId)>> = const { RefCell::new(None) };
+}
+
+fn first_due(schedule: &'static LocalKey<Schedule>) -> Option<u64> {
+    schedule.with(|schedule| schedule.borrow().iter().next().map(|(key, _)| key.at))
+}
+
+fn take_due(schedule: &'static LocalKey<Schedule>, now: u64) -> Vec<ScheduleKey> {
+    schedule.with(|schedule| {
+        let mut schedule = schedule.borrow_mut();
+        let due: Vec<ScheduleKey> = schedule
+            .iter()
+            .take_while(|(key, _)| key.at <= now)
+            .map(|(key, _)| key)
+            .collect();
+        for key in &due {
+            schedule.remove(key);
+        }
+        due
+    })
+}
+
+// Arm a timer for the earliest scheduled publication, unpublication or
+// expiry, replacing a later one, or for the end of a freeze it falls into.
+// Timers don't survive upgrades, so this also runs from `post_upgrade`.
+pub(crate) fn arm() {
+    let Some(next) = [
+        first_due(&SCHEDULE),
+        first_due(&UNPUBLISH_SCHEDULE),
+        first_due(&EXPIRY_SCHEDULE),
+    ]
+    .into_iter()
+    .flatten()
+    .min() else {
+        return;
+    };
+    let next = config::frozen_until(&ic_cdk::id(), next).unwrap_or(next);
+    TIMER.with(|timer| {
+        let mut timer = timer.borrow_mut();
+        if let Some((at, id)) = *timer {
+            if at <= next {
+                return;
+            }
+            ic_cdk_timers::clear_timer(id);
+        }
+        let delay = Duration::from_nanos(next.saturating_sub(time()));
+        *timer = Some((next, ic_cdk_timers::set_timer(delay, run_due)));
+    });
+}
+
+// Whether posts are due to be published, unpublished or archived with no
+// timer armed to do it
+pub(crate) fn is_stalled() -> bool {
+    let pending = first_due(&SCHEDULE).is_some()
+        || first_due(&UNPUBLISH_SCHEDULE).is_some()
+        || first_due(&EXPIRY_SCHEDULE).is_some();
+    pending && TIMER.with(|timer| timer.borrow().is_none())
+}
+
+// Publish, then unpublish, then archive every post whose time has come
+fn run_due() {
+    TIMER.with(|timer| *timer.borrow_mut() = None);
+    if backup::is_restoring() {
+        return;
+    }
+    let now = time();
+    if config::frozen_until(&ic_cdk::id(), now).is_some() {
+        // the freeze started after the timer was armed; wait for its end
+        arm();
+        return;
+    }
+    for key in take_due(&SCHEDULE, now) {
+        if let Some(mut blog_post) = _get_blog_post(&key.post_id) {
+            // a moderator may have hidden the post in the meantime
+            let pending = matches!(blog_post.status, PostStatus::Draft | PostStatus::Embargoed);
+            if pending && blog_post.publish_at == Some(key.at) {
+                EMBARGOES.with(|embargoes| embargoes.borrow_mut().remove(&blog_post.id));
+                blog_post.status = PostStatus::Published;
+                blog_post.publish_at = None;
+                do_insert(&blog_post);
+                asset_sync::publish(&blog_post);
+                activity::log_published(&blog_post);
+                webhooks::notify_published(&blog_post);
+                mentions::notify_post(None, &blog_post);
+            }
+        }
+    }
+    for key in take_due(&UNPUBLISH_SCHEDULE, now) {
+        if let Some(mut blog_post) = _get_blog_post(&key.post_id) {
+            if blog_post.unpublish_at != Some(key.at) {
+                continue;
+            }
+            blog_post.unpublish_at = None;
+            // posts hidden or never published in the meantime keep their status
+            if blog_post.status == PostStatus::Published {
+                blog_post.status = PostStatus::Draft;
+                asset_sync::unpublish(&blog_post);
+            }
+            do_insert(&blog_post);
+        }
+    }
+    for key in take_due(&EXPIRY_SCHEDULE, now) {
+        if let Some(mut blog_post) = _get_blog_post(&key.post_id) {
+            // posts hidden or unpublished in the meantime keep their status
+            if blog_post.expires_at == Some(key.at) && blog_post.status == PostStatus::Published {
+                blog_post.status = PostStatus::Archived;
+                asset_sync::unpublish(&blog_post);
+                do_insert(&blog_post);
+            }
+        }
+    }
+    arm();
+}
+
+// Drop the pending publication and early-access audience of a post, if any
+pub(crate) fn unschedule(blog_post: &BlogPost) {
+    EMBARGOES.with(|embargoes| embargoes.borrow_mut().remove(&blog_post.id));
+    if let Some(at) = blog_post.publish_at {
+        let key = ScheduleKey {
+            at,
+            post_id: blog_post.id,
+        };
+        SCHEDULE.with(|schedule| schedule.borrow_mut().remove(&key));
+    }
+}
+
+// Drop the pending unpublication of a post, if any
+pub(crate) fn unschedule_unpublish(blog_post: &BlogPost) {
+    if let Some(at) = blog_post.unpublish_at {
+        let key = ScheduleKey {
+            at,
+            post_id: blog_post.id,
+        };
+        UNPUBLISH_SCHEDULE.with(|schedule| schedule.borrow_mut().remove(&key));
+    }
+}
+
+// Drop the pending expiry of a post, if any
+pub(crate) fn unschedule_expiry(blog_post: &BlogPost) {
+    if let Some(at) = blog_post.expires_at {
+        let key = ScheduleKey {
+            at,
+            post_id: blog_post.id,
+        };
+        EXPIRY_SCHEDULE.with(|schedule| schedule.borrow_mut().remove(&key));
+    }
+}
+
+// Load a post the caller may schedule
+fn owned_post(id: u64) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let Some(blog_post) = _get_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to schedule post with id={}.", id),
+        });
+    }
+    Ok(blog_post)
+}
+
+// Load a draft the caller may schedule
+fn owned_draft(id: u64) -> Result<BlogPost, Error> {
+    let blog_post = owned_post(id)?;
+    if blog_post.status != PostStatus::Draft {
+        return Err(Error::ValidationErrors {
+            errors: format!("Blog post with ID {} is not a draft", id),
+        });
+    }
+    Ok(blog_post)
+}
+
+// Whether the caller is in the early-access audience of an embargoed post
+pub(crate) fn in_embargo_audience(blog_post: &BlogPost) -> bool {
+    let principal = caller();
+    match EMBARGOES.with(|embargoes| embargoes.borrow().get(&blog_post.id)) {
+        Some(EmbargoAudience::Principals(principals)) => principals.contains(&principal),
+        Some(EmbargoAudience::Followers) => Principal::from_text(&blog_post.author)
+            .is_ok_and(|author| follows::is_following(&principal, &author)),
+        None => false,
+    }
+}
+
+// Update function for the author to publish a draft at a given time, in
+// nanoseconds since the epoch. A time in the past publishes right away.
+#[ic_cdk::update]
+fn schedule_publish(id: u64, publish_at: u64) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_draft(id)?;
+    unschedule(&blog_post);
+    blog_post.publish_at = Some(publish_at);
+    do_insert(&blog_post);
+    add_to_schedule(id, publish_at);
+    Ok(blog_post)
+}
+
+// Update function for the author to release a draft to an early-access
+// audience first; it becomes public for everyone after `hours`
+#[ic_cdk::update]
+fn publish_with_embargo(id: u64, audience: EmbargoAudience, hours: u64) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_draft(id)?;
+    if hours == 0 || hours > MAX_EMBARGO_HOURS {
+        return Err(Error::ValidationErrors {
+            errors: format!("Embargoes last between 1 and {} hours", MAX_EMBARGO_HOURS),
+        });
+    }
+    if let EmbargoAudience::Principals(principals) = &audience {
+        if principals.is_empty() || principals.len() > MAX_EMBARGO_PRINCIPALS {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Early-access lists name between 1 and {} principals",
+                    MAX_EMBARGO_PRINCIPALS
+                ),
+            });
+        }
+    }
+    unschedule(&blog_post);
+    let publish_at = time() + hours * NANOS_PER_HOUR;
+    blog_post.status = PostStatus::Embargoed;
+    blog_post.publish_at = Some(publish_at);
+    do_insert(&blog_post);
+    EMBARGOES.with(|embargoes| embargoes.borrow_mut().insert(id, audience));
+    add_to_schedule(id, publish_at);
+    Ok(blog_post)
+}
+
+fn add_to_schedule(id: u64, publish_at: u64) {
+    SCHEDULE.with(|schedule| {
+        schedule.borrow_mut().insert(
+            ScheduleKey {
+                at: publish_at,
+                post_id: id,
+            },
+            (),
+        )
+    });
+    arm();
+}
+
+// Update function for the author to keep a scheduled draft unpublished
+#[ic_cdk::update]
+fn cancel_scheduled_publish(id: u64) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_draft(id)?;
+    if blog_post.publish_at.is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} is not scheduled", id),
+        });
+    }
+    unschedule(&blog_post);
+    blog_post.publish_at = None;
+    do_insert(&blog_post);
+    Ok(blog_post)
+}
+
+// Update function for the author to take a post down at a given time, in
+// nanoseconds since the epoch, e.g. when a promotion ends. The post goes back
+// to draft rather than being deleted. Scheduled drafts may be given a time
+// after their publication.
+#[ic_cdk::update]
+fn schedule_unpublish(id: u64, unpublish_at: u64) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_post(id)?;
+    let publish_at = match blog_post.status {
+        PostStatus::Published => None,
+        PostStatus::Draft | PostStatus::Embargoed if blog_post.publish_at.is_some() => {
+            blog_post.publish_at
+        }
+        _ => {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Blog post with ID {} is neither published nor scheduled",
+                    id
+                ),
+            })
+        }
+    };
+    if publish_at.is_some_and(|publish_at| unpublish_at <= publish_at) {
+        return Err(Error::ValidationErrors {
+            errors: "Posts can only be unpublished after they are published".to_string(),
+        });
+    }
+    unschedule_unpublish(&blog_post);
+    blog_post.unpublish_at = Some(unpublish_at);
+    do_insert(&blog_post);
+    UNPUBLISH_SCHEDULE.with(|schedule| {
+        schedule.borrow_mut().insert(
+            ScheduleKey {
+                at: unpublish_at,
+                post_id: id,
+            },
+            (),
+        )
+    });
+    arm();
+    Ok(blog_post)
+}
+
+// Update function for the author to keep a post up past its scheduled
+// unpublication
+#[ic_cdk::update]
+fn cancel_scheduled_unpublish(id: u64) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_post(id)?;
+    if blog_post.unpublish_at.is_none() {
+        return Err(Error::NotFound {
+            msg: format!(
+                "Blog post with ID {} is not scheduled to be unpublished",
+                id
+            ),
+        });
+    }
+    unschedule_unpublish(&blog_post);
+    blog_post.unpublish_at = None;
+    do_insert(&blog_post);
+    Ok(blog_post)
+}
+
+// Update function for the author to have a published post expire at a given
+// time, in nanoseconds since the epoch: it is then archived, left out of
+// listings and only shown to its author. Setting a later time before then
+// renews the post; `null` keeps it up for good.
+#[ic_cdk::update]
+fn set_post_expiry(id: u64, expires_at: Option<u64>) -> Result<BlogPost, Error> {
+    let mut blog_post = owned_post(id)?;
+    if blog_post.status != PostStatus::Published {
+        return Err(Error::ValidationErrors {
+            errors: format!("Blog post with ID {} is not published", id),
+        });
+    }
+    if expires_at.is_some_and(|expires_at| expires_at <= time()) {
+        return Err(Error::ValidationErrors {
+            errors: "Posts can only be set to expire in the future".to_string(),
+        });
+    }
+    unschedule_expiry(&blog_post);
+    blog_post.expires_at = expires_at;
+    do_insert(&blog_post);
+    if let Some(at) = expires_at {
+        EXPIRY_SCHEDULE.with(|schedule| {
+            schedule
+                .borrow_mut()
+                .insert(ScheduleKey { at, post_id: id }, ())
+        });
+        arm();
+    }
+    Ok(blog_post)
+}
+
+// Query function for authors to list their expired posts, newest first
+#[ic_cdk::query]
+fn get_archived_posts(page: Pagination) -> Result<Vec<BlogPost>, Error> {
+    auth::require_signed_in()?;
+    Ok(
+        authors::newest_post_ids(&linking::primary(&caller()), usize::MAX)
+            .into_iter()
+            .filter_map(|id| _get_blog_post(&id))
+            .filter(|blog_post| blog_post.status == PostStatus::Archived)
+            .skip(page.offset as usize)
+            .take(page.limit.min(MAX_ARCHIVED_PAGE) as usize)
+            .collect(),
+    )
+}
@@ -0,0 +1,154 @@
+use crate::memory::{self, Memory};
+use crate::{_check_if_owner, _get_blog_post, auth, linking, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest accepted series title, in bytes
+const MAX_SERIES_TITLE_LEN: usize = 200;
+// Most posts a series can hold
+const MAX_SERIES_POSTS: usize = 100;
+
+// An ordered collection of posts by one author, such as a tutorial
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Series {
+    pub(crate) id: u64,
+    pub(crate) title: String,
+    pub(crate) author: Principal,
+    // in reading order; posts deleted since are skipped by readers
+    pub(crate) post_ids: Vec<u64>,
+    pub(crate) created_at: u64,
+    pub(crate) updated_at: Option<u64>,
+}
+
+impl Storable for Series {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static SERIES_ID_COUNTER: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::SERIES_ID_COUNTER_MEMORY), 0)
+            .expect("Cannot create a series counter")
+    );
+
+    static SERIES: RefCell<StableBTreeMap<u64, Series, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SERIES_MEMORY)));
+}
+
+pub(crate) fn get(series_id: u64) -> Option<Series> {
+    SERIES.with(|series| series.borrow().get(&series_id))
+}
+
+fn not_found(series_id: u64) -> Error {
+    Error::NotFound {
+        msg: format!("Series with ID {} not found", series_id),
+    }
+}
+
+// Load a series the caller may change
+fn owned(series_id: u64) -> Result<Series, Error> {
+    auth::require_signed_in()?;
+    let series = get(series_id).ok_or_else(|| not_found(series_id))?;
+    if !linking::same_person(&series.author, &caller()) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to change series with id={}.", series_id),
+        });
+    }
+    Ok(series)
+}
+
+fn save(mut series: Series) -> Series {
+    series.updated_at = Some(time());
+    SERIES.with(|all| all.borrow_mut().insert(series.id, series.clone()));
+    series
+}
+
+// Update function to start a new, empty series
+#[ic_cdk::update]
+fn create_series(title: String) -> Result<Series, Error> {
+    auth::require_signed_in()?;
+    if title.is_empty() || title.len() > MAX_SERIES_TITLE_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Series title must be between 1 and {} bytes long",
+                MAX_SERIES_TITLE_LEN
+            ),
+        });
+    }
+    let id = SERIES_ID_COUNTER.with(|counter| {
+        let id = *counter.borrow().get();
+        counter
+            .borrow_mut()
+            .set(id + 1)
+            .expect("Cannot increment the series counter");
+        id
+    });
+    let series = Series {
+        id,
+        title,
+        author: linking::primary(&caller()),
+        post_ids: Vec::new(),
+        created_at: time(),
+        updated_at: None,
+    };
+    SERIES.with(|all| all.borrow_mut().insert(id, series.clone()));
+    Ok(series)
+}
+
+// Update function for the author to append one of their posts to a series
+#[ic_cdk::update]
+fn add_post_to_series(series_id: u64, post_id: u64) -> Result<Series, Error> {
+    let mut series = owned(series_id)?;
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to add post with id={} to a series.", post_id),
+        });
+    }
+    if series.post_ids.contains(&post_id) {
+        return Err(Error::ValidationErrors {
+            errors: format!("Post {} is already part of series {}", post_id, series_id),
+        });
+    }
+    if series.post_ids.len() >= MAX_SERIES_POSTS {
+        return Err(Error::ValidationErrors {
+            errors: format!("A series holds at most {} posts", MAX_SERIES_POSTS),
+        });
+    }
+    series.post_ids.push(post_id);
+    Ok(save(series))
+}
+
+// Update function for the author to take a post out of a series
+#[ic_cdk::update]
+fn remove_post_from_series(series_id: u64, post_id: u64) -> Result<Series, Error> {
+    let mut series = owned(series_id)?;
+    let before = series.post_ids.len();
+    series.post_ids.retain(|id| *id != post_id);
+    if series.post_ids.len() == before {
+        return Err(Error::NotFound {
+            msg: format!("Post {} is not part of series {}", post_id, series_id),
+        });
+    }
+    Ok(save(series))
+}
+
+// Query function to get a series by ID
+#[ic_cdk::query]
+fn get_series(series_id: u64) -> Result<Series, Error> {
+    get(series_id).ok_or_else(|| not_found(series_id))
+}
@@ -0,0 +1,219 @@
+use crate::keys::{principal_key, PrincipalIdKey};
+use crate::memory::{self, Memory};
+use crate::{auth, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Longest accepted device label and frontend origin, in bytes
+const MAX_DEVICE_LABEL_LEN: usize = 64;
+const MAX_ORIGIN_LEN: usize = 256;
+// Writes kept per principal; older ones are dropped
+const MAX_ACTIVITY: usize = 200;
+// Most principals the destructive action guard may trust
+const MAX_TRUSTED_PRINCIPALS: usize = 1000;
+
+// What a frontend says about the session it calls from. It is not verified,
+// but a write without it, or with an unfamiliar one, stands out.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionMetadata {
+    device_label: Option<String>,
+    frontend_origin: Option<String>,
+}
+
+impl Storable for SessionMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum WriteAction {
+    CreatePost,
+    UpdatePost,
+    DeletePost,
+    AddComment,
+    DeleteComment,
+}
+
+// A write made by a principal and the session it declared at the time
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionActivity {
+    action: WriteAction,
+    target_id: u64,
+    at: u64,
+    session: Option<SessionMetadata>,
+}
+
+impl Storable for SessionActivity {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Key of the activity map: a principal and the time of a write, so the
+// writes of a principal are one contiguous range in time order
+type ActivityKey = PrincipalIdKey;
+
+// Policy for destructive actions such as deleting, transferring ownership or
+// redirecting payouts. When enabled, they are only accepted from admins and
+// from the trusted principals. Internet Identity derives a different principal
+// for every frontend origin, so listing the principals authors get through the
+// blog's own frontend keeps a delegation obtained through another dapp from
+// acting for them. The declared session origin is not used: callers set it
+// themselves.
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct DestructiveActionGuard {
+    enabled: bool,
+    trusted_principals: Vec<Principal>,
+}
+
+impl Storable for DestructiveActionGuard {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static GUARD: RefCell<Cell<DestructiveActionGuard, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::DESTRUCTIVE_ACTION_GUARD_MEMORY), DestructiveActionGuard::default())
+            .expect("Cannot create the destructive action guard cell")
+    );
+
+    // The session each principal last declared
+    static SESSIONS: RefCell<StableBTreeMap<Blob<29>, SessionMetadata, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SESSIONS_MEMORY)));
+
+    static ACTIVITY: RefCell<StableBTreeMap<ActivityKey, SessionActivity, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SESSION_ACTIVITY_MEMORY)));
+}
+
+// Note a write by the caller along with the session it declared
+pub(crate) fn record(action: WriteAction, target_id: u64) {
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return;
+    }
+    let session = SESSIONS.with(|sessions| sessions.borrow().get(&principal_key(&principal)));
+    let at = time();
+    ACTIVITY.with(|activity| {
+        let mut activity = activity.borrow_mut();
+        activity.insert(
+            ActivityKey::new(&principal, at),
+            SessionActivity {
+                action,
+                target_id,
+                at,
+                session,
+            },
+        );
+        let keys: Vec<ActivityKey> = activity
+            .range(ActivityKey::all_of(&principal))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys.iter().take(keys.len().saturating_sub(MAX_ACTIVITY)) {
+            activity.remove(key);
+        }
+    });
+}
+
+// Guard for destructive actions such as deleting a post
+pub(crate) fn require_trusted_origin() -> Result<(), Error> {
+    let guard = GUARD.with(|cell| cell.borrow().get().clone());
+    if !guard.enabled || auth::is_admin() || guard.trusted_principals.contains(&caller()) {
+        return Ok(());
+    }
+    Err(Error::UntrustedOrigin {
+        msg: format!(
+            "Destructive actions are not allowed from {}. Sign in through the blog's own frontend.",
+            caller()
+        ),
+    })
+}
+
+// Update function for a frontend to declare the session the caller's next
+// writes come from
+#[ic_cdk::update]
+fn set_session_metadata(metadata: SessionMetadata) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let too_long =
+        |value: &Option<String>, max: usize| value.as_ref().is_some_and(|v| v.len() > max);
+    if too_long(&metadata.device_label, MAX_DEVICE_LABEL_LEN)
+        || too_long(&metadata.frontend_origin, MAX_ORIGIN_LEN)
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Device labels are limited to {} bytes and origins to {} bytes",
+                MAX_DEVICE_LABEL_LEN, MAX_ORIGIN_LEN
+            ),
+        });
+    }
+    SESSIONS.with(|sessions| {
+        sessions
+            .borrow_mut()
+            .insert(principal_key(&caller()), metadata)
+    });
+    Ok(())
+}
+
+// Query function for the caller to review their recent writes and the
+// sessions they came from, newest first
+#[ic_cdk::query]
+fn get_my_sessions_activity() -> Result<Vec<SessionActivity>, Error> {
+    auth::require_signed_in()?;
+    let principal = caller();
+    Ok(ACTIVITY.with(|activity| {
+        activity
+            .borrow()
+            .range(ActivityKey::all_of(&principal))
+            .rev()
+            .map(|(_, entry)| entry)
+            .collect()
+    }))
+}
+
+// Query function to get the destructive action policy
+#[ic_cdk::query]
+fn get_destructive_action_guard() -> DestructiveActionGuard {
+    GUARD.with(|cell| cell.borrow().get().clone())
+}
+
+// Update function for admins to change the destructive action policy
+#[ic_cdk::update]
+fn set_destructive_action_guard(guard: DestructiveActionGuard) -> Result<(), Error> {
+    auth::require_admin()?;
+    if guard.trusted_principals.len() > MAX_TRUSTED_PRINCIPALS {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "At most {} trusted principals are allowed",
+                MAX_TRUSTED_PRINCIPALS
+            ),
+        });
+    }
+    GUARD.with(|cell| {
+        cell.borrow_mut()
+            .set(guard)
+            .expect("Cannot write the destructive action guard")
+    });
+    Ok(())
+}
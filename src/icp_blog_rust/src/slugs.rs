@@ -0,0 +1,141 @@
+use crate::memory::{self, Memory};
+use crate::{_get_visible_blog_post, config, public_ids, BlogPost, Error};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// Longest slug generated from a title, in bytes
+const MAX_SLUG_LEN: usize = 80;
+
+thread_local! {
+    // slug -> post id, used to serve posts under readable URLs
+    static SLUGS: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SLUGS_MEMORY)));
+}
+
+// URL-friendly form of a title: lowercase ASCII letters and digits separated
+// by single dashes
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() >= MAX_SLUG_LEN {
+                break;
+            }
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "post".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+// Reserve a unique slug for a new post. The slug never changes afterwards so
+// links keep working when the title is edited. When another post holds the
+// slug of the title, the post's label is appended, then a counter.
+pub(crate) fn assign_slug(post_id: u64, title: &str) -> String {
+    let base = slugify(title);
+    let labelled = format!("{}-{}", base, public_ids::label(post_id));
+    let candidates = std::iter::once(base)
+        .chain(std::iter::once(labelled.clone()))
+        .chain((2..).map(|n| format!("{}-{}", labelled, n)));
+    SLUGS.with(|slugs| {
+        let mut slugs = slugs.borrow_mut();
+        let slug = candidates
+            .into_iter()
+            .find(|slug| slugs.get(slug).is_none_or(|owner| owner == post_id))
+            .expect("Some numbered slug is always free");
+        slugs.insert(slug.clone(), post_id);
+        slug
+    })
+}
+
+// Free the slug of a deleted post
+pub(crate) fn release_slug(slug: &str) {
+    SLUGS.with(|slugs| slugs.borrow_mut().remove(&slug.to_string()));
+}
+
+pub(crate) fn lookup(slug: &str) -> Option<u64> {
+    SLUGS.with(|slugs| slugs.borrow().get(&slug.to_string()))
+}
+
+// Slugs that point at a post that is gone or has another slug, released when
+// `repair` is set
+pub(crate) fn stale_entries(
+    is_current: impl Fn(u64, &str) -> bool,
+    repair: bool,
+) -> Vec<(String, u64)> {
+    let stale: Vec<(String, u64)> = SLUGS.with(|slugs| {
+        slugs
+            .borrow()
+            .iter()
+            .filter(|(slug, post_id)| !is_current(*post_id, slug))
+            .collect()
+    });
+    if repair {
+        for (slug, _) in &stale {
+            release_slug(slug);
+        }
+    }
+    stale
+}
+
+// Point a slug back at its post, unless another post holds it. Returns
+// whether the slug now points at the post.
+pub(crate) fn restore_slug(slug: &str, post_id: u64) -> bool {
+    SLUGS.with(|slugs| {
+        let mut slugs = slugs.borrow_mut();
+        match slugs.get(&slug.to_string()) {
+            Some(owner) => owner == post_id,
+            None => {
+                slugs.insert(slug.to_string(), post_id);
+                true
+            }
+        }
+    })
+}
+
+// Query function to get a blog post by its slug
+#[ic_cdk::query]
+fn get_blog_post_by_slug(slug: String) -> Result<BlogPost, Error> {
+    config::require_reader()?;
+    match lookup(&slug).and_then(|id| _get_visible_blog_post(&id)) {
+        Some(blog_post) => Ok(blog_post),
+        None => Err(Error::NotFound {
+            msg: format!("Blog post with slug {} not found", slug),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_joins_lowercase_words_with_dashes() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Rust -- on the IC  "), "rust-on-the-ic");
+    }
+
+    #[test]
+    fn slugify_drops_non_ascii_characters() {
+        assert_eq!(slugify("Café über alles"), "caf-ber-alles");
+    }
+
+    #[test]
+    fn slugify_falls_back_for_titles_without_letters() {
+        assert_eq!(slugify("!!!"), "post");
+        assert_eq!(slugify("日本語"), "post");
+    }
+
+    #[test]
+    fn slugify_caps_the_length() {
+        let slug = slugify(&"word ".repeat(50));
+        assert!(slug.len() <= MAX_SLUG_LEN + 1);
+        assert!(!slug.ends_with('-'));
+    }
+}
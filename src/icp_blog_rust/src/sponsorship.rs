@@ -0,0 +1,141 @@
+use crate::audit::{self, AuditAction};
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::render::escape;
+use crate::{
+    _check_if_owner, _get_blog_post, asset_sync, auth, do_insert, BlogPost, Error, PostStatus,
+};
+use candid::Principal;
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// Longest accepted sponsor name and link, in bytes
+const MAX_SPONSOR_LEN: usize = 100;
+const MAX_URL_LEN: usize = 500;
+
+// Who paid for a post. Every page and feed item of a sponsored post discloses
+// it; authors can't leave the disclosure out.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct SponsorInfo {
+    sponsor: String,
+    url: Option<String>,
+}
+
+thread_local! {
+    // Principals who don't want sponsored posts in their listings. Only
+    // readers who opted out have an entry.
+    static OPTED_OUT: RefCell<StableBTreeMap<Blob<29>, bool, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SPONSORED_OPT_OUT_MEMORY)));
+}
+
+fn validate(sponsor: &SponsorInfo) -> Result<(), Error> {
+    if sponsor.sponsor.trim().is_empty() || sponsor.sponsor.len() > MAX_SPONSOR_LEN {
+        return Err(Error::ValidationErrors {
+            errors: format!("Sponsors need a name of up to {} bytes", MAX_SPONSOR_LEN),
+        });
+    }
+    if let Some(url) = &sponsor.url {
+        let web = url.starts_with("https://") || url.starts_with("http://");
+        if !web || url.len() > MAX_URL_LEN {
+            return Err(Error::ValidationErrors {
+                errors: format!(
+                    "Sponsor links must be http(s) URLs of up to {} bytes",
+                    MAX_URL_LEN
+                ),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Whether the caller wants to see a post in listings. Posts opened directly
+// are always shown, with their disclosure.
+pub(crate) fn listed_for_caller(blog_post: &BlogPost) -> bool {
+    listed_for(blog_post, &caller())
+}
+
+pub(crate) fn listed_for(blog_post: &BlogPost, principal: &Principal) -> bool {
+    blog_post.sponsored.is_none() || !opted_out(principal)
+}
+
+fn opted_out(principal: &Principal) -> bool {
+    OPTED_OUT
+        .with(|opted_out| opted_out.borrow().get(&principal_key(principal)))
+        .unwrap_or(false)
+}
+
+// Disclosure of a sponsored post as HTML, empty if it isn't sponsored
+pub(crate) fn to_html(sponsored: &Option<SponsorInfo>) -> String {
+    let Some(sponsor) = sponsored else {
+        return String::new();
+    };
+    let name = match &sponsor.url {
+        Some(url) => format!(
+            r#"<a href="{}" rel="sponsored nofollow">{}</a>"#,
+            escape(url),
+            escape(&sponsor.sponsor)
+        ),
+        None => escape(&sponsor.sponsor),
+    };
+    format!(r#"<p class="sponsored">Sponsored by {}</p>"#, name)
+}
+
+// Disclosure of a sponsored post as plain text, for feeds
+pub(crate) fn to_text(sponsored: &Option<SponsorInfo>) -> Option<String> {
+    sponsored
+        .as_ref()
+        .map(|sponsor| format!("Sponsored by {}", sponsor.sponsor))
+}
+
+// Update function for the author to mark a post as sponsored, or to clear
+// the sponsor with `null`
+#[ic_cdk::update]
+fn set_post_sponsor(id: u64, sponsored: Option<SponsorInfo>) -> Result<BlogPost, Error> {
+    auth::require_signed_in()?;
+    let Some(mut blog_post) = _get_blog_post(&id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to change sponsor of post with id={}.", id),
+        });
+    }
+    if let Some(sponsor) = &sponsored {
+        validate(sponsor)?;
+    }
+    blog_post.sponsored = sponsored;
+    do_insert(&blog_post);
+    audit::record(AuditAction::UpdatePost, id);
+    if blog_post.status == PostStatus::Published {
+        asset_sync::publish(&blog_post);
+    }
+    Ok(blog_post)
+}
+
+// Query function to know whether sponsored posts are left out of the
+// caller's listings
+#[ic_cdk::query]
+fn get_hide_sponsored() -> bool {
+    opted_out(&caller())
+}
+
+// Update function for readers to leave sponsored posts out of the timeline,
+// their following feed and trending posts, or to bring them back
+#[ic_cdk::update]
+fn set_hide_sponsored(hide: bool) -> Result<(), Error> {
+    auth::require_signed_in()?;
+    let key = principal_key(&caller());
+    OPTED_OUT.with(|opted_out| {
+        let mut opted_out = opted_out.borrow_mut();
+        if hide {
+            opted_out.insert(key, true);
+        } else {
+            opted_out.remove(&key);
+        }
+    });
+    Ok(())
+}
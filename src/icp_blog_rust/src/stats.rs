@@ -0,0 +1,99 @@
+use crate::{_count_blog_posts, authors, comments, date, likes, timeline, views};
+use ic_cdk::api::stable::stable64_size;
+use ic_cdk::api::{canister_balance128, time};
+use std::collections::BTreeMap;
+
+pub(crate) const WASM_PAGE_SIZE: u64 = 64 * 1024;
+// Days `get_stats` counts new posts for, today included
+const STATS_DAYS: u64 = 90;
+// Days `get_site_stats` counts as this week, today included
+const WEEK_DAYS: u64 = 7;
+
+// Posts created on one day, days counted since the Unix epoch
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct DailyPosts {
+    day: u64,
+    posts: u64,
+}
+
+// Size and health of the canister. Post counts include drafts and hidden
+// posts.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Stats {
+    total_posts: u64,
+    total_comments: u64,
+    total_likes: u64,
+    unique_authors: u64,
+    // in bytes
+    stable_memory_size: u64,
+    heap_size: u64,
+    cycle_balance: u128,
+    // oldest day first; days without new posts are left out
+    daily_posts: Vec<DailyPosts>,
+}
+
+// Totals of the whole site for an about page or an external dashboard. Post
+// counts include drafts and hidden posts.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct SiteStats {
+    total_posts: u64,
+    total_authors: u64,
+    total_comments: u64,
+    total_likes: u64,
+    total_views: u64,
+    posts_this_week: u64,
+}
+
+pub(crate) fn heap_size() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_SIZE
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+fn daily_posts(days: u64) -> Vec<DailyPosts> {
+    let since = (date::day_number(time()) + 1).saturating_sub(days) * date::NANOS_PER_DAY;
+    let mut daily: BTreeMap<u64, u64> = BTreeMap::new();
+    for created_at in timeline::created_since(since) {
+        *daily.entry(date::day_number(created_at)).or_insert(0) += 1;
+    }
+    daily
+        .into_iter()
+        .map(|(day, posts)| DailyPosts { day, posts })
+        .collect()
+}
+
+// Query function for operators and dashboards to monitor the canister, with
+// the posts created on each of the last 90 days
+#[ic_cdk::query]
+fn get_stats() -> Stats {
+    Stats {
+        total_posts: _count_blog_posts(),
+        total_comments: comments::total(),
+        total_likes: likes::count(),
+        unique_authors: authors::count(),
+        stable_memory_size: stable64_size() * WASM_PAGE_SIZE,
+        heap_size: heap_size(),
+        cycle_balance: canister_balance128(),
+        daily_posts: daily_posts(STATS_DAYS),
+    }
+}
+
+// Query function for an about page or external dashboards to show how big
+// the site is. The totals are kept as they change; only this week's posts
+// are counted by the call.
+#[ic_cdk::query]
+fn get_site_stats() -> SiteStats {
+    SiteStats {
+        total_posts: _count_blog_posts(),
+        total_authors: authors::count(),
+        total_comments: comments::total(),
+        total_likes: likes::count(),
+        total_views: views::total(),
+        posts_this_week: daily_posts(WEEK_DAYS).iter().map(|daily| daily.posts).sum(),
+    }
+}
@@ -0,0 +1,50 @@
+// Fixtures the unit tests of several modules share
+use crate::visibility::Reader;
+use crate::{authors, categories, do_insert, timeline, BlogPost};
+use candid::Principal;
+
+pub(crate) fn principal(byte: u8) -> Principal {
+    Principal::from_slice(&[byte])
+}
+
+// A reader who is no moderator, at `now`
+pub(crate) fn reader(principal: Principal, now: u64) -> Reader {
+    Reader {
+        principal,
+        now,
+        moderator: false,
+    }
+}
+
+// Store a published public post and index it like `create_blog_post` does
+pub(crate) fn insert_post(
+    id: u64,
+    author: Principal,
+    created_at: u64,
+    categories: &[&str],
+) -> BlogPost {
+    let blog_post = BlogPost {
+        id,
+        title: format!("Post {}", id),
+        content: format!("The content of post {}", id),
+        author: author.to_text(),
+        created_at,
+        categories: categories
+            .iter()
+            .map(|category| category.to_string())
+            .collect(),
+        slug: format!("post-{}", id),
+        revision: 1,
+        comments_enabled: true,
+        ..BlogPost::default()
+    };
+    do_insert(&blog_post);
+    categories::index_post(blog_post.id, &blog_post.categories);
+    authors::index_post(blog_post.id, &blog_post.author);
+    timeline::index_post(&blog_post);
+    blog_post
+}
+
+pub(crate) fn ids(posts: &[BlogPost]) -> Vec<u64> {
+    posts.iter().map(|blog_post| blog_post.id).collect()
+}
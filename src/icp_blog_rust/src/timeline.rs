@@ -0,0 +1,357 @@
+use crate::memory::{self, Memory};
+use crate::visibility::{self, Reader};
+use crate::{_get_blog_post, config, date, BlogPost, Error};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Largest page `list_blog_posts` returns
+const MAX_TIMELINE_PAGE: u64 = 50;
+// Most posts `get_on_this_day` returns
+const MAX_ON_THIS_DAY: usize = 50;
+
+type RankIndex = StableBTreeMap<RankedPost, (), Memory>;
+
+// Orders `list_blog_posts` can return posts in. Each has its own index, so
+// no listing has to load and sort every post.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize)]
+pub(crate) enum SortBy {
+    Newest,
+    MostLiked,
+    MostViewed,
+    TopScore,
+}
+
+const SORTS: [SortBy; 4] = [
+    SortBy::Newest,
+    SortBy::MostLiked,
+    SortBy::MostViewed,
+    SortBy::TopScore,
+];
+
+// Key of the sort indexes: the big-endian rank (creation time, likes, views
+// or score) followed by the post id, so iterating an index walks the posts in
+// ascending rank
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct RankedPost {
+    rank: u64,
+    post_id: u64,
+}
+
+impl Storable for RankedPost {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.rank.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RankedPost {
+            rank: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+thread_local! {
+    static POSTS_BY_DATE: RefCell<RankIndex> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POSTS_BY_DATE_MEMORY)));
+
+    static POSTS_BY_LIKES: RefCell<RankIndex> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POSTS_BY_LIKES_MEMORY)));
+
+    static POSTS_BY_VIEWS: RefCell<RankIndex> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POSTS_BY_VIEWS_MEMORY)));
+
+    static POSTS_BY_SCORE: RefCell<RankIndex> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POSTS_BY_SCORE_MEMORY)));
+}
+
+fn with_index<R>(sort: SortBy, f: impl FnOnce(&RefCell<RankIndex>) -> R) -> R {
+    match sort {
+        SortBy::Newest => POSTS_BY_DATE.with(f),
+        SortBy::MostLiked => POSTS_BY_LIKES.with(f),
+        SortBy::MostViewed => POSTS_BY_VIEWS.with(f),
+        SortBy::TopScore => POSTS_BY_SCORE.with(f),
+    }
+}
+
+// Rank of a score; flipping the sign bit keeps negative scores below positive
+// ones
+pub(crate) fn score_rank(score: i64) -> u64 {
+    (score as u64) ^ (1 << 63)
+}
+
+fn rank(sort: SortBy, blog_post: &BlogPost) -> u64 {
+    match sort {
+        SortBy::Newest => blog_post.created_at,
+        SortBy::MostLiked => blog_post.likes as u64,
+        SortBy::MostViewed => blog_post.views,
+        SortBy::TopScore => score_rank(blog_post.score),
+    }
+}
+
+// Add a post to one sort index
+pub(crate) fn insert(sort: SortBy, post_id: u64, rank: u64) {
+    with_index(sort, |index| {
+        index.borrow_mut().insert(RankedPost { rank, post_id }, ())
+    });
+}
+
+fn remove(sort: SortBy, post_id: u64, rank: u64) {
+    with_index(sort, |index| {
+        index.borrow_mut().remove(&RankedPost { rank, post_id })
+    });
+}
+
+// Add a new post to every sort index
+pub(crate) fn index_post(blog_post: &BlogPost) {
+    for sort in SORTS {
+        insert(sort, blog_post.id, rank(sort, blog_post));
+    }
+}
+
+// Remove a deleted post from every sort index
+pub(crate) fn unindex_post(blog_post: &BlogPost) {
+    for sort in SORTS {
+        remove(sort, blog_post.id, rank(sort, blog_post));
+    }
+}
+
+// Whether a post is in every sort index, at its current rank
+pub(crate) fn is_indexed(blog_post: &BlogPost) -> bool {
+    SORTS.into_iter().all(|sort| {
+        let key = RankedPost {
+            rank: rank(sort, blog_post),
+            post_id: blog_post.id,
+        };
+        with_index(sort, |index| index.borrow().contains_key(&key))
+    })
+}
+
+// Rank of a post in each sort index, in the order of `SORTS`
+pub(crate) fn ranks(blog_post: &BlogPost) -> [u64; 4] {
+    SORTS.map(|sort| rank(sort, blog_post))
+}
+
+// Post IDs of the sort index entries whose post is gone or has another rank
+// by now, removed when `repair` is set. `ranks` gives the current ranks of a
+// post as `ranks` above does, or None for posts that are gone.
+pub(crate) fn stale_entries(ranks: impl Fn(u64) -> Option<[u64; 4]>, repair: bool) -> Vec<u64> {
+    let mut stale_ids = Vec::new();
+    for (i, sort) in SORTS.into_iter().enumerate() {
+        let stale: Vec<RankedPost> = with_index(sort, |index| {
+            index
+                .borrow()
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|key| ranks(key.post_id).map(|ranks| ranks[i]) != Some(key.rank))
+                .collect()
+        });
+        if repair {
+            for key in &stale {
+                remove(sort, key.post_id, key.rank);
+            }
+        }
+        stale_ids.extend(stale.into_iter().map(|key| key.post_id));
+    }
+    stale_ids
+}
+
+// Number of posts in the sort indexes
+pub(crate) fn count() -> u64 {
+    POSTS_BY_DATE.with(|index| index.borrow().len())
+}
+
+// Creation times of the posts created since `since`, oldest first
+pub(crate) fn created_since(since: u64) -> Vec<u64> {
+    let start = RankedPost {
+        rank: since,
+        post_id: 0,
+    };
+    POSTS_BY_DATE.with(|index| {
+        index
+            .borrow()
+            .range(start..)
+            .map(|(key, _)| key.rank)
+            .collect()
+    })
+}
+
+// IDs of the posts created between `from` and `to`, both included, newest
+// first
+pub(crate) fn newest_created_between(from: u64, to: u64) -> Vec<u64> {
+    let start = RankedPost {
+        rank: from,
+        post_id: 0,
+    };
+    let end = RankedPost {
+        rank: to,
+        post_id: u64::MAX,
+    };
+    POSTS_BY_DATE.with(|index| {
+        index
+            .borrow()
+            .range(start..=end)
+            .rev()
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// IDs of the posts that were never viewed, oldest first
+pub(crate) fn never_viewed() -> Vec<u64> {
+    let end = RankedPost {
+        rank: 1,
+        post_id: 0,
+    };
+    POSTS_BY_VIEWS.with(|index| {
+        index
+            .borrow()
+            .range(..end)
+            .map(|(key, _)| key.post_id)
+            .collect()
+    })
+}
+
+// Creation time of the oldest post
+fn oldest_created_at() -> Option<u64> {
+    POSTS_BY_DATE.with(|index| index.borrow().first_key_value().map(|(key, _)| key.rank))
+}
+
+// Move a post within a sort index after its likes, views or score changed
+pub(crate) fn rerank(sort: SortBy, post_id: u64, old: u64, new: u64) {
+    remove(sort, post_id, old);
+    insert(sort, post_id, new);
+}
+
+// Query function to page through published posts in the given order,
+// highest rank first. `cursor` is the ID of the last post of the previous
+// page; leave it out for the first. Likes and views keep changing, so pages
+// of those orders are only consistent with the rank the cursor post has now.
+#[ic_cdk::query]
+pub(crate) fn list_blog_posts(
+    sort_by: SortBy,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    let end = match cursor {
+        Some(id) => match _get_blog_post(&id) {
+            Some(blog_post) => RankedPost {
+                rank: rank(sort_by, &blog_post),
+                post_id: id,
+            },
+            None => {
+                return Err(Error::NotFound {
+                    msg: format!("Blog post with ID {} not found", id),
+                })
+            }
+        },
+        None => RankedPost {
+            rank: u64::MAX,
+            post_id: u64::MAX,
+        },
+    };
+    let limit = limit.min(MAX_TIMELINE_PAGE) as usize;
+    Ok(listed_before(sort_by, end, limit, &Reader::current()))
+}
+
+// The posts listed for `reader` that rank below `end` in the given order,
+// highest rank first
+fn listed_before(sort_by: SortBy, end: RankedPost, limit: usize, reader: &Reader) -> Vec<BlogPost> {
+    with_index(sort_by, |index| {
+        index
+            .borrow()
+            .range(..end)
+            .rev()
+            .filter_map(|(key, _)| _get_blog_post(&key.post_id))
+            .filter(|blog_post| visibility::is_listed_for(blog_post, reader))
+            .take(limit)
+            .collect()
+    })
+}
+
+// Query function to page through published posts, newest first
+#[ic_cdk::query]
+fn list_blog_posts_by_date(cursor: Option<u64>, limit: u64) -> Result<Vec<BlogPost>, Error> {
+    list_blog_posts(SortBy::Newest, cursor, limit)
+}
+
+// Query function for a nostalgia widget: published posts created on today's
+// calendar date (UTC) in past years, the most recent year first. Posts from
+// February 29 only come back in leap years.
+#[ic_cdk::query]
+fn get_on_this_day() -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    Ok(on_this_day(&Reader::current()))
+}
+
+// The posts listed for `reader` created on the calendar date of `reader.now`
+// in past years, the most recent year first
+fn on_this_day(reader: &Reader) -> Vec<BlogPost> {
+    let Some(oldest) = oldest_created_at() else {
+        return Vec::new();
+    };
+    let (first_year, _, _) = date::civil_from_days(date::day_number(oldest));
+    let (this_year, month, day) = date::civil_from_days(date::day_number(reader.now));
+    let mut posts = Vec::new();
+    for year in (first_year..this_year).rev() {
+        let days = date::days_from_civil(year, month, day);
+        if date::civil_from_days(days) != (year, month, day) {
+            continue;
+        }
+        let start = days * date::NANOS_PER_DAY;
+        let ids = newest_created_between(start, start + date::NANOS_PER_DAY - 1);
+        posts.extend(
+            ids.into_iter()
+                .filter_map(|id| _get_blog_post(&id))
+                .filter(|blog_post| visibility::is_listed_for(blog_post, reader)),
+        );
+        if posts.len() >= MAX_ON_THIS_DAY {
+            break;
+        }
+    }
+    posts.truncate(MAX_ON_THIS_DAY);
+    posts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paywall;
+    use crate::testing::{ids, insert_post, principal, reader};
+
+    #[test]
+    fn list_blog_posts_leaves_out_paywalled_posts() {
+        let author = principal(1);
+        for id in 1..=3 {
+            insert_post(id, author, id * date::NANOS_PER_DAY, &[]);
+        }
+        paywall::set_price(2, 100);
+        let end = RankedPost {
+            rank: u64::MAX,
+            post_id: u64::MAX,
+        };
+        let page = listed_before(SortBy::Newest, end, 10, &reader(principal(2), 0));
+        assert_eq!(ids(&page), vec![3, 1]);
+        // their author can read them
+        let page = listed_before(SortBy::Newest, end, 10, &reader(author, 0));
+        assert_eq!(ids(&page), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn on_this_day_leaves_out_paywalled_posts() {
+        let march_5 = |year| date::days_from_civil(year, 3, 5) * date::NANOS_PER_DAY;
+        insert_post(1, principal(1), march_5(2022), &[]);
+        insert_post(2, principal(1), march_5(2023), &[]);
+        paywall::set_price(2, 100);
+        let posts = on_this_day(&reader(principal(2), march_5(2024)));
+        assert_eq!(ids(&posts), vec![1]);
+    }
+}
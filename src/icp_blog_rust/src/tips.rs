@@ -0,0 +1,473 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::notifications::{self, NotificationKind};
+use crate::{
+    _check_if_owner, _get_blog_post, _get_visible_blog_post, auth, credits, linking, rate_limits,
+    sessions, Error, PostStatus,
+};
+use candid::{Decode, Encode, Nat, Principal};
+use ic_cdk::api::{caller, id, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use serde_bytes::ByteBuf;
+use std::{borrow::Cow, cell::RefCell};
+
+// Most recipients a post's tips may be split between
+const MAX_SPLITS: usize = 10;
+
+// The ICRC-1 ledger tips are paid on; `None` while tipping is turned off
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredLedger(Option<Principal>);
+
+impl Storable for StoredLedger {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Share of the tips on a post paid to one of its authors
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct PayoutSplit {
+    recipient: Principal,
+    percent: u8,
+}
+
+// The splits of a post, in the order they were set
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct PayoutSplits(Vec<PayoutSplit>);
+
+impl Storable for PayoutSplits {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static LEDGER: RefCell<Cell<StoredLedger, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::TIP_LEDGER_MEMORY), StoredLedger::default())
+            .expect("Cannot create the tip ledger cell")
+    );
+
+    // Total tipped per post and per author, in the ledger's base units
+    static POST_TIPS: RefCell<StableBTreeMap<u64, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::POST_TIPS_MEMORY)));
+
+    static AUTHOR_TIPS: RefCell<StableBTreeMap<Blob<29>, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::AUTHOR_TIPS_MEMORY)));
+
+    // Post -> how its tips are split; posts without splits pay their author
+    static SPLITS: RefCell<StableBTreeMap<u64, PayoutSplits, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::PAYOUT_SPLITS_MEMORY)));
+}
+
+// An ICRC-1 account
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Account {
+    owner: Principal,
+    subaccount: Option<ByteBuf>,
+}
+
+// Argument of the ledger's `icrc1_transfer` method
+#[derive(candid::CandidType)]
+struct TransferArg {
+    from_subaccount: Option<ByteBuf>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(candid::CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+pub(crate) fn ledger() -> Option<Principal> {
+    LEDGER.with(|cell| cell.borrow().get().0)
+}
+
+fn splits(post_id: u64) -> Vec<PayoutSplit> {
+    SPLITS.with(|splits| splits.borrow().get(&post_id).unwrap_or_default().0)
+}
+
+// Drop the payout splits of a deleted post
+pub(crate) fn remove_post_splits(post_id: u64) {
+    SPLITS.with(|splits| splits.borrow_mut().remove(&post_id));
+}
+
+// How a tip on a post is shared out: by its splits, rounded down, with what
+// rounding leaves going to the first recipient, or all to the author of a
+// post without splits. Empty shares are left out.
+pub(crate) fn shares(post_id: u64, author: Principal, amount: u64) -> Vec<(Principal, u64)> {
+    let splits = splits(post_id);
+    if splits.is_empty() {
+        return vec![(author, amount)];
+    }
+    let mut shares: Vec<(Principal, u64)> = splits
+        .iter()
+        .map(|split| {
+            let share = amount as u128 * split.percent as u128 / 100;
+            (split.recipient, share as u64)
+        })
+        .collect();
+    let rest = amount - shares.iter().map(|(_, share)| share).sum::<u64>();
+    shares[0].1 += rest;
+    shares.retain(|(_, share)| *share > 0);
+    shares
+}
+
+// Count a share that reached its recipient
+fn record_tip(post_id: u64, recipient: &Principal, amount: u64) {
+    POST_TIPS.with(|tips| {
+        let mut tips = tips.borrow_mut();
+        let total = tips.get(&post_id).unwrap_or(0).saturating_add(amount);
+        tips.insert(post_id, total);
+    });
+    AUTHOR_TIPS.with(|tips| {
+        let mut tips = tips.borrow_mut();
+        let total = tips
+            .get(&principal_key(recipient))
+            .unwrap_or(0)
+            .saturating_add(amount);
+        tips.insert(principal_key(recipient), total);
+    });
+    notifications::notify(recipient, NotificationKind::Tipped { post_id, amount });
+}
+
+// Subaccount of this canister a reader deposits tips into: the principal's
+// length followed by its bytes, zero padded
+fn deposit_subaccount(principal: &Principal) -> ByteBuf {
+    let mut subaccount = vec![0; 32];
+    let bytes = principal.as_slice();
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    ByteBuf::from(subaccount)
+}
+
+// Query function for the account the caller deposits tips into before
+// calling `tip_post`, and payments before buying or gifting access to a post
+#[ic_cdk::query]
+fn get_tip_deposit_account() -> Result<Account, Error> {
+    auth::require_signed_in()?;
+    Ok(Account {
+        owner: id(),
+        subaccount: Some(deposit_subaccount(&caller())),
+    })
+}
+
+// Update function to tip the author of a post from the caller's deposit. The
+// ledger moves the tokens straight to the author's default account, and only
+// a completed transfer is counted. Tips on a post with payout splits are
+// shared between its recipients, one transfer each, so each share is charged
+// the ledger fee; if a transfer fails, the shares already paid stay paid.
+#[ic_cdk::update]
+async fn tip_post(id: u64, amount: u64) -> Result<u64, Error> {
+    auth::require_signed_in()?;
+    let Some(ledger) = ledger() else {
+        return Err(Error::NotFound {
+            msg: "Tipping is not enabled".to_string(),
+        });
+    };
+    let blog_post = match _get_visible_blog_post(&id) {
+        Some(blog_post) if blog_post.status == PostStatus::Published => blog_post,
+        _ => {
+            return Err(Error::NotFound {
+                msg: format!("Blog post with ID {} not found. Cannot tip.", id),
+            })
+        }
+    };
+    let Ok(author) = Principal::from_text(&blog_post.author) else {
+        return Err(Error::NotFound {
+            msg: format!("Author of blog post with ID {} not found", id),
+        });
+    };
+    let tipper = caller();
+    let shares = shares(id, author, amount);
+    if amount == 0 || shares.iter().any(|(recipient, _)| *recipient == tipper) {
+        return Err(Error::ValidationErrors {
+            errors: "Tips must be positive and can't go to oneself".to_string(),
+        });
+    }
+    rate_limits::check(rate_limits::Action::Interact)?;
+
+    pay(ledger, &tipper, id, shares, "tip", |recipient, share| {
+        record_tip(id, recipient, share)
+    })
+    .await?;
+    Ok(get_post_tips(id).unwrap_or(amount))
+}
+
+// Check that `payer`'s deposit covers all the shares of a payment for a post
+// and the ledger fee charged on each, before any of them is paid
+pub(crate) async fn check_funds(
+    ledger: Principal,
+    payer: &Principal,
+    shares: &[(Principal, u64)],
+) -> Result<(), Error> {
+    let deposit = Account {
+        owner: id(),
+        subaccount: Some(deposit_subaccount(payer)),
+    };
+    let unreachable =
+        |(code, msg): (ic_cdk::api::call::RejectionCode, String)| Error::TransferFailed {
+            msg: format!("Cannot reach the ledger: {:?} {}", code, msg),
+        };
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(unreachable)?;
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (deposit,))
+        .await
+        .map_err(unreachable)?;
+    let amount: u64 = shares.iter().map(|(_, share)| share).sum();
+    let needed = Nat::from(amount) + fee * Nat::from(shares.len());
+    if balance < needed {
+        return Err(Error::TransferFailed {
+            msg: format!(
+                "The deposit holds {} but {} is needed, fees included",
+                balance, needed
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Pay shares of a payment for a post from `payer`'s deposit, one transfer
+// each, calling `paid` for every share that reached its recipient. Stops at
+// the first failed transfer; the shares already paid stay paid.
+pub(crate) async fn pay(
+    ledger: Principal,
+    payer: &Principal,
+    post_id: u64,
+    shares: Vec<(Principal, u64)>,
+    what: &str,
+    mut paid: impl FnMut(&Principal, u64),
+) -> Result<(), Error> {
+    let amount: u64 = shares.iter().map(|(_, share)| share).sum();
+    let mut total = 0;
+    for (recipient, share) in shares {
+        let arg = TransferArg {
+            from_subaccount: Some(deposit_subaccount(payer)),
+            to: Account {
+                owner: recipient,
+                subaccount: None,
+            },
+            amount: Nat::from(share),
+            fee: None,
+            memo: Some(ByteBuf::from(post_id.to_be_bytes().to_vec())),
+            created_at_time: Some(time()),
+        };
+        let result: Result<(Result<Nat, TransferError>,), _> =
+            ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await;
+        let failure = match result {
+            Ok((Ok(_block_index),)) => None,
+            Ok((Err(error),)) => Some(format!("The ledger rejected the {}: {:?}", what, error)),
+            Err((code, msg)) => Some(format!("Cannot reach the ledger: {:?} {}", code, msg)),
+        };
+        if let Some(msg) = failure {
+            let msg = match total {
+                0 => msg,
+                total => format!("{}; {} of {} was paid", msg, total, amount),
+            };
+            return Err(Error::TransferFailed { msg });
+        }
+        paid(&recipient, share);
+        total += share;
+    }
+    Ok(())
+}
+
+// Query function to get the total tipped to a post
+#[ic_cdk::query]
+fn get_post_tips(id: u64) -> Result<u64, Error> {
+    if _get_blog_post(&id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", id),
+        });
+    }
+    Ok(POST_TIPS.with(|tips| tips.borrow().get(&id).unwrap_or(0)))
+}
+
+// Query function to get how the tips on a post are split, empty when they
+// all go to its author
+#[ic_cdk::query]
+fn get_payout_splits(post_id: u64) -> Result<Vec<PayoutSplit>, Error> {
+    if _get_blog_post(&post_id).is_none() {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    }
+    Ok(splits(post_id))
+}
+
+// Update function for authors to split the tips on a post between its
+// co-authors: the author and principals credited on the post, each with a
+// percentage, summing to 100. An empty list pays the author everything
+// again. Tips already paid aren't shared out again.
+#[ic_cdk::update]
+fn set_payout_splits(post_id: u64, splits: Vec<PayoutSplit>) -> Result<Vec<PayoutSplit>, Error> {
+    auth::require_signed_in()?;
+    let blog_post = match _get_blog_post(&post_id) {
+        Some(blog_post) if _check_if_owner(&blog_post) => blog_post,
+        Some(_) => {
+            return Err(Error::NotAuthorized {
+                msg: format!("Unauthorized to update post with id={}.", post_id),
+            })
+        }
+        None => {
+            return Err(Error::NotFound {
+                msg: format!("Blog post with ID {} not found", post_id),
+            })
+        }
+    };
+    sessions::require_trusted_origin()?;
+    if splits.is_empty() {
+        remove_post_splits(post_id);
+        return Ok(splits);
+    }
+    let total: u32 = splits.iter().map(|split| split.percent as u32).sum();
+    let duplicated = splits.iter().enumerate().any(|(i, split)| {
+        splits[..i]
+            .iter()
+            .any(|other| linking::same_person(&other.recipient, &split.recipient))
+    });
+    if splits.len() > MAX_SPLITS
+        || total != 100
+        || duplicated
+        || splits.iter().any(|split| split.percent == 0)
+    {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Tips can be split between at most {} recipients, each with a positive percentage, summing to 100",
+                MAX_SPLITS
+            ),
+        });
+    }
+    let is_co_author = |recipient: &Principal| {
+        Principal::from_text(&blog_post.author)
+            .is_ok_and(|author| linking::same_person(&author, recipient))
+            || credits::is_credited(&blog_post, recipient)
+    };
+    if let Some(split) = splits.iter().find(|split| !is_co_author(&split.recipient)) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "{} is not the author of, or credited on, post with id={}",
+                split.recipient, post_id
+            ),
+        });
+    }
+    SPLITS.with(|stored| {
+        stored
+            .borrow_mut()
+            .insert(post_id, PayoutSplits(splits.clone()))
+    });
+    Ok(splits)
+}
+
+// Query function to get the total tipped to an author across their posts,
+// including their shares of co-authored ones
+#[ic_cdk::query]
+fn get_author_tips(author: Principal) -> u64 {
+    AUTHOR_TIPS.with(|tips| tips.borrow().get(&principal_key(&author)).unwrap_or(0))
+}
+
+// Query function for admins to see which ledger tips are paid on
+#[ic_cdk::query]
+fn get_tip_ledger() -> Result<Option<Principal>, Error> {
+    auth::require_admin()?;
+    Ok(ledger())
+}
+
+// Update function for admins to point tipping at an ICRC-1 ledger, or turn
+// it off with `null`
+#[ic_cdk::update]
+fn set_tip_ledger(ledger: Option<Principal>) -> Result<(), Error> {
+    auth::require_admin()?;
+    LEDGER.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredLedger(ledger))
+            .expect("Cannot write the tip ledger")
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_splits(post_id: u64, splits: &[(Principal, u8)]) {
+        let splits = splits
+            .iter()
+            .map(|&(recipient, percent)| PayoutSplit { recipient, percent })
+            .collect();
+        SPLITS.with(|cell| cell.borrow_mut().insert(post_id, PayoutSplits(splits)));
+    }
+
+    fn principal(byte: u8) -> Principal {
+        Principal::from_slice(&[byte])
+    }
+
+    #[test]
+    fn shares_pay_the_author_of_a_post_without_splits() {
+        assert_eq!(shares(1, principal(9), 1_000), vec![(principal(9), 1_000)]);
+    }
+
+    #[test]
+    fn shares_give_the_rounding_rest_to_the_first_recipient() {
+        set_splits(
+            2,
+            &[(principal(1), 50), (principal(2), 30), (principal(3), 20)],
+        );
+        assert_eq!(
+            shares(2, principal(9), 101),
+            vec![(principal(1), 51), (principal(2), 30), (principal(3), 20)]
+        );
+        set_splits(
+            3,
+            &[(principal(1), 34), (principal(2), 33), (principal(3), 33)],
+        );
+        assert_eq!(
+            shares(3, principal(9), 10),
+            vec![(principal(1), 4), (principal(2), 3), (principal(3), 3)]
+        );
+    }
+
+    #[test]
+    fn shares_leave_out_empty_shares() {
+        set_splits(4, &[(principal(1), 50), (principal(2), 50)]);
+        assert_eq!(shares(4, principal(9), 1), vec![(principal(1), 1)]);
+    }
+
+    #[test]
+    fn shares_of_large_amounts_add_up() {
+        set_splits(5, &[(principal(1), 60), (principal(2), 40)]);
+        let shares = shares(5, principal(9), u64::MAX);
+        assert_eq!(
+            shares.iter().map(|(_, share)| *share as u128).sum::<u128>(),
+            u64::MAX as u128
+        );
+        assert_eq!(
+            shares[1],
+            (principal(2), (u64::MAX as u128 * 40 / 100) as u64)
+        );
+    }
+}
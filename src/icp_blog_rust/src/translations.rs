@@ -0,0 +1,243 @@
+use crate::memory::{self, Memory};
+use crate::moderation::ReportTarget;
+use crate::{
+    _analyze_content, _check_if_owner, _get_blog_post, auth, blocklist, config, get_blog_post,
+    BlogPost, BlogPostPayload, Error,
+};
+use candid::{Decode, Encode};
+use ic_cdk::api::time;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+use validator::{Validate, ValidationError};
+
+// Longest accepted language tag, in bytes
+const MAX_LANGUAGE_LEN: usize = 35;
+
+// A post's title and content in another language
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub(crate) struct Translation {
+    language: String,
+    title: String,
+    content: String,
+    updated_at: u64,
+}
+
+impl Storable for Translation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Translation {
+    pub(crate) fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub(crate) fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+// Key of the translations map: the post id followed by the language tag, so
+// all translations of a post are one contiguous range
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct TranslationKey {
+    post_id: u64,
+    language: String,
+}
+
+impl Storable for TranslationKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.post_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.language.as_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        TranslationKey {
+            post_id: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            language: String::from_utf8(bytes[8..].to_vec()).unwrap(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + MAX_LANGUAGE_LEN as u32,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    static TRANSLATIONS: RefCell<StableBTreeMap<TranslationKey, Translation, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::TRANSLATIONS_MEMORY)));
+}
+
+// Validator for BCP 47 style language tags such as "en", "pt-br" or "zh-hant"
+pub(crate) fn validate_language(language: &str) -> Result<(), ValidationError> {
+    let mut subtags = language.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let valid = language.len() <= MAX_LANGUAGE_LEN
+        && (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(ValidationError::new("invalid_language"))
+    }
+}
+
+fn post_range(post_id: u64) -> std::ops::RangeFrom<TranslationKey> {
+    TranslationKey {
+        post_id,
+        language: String::new(),
+    }..
+}
+
+pub(crate) fn get(post_id: u64, language: &str) -> Option<Translation> {
+    TRANSLATIONS.with(|translations| {
+        translations.borrow().get(&TranslationKey {
+            post_id,
+            language: language.to_ascii_lowercase(),
+        })
+    })
+}
+
+// Languages a post has been translated to
+pub(crate) fn languages(post_id: u64) -> Vec<String> {
+    TRANSLATIONS.with(|translations| {
+        translations
+            .borrow()
+            .range(post_range(post_id))
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| key.language)
+            .collect()
+    })
+}
+
+// Drop every translation of a post that is being deleted
+pub(crate) fn remove_post_translations(post_id: u64) {
+    TRANSLATIONS.with(|translations| {
+        let mut translations = translations.borrow_mut();
+        let keys: Vec<TranslationKey> = translations
+            .range(post_range(post_id))
+            .take_while(|(key, _)| key.post_id == post_id)
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            translations.remove(&key);
+        }
+    });
+}
+
+// Query function to get a blog post in `language`: its translation when it
+// has one, in the original language otherwise. The language of the post
+// returned tells which one it is.
+#[ic_cdk::query]
+fn get_blog_post_localized(id: u64, language: String) -> Result<BlogPost, Error> {
+    let mut blog_post = get_blog_post(id)?;
+    let language = language.to_ascii_lowercase();
+    if blog_post.language.as_ref() == Some(&language) {
+        return Ok(blog_post);
+    }
+    if let Some(translation) = get(id, &language) {
+        blog_post.title = translation.title;
+        blog_post.content = translation.content;
+        blog_post.language = Some(language);
+        // word counts and the like follow the text returned
+        _analyze_content(&mut blog_post);
+    }
+    Ok(blog_post)
+}
+
+// Update function for the author to add or replace a translation of a post
+#[ic_cdk::update]
+fn add_translation(
+    post_id: u64,
+    language: String,
+    title: String,
+    content: String,
+) -> Result<Translation, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to translate post with id={}.", post_id),
+        });
+    }
+    let language = language.to_ascii_lowercase();
+    // translations follow the same rules as the post itself
+    let payload = BlogPostPayload {
+        title,
+        content,
+        ..Default::default()
+    };
+    let check = validate_language(&language)
+        .map_err(|e| e.to_string())
+        .and_then(|_| payload.validate().map_err(|e| e.to_string()));
+    if let Err(errors) = check {
+        return Err(Error::ValidationErrors { errors });
+    }
+    config::check_post(Some(&payload.title), Some(&payload.content), None)?;
+    let (title, content, warned) = blocklist::screen_post(payload.title, payload.content, false)?;
+
+    let translation = Translation {
+        language: language.clone(),
+        title,
+        content,
+        updated_at: time(),
+    };
+    TRANSLATIONS.with(|translations| {
+        translations
+            .borrow_mut()
+            .insert(TranslationKey { post_id, language }, translation.clone())
+    });
+    blocklist::warn(ReportTarget::Post(post_id), &warned);
+    Ok(translation)
+}
+
+// Update function for the author to remove a translation of a post
+#[ic_cdk::update]
+fn remove_translation(post_id: u64, language: String) -> Result<Translation, Error> {
+    auth::require_signed_in()?;
+    config::require_not_frozen()?;
+    let Some(blog_post) = _get_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    if !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!(
+                "Unauthorized to remove translations of post with id={}.",
+                post_id
+            ),
+        });
+    }
+    let key = TranslationKey {
+        post_id,
+        language: language.to_ascii_lowercase(),
+    };
+    match TRANSLATIONS.with(|translations| translations.borrow_mut().remove(&key)) {
+        Some(translation) => Ok(translation),
+        None => Err(Error::NotFound {
+            msg: format!(
+                "Blog post with ID {} has no {} translation",
+                post_id, language
+            ),
+        }),
+    }
+}
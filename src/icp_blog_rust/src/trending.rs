@@ -0,0 +1,196 @@
+use crate::visibility::{self, Reader};
+use crate::{_get_blog_post, comments, config, BlogPost, Error, PostStatus, BLOG_POSTS};
+use ic_cdk::api::time;
+use std::{
+    cell::{Cell, RefCell},
+    ops::Bound,
+    time::Duration,
+};
+
+// How often the scores are recomputed
+pub(crate) const RECOMPUTE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+// Age at which a post's score has halved, in nanoseconds
+const HALF_LIFE: f64 = 24.0 * 60.0 * 60.0 * 1e9;
+// Posts kept in the ranking
+const MAX_TRENDING: usize = 100;
+// Posts scored per message, so a recomputation over every post is spread
+// over several messages that each stay well within the instruction limit
+const BATCH_SIZE: usize = 200;
+
+// How much each kind of interaction counts towards a post's score
+const LIKE_WEIGHT: f64 = 3.0;
+const COMMENT_WEIGHT: f64 = 2.0;
+const VIEW_WEIGHT: f64 = 1.0;
+
+thread_local! {
+    // IDs of the highest scoring posts, best first. Recomputed from the posts
+    // on every run, so it lives on the heap and is rebuilt after an upgrade.
+    static TRENDING: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+
+    // When the scores were last recomputed, since the last upgrade
+    static LAST_RECOMPUTED: Cell<Option<u64>> = const { Cell::new(None) };
+
+    // The recomputation under way, if any
+    static PASS: RefCell<Option<Pass>> = const { RefCell::new(None) };
+}
+
+// A recomputation of the scores, walking the posts from the newest down
+struct Pass {
+    // Time the scores are computed at
+    now: u64,
+    // ID of the last post scored, `None` before the first batch
+    last: Option<u64>,
+    // The highest scores so far, best first
+    scored: Vec<(f64, u64)>,
+}
+
+pub(crate) fn last_recomputed() -> Option<u64> {
+    LAST_RECOMPUTED.with(Cell::get)
+}
+
+// Start recomputing the scores periodically, beginning right away. Timers
+// don't survive upgrades, so this also runs from `post_upgrade`.
+pub(crate) fn start() {
+    ic_cdk_timers::set_timer(Duration::ZERO, recompute);
+    ic_cdk_timers::set_timer_interval(RECOMPUTE_INTERVAL, recompute);
+}
+
+// Interactions weighted by kind, halved for every `HALF_LIFE` the post has
+// been around
+fn score(blog_post: &BlogPost, now: u64) -> f64 {
+    let interactions = blog_post.likes as f64 * LIKE_WEIGHT
+        + comments::count(blog_post.id) as f64 * COMMENT_WEIGHT
+        + blog_post.views as f64 * VIEW_WEIGHT;
+    let age = now.saturating_sub(blog_post.created_at) as f64;
+    interactions * 0.5f64.powf(age / HALF_LIFE)
+}
+
+// Start a recomputation, unless one is under way, and score its next batch
+fn recompute() {
+    start_pass(time());
+    continue_pass();
+}
+
+fn start_pass(now: u64) {
+    PASS.with(|pass| {
+        pass.borrow_mut().get_or_insert(Pass {
+            now,
+            last: None,
+            scored: Vec::new(),
+        });
+    });
+}
+
+fn continue_pass() {
+    if !score_batch() {
+        ic_cdk_timers::set_timer(Duration::ZERO, continue_pass);
+    }
+}
+
+// Score the next `BATCH_SIZE` posts of the recomputation under way, and
+// publish the ranking once every post has been scored. Returns whether the
+// recomputation is over.
+fn score_batch() -> bool {
+    PASS.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let Some(pass) = cell.as_mut() else {
+            return true;
+        };
+        let end = pass.last.map_or(Bound::Unbounded, Bound::Excluded);
+        let batch: Vec<BlogPost> = BLOG_POSTS.with(|posts| {
+            posts
+                .borrow()
+                .range((Bound::Unbounded, end))
+                .rev()
+                .take(BATCH_SIZE)
+                .map(|(_, blog_post)| blog_post)
+                .collect()
+        });
+        pass.last = batch.last().map(|blog_post| blog_post.id).or(pass.last);
+        pass.scored.extend(
+            batch
+                .iter()
+                .filter(|blog_post| {
+                    blog_post.status == PostStatus::Published && visibility::is_listed(blog_post)
+                })
+                .map(|blog_post| (score(blog_post, pass.now), blog_post.id))
+                .filter(|(score, _)| *score > 0.0),
+        );
+        pass.scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        pass.scored.truncate(MAX_TRENDING);
+        if batch.len() == BATCH_SIZE {
+            return false;
+        }
+        let Pass { now, scored, .. } = cell.take().expect("A recomputation is under way");
+        TRENDING.with(|trending| {
+            *trending.borrow_mut() = scored.into_iter().map(|(_, id)| id).collect()
+        });
+        LAST_RECOMPUTED.with(|last| last.set(Some(now)));
+        true
+    })
+}
+
+// IDs of the highest scoring posts, best first
+pub(crate) fn ranked_post_ids() -> Vec<u64> {
+    TRENDING.with(|trending| trending.borrow().clone())
+}
+
+// Query function to get the posts that are hot right now, best first
+#[ic_cdk::query]
+fn get_trending_posts(limit: u64) -> Result<Vec<BlogPost>, Error> {
+    config::require_reader()?;
+    Ok(trending_posts(limit as usize, &Reader::current()))
+}
+
+// The highest scoring posts listed for `reader`, best first
+fn trending_posts(limit: usize, reader: &Reader) -> Vec<BlogPost> {
+    TRENDING.with(|trending| {
+        trending
+            .borrow()
+            .iter()
+            .filter_map(_get_blog_post)
+            .filter(|blog_post| visibility::is_listed_for(blog_post, reader))
+            .take(limit)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ids, insert_post, principal, reader};
+    use crate::{do_insert, paywall};
+
+    #[test]
+    fn trending_posts_leave_out_paywalled_posts() {
+        for id in 1..=3 {
+            insert_post(id, principal(1), id, &[]);
+        }
+        paywall::set_price(1, 100);
+        TRENDING.with(|trending| *trending.borrow_mut() = vec![1, 3, 2]);
+        let posts = trending_posts(10, &reader(principal(2), 0));
+        assert_eq!(ids(&posts), vec![3, 2]);
+    }
+
+    #[test]
+    fn recomputing_scores_every_post_over_several_batches() {
+        let count = 2 * BATCH_SIZE as u64 + 1;
+        for id in 1..=count {
+            let mut blog_post = insert_post(id, principal(1), 0, &[]);
+            blog_post.likes = (count - id + 1) as u32;
+            do_insert(&blog_post);
+        }
+        start_pass(0);
+        let mut batches = 1;
+        while !score_batch() {
+            batches += 1;
+        }
+        assert_eq!(batches, 3);
+        assert!(PASS.with(|pass| pass.borrow().is_none()));
+        assert_eq!(last_recomputed(), Some(0));
+
+        // the oldest posts have the most likes and are scored last
+        let best: Vec<u64> = (1..=MAX_TRENDING as u64).collect();
+        assert_eq!(ranked_post_ids(), best);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::keys::principal_key;
+use crate::memory::{self, Memory};
+use crate::{auth, linking, Error};
+use candid::Principal;
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::Blob;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+
+// Shortest and longest username, in characters
+const MIN_USERNAME_LEN: usize = 3;
+const MAX_USERNAME_LEN: usize = 30;
+
+thread_local! {
+    // lowercased username -> the principal that registered it
+    static USERNAMES: RefCell<StableBTreeMap<String, Blob<29>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::USERNAMES_MEMORY)));
+
+    // principal -> its username, as it was registered
+    static USERNAME_OF: RefCell<StableBTreeMap<Blob<29>, String, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::USERNAME_OF_MEMORY)));
+}
+
+// Linked principals share the username of their primary principal
+fn user_key(principal: &Principal) -> Blob<29> {
+    principal_key(&linking::primary(principal))
+}
+
+fn is_username(name: &str) -> bool {
+    (MIN_USERNAME_LEN..=MAX_USERNAME_LEN).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Username of a post or comment author, if they registered one
+pub(crate) fn username(principal: &Principal) -> Option<String> {
+    USERNAME_OF.with(|names| names.borrow().get(&user_key(principal)))
+}
+
+// Principal that registered a username, whatever its case
+pub(crate) fn lookup(name: &str) -> Option<Principal> {
+    USERNAMES
+        .with(|names| names.borrow().get(&name.to_ascii_lowercase()))
+        .map(|principal| Principal::from_slice(principal.as_slice()))
+}
+
+// Free the username of `principal`, if any
+pub(crate) fn release(principal: &Principal) {
+    let key = user_key(principal);
+    if let Some(name) = USERNAME_OF.with(|names| names.borrow_mut().remove(&key)) {
+        USERNAMES.with(|names| names.borrow_mut().remove(&name.to_ascii_lowercase()));
+    }
+}
+
+// Update function for users to claim a readable handle for bylines and
+// URLs, replacing the one they had. Usernames are 3 to 30 letters, digits
+// or '_', and unique ignoring case.
+#[ic_cdk::update]
+fn register_username(name: String) -> Result<String, Error> {
+    auth::require_signed_in()?;
+    if !is_username(&name) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Usernames are {} to {} letters, digits or '_'",
+                MIN_USERNAME_LEN, MAX_USERNAME_LEN
+            ),
+        });
+    }
+    let key = user_key(&caller());
+    let lowercase = name.to_ascii_lowercase();
+    let taken = USERNAMES.with(|names| names.borrow().get(&lowercase));
+    if taken.is_some_and(|owner| owner != key) {
+        return Err(Error::ValidationErrors {
+            errors: format!("The username {} is taken", name),
+        });
+    }
+    release(&caller());
+    USERNAMES.with(|names| names.borrow_mut().insert(lowercase, key));
+    USERNAME_OF.with(|names| names.borrow_mut().insert(key, name.clone()));
+    Ok(name)
+}
+
+// Query function to find who registered a username, ignoring case
+#[ic_cdk::query]
+fn resolve_username(name: String) -> Result<Principal, Error> {
+    lookup(&name).ok_or_else(|| Error::NotFound {
+        msg: format!("No user is named {}", name),
+    })
+}
+
+// Query function to get the username of a principal, if it registered one
+#[ic_cdk::query]
+fn get_username(principal: Principal) -> Option<String> {
+    username(&principal)
+}
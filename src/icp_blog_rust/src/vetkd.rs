@@ -0,0 +1,205 @@
+use crate::memory::{self, Memory};
+use crate::visibility::Visibility;
+use crate::{_check_if_owner, _get_blog_post, auth, linking, rate_limits, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::call::call_with_payment128;
+use ic_cdk::api::caller;
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, Storable};
+use serde_bytes::ByteBuf;
+use std::{borrow::Cow, cell::RefCell};
+
+// Domain separator of the keys, so they differ from any other use of the
+// canister's vetKD keys
+const CONTEXT: &[u8] = b"icp_blog_rust/private-posts";
+// Cycles attached to a key derivation, enough for the production key; the
+// management canister refunds what it doesn't charge
+const DERIVE_KEY_CYCLES: u128 = 26_153_846_153;
+
+// Name of the vetKD master key, such as "key_1" on mainnet or "dfx_test_key"
+// locally; `None` while encrypted posts are turned off
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+struct StoredKeyName(Option<String>);
+
+impl Storable for StoredKeyName {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static KEY_NAME: RefCell<Cell<StoredKeyName, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::VETKD_KEY_NAME_MEMORY), StoredKeyName::default())
+            .expect("Cannot create the vetKD key name cell")
+    );
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+enum VetKdCurve {
+    #[serde(rename = "bls12_381_g2")]
+    Bls12381G2,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VetKdKeyId {
+    curve: VetKdCurve,
+    name: String,
+}
+
+// Arguments and results of the management canister's vetKD methods
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VetKdPublicKeyArgs {
+    canister_id: Option<Principal>,
+    context: ByteBuf,
+    key_id: VetKdKeyId,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct VetKdPublicKeyResult {
+    public_key: ByteBuf,
+}
+
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct VetKdDeriveKeyArgs {
+    input: ByteBuf,
+    context: ByteBuf,
+    transport_public_key: ByteBuf,
+    key_id: VetKdKeyId,
+}
+
+#[derive(candid::CandidType, Deserialize)]
+struct VetKdDeriveKeyResult {
+    encrypted_key: ByteBuf,
+}
+
+fn key_name() -> Option<String> {
+    KEY_NAME.with(|cell| cell.borrow().get().0.clone())
+}
+
+fn key_id() -> Result<VetKdKeyId, Error> {
+    match key_name() {
+        Some(name) => Ok(VetKdKeyId {
+            curve: VetKdCurve::Bls12381G2,
+            name,
+        }),
+        None => Err(Error::NotFound {
+            msg: "Encrypted posts are not enabled".to_string(),
+        }),
+    }
+}
+
+fn vetkd_error(error: (ic_cdk::api::call::RejectionCode, String)) -> Error {
+    Error::NotFound {
+        msg: format!("Cannot reach vetKD: {:?} {}", error.0, error.1),
+    }
+}
+
+// Only private posts may be encrypted, so a post never shows ciphertext to
+// readers who can't get its key
+pub(crate) fn check(encrypted: bool, visibility: &Visibility) -> Result<(), Error> {
+    if encrypted && !matches!(visibility, Visibility::Private(_)) {
+        return Err(Error::ValidationErrors {
+            errors: "Only private posts can be encrypted".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Update function to get the public key clients encrypt private posts with.
+// A post's content is encrypted for its ID, as big-endian bytes, so clients
+// create the post first and then set its content with `encrypted` on; the
+// canister only ever stores the ciphertext.
+#[ic_cdk::update]
+async fn get_post_encryption_key() -> Result<ByteBuf, Error> {
+    let args = VetKdPublicKeyArgs {
+        canister_id: None,
+        context: ByteBuf::from(CONTEXT),
+        key_id: key_id()?,
+    };
+    let (result,): (VetKdPublicKeyResult,) = ic_cdk::call(
+        Principal::management_canister(),
+        "vetkd_public_key",
+        (args,),
+    )
+    .await
+    .map_err(vetkd_error)?;
+    Ok(result.public_key)
+}
+
+// Update function for the author of an encrypted post, and the principals it
+// is shared with, to get the key that decrypts it. The key comes encrypted
+// under `transport_public_key`, a key pair the client makes for the call, so
+// only the caller can read it. Moderators can't get keys.
+#[ic_cdk::update]
+async fn get_post_decryption_key(id: u64, transport_public_key: ByteBuf) -> Result<ByteBuf, Error> {
+    auth::require_signed_in()?;
+    let key_id = key_id()?;
+    let blog_post = match _get_blog_post(&id) {
+        Some(blog_post) if blog_post.encrypted => blog_post,
+        _ => {
+            return Err(Error::NotFound {
+                msg: format!("Encrypted post with ID {} not found", id),
+            })
+        }
+    };
+    let allowed = match &blog_post.visibility {
+        Visibility::Private(allowed) => allowed
+            .iter()
+            .any(|principal| linking::same_person(principal, &caller())),
+        _ => false,
+    };
+    if !allowed && !_check_if_owner(&blog_post) {
+        return Err(Error::NotAuthorized {
+            msg: format!("Unauthorized to decrypt post with id={}.", id),
+        });
+    }
+    // each derivation costs the canister cycles
+    rate_limits::check(rate_limits::Action::Interact)?;
+    let args = VetKdDeriveKeyArgs {
+        input: ByteBuf::from(id.to_be_bytes().to_vec()),
+        context: ByteBuf::from(CONTEXT),
+        transport_public_key,
+        key_id,
+    };
+    let (result,): (VetKdDeriveKeyResult,) = call_with_payment128(
+        Principal::management_canister(),
+        "vetkd_derive_key",
+        (args,),
+        DERIVE_KEY_CYCLES,
+    )
+    .await
+    .map_err(vetkd_error)?;
+    Ok(result.encrypted_key)
+}
+
+// Query function for admins to see which vetKD key encrypts private posts
+#[ic_cdk::query]
+fn get_vetkd_key_name() -> Result<Option<String>, Error> {
+    auth::require_admin()?;
+    Ok(key_name())
+}
+
+// Update function for admins to pick the vetKD key private posts are
+// encrypted with, or turn encryption off with `null`. Changing the key makes
+// existing encrypted posts unreadable, so it should be set once.
+#[ic_cdk::update]
+fn set_vetkd_key_name(name: Option<String>) -> Result<(), Error> {
+    auth::require_admin()?;
+    if name.as_ref().is_some_and(|name| name.is_empty()) {
+        return Err(Error::ValidationErrors {
+            errors: "Key names can't be empty".to_string(),
+        });
+    }
+    KEY_NAME.with(|cell| {
+        cell.borrow_mut()
+            .set(StoredKeyName(name))
+            .expect("Cannot write the vetKD key name")
+    });
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use crate::memory::{self, Memory};
+use crate::timeline::{self, SortBy};
+use crate::{_get_visible_blog_post, date, do_insert, referrals, Error};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most entries of past days dropped per call, so cleanup stays cheap
+const PRUNE_BATCH: usize = 100;
+
+// Key of the daily views map: the day, the post id and the principal that
+// viewed it. Days come first so the entries of past days can be dropped from
+// the front of the map.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct ViewKey {
+    day: u64,
+    post_id: u64,
+    principal: Vec<u8>,
+}
+
+impl Storable for ViewKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = self.day.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.post_id.to_be_bytes());
+        bytes.extend_from_slice(&self.principal);
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        ViewKey {
+            day: u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            post_id: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+            principal: bytes[16..].to_vec(),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8 + 8 + 29,
+        is_fixed_size: false,
+    };
+}
+
+thread_local! {
+    // Who viewed which post today; only the current day is needed
+    static DAILY_VIEWS: RefCell<StableBTreeMap<ViewKey, (), Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::DAILY_VIEWS_MEMORY)));
+
+    // Views counted on every post, deleted ones included
+    static TOTAL_VIEWS: RefCell<Cell<u64, Memory>> = RefCell::new(
+        Cell::init(memory::get(memory::TOTAL_VIEWS_MEMORY), 0)
+            .expect("Cannot create the total views counter")
+    );
+}
+
+pub(crate) fn total() -> u64 {
+    TOTAL_VIEWS.with(|total| *total.borrow().get())
+}
+
+pub(crate) fn set_total(views: u64) {
+    TOTAL_VIEWS.with(|total| {
+        total
+            .borrow_mut()
+            .set(views)
+            .expect("Cannot write the total views counter")
+    });
+}
+
+// Drop a batch of entries of days before `today`
+fn prune(today: u64) {
+    DAILY_VIEWS.with(|views| {
+        let mut views = views.borrow_mut();
+        let old: Vec<ViewKey> = views
+            .iter()
+            .take_while(|(key, _)| key.day < today)
+            .take(PRUNE_BATCH)
+            .map(|(key, _)| key)
+            .collect();
+        for key in old {
+            views.remove(&key);
+        }
+    });
+}
+
+// Update function to count a view of a post by the caller. Every principal
+// counts at most once per post per UTC day; anonymous readers share one.
+// A counted view is credited to the owner of `referral`, the `?ref=` code of
+// the link the reader came by. Returns the post's view count.
+#[ic_cdk::update]
+fn record_view(post_id: u64, referral: Option<String>) -> Result<u64, Error> {
+    let Some(mut blog_post) = _get_visible_blog_post(&post_id) else {
+        return Err(Error::NotFound {
+            msg: format!("Blog post with ID {} not found", post_id),
+        });
+    };
+    let today = date::day_number(time());
+    prune(today);
+    let key = ViewKey {
+        day: today,
+        post_id,
+        principal: caller().as_slice().to_vec(),
+    };
+    if DAILY_VIEWS.with(|views| views.borrow_mut().insert(key, ()).is_none()) {
+        blog_post.views += 1;
+        do_insert(&blog_post);
+        set_total(total() + 1);
+        timeline::rerank(
+            SortBy::MostViewed,
+            post_id,
+            blog_post.views - 1,
+            blog_post.views,
+        );
+        referrals::record_view(referral.as_deref());
+    }
+    Ok(blog_post.views)
+}
@@ -0,0 +1,82 @@
+use crate::{_check_if_owner, auth, linking, paywall, sponsorship, BlogPost, PostStatus};
+use candid::Principal;
+use ic_cdk::api::{caller, time};
+use validator::ValidationError;
+
+// Most principals a private post may be shared with
+const MAX_ALLOWED: usize = 100;
+
+// Who may find and read a post
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) enum Visibility {
+    // listed everywhere
+    #[default]
+    Public,
+    // readable by anyone with its ID or link, but left out of listings,
+    // feeds and notifications
+    Unlisted,
+    // readable only by its author, the principals listed and moderators
+    Private(Vec<Principal>),
+}
+
+pub(crate) fn validate(visibility: &Visibility) -> Result<(), ValidationError> {
+    match visibility {
+        Visibility::Private(allowed) if allowed.len() > MAX_ALLOWED => {
+            Err(ValidationError::new("too_many_allowed_principals"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Who a listing is for, taken once per call so every post of the listing is
+// checked against the same caller and time
+pub(crate) struct Reader {
+    pub(crate) principal: Principal,
+    pub(crate) now: u64,
+    pub(crate) moderator: bool,
+}
+
+impl Reader {
+    pub(crate) fn current() -> Self {
+        Reader {
+            principal: caller(),
+            now: time(),
+            moderator: auth::is_moderator(),
+        }
+    }
+}
+
+// Whether the caller may read a post as far as its visibility, and its
+// paywall, go
+pub(crate) fn can_see(blog_post: &BlogPost) -> bool {
+    let visible = match &blog_post.visibility {
+        Visibility::Public | Visibility::Unlisted => true,
+        Visibility::Private(allowed) => {
+            _check_if_owner(blog_post)
+                || allowed
+                    .iter()
+                    .any(|principal| linking::same_person(principal, &caller()))
+                || auth::is_moderator()
+        }
+    };
+    visible && paywall::can_read(blog_post)
+}
+
+// Whether a post may show up in listings, feeds and notifications
+pub(crate) fn is_listed(blog_post: &BlogPost) -> bool {
+    blog_post.visibility == Visibility::Public
+}
+
+// Whether a post shows up, with its content, in a listing for `reader`:
+// published, listed, readable past its paywall, and not a sponsored post
+// they opted out of
+pub(crate) fn is_listed_for(blog_post: &BlogPost, reader: &Reader) -> bool {
+    blog_post.status == PostStatus::Published
+        && is_listed(blog_post)
+        && paywall::can_read_as(blog_post, reader)
+        && sponsorship::listed_for(blog_post, &reader.principal)
+}
+
+pub(crate) fn is_private(blog_post: &BlogPost) -> bool {
+    matches!(blog_post.visibility, Visibility::Private(_))
+}
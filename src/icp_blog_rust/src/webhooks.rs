@@ -0,0 +1,176 @@
+use crate::memory::{self, Memory};
+use crate::{auth, visibility, BlogPost, Error};
+use candid::{Decode, Encode, Principal};
+use ic_cdk::api::{caller, time};
+use ic_stable_structures::storable::{Blob, Bound};
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::{borrow::Cow, cell::RefCell};
+
+// Most canisters that may subscribe at once
+const MAX_SUBSCRIBERS: u64 = 100;
+// Longest callback method name
+const MAX_METHOD_LEN: usize = 100;
+
+// Callback a canister registered to hear about new posts
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+struct Subscription {
+    method: String,
+    subscribed_at: u64,
+}
+
+impl Storable for Subscription {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// A subscriber as listed by `list_post_subscribers`
+#[derive(candid::CandidType, Serialize, Deserialize)]
+pub(crate) struct Subscriber {
+    canister: Principal,
+    method: String,
+    subscribed_at: u64,
+}
+
+// Argument the callback method is called with. The method must take it as
+// its only argument; its reply is never read.
+#[derive(candid::CandidType, Serialize, Deserialize)]
+struct PostPublished {
+    id: u64,
+    title: String,
+    slug: String,
+    author: String,
+    published_at: u64,
+}
+
+thread_local! {
+    // Canister -> its callback
+    static SUBSCRIBERS: RefCell<StableBTreeMap<Blob<29>, Subscription, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory::get(memory::SUBSCRIBERS_MEMORY)));
+}
+
+// Canister ids end with the opaque id tag; users' self-authenticating
+// principals end with a different one
+pub(crate) fn is_canister(principal: &Principal) -> bool {
+    principal.as_slice().last() == Some(&1)
+}
+
+pub(crate) fn is_method_name(method: &str) -> bool {
+    !method.is_empty()
+        && method.len() <= MAX_METHOD_LEN
+        && method
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Tell every subscriber that a public post was published, with one-way calls
+// sent after the current message commits. Subscribers that can't be reached
+// miss the notification; they stay subscribed.
+pub(crate) fn notify_published(blog_post: &BlogPost) {
+    if !visibility::is_listed(blog_post) {
+        return;
+    }
+    let subscribers: Vec<(Blob<29>, Subscription)> =
+        SUBSCRIBERS.with(|subscribers| subscribers.borrow().iter().collect());
+    for (canister, subscription) in subscribers {
+        let canister = Principal::from_slice(canister.as_slice());
+        let event = PostPublished {
+            id: blog_post.id,
+            title: blog_post.title.clone(),
+            slug: blog_post.slug.clone(),
+            author: blog_post.author.clone(),
+            published_at: time(),
+        };
+        if let Err(code) = ic_cdk::notify(canister, &subscription.method, (event,)) {
+            ic_cdk::println!(
+                "Cannot notify {} of post {}: {:?}",
+                canister,
+                blog_post.id,
+                code
+            );
+        }
+    }
+}
+
+// Update function for canisters to have `method` called whenever a post is
+// published, replacing the method they registered before
+#[ic_cdk::update]
+fn subscribe_to_posts(method: String) -> Result<(), Error> {
+    let canister = caller();
+    if !is_canister(&canister) {
+        return Err(Error::NotAuthorized {
+            msg: "Only canisters can subscribe to new posts".to_string(),
+        });
+    }
+    if !is_method_name(&method) {
+        return Err(Error::ValidationErrors {
+            errors: format!(
+                "Callback methods are 1 to {} letters, digits or '_'",
+                MAX_METHOD_LEN
+            ),
+        });
+    }
+    let key = Blob::try_from(canister.as_slice()).unwrap();
+    SUBSCRIBERS.with(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        if !subscribers.contains_key(&key) && subscribers.len() >= MAX_SUBSCRIBERS {
+            return Err(Error::ValidationErrors {
+                errors: format!("At most {} canisters can subscribe", MAX_SUBSCRIBERS),
+            });
+        }
+        subscribers.insert(
+            key,
+            Subscription {
+                method,
+                subscribed_at: time(),
+            },
+        );
+        Ok(())
+    })
+}
+
+// Update function for a canister to stop being notified of new posts
+#[ic_cdk::update]
+fn unsubscribe_from_posts() -> Result<(), Error> {
+    remove(caller())
+}
+
+fn remove(canister: Principal) -> Result<(), Error> {
+    let key = Blob::try_from(canister.as_slice()).unwrap();
+    match SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().remove(&key)) {
+        Some(_) => Ok(()),
+        None => Err(Error::NotFound {
+            msg: format!("{} is not subscribed", canister),
+        }),
+    }
+}
+
+// Query function for admins to list the canisters notified of new posts
+#[ic_cdk::query]
+fn list_post_subscribers() -> Result<Vec<Subscriber>, Error> {
+    auth::require_admin()?;
+    Ok(SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .iter()
+            .map(|(canister, subscription)| Subscriber {
+                canister: Principal::from_slice(canister.as_slice()),
+                method: subscription.method,
+                subscribed_at: subscription.subscribed_at,
+            })
+            .collect()
+    }))
+}
+
+// Update function for admins to stop notifying a canister of new posts
+#[ic_cdk::update]
+fn remove_post_subscriber(canister: Principal) -> Result<(), Error> {
+    auth::require_admin()?;
+    remove(canister)
+}